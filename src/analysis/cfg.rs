@@ -0,0 +1,551 @@
+use std::{collections::{BTreeMap, BTreeSet, VecDeque}, ops::Range};
+
+use crate::{cpu::CpuState, instruction::{get_instruction, Architecture, Condition, ControlFlow, DataProcessingOpcode, DataProccessingInstruction, DataProccessingOperand, DescribeInstruction, InstructionError, InstructionType, ShiftBy, SingleDataTransferInstruction, SingleDataTransferOffset, LINK_REGISTER, PROGRAM_COUNTER}, memory::{AccessWidth, Bus, FlatBus}};
+
+use super::{thumb_flow, ThumbFlow};
+
+// First instruction of the cartridge, a branch over the header
+pub const ROM_ENTRY_POINT: u32 = 0x0800_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    // Continues with the next instruction
+    Fallthrough,
+    // Branch, possibly conditional
+    Jump,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u32,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u32,
+    pub end: u32, // Address after the last instruction
+    pub state: CpuState,
+    pub successors: Vec<Edge>,
+    pub calls: Vec<u32>, // Functions called from the block
+    pub unresolved_jump: bool, // Ends with a jump to an unknown address (returns, jump tables)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: u32,
+    pub state: CpuState,
+    pub blocks: Vec<u32>, // Start addresses of the blocks reachable without calls
+    pub calls: Vec<u32>, // Entries of the called functions, including tail calls
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<u32, BasicBlock>,
+    pub functions: BTreeMap<u32, Function>,
+}
+
+// One decoded instruction and where execution continues after it
+#[derive(Debug)]
+struct Step {
+    size: u32,
+    ends_block: bool,
+    unresolved_jump: bool,
+    successors: Vec<(u32, CpuState, EdgeKind)>,
+    calls: Vec<(u32, CpuState)>,
+}
+
+impl Step {
+    fn sequential(size: u32) -> Self {
+        Step { size, ends_block: false, unresolved_jump: false, successors: Vec::new(), calls: Vec::new() }
+    }
+
+    fn jump(size: u32, target: Option<(u32, CpuState)>, conditional: bool, address: u32, state: CpuState) -> Self {
+        let mut successors = Vec::new();
+        let unresolved_jump = target.is_none();
+        if let Some((target, target_state)) = target {
+            successors.push((target, target_state, EdgeKind::Jump));
+        }
+        if conditional {
+            successors.push((address.wrapping_add(size), state, EdgeKind::Fallthrough));
+        }
+        Step { size, ends_block: true, unresolved_jump, successors, calls: Vec::new() }
+    }
+
+    fn call(size: u32, target: Option<(u32, CpuState)>) -> Self {
+        Step { calls: target.into_iter().collect(), ..Step::sequential(size) }
+    }
+}
+
+// Walks code from the entry points without running it, following branches and BX state changes
+#[derive(Debug)]
+pub struct Analyzer<'a, B: Bus> {
+    bus: &'a B,
+    architecture: Architecture,
+    code_regions: Vec<Range<u32>>,
+    entry_points: Vec<(u32, CpuState)>,
+}
+
+impl<'a, B: Bus> Analyzer<'a, B> {
+    pub fn new(bus: &'a B) -> Self {
+        Analyzer { bus, architecture: Architecture::default(), code_regions: Vec::new(), entry_points: Vec::new() }
+    }
+
+    pub fn with_architecture(mut self, architecture: Architecture) -> Self {
+        self.architecture = architecture;
+        self
+    }
+
+    // Code is only followed inside the regions, everything is code when none are set
+    pub fn with_code_region(mut self, region: Range<u32>) -> Self {
+        self.code_regions.push(region);
+        self
+    }
+
+    pub fn with_entry_point(mut self, address: u32, state: CpuState) -> Self {
+        self.entry_points.push((address, state));
+        self
+    }
+
+    fn is_code(&self, address: u32) -> bool {
+        self.code_regions.is_empty() || self.code_regions.iter().any(|region| region.contains(&address))
+    }
+
+    fn read(&self, address: u32, width: AccessWidth) -> Option<u32> {
+        self.bus.peek(address, width).ok()
+    }
+
+    pub fn analyze(&self) -> ControlFlowGraph {
+        let mut steps: BTreeMap<u32, (CpuState, Step)> = BTreeMap::new();
+        let mut leaders: BTreeSet<u32> = BTreeSet::new();
+        let mut entries: BTreeMap<u32, CpuState> = BTreeMap::new();
+        let mut worklist: VecDeque<(u32, CpuState)> = VecDeque::new();
+
+        for (address, state) in &self.entry_points {
+            if self.is_code(*address) && entries.insert(*address, state.clone()).is_none() {
+                worklist.push_back((*address, state.clone()));
+            }
+        }
+
+        while let Some((start, state)) = worklist.pop_front() {
+            leaders.insert(start);
+            // Register values known from literal loads and PC relative addresses
+            let mut known: [Option<u32>; 16] = [None; 16];
+            let mut address = start;
+
+            loop {
+                if steps.contains_key(&address) {
+                    // Runs into code that was already walked
+                    leaders.insert(address);
+                    break;
+                }
+                if !self.is_code(address) {
+                    break;
+                }
+
+                let step = match state {
+                    CpuState::THUMB => self.step_thumb(address, &mut known, &mut entries, &mut worklist),
+                    _ => self.step_arm(address, &mut known, &mut entries, &mut worklist),
+                };
+                let Some(step) = step else { break };
+
+                for (target, target_state, _) in &step.successors {
+                    if self.is_code(*target) {
+                        leaders.insert(*target);
+                        worklist.push_back((*target, target_state.clone()));
+                    }
+                }
+                for (target, target_state) in &step.calls {
+                    if self.is_code(*target) && entries.insert(*target, target_state.clone()).is_none() {
+                        worklist.push_back((*target, target_state.clone()));
+                    }
+                }
+
+                let ends_block = step.ends_block;
+                let next = address.wrapping_add(step.size);
+                steps.insert(address, (state.clone(), step));
+                if ends_block {
+                    break;
+                }
+                address = next;
+            }
+        }
+
+        let blocks = build_blocks(&steps, &leaders);
+        let functions = build_functions(&blocks, &entries);
+        ControlFlowGraph { blocks, functions }
+    }
+
+    // Adds a literal pool word that looks like a THUMB function pointer as a function entry
+    fn literal_pointer(&self, value: u32, entries: &mut BTreeMap<u32, CpuState>, worklist: &mut VecDeque<(u32, CpuState)>) {
+        if (value & 1) != 0 && self.is_code(value & !1) && entries.insert(value & !1, CpuState::THUMB).is_none() {
+            worklist.push_back((value & !1, CpuState::THUMB));
+        }
+    }
+
+    fn step_arm(&self, address: u32, known: &mut [Option<u32>; 16], entries: &mut BTreeMap<u32, CpuState>, worklist: &mut VecDeque<(u32, CpuState)>) -> Option<Step> {
+        let value = self.read(address, AccessWidth::Word)?;
+        let instruction = match get_instruction(value, self.architecture) {
+            Ok(instruction) => instruction,
+            // SWI and the other unimplemented instructions continue with the next one
+            Err(InstructionError::Unimplemented(_)) => return Some(Step::sequential(4)),
+            Err(_) => return None,
+        };
+
+        // NV only encodes BLX imm on ARMv5
+        let condition = instruction.condition();
+        if condition == Condition::NV && !matches!(instruction, InstructionType::Branch(_)) {
+            return Some(Step::sequential(4));
+        }
+        let conditional = condition != Condition::AL;
+        let pc = address.wrapping_add(8);
+
+        // Word loaded from a literal pool: LDR Rd, [PC, #imm]
+        let literal = match &instruction {
+            InstructionType::SingleDataTransfer(SingleDataTransferInstruction { load: true, byte: false, pre_index: true, write_back: false, rn: PROGRAM_COUNTER, up, offset: SingleDataTransferOffset::Immediate(offset), .. }) => {
+                let literal_address = if *up { pc.wrapping_add(*offset as u32) } else { pc.wrapping_sub(*offset as u32) };
+                self.read(literal_address, AccessWidth::Word)
+            }
+            _ => None,
+        };
+        if let Some(literal) = literal {
+            self.literal_pointer(literal, entries, worklist);
+        }
+
+        let metadata = instruction.metadata();
+        let step = match metadata.control_flow {
+            ControlFlow::Sequential => {
+                let computed = match &instruction {
+                    InstructionType::SingleDataTransfer(_) => literal,
+                    InstructionType::DataProcessing(instruction) => arm_constant(instruction, pc, known),
+                    _ => None,
+                };
+                for register in &metadata.registers_written {
+                    known[*register as usize] = None;
+                }
+                if let (Some(value), Some(rd)) = (computed, metadata.registers_written.first()) {
+                    known[*rd as usize] = Some(value);
+                }
+                return Some(Step::sequential(4));
+            }
+            ControlFlow::Branch { relative_target, link, exchange } => {
+                let target = address.wrapping_add(relative_target as u32);
+                let state = if exchange { CpuState::THUMB } else { CpuState::ARM };
+                if link {
+                    Step::call(4, Some((target, state)))
+                } else {
+                    Step::jump(4, Some((target, state)), conditional, address, CpuState::ARM)
+                }
+            }
+            ControlFlow::Indirect { link } => {
+                let target = match &instruction {
+                    InstructionType::Branch(_) => metadata.registers_read.first().and_then(|rm| known[*rm as usize]).map(exchange_target),
+                    InstructionType::SingleDataTransfer(_) if self.architecture.has_load_interworking() => literal.map(exchange_target),
+                    InstructionType::SingleDataTransfer(_) => literal.map(|target| (target & !3, CpuState::ARM)),
+                    // MOV PC, Rm
+                    InstructionType::DataProcessing(DataProccessingInstruction { operand: DataProccessingOperand::Register { shift_by: ShiftBy::Immediate(0), rm, shift_type }, .. })
+                        if instruction_is_move(&instruction) && shift_type.bits() == 0 => known[*rm as usize].map(|target| (target & !3, CpuState::ARM)),
+                    _ => None,
+                };
+
+                // MOV LR, PC before the jump makes it a call
+                if link || known[LINK_REGISTER as usize] == Some(address.wrapping_add(4)) {
+                    Step::call(4, target)
+                } else {
+                    Step::jump(4, target, conditional, address, CpuState::ARM)
+                }
+            }
+        };
+
+        for register in &metadata.registers_written {
+            known[*register as usize] = None;
+        }
+        Some(step)
+    }
+
+    fn step_thumb(&self, address: u32, known: &mut [Option<u32>; 16], entries: &mut BTreeMap<u32, CpuState>, worklist: &mut VecDeque<(u32, CpuState)>) -> Option<Step> {
+        let value = self.read(address, AccessWidth::Halfword)? as u16;
+        let pc = address.wrapping_add(4);
+        let has_v5te = self.architecture.has_v5te();
+
+        let step = match thumb_flow(value, has_v5te) {
+            ThumbFlow::Sequential => {
+                // Without a THUMB decoder the written registers are unknown
+                *known = [None; 16];
+                Step::sequential(2)
+            }
+            ThumbFlow::MoveImmediate { rd, value } => {
+                known[rd as usize] = Some(value);
+                Step::sequential(2)
+            }
+            ThumbFlow::PcRelativeAddress { rd, offset } => {
+                known[rd as usize] = Some((pc & !3).wrapping_add(offset));
+                Step::sequential(2)
+            }
+            ThumbFlow::LiteralLoad { rd, offset } => {
+                let literal = self.read((pc & !3).wrapping_add(offset), AccessWidth::Word);
+                if let Some(literal) = literal {
+                    self.literal_pointer(literal, entries, worklist);
+                }
+                known[rd as usize] = literal;
+                Step::sequential(2)
+            }
+            ThumbFlow::Branch { relative_target } => {
+                Step::jump(2, Some((address.wrapping_add(relative_target as u32), CpuState::THUMB)), false, address, CpuState::THUMB)
+            }
+            ThumbFlow::ConditionalBranch { relative_target } => {
+                Step::jump(2, Some((address.wrapping_add(relative_target as u32), CpuState::THUMB)), true, address, CpuState::THUMB)
+            }
+            ThumbFlow::LongBranchPrefix { offset_high } => {
+                // BL/BLX is a pair of instructions, the second half holds the lower offset
+                let suffix = self.read(address.wrapping_add(2), AccessWidth::Halfword).map(|suffix| thumb_flow(suffix as u16, has_v5te));
+                match suffix {
+                    Some(ThumbFlow::LongBranchSuffix { offset_low, exchange }) => {
+                        let target = pc.wrapping_add(offset_high as u32).wrapping_add(offset_low << 1);
+                        *known = [None; 16];
+                        if exchange {
+                            Step::call(4, Some((target & !3, CpuState::ARM)))
+                        } else {
+                            Step::call(4, Some((target, CpuState::THUMB)))
+                        }
+                    }
+                    _ => Step::sequential(2),
+                }
+            }
+            ThumbFlow::BranchExchange { rm, link } => {
+                let target = known[rm as usize].map(exchange_target);
+                if link {
+                    *known = [None; 16];
+                    Step::call(2, target)
+                } else {
+                    Step::jump(2, target, false, address, CpuState::THUMB)
+                }
+            }
+            ThumbFlow::Indirect { .. } => Step::jump(2, None, false, address, CpuState::THUMB),
+            // A lone BL/BLX second half or an undefined encoding
+            ThumbFlow::LongBranchSuffix { .. } | ThumbFlow::Undefined => return None,
+        };
+        Some(step)
+    }
+}
+
+// Builds the graph of a cartridge ROM, mapped at its usual address. The extra entry points add
+// code only reached through pointers, bit 0 selects THUMB as for BX.
+pub fn analyze_rom(rom: &[u8], architecture: Architecture, entry_points: &[u32]) -> ControlFlowGraph {
+    let bus = FlatBus::new();
+    bus.load(ROM_ENTRY_POINT, rom);
+    let analyzer = Analyzer::new(&bus)
+        .with_architecture(architecture)
+        .with_code_region(ROM_ENTRY_POINT..ROM_ENTRY_POINT.wrapping_add(rom.len() as u32))
+        .with_entry_point(ROM_ENTRY_POINT, CpuState::ARM);
+    entry_points.iter()
+        .fold(analyzer, |analyzer, entry| {
+            let (address, state) = exchange_target(*entry);
+            analyzer.with_entry_point(address, state)
+        })
+        .analyze()
+}
+
+// Bit 0 of a BX target selects the state
+fn exchange_target(target: u32) -> (u32, CpuState) {
+    if (target & 1) != 0 {
+        (target & !1, CpuState::THUMB)
+    } else {
+        (target & !3, CpuState::ARM)
+    }
+}
+
+fn instruction_is_move(instruction: &InstructionType) -> bool {
+    matches!(instruction, InstructionType::DataProcessing(instruction) if instruction.opcode() == DataProcessingOpcode::MOV)
+}
+
+// Value of MOV Rd, #imm, MOV Rd, PC and ADD/SUB Rd, PC, #imm
+fn arm_constant(instruction: &DataProccessingInstruction, pc: u32, known: &[Option<u32>; 16]) -> Option<u32> {
+    let immediate = match instruction.operand {
+        DataProccessingOperand::Immediate { shift_amount, nn } => Some((nn as u32).rotate_right(shift_amount as u32 * 2)),
+        _ => None,
+    };
+    let rm = match &instruction.operand {
+        DataProccessingOperand::Register { shift_by: ShiftBy::Immediate(0), rm, shift_type } if shift_type.bits() == 0 => Some(*rm),
+        _ => None,
+    };
+    let rn = if instruction.rn == PROGRAM_COUNTER { Some(pc) } else { known[instruction.rn as usize] };
+
+    match instruction.opcode() {
+        DataProcessingOpcode::MOV => immediate.or_else(|| rm.and_then(|rm| if rm == PROGRAM_COUNTER { Some(pc) } else { known[rm as usize] })),
+        DataProcessingOpcode::ADD => Some(rn?.wrapping_add(immediate?)),
+        DataProcessingOpcode::SUB => Some(rn?.wrapping_sub(immediate?)),
+        _ => None,
+    }
+}
+
+fn build_blocks(steps: &BTreeMap<u32, (CpuState, Step)>, leaders: &BTreeSet<u32>) -> BTreeMap<u32, BasicBlock> {
+    let mut blocks = BTreeMap::new();
+    let mut current: Option<BasicBlock> = None;
+
+    for (address, (state, step)) in steps {
+        // A block continues while the instructions follow each other in the same state
+        let continues = matches!(&current, Some(block) if block.end == *address && block.state == *state && !leaders.contains(address));
+        if !continues {
+            if let Some(mut block) = current.take() {
+                if steps.contains_key(&block.end) && block.successors.is_empty() && !block.unresolved_jump {
+                    block.successors.push(Edge { target: block.end, kind: EdgeKind::Fallthrough });
+                }
+                blocks.insert(block.start, block);
+            }
+            current = Some(BasicBlock { start: *address, end: *address, state: state.clone(), successors: Vec::new(), calls: Vec::new(), unresolved_jump: false });
+        }
+
+        let block = current.as_mut().expect("block was just started");
+        block.end = address.wrapping_add(step.size);
+        block.calls.extend(step.calls.iter().map(|(target, _)| *target));
+        if step.ends_block {
+            block.successors = step.successors.iter().map(|(target, _, kind)| Edge { target: *target, kind: *kind }).collect();
+            block.unresolved_jump = step.unresolved_jump;
+            let block = current.take().expect("block was just started");
+            blocks.insert(block.start, block);
+        }
+    }
+
+    if let Some(mut block) = current.take() {
+        if steps.contains_key(&block.end) {
+            block.successors.push(Edge { target: block.end, kind: EdgeKind::Fallthrough });
+        }
+        blocks.insert(block.start, block);
+    }
+    blocks
+}
+
+fn build_functions(blocks: &BTreeMap<u32, BasicBlock>, entries: &BTreeMap<u32, CpuState>) -> BTreeMap<u32, Function> {
+    let mut functions = BTreeMap::new();
+
+    for (entry, state) in entries {
+        if !blocks.contains_key(entry) {
+            continue;
+        }
+
+        let mut visited: BTreeSet<u32> = BTreeSet::new();
+        let mut calls: BTreeSet<u32> = BTreeSet::new();
+        let mut worklist = vec![*entry];
+        while let Some(start) = worklist.pop() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let Some(block) = blocks.get(&start) else { continue };
+            calls.extend(block.calls.iter().filter(|target| entries.contains_key(target)));
+            for edge in &block.successors {
+                // Jumping to another function is a tail call
+                if edge.target != *entry && entries.contains_key(&edge.target) {
+                    calls.insert(edge.target);
+                } else {
+                    worklist.push(edge.target);
+                }
+            }
+        }
+
+        functions.insert(*entry, Function {
+            entry: *entry,
+            state: state.clone(),
+            blocks: visited.into_iter().filter(|start| blocks.contains_key(start)).collect(),
+            calls: calls.into_iter().collect(),
+        });
+    }
+    functions
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn rom(words: &[(u32, u32)], halfwords: &[(u32, u16)]) -> Vec<u8> {
+        let mut rom = vec![0; 0x200];
+        for (offset, word) in words {
+            rom[*offset as usize..*offset as usize + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        for (offset, halfword) in halfwords {
+            rom[*offset as usize..*offset as usize + 2].copy_from_slice(&halfword.to_le_bytes());
+        }
+        rom
+    }
+
+    #[test]
+    fn test_arm_blocks_and_calls() {
+        let rom = rom(&[
+            (0x00, 0xEB000003), // BL 0x08000014
+            (0x04, 0xE28F0001), // ADD R0, PC, #1
+            (0x08, 0xE12FFF10), // BX R0 (THUMB 0x0800000C)
+            (0x14, 0xE3500000), // CMP R0, #0
+            (0x18, 0x0A000000), // BEQ 0x08000020
+            (0x1C, 0xE3A00001), // MOV R0, #1
+            (0x20, 0xE12FFF1E), // BX LR
+        ], &[
+            (0x0C, 0xE7FE), // B $
+        ]);
+        let cfg = analyze_rom(&rom, Architecture::ARMv4T, &[]);
+
+        assert_eq!(cfg.functions.keys().copied().collect::<Vec<u32>>(), vec![0x0800_0000, 0x0800_0014]);
+        let main = &cfg.functions[&0x0800_0000];
+        assert_eq!(main.blocks, vec![0x0800_0000, 0x0800_000C]);
+        assert_eq!(main.calls, vec![0x0800_0014]);
+        assert_eq!(cfg.functions[&0x0800_0014].blocks, vec![0x0800_0014, 0x0800_001C, 0x0800_0020]);
+
+        let block = &cfg.blocks[&0x0800_0000];
+        assert_eq!(block.end, 0x0800_000C);
+        assert_eq!(block.successors, vec![Edge { target: 0x0800_000C, kind: EdgeKind::Jump }]);
+        assert_eq!(cfg.blocks[&0x0800_000C].state, CpuState::THUMB);
+        assert_eq!(cfg.blocks[&0x0800_000C].successors, vec![Edge { target: 0x0800_000C, kind: EdgeKind::Jump }]);
+
+        let block = &cfg.blocks[&0x0800_0014];
+        assert_eq!(block.successors, vec![Edge { target: 0x0800_0020, kind: EdgeKind::Jump }, Edge { target: 0x0800_001C, kind: EdgeKind::Fallthrough }]);
+        assert_eq!(cfg.blocks[&0x0800_001C].successors, vec![Edge { target: 0x0800_0020, kind: EdgeKind::Fallthrough }]);
+        assert!(cfg.blocks[&0x0800_0020].unresolved_jump);
+    }
+
+    #[test]
+    fn test_thumb_calls_and_literal_pointers() {
+        let rom = rom(&[
+            (0x00, 0xEA00003E), // B 0x08000100
+            (0x100, 0xE28F0001), // ADD R0, PC, #1
+            (0x104, 0xE12FFF10), // BX R0 (THUMB 0x08000108)
+            (0x114, 0x0800_0119), // Literal: THUMB 0x08000118
+        ], &[
+            (0x108, 0xF000), (0x10A, 0xF802), // BL 0x08000110
+            (0x10C, 0x4801), // LDR R0, [PC, #4]
+            (0x10E, 0x4700), // BX R0
+            (0x110, 0x4770), // BX LR
+            (0x118, 0x4770), // BX LR
+        ]);
+        let cfg = analyze_rom(&rom, Architecture::ARMv4T, &[]);
+
+        assert_eq!(cfg.functions.keys().copied().collect::<Vec<u32>>(), vec![0x0800_0000, 0x0800_0110, 0x0800_0118]);
+        let main = &cfg.functions[&0x0800_0000];
+        assert_eq!(main.blocks, vec![0x0800_0000, 0x0800_0100, 0x0800_0108]);
+        // The BX R0 into the literal pool function is a tail call
+        assert_eq!(main.calls, vec![0x0800_0110, 0x0800_0118]);
+        assert_eq!(cfg.functions[&0x0800_0110].state, CpuState::THUMB);
+        assert_eq!(cfg.blocks[&0x0800_0108].end, 0x0800_0110);
+    }
+
+    #[test]
+    fn test_entry_points_and_architecture() {
+        let rom = rom(&[
+            (0x00, 0xEAFFFFFE), // B $
+            (0x20, 0xFA000002), // BLX 0x08000030
+            (0x24, 0xE12FFF1E), // BX LR
+        ], &[
+            (0x10, 0xE7FE), // B $
+            (0x30, 0x4770), // BX LR
+        ]);
+        let cfg = analyze_rom(&rom, Architecture::ARMv4T, &[]);
+        assert_eq!(cfg.functions.keys().copied().collect::<Vec<u32>>(), vec![0x0800_0000]);
+
+        // Code only reached through pointers, the BLX is only followed on ARMv5
+        let cfg = analyze_rom(&rom, Architecture::ARMv4T, &[0x0800_0011, 0x0800_0020]);
+        assert_eq!(cfg.functions.keys().copied().collect::<Vec<u32>>(), vec![0x0800_0000, 0x0800_0010, 0x0800_0020]);
+        assert_eq!(cfg.functions[&0x0800_0010].state, CpuState::THUMB);
+        let cfg = analyze_rom(&rom, Architecture::ARMv5TE, &[0x0800_0020]);
+        assert_eq!(cfg.functions[&0x0800_0020].calls, vec![0x0800_0030]);
+        assert_eq!(cfg.functions[&0x0800_0030].state, CpuState::THUMB);
+    }
+}
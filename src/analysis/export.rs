@@ -0,0 +1,336 @@
+use std::fmt::Write;
+
+use crate::cpu::CpuState;
+
+use super::{ControlFlowGraph, EdgeKind};
+
+// Output formats of a control flow graph, as named on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Dot, // Basic blocks
+    CallGraph,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name {
+            "dot" => Some(ExportFormat::Dot),
+            "calls" => Some(ExportFormat::CallGraph),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+fn state_name(state: &CpuState) -> &'static str {
+    match state {
+        CpuState::ARM => "ARM",
+        CpuState::THUMB => "THUMB",
+        CpuState::UNDEFINED => "UNDEFINED",
+    }
+}
+
+fn edge_kind_name(kind: EdgeKind) -> &'static str {
+    match kind {
+        EdgeKind::Fallthrough => "fallthrough",
+        EdgeKind::Jump => "jump",
+    }
+}
+
+impl ControlFlowGraph {
+    pub fn export(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Dot => self.to_dot(),
+            ExportFormat::CallGraph => self.call_graph_to_dot(),
+            ExportFormat::Json => self.to_json(),
+        }
+    }
+
+    // Graphviz graph of the basic blocks, one cluster per function
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+
+        for function in self.functions.values() {
+            writeln!(dot, "    subgraph cluster_{:08X} {{", function.entry).unwrap();
+            writeln!(dot, "        label=\"sub_{:08X} ({})\";", function.entry, state_name(&function.state)).unwrap();
+            for start in &function.blocks {
+                writeln!(dot, "        \"{:08X}\";", start).unwrap();
+            }
+            dot.push_str("    }\n");
+        }
+
+        for block in self.blocks.values() {
+            let style = if block.unresolved_jump { ", style=dashed" } else { "" };
+            writeln!(dot, "    \"{:08X}\" [label=\"{:08X}-{:08X}\"{}];", block.start, block.start, block.end, style).unwrap();
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => " [style=dotted]",
+                    EdgeKind::Jump => "",
+                };
+                writeln!(dot, "    \"{:08X}\" -> \"{:08X}\"{};", block.start, edge.target, style).unwrap();
+            }
+            for call in &block.calls {
+                writeln!(dot, "    \"{:08X}\" -> \"{:08X}\" [color=blue];", block.start, call).unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Graphviz graph of the functions and who calls whom
+    pub fn call_graph_to_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n    node [shape=box, fontname=monospace];\n");
+        for function in self.functions.values() {
+            writeln!(dot, "    \"{:08X}\" [label=\"sub_{:08X}\"];", function.entry, function.entry).unwrap();
+            for call in &function.calls {
+                writeln!(dot, "    \"{:08X}\" -> \"{:08X}\";", function.entry, call).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Blocks and functions as JSON, addresses are plain numbers
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"blocks\":[");
+        for (index, block) in self.blocks.values().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            let successors: Vec<String> = block.successors.iter()
+                .map(|edge| format!("{{\"target\":{},\"kind\":\"{}\"}}", edge.target, edge_kind_name(edge.kind)))
+                .collect();
+            write!(json, "{{\"start\":{},\"end\":{},\"state\":\"{}\",\"successors\":[{}],\"calls\":{},\"unresolved_jump\":{}}}",
+                block.start, block.end, state_name(&block.state), successors.join(","), json_array(&block.calls), block.unresolved_jump).unwrap();
+        }
+
+        json.push_str("],\"functions\":[");
+        for (index, function) in self.functions.values().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            write!(json, "{{\"entry\":{},\"state\":\"{}\",\"blocks\":{},\"calls\":{}}}",
+                function.entry, state_name(&function.state), json_array(&function.blocks), json_array(&function.calls)).unwrap();
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+fn json_array(values: &[u32]) -> String {
+    let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{collections::HashSet, iter::Peekable, str::Chars};
+
+    use super::*;
+    use crate::{analysis::{analyze_rom, BasicBlock, Edge, Function}, instruction::Architecture};
+
+    fn graph() -> ControlFlowGraph {
+        let mut cfg = ControlFlowGraph::default();
+        cfg.blocks.insert(0x100, BasicBlock { start: 0x100, end: 0x108, state: CpuState::ARM, successors: vec![Edge { target: 0x108, kind: EdgeKind::Fallthrough }], calls: vec![0x200], unresolved_jump: false });
+        cfg.blocks.insert(0x108, BasicBlock { start: 0x108, end: 0x10C, state: CpuState::ARM, successors: Vec::new(), calls: Vec::new(), unresolved_jump: true });
+        cfg.blocks.insert(0x200, BasicBlock { start: 0x200, end: 0x202, state: CpuState::THUMB, successors: Vec::new(), calls: Vec::new(), unresolved_jump: true });
+        cfg.functions.insert(0x100, Function { entry: 0x100, state: CpuState::ARM, blocks: vec![0x100, 0x108], calls: vec![0x200] });
+        cfg.functions.insert(0x200, Function { entry: 0x200, state: CpuState::THUMB, blocks: vec![0x200], calls: Vec::new() });
+        cfg
+    }
+
+    #[test]
+    fn test_dot() {
+        let cfg = graph();
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("subgraph cluster_00000100 {"));
+        assert!(dot.contains("label=\"sub_00000200 (THUMB)\";"));
+        assert!(dot.contains("\"00000100\" -> \"00000108\" [style=dotted];"));
+        assert!(dot.contains("\"00000100\" -> \"00000200\" [color=blue];"));
+
+        let calls = cfg.call_graph_to_dot();
+        assert!(calls.contains("\"00000100\" -> \"00000200\";"));
+    }
+
+    #[test]
+    fn test_json() {
+        let json = graph().to_json();
+        assert!(json.starts_with("{\"blocks\":[{\"start\":256,\"end\":264,\"state\":\"ARM\",\"successors\":[{\"target\":264,\"kind\":\"fallthrough\"}],\"calls\":[512],\"unresolved_jump\":false},"));
+        assert!(json.ends_with("\"functions\":[{\"entry\":256,\"state\":\"ARM\",\"blocks\":[256,264],\"calls\":[512]},{\"entry\":512,\"state\":\"THUMB\",\"blocks\":[512],\"calls\":[]}]}"));
+    }
+
+    // Calls, a conditional branch, an exchange into THUMB and a return
+    fn analyzed_graph() -> ControlFlowGraph {
+        let words: [u32; 9] = [
+            0xEB000003, // BL 0x08000014
+            0xE28F0001, // ADD R0, PC, #1
+            0xE12FFF10, // BX R0 (THUMB 0x0800000C)
+            0x0000E7FE, // B $
+            0,
+            0xE3500000, // CMP R0, #0
+            0x0A000000, // BEQ 0x08000020
+            0xE3A00001, // MOV R0, #1
+            0xE12FFF1E, // BX LR
+        ];
+        let rom: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        analyze_rom(&rom, Architecture::ARMv4T, &[])
+    }
+
+    // Just enough JSON to read what to_json writes
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Number(u64),
+        Bool(bool),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => &fields.iter().find(|(name, _)| name == key).unwrap_or_else(|| panic!("no {}", key)).1,
+                _ => panic!("{:?} is not an object", self),
+            }
+        }
+
+        fn items(&self) -> &[Json] {
+            match self {
+                Json::Array(items) => items,
+                _ => panic!("{:?} is not an array", self),
+            }
+        }
+
+        fn number(&self) -> u32 {
+            match self {
+                Json::Number(value) => *value as u32,
+                _ => panic!("{:?} is not a number", self),
+            }
+        }
+    }
+
+    fn parse_json(text: &str) -> Json {
+        let mut chars = text.chars().peekable();
+        let value = parse_json_value(&mut chars);
+        assert_eq!(chars.next(), None, "trailing characters");
+        value
+    }
+
+    fn parse_json_value(chars: &mut Peekable<Chars>) -> Json {
+        match chars.next() {
+            Some('{') => {
+                let mut fields = Vec::new();
+                if chars.next_if_eq(&'}').is_some() {
+                    return Json::Object(fields);
+                }
+                loop {
+                    let Json::String(name) = parse_json_value(chars) else { panic!("object key is not a string") };
+                    assert_eq!(chars.next(), Some(':'));
+                    fields.push((name, parse_json_value(chars)));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Json::Object(fields),
+                        other => panic!("unexpected {:?} in object", other),
+                    }
+                }
+            }
+            Some('[') => {
+                let mut items = Vec::new();
+                if chars.next_if_eq(&']').is_some() {
+                    return Json::Array(items);
+                }
+                loop {
+                    items.push(parse_json_value(chars));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Json::Array(items),
+                        other => panic!("unexpected {:?} in array", other),
+                    }
+                }
+            }
+            Some('"') => Json::String(chars.by_ref().take_while(|c| *c != '"').collect()),
+            Some(first) => {
+                let mut word = first.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric()) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Json::Bool(true),
+                    "false" => Json::Bool(false),
+                    _ => Json::Number(word.parse().unwrap_or_else(|_| panic!("invalid value {}", word))),
+                }
+            }
+            None => panic!("unexpected end"),
+        }
+    }
+
+    #[test]
+    fn test_json_parses() {
+        let cfg = analyzed_graph();
+        let json = parse_json(&cfg.export(ExportFormat::Json));
+
+        let blocks = json.get("blocks").items();
+        assert_eq!(blocks.len(), cfg.blocks.len());
+        let starts: HashSet<u32> = blocks.iter().map(|block| block.get("start").number()).collect();
+        for block in blocks {
+            assert!(block.get("start").number() < block.get("end").number());
+            for successor in block.get("successors").items() {
+                assert!(starts.contains(&successor.get("target").number()));
+            }
+        }
+        assert_eq!(blocks[0].get("calls"), &Json::Array(vec![Json::Number(0x0800_0014)]));
+
+        let functions = json.get("functions").items();
+        let entries: Vec<u32> = functions.iter().map(|function| function.get("entry").number()).collect();
+        assert_eq!(entries, vec![0x0800_0000, 0x0800_0014]);
+        for function in functions {
+            assert!(function.get("blocks").items().iter().all(|block| starts.contains(&block.number())));
+        }
+        assert_eq!(functions[1].get("state"), &Json::String("ARM".to_string()));
+    }
+
+    // Node names of the edges of a DOT graph, checking the braces match on the way
+    fn dot_edges(dot: &str) -> (HashSet<String>, Vec<(String, String)>) {
+        let mut depth = 0;
+        let mut nodes = HashSet::new();
+        let mut edges = Vec::new();
+        for line in dot.lines().map(str::trim) {
+            depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+            assert!(depth >= 0, "{}", line);
+            let names: Vec<&str> = line.split('"').skip(1).step_by(2).collect();
+            if line.contains(" -> ") {
+                edges.push((names[0].to_string(), names[1].to_string()));
+            } else if line.starts_with('"') {
+                nodes.insert(names[0].to_string());
+            }
+        }
+        assert_eq!(depth, 0);
+        (nodes, edges)
+    }
+
+    #[test]
+    fn test_dot_edges_reach_declared_nodes() {
+        let cfg = analyzed_graph();
+
+        let (nodes, edges) = dot_edges(&cfg.export(ExportFormat::Dot));
+        assert_eq!(nodes.len(), cfg.blocks.len());
+        assert!(edges.iter().all(|(from, to)| nodes.contains(from) && nodes.contains(to)));
+        assert!(edges.contains(&("08000014".to_string(), "08000020".to_string())));
+
+        let (nodes, edges) = dot_edges(&cfg.export(ExportFormat::CallGraph));
+        assert_eq!(nodes, HashSet::from(["08000000".to_string(), "08000014".to_string()]));
+        assert_eq!(edges, vec![("08000000".to_string(), "08000014".to_string())]);
+    }
+
+    #[test]
+    fn test_export_format_names() {
+        assert_eq!(ExportFormat::from_name("dot"), Some(ExportFormat::Dot));
+        assert_eq!(ExportFormat::from_name("calls"), Some(ExportFormat::CallGraph));
+        assert_eq!(ExportFormat::from_name("json"), Some(ExportFormat::Json));
+        assert_eq!(ExportFormat::from_name("svg"), None);
+    }
+}
@@ -0,0 +1,7 @@
+mod thumb;
+mod cfg;
+mod export;
+
+pub use thumb::*;
+pub use cfg::*;
+pub use export::*;
@@ -0,0 +1,90 @@
+// Control flow of a THUMB instruction. There is no THUMB decoder yet, so only the
+// instructions that change control flow or produce a known address are told apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThumbFlow {
+    // Continues with the next instruction, registers it writes are unknown
+    Sequential,
+    // B<cond>: bits 15-12 1101b (Target relative to the address of the instruction)
+    ConditionalBranch { relative_target: i32 },
+    // B: bits 15-11 11100b
+    Branch { relative_target: i32 },
+    // BL/BLX first half: bits 15-11 11110b (Upper 11 bits of the offset, shifted by 12)
+    LongBranchPrefix { offset_high: i32 },
+    // BL second half: bits 15-11 11111b, BLX (ARMv5): 11101b (Lower 11 bits of the offset, in halfwords)
+    LongBranchSuffix { offset_low: u32, exchange: bool },
+    // BX/BLX Rm: bits 15-7 0100_0111_0b/1b
+    BranchExchange { rm: u8, link: bool },
+    // POP {.., PC} and ADD/MOV PC, Rs
+    Indirect { returns: bool },
+    // LDR Rd, [PC, #imm]: bits 15-11 01001b (Offset from the word aligned PC)
+    LiteralLoad { rd: u8, offset: u32 },
+    // ADD Rd, PC, #imm: bits 15-11 10100b
+    PcRelativeAddress { rd: u8, offset: u32 },
+    // MOV Rd, #imm: bits 15-11 00100b
+    MoveImmediate { rd: u8, value: u32 },
+    // Undefined encoding of B<cond> (bits 11-8 1110b) and the BLX suffix on ARMv4
+    Undefined,
+}
+
+// Sign extends the lowest `bits` bits
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+pub fn thumb_flow(value: u16, has_v5te: bool) -> ThumbFlow {
+    let value = value as u32;
+    match value >> 11 {
+        0b00100 => ThumbFlow::MoveImmediate { rd: ((value >> 8) & 0x7) as u8, value: value & 0xFF },
+        0b01001 => ThumbFlow::LiteralLoad { rd: ((value >> 8) & 0x7) as u8, offset: (value & 0xFF) * 4 },
+        0b10100 => ThumbFlow::PcRelativeAddress { rd: ((value >> 8) & 0x7) as u8, offset: (value & 0xFF) * 4 },
+        0b11100 => ThumbFlow::Branch { relative_target: 4 + sign_extend(value & 0x7FF, 11) * 2 },
+        0b11110 => ThumbFlow::LongBranchPrefix { offset_high: sign_extend(value & 0x7FF, 11) << 12 },
+        0b11111 => ThumbFlow::LongBranchSuffix { offset_low: value & 0x7FF, exchange: false },
+        0b11101 if has_v5te && (value & 1) == 0 => ThumbFlow::LongBranchSuffix { offset_low: value & 0x7FF, exchange: true },
+        0b11101 => ThumbFlow::Undefined,
+        0b11010 | 0b11011 => match (value >> 8) & 0xF {
+            // SWI returns to the next instruction
+            0b1111 => ThumbFlow::Sequential,
+            0b1110 => ThumbFlow::Undefined,
+            _ => ThumbFlow::ConditionalBranch { relative_target: 4 + sign_extend(value & 0xFF, 8) * 2 },
+        },
+        // POP {.., PC}
+        0b10111 if (value & 0xFF00) == 0xBD00 => ThumbFlow::Indirect { returns: true },
+        // Hi register operations: ADD/CMP/MOV/BX
+        0b01000 if (value & 0xFC00) == 0x4400 => {
+            let opcode = (value >> 8) & 0x3;
+            let rd = (((value >> 4) & 0x8) | (value & 0x7)) as u8;
+            let rs = ((value >> 3) & 0xF) as u8;
+            match opcode {
+                0b11 => ThumbFlow::BranchExchange { rm: rs, link: (value & (1 << 7)) != 0 },
+                // MOV PC, LR
+                0b10 if rd == 15 => ThumbFlow::Indirect { returns: rs == 14 },
+                0b00 if rd == 15 => ThumbFlow::Indirect { returns: false },
+                _ => ThumbFlow::Sequential,
+            }
+        }
+        _ => ThumbFlow::Sequential,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_thumb_flow() {
+        // B $ / BNE $+8 / BL (prefix, suffix) / BX LR / POP {R4, PC} / LDR R1, [PC, #8]
+        assert_eq!(thumb_flow(0xE7FE, false), ThumbFlow::Branch { relative_target: 0 });
+        assert_eq!(thumb_flow(0xD102, false), ThumbFlow::ConditionalBranch { relative_target: 8 });
+        assert_eq!(thumb_flow(0xF7FF, false), ThumbFlow::LongBranchPrefix { offset_high: -4096 });
+        assert_eq!(thumb_flow(0xF806, false), ThumbFlow::LongBranchSuffix { offset_low: 6, exchange: false });
+        assert_eq!(thumb_flow(0x4770, false), ThumbFlow::BranchExchange { rm: 14, link: false });
+        assert_eq!(thumb_flow(0xBD10, false), ThumbFlow::Indirect { returns: true });
+        assert_eq!(thumb_flow(0x4902, false), ThumbFlow::LiteralLoad { rd: 1, offset: 8 });
+        // SWI 5 / BLX suffix on ARMv4
+        assert_eq!(thumb_flow(0xDF05, false), ThumbFlow::Sequential);
+        assert_eq!(thumb_flow(0xE802, false), ThumbFlow::Undefined);
+        assert_eq!(thumb_flow(0xE802, true), ThumbFlow::LongBranchSuffix { offset_low: 2, exchange: true });
+    }
+}
@@ -0,0 +1,42 @@
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoprocessorError {
+    // The coprocessor does not support the operation, the CPU raises the Undefined exception
+    UndefinedInstruction,
+    AccessError(String),
+}
+
+// A coprocessor the CPU dispatches CDP/MCR/MRC/LDC/STC to by coprocessor number.
+// Every operation defaults to undefined, so implementations only provide what they support.
+pub trait Coprocessor: fmt::Debug {
+    // CDP: internal coprocessor operation
+    fn data_operation(&mut self, _cp_opc: u8, _crd: u8, _crn: u8, _crm: u8, _cp: u8) -> Result<(), CoprocessorError> {
+        Err(CoprocessorError::UndefinedInstruction)
+    }
+
+    // MCR: ARM register to coprocessor register
+    fn write_register(&mut self, _cp_opc: u8, _crn: u8, _crm: u8, _cp: u8, _value: u32) -> Result<(), CoprocessorError> {
+        Err(CoprocessorError::UndefinedInstruction)
+    }
+
+    // MRC: coprocessor register to ARM register
+    fn read_register(&mut self, _cp_opc: u8, _crn: u8, _crm: u8, _cp: u8) -> Result<u32, CoprocessorError> {
+        Err(CoprocessorError::UndefinedInstruction)
+    }
+
+    // Number of words transferred by LDC/STC for the given register
+    fn transfer_length(&self, _crd: u8, _long: bool) -> usize {
+        1
+    }
+
+    // LDC: words loaded from memory into the coprocessor
+    fn load(&mut self, _crd: u8, _long: bool, _data: &[u32]) -> Result<(), CoprocessorError> {
+        Err(CoprocessorError::UndefinedInstruction)
+    }
+
+    // STC: words stored from the coprocessor into memory
+    fn store(&mut self, _crd: u8, _long: bool) -> Result<Vec<u32>, CoprocessorError> {
+        Err(CoprocessorError::UndefinedInstruction)
+    }
+}
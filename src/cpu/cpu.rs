@@ -1,5 +1,10 @@
-use crate::register::RegisterMap;
-use crate::memory::MemoryBus;
+use std::collections::HashMap;
+
+use crate::instruction::{branch_exchange, get_instruction, Architecture, Condition, CoprocessorInstruction, CoprocessorOperation, Instruction, InstructionError, InstructionType, LINK_REGISTER, PROGRAM_COUNTER};
+use crate::register::{read_register_set, write_register_set, Mode, ReadRegister, RegisterMap, RegisterSet, WriteRegister, CPSR};
+use crate::memory::{Bus, MemoryBus};
+
+use super::{swi_number, Coprocessor, CoprocessorError, CpuError, Exception, InterruptController, SwiHandler, SwiOutcome, UnimplementedPolicy, UnimplementedReport};
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -23,13 +28,691 @@ impl Default for CpuState {
 
 
 #[derive(Debug, Default)]
-pub struct CPU {
+pub struct CPU<B: Bus = MemoryBus> {
     pub register_map: RegisterMap,
-    pub memory_bus: MemoryBus,
+    pub memory_bus: B,
+    pub architecture: Architecture,
+    unimplemented_policy: UnimplementedPolicy,
+    unimplemented_report: UnimplementedReport,
+    // Coprocessor number (P0-P15) -> coprocessor
+    coprocessors: HashMap<u8, Box<dyn Coprocessor>>,
+    // Emulates BIOS calls, without one SWI enters the BIOS
+    swi_handler: Option<Box<dyn SwiHandler<B>>>,
+    // Raises IRQs, without one the CPU never takes them
+    interrupt_controller: Option<Box<dyn InterruptController>>,
+    // Waiting in a BIOS call, see `SwiOutcome::Halted`
+    halted: bool,
+    // Address the wait continues at once the IRQ that interrupted it returns
+    wait_return: Option<u32>,
+    // Cycles the steps took so far
+    cycles: u64,
 }
 
-impl CPU {
-    pub fn new(register_map: RegisterMap, memory_bus: MemoryBus) -> CPU {
-        CPU { register_map, memory_bus }
+impl<B: Bus> CPU<B> {
+    pub fn new(register_map: RegisterMap, memory_bus: B) -> CPU<B> {
+        CPU::with_architecture(register_map, memory_bus, Architecture::default())
+    }
+
+    pub fn with_architecture(register_map: RegisterMap, memory_bus: B, architecture: Architecture) -> CPU<B> {
+        CPU {
+            register_map,
+            memory_bus,
+            architecture,
+            unimplemented_policy: UnimplementedPolicy::default(),
+            unimplemented_report: UnimplementedReport::default(),
+            coprocessors: HashMap::new(),
+            swi_handler: None,
+            interrupt_controller: None,
+            halted: false,
+            wait_return: None,
+            cycles: 0,
+        }
+    }
+
+    pub fn set_swi_handler(&mut self, handler: Box<dyn SwiHandler<B>>) {
+        self.swi_handler = Some(handler);
+    }
+
+    pub fn set_interrupt_controller(&mut self, controller: Box<dyn InterruptController>) {
+        self.interrupt_controller = Some(controller);
+    }
+
+    pub fn register_coprocessor(&mut self, cp_num: u8, coprocessor: Box<dyn Coprocessor>) -> Result<(), CpuError> {
+        if cp_num > 15 {
+            return Err(CpuError::InvalidCoprocessor(cp_num));
+        }
+
+        if self.coprocessors.contains_key(&cp_num) {
+            return Err(CpuError::DuplicateCoprocessor(cp_num));
+        }
+
+        self.coprocessors.insert(cp_num, coprocessor);
+        Ok(())
+    }
+
+    pub fn cpsr(&self) -> Result<CPSR, CpuError> {
+        // The CPSR is shared between all register sets
+        let register_set = self.register_map.get(Mode::SYSTEM).ok_or(CpuError::RegisterError("Missing system registers".to_string()))?;
+        let cpsr = register_set.cpsr.read().map_err(|e| CpuError::RegisterError(e.to_string()))?;
+        CPSR::from_bits(cpsr).ok_or(CpuError::RegisterError(format!("Invalid CPSR {:#010X}", cpsr)))
+    }
+
+    pub fn set_cpsr(&self, cpsr: CPSR) -> Result<(), CpuError> {
+        let mut register_set = self.register_map.get(Mode::SYSTEM).ok_or(CpuError::RegisterError("Missing system registers".to_string()))?;
+        register_set.cpsr.write(cpsr.bits()).map_err(|e| CpuError::RegisterError(e.to_string()))
+    }
+
+    pub fn mode(&self) -> Result<Mode, CpuError> {
+        let cpsr = self.cpsr()?;
+        cpsr.mode().ok_or(CpuError::InvalidMode(cpsr.bits() & CPSR::M.bits()))
+    }
+
+    // Register set of the current mode
+    pub fn register_set(&self) -> Result<RegisterSet, CpuError> {
+        let mode = match self.mode()? {
+            // User mode shares its registers with System mode
+            Mode::USER => Mode::SYSTEM,
+            mode => mode,
+        };
+        self.register_map.get(mode.clone()).ok_or(CpuError::InvalidMode(mode.bits()))
+    }
+
+    pub fn read_register(&self, register: u8) -> Result<u32, CpuError> {
+        read_register_set(&self.register_set()?, register).map_err(|e| CpuError::RegisterError(e.to_string()))
+    }
+
+    pub fn write_register(&self, register: u8, value: u32) -> Result<(), CpuError> {
+        write_register_set(&mut self.register_set()?, register, value).map_err(|e| CpuError::RegisterError(e.to_string()))
+    }
+
+    // Switches to the exception mode, saving the CPSR in its SPSR and the return address in its LR
+    pub fn raise_exception(&mut self, exception: Exception, return_address: u32) -> Result<(), CpuError> {
+        let mode = exception.mode();
+        let mut register_set = self.register_map.get(mode.clone()).ok_or(CpuError::InvalidMode(mode.bits()))?;
+
+        let mut cpsr = self.cpsr()?;
+        register_set.spsr.write(cpsr.bits()).map_err(|e| CpuError::RegisterError(e.to_string()))?;
+
+        cpsr.set_mode(mode);
+        cpsr.set_state(CpuState::ARM);
+        cpsr.seti(true);
+        if exception.disables_fiq() {
+            cpsr.setf(true);
+        }
+        self.set_cpsr(cpsr)?;
+
+        write_register_set(&mut register_set, LINK_REGISTER, return_address).map_err(|e| CpuError::RegisterError(e.to_string()))?;
+        write_register_set(&mut register_set, PROGRAM_COUNTER, exception.vector()).map_err(|e| CpuError::RegisterError(e.to_string()))
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_unimplemented_policy(&mut self, policy: UnimplementedPolicy) {
+        self.unimplemented_policy = policy;
+    }
+
+    pub fn unimplemented_report(&self) -> &UnimplementedReport {
+        &self.unimplemented_report
     }
-}
\ No newline at end of file
+
+    // Executes the instruction located at the current PC, leaving the PC at the next instruction or the branch target
+    pub fn execute(&mut self, value: u32) -> Result<(), CpuError> {
+        let register_set = self.register_set()?;
+        let pc = self.read_register(PROGRAM_COUNTER)?;
+
+        // PC reads as the address of the instruction + 8 while it executes
+        self.write_register(PROGRAM_COUNTER, pc.wrapping_add(8))?;
+
+        match self.execute_instruction(value, &register_set) {
+            Ok(true) => Ok(()),
+            Ok(false) => self.write_register(PROGRAM_COUNTER, pc.wrapping_add(4)),
+            Err(CpuError::InstructionError(InstructionError::UndefinedInstruction())) => {
+                self.raise_exception(Exception::Undefined, pc.wrapping_add(4))
+            }
+            Err(CpuError::InstructionError(InstructionError::Unimplemented(mnemonic))) => {
+                let first_hit = self.unimplemented_report.record(pc, value, mnemonic);
+                match self.unimplemented_policy {
+                    UnimplementedPolicy::RaiseUndefined => self.raise_exception(Exception::Undefined, pc.wrapping_add(4)),
+                    UnimplementedPolicy::ReturnError => {
+                        self.write_register(PROGRAM_COUNTER, pc)?;
+                        Err(CpuError::Unimplemented { pc, opcode: value, mnemonic })
+                    }
+                    UnimplementedPolicy::Skip => {
+                        if first_hit {
+                            eprintln!("Skipping unimplemented {} {:#010X} at {:#010X}", mnemonic, value, pc);
+                        }
+                        self.write_register(PROGRAM_COUNTER, pc.wrapping_add(4))
+                    }
+                }
+            }
+            Err(e) => {
+                self.write_register(PROGRAM_COUNTER, pc)?;
+                Err(e)
+            }
+        }
+    }
+
+    // Returns whether the instruction wrote the PC
+    fn execute_instruction(&mut self, value: u32, register_set: &RegisterSet) -> Result<bool, CpuError> {
+        if let Some(number) = swi_number(value) {
+            if !Condition::from_bits_truncate((value >> 28) as u8).is_satisfied(&self.cpsr()?) {
+                return Ok(false);
+            }
+            return self.software_interrupt(number);
+        }
+
+        let instruction = match get_instruction(value, self.architecture) {
+            // Unimplemented instructions only count when they would have executed
+            Err(InstructionError::Unimplemented(_)) if !Condition::from_bits_truncate((value >> 28) as u8).is_satisfied(&self.cpsr()?) => {
+                return Ok(false);
+            }
+            instruction => instruction.map_err(CpuError::InstructionError)?,
+        };
+        if !instruction.condition().is_satisfied(&self.cpsr()?) {
+            return Ok(false);
+        }
+
+        let writes_pc = instruction.writes_pc();
+        let loads_pc = instruction.loads_pc();
+        match instruction {
+            InstructionType::Coprocessor(instruction) => self.execute_coprocessor(&instruction, register_set)?,
+            mut instruction => instruction.execute(register_set, &self.memory_bus).map_err(CpuError::InstructionError)?,
+        }
+
+        if loads_pc && self.architecture.has_load_interworking() {
+            // ARMv5: bit 0 of the loaded value selects the state
+            let target = self.read_register(PROGRAM_COUNTER)?;
+            branch_exchange(register_set, target).map_err(CpuError::InstructionError)?;
+        } else if writes_pc {
+            let target = self.read_register(PROGRAM_COUNTER)?;
+            let alignment = if self.cpsr()?.state() == CpuState::THUMB { !1 } else { !3 };
+            self.write_register(PROGRAM_COUNTER, target & alignment)?;
+        }
+        Ok(writes_pc)
+    }
+
+    // Returns whether the SWI entered the BIOS
+    fn software_interrupt(&mut self, number: u8) -> Result<bool, CpuError> {
+        // The handler is taken out while it runs so it can see the CPU
+        let mut handler = self.swi_handler.take();
+        let outcome = match handler.as_mut() {
+            Some(handler) => handler.software_interrupt(number, self),
+            None => Ok(SwiOutcome::Unhandled),
+        };
+        self.swi_handler = handler;
+
+        match outcome? {
+            SwiOutcome::Returned => return Ok(false),
+            SwiOutcome::Branched => return Ok(true),
+            SwiOutcome::Halted => {
+                self.halted = true;
+                return Ok(false);
+            }
+            SwiOutcome::Unhandled => {}
+        }
+
+        // PC is the address of the SWI + 8, the BIOS returns to the next instruction
+        let return_address = self.read_register(PROGRAM_COUNTER)?.wrapping_sub(4);
+        self.raise_exception(Exception::SoftwareInterrupt, return_address)?;
+        Ok(true)
+    }
+
+    // Runs one instruction and returns the cycles its bus accesses took
+    pub fn step(&mut self) -> Result<u32, CpuError> {
+        let start = self.memory_bus.cycle_count();
+        let result = self.step_instruction();
+        let cycles = self.memory_bus.cycle_count() - start;
+        self.cycles += cycles;
+        result.map(|_| cycles as u32)
+    }
+
+    // Takes a pending IRQ or fetches the ARM instruction at PC and executes it, idles a cycle while halted
+    fn step_instruction(&mut self) -> Result<(), CpuError> {
+        let pc = self.read_register(PROGRAM_COUNTER)?;
+        // The IRQ handler returned into the BIOS call, which goes on waiting
+        if self.wait_return == Some(pc) && self.mode()? != Mode::IRQ {
+            self.wait_return = None;
+            self.halted = true;
+        }
+
+        if self.halted && !self.resume()? {
+            if self.irq_pending()? {
+                self.halted = false;
+                self.wait_return = Some(pc);
+                return self.raise_exception(Exception::IRQ, pc.wrapping_add(4));
+            }
+            self.memory_bus.idle(1);
+            return Ok(());
+        }
+
+        if self.irq_pending()? {
+            // LR is the next instruction + 4, handlers return with SUBS PC, LR, #4
+            return self.raise_exception(Exception::IRQ, pc.wrapping_add(4));
+        }
+
+        let cpsr = self.cpsr()?;
+        if cpsr.state() != CpuState::ARM {
+            return Err(CpuError::UnsupportedState(cpsr.state()));
+        }
+
+        let value = self.memory_bus.fetch_u32(pc).map_err(CpuError::MemoryError)?;
+        let in_handler = self.wait_return.is_some() && self.mode()? == Mode::IRQ;
+        self.execute(value)?;
+
+        // The handler returned somewhere else than into the wait (a thread switch), the BIOS call is
+        // over. Switching to System mode for nested IRQs leaves IRQ mode too but goes on in order.
+        if in_handler && self.mode()? != Mode::IRQ {
+            let address = self.read_register(PROGRAM_COUNTER)?;
+            if address != pc.wrapping_add(4) && self.wait_return != Some(address) {
+                self.wait_return = None;
+            }
+        }
+        Ok(())
+    }
+
+    // An IRQ is requested and CPSR.I does not mask it
+    fn irq_pending(&self) -> Result<bool, CpuError> {
+        match &self.interrupt_controller {
+            Some(controller) if controller.irq_pending() => Ok(!self.cpsr()?.is_irq_disable()),
+            _ => Ok(false),
+        }
+    }
+
+    fn resume(&mut self) -> Result<bool, CpuError> {
+        let mut handler = self.swi_handler.take();
+        let resumed = match handler.as_mut() {
+            Some(handler) => handler.resume(self),
+            None => Ok(true),
+        };
+        self.swi_handler = handler;
+
+        self.halted = !resumed?;
+        Ok(!self.halted)
+    }
+
+    fn execute_coprocessor(&mut self, instruction: &CoprocessorInstruction, register_set: &RegisterSet) -> Result<(), CpuError> {
+        let cp_num = instruction.cp_num;
+        let coprocessor = self.coprocessors.get_mut(&cp_num).ok_or(CpuError::InstructionError(InstructionError::UndefinedInstruction()))?;
+
+        let result = match instruction.operation {
+            CoprocessorOperation::DataOperation { cp_opc, crn, crd, cp, crm } => {
+                coprocessor.data_operation(cp_opc, crd, crn, crm, cp)
+            }
+            CoprocessorOperation::RegisterTransfer { cp_opc, load: false, crn, rd, cp, crm } => {
+                let value = read_register_set(register_set, rd).map_err(|e| CpuError::RegisterError(e.to_string()))?;
+                coprocessor.write_register(cp_opc, crn, crm, cp, value)
+            }
+            CoprocessorOperation::RegisterTransfer { cp_opc, load: true, crn, rd, cp, crm } => {
+                coprocessor.read_register(cp_opc, crn, crm, cp).and_then(|value| {
+                    let mut register_set = register_set.clone();
+                    write_register_set(&mut register_set, rd, value).map_err(|e| CoprocessorError::AccessError(e.to_string()))
+                })
+            }
+            CoprocessorOperation::DataTransfer { pre_index, up, long, write_back, load, rn, crd, offset } => {
+                let base = read_register_set(register_set, rn).map_err(|e| CpuError::RegisterError(e.to_string()))?;
+                let offset = offset as u32 * 4;
+                let offset_address = if up { base.wrapping_add(offset) } else { base.wrapping_sub(offset) };
+                let address = if pre_index { offset_address } else { base };
+
+                let result = if load {
+                    let mut data = Vec::with_capacity(coprocessor.transfer_length(crd, long));
+                    for i in 0..coprocessor.transfer_length(crd, long) {
+                        data.push(self.memory_bus.read_u32(address.wrapping_add(i as u32 * 4)).map_err(CpuError::MemoryError)?);
+                    }
+                    coprocessor.load(crd, long, &data)
+                } else {
+                    coprocessor.store(crd, long).and_then(|data| {
+                        for (i, word) in data.iter().enumerate() {
+                            self.memory_bus.write_u32(address.wrapping_add(i as u32 * 4), *word)
+                                .map_err(|e| CoprocessorError::AccessError(format!("{:?}", e)))?;
+                        }
+                        Ok(())
+                    })
+                };
+
+                // Post-indexed transfers always write back
+                if result.is_ok() && (write_back || !pre_index) {
+                    let mut register_set = register_set.clone();
+                    write_register_set(&mut register_set, rn, offset_address).map_err(|e| CpuError::RegisterError(e.to_string()))?;
+                }
+                result
+            }
+        };
+
+        result.map_err(|e| match e {
+            CoprocessorError::UndefinedInstruction => CpuError::InstructionError(InstructionError::UndefinedInstruction()),
+            CoprocessorError::AccessError(message) => CpuError::CoprocessorError(cp_num, message),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{cpu::{swi_read_register, swi_write_register}, gba::{init_gba_cpu, init_gba_registers, GbaConfig, GAMEPAK_ROM_WS0_START, WRAM_ONBOARD_START}, memory::{write_memory, AccessWidth, FlatBus, MemoryError}};
+
+    use super::*;
+
+    // CP14 style debug comms channel: a single data register written by MCR and read back by MRC
+    #[derive(Debug, Default)]
+    struct DebugChannel {
+        data: Rc<RefCell<Vec<u32>>>,
+    }
+
+    impl Coprocessor for DebugChannel {
+        fn write_register(&mut self, _cp_opc: u8, _crn: u8, _crm: u8, _cp: u8, value: u32) -> Result<(), CoprocessorError> {
+            self.data.borrow_mut().push(value);
+            Ok(())
+        }
+
+        fn read_register(&mut self, _cp_opc: u8, _crn: u8, _crm: u8, _cp: u8) -> Result<u32, CoprocessorError> {
+            self.data.borrow_mut().pop().ok_or(CoprocessorError::UndefinedInstruction)
+        }
+
+        fn store(&mut self, _crd: u8, _long: bool) -> Result<Vec<u32>, CoprocessorError> {
+            Ok(self.data.borrow_mut().drain(..).collect())
+        }
+    }
+
+    #[test]
+    fn test_coprocessor_register_transfer() {
+        let mut cpu = init_gba_cpu(&GbaConfig::default()).unwrap();
+        let data = Rc::new(RefCell::new(Vec::new()));
+        cpu.register_coprocessor(14, Box::new(DebugChannel { data: data.clone() })).unwrap();
+
+        // MCR p14, 0, R1, c1, c0, 0
+        cpu.write_register(1, 0xCAFE).unwrap();
+        cpu.execute(0xEE011E10).unwrap();
+        assert_eq!(*data.borrow(), vec![0xCAFE]);
+
+        // MRC p14, 0, R2, c1, c0, 0
+        cpu.execute(0xEE112E10).unwrap();
+        assert_eq!(cpu.read_register(2).unwrap(), 0xCAFE);
+        assert!(data.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_coprocessor() {
+        let mut cpu = init_gba_cpu(&GbaConfig::default()).unwrap();
+        cpu.register_coprocessor(14, Box::new(DebugChannel::default())).unwrap();
+        assert_eq!(cpu.register_coprocessor(14, Box::new(DebugChannel::default())), Err(CpuError::DuplicateCoprocessor(14)));
+        assert_eq!(cpu.register_coprocessor(16, Box::new(DebugChannel::default())), Err(CpuError::InvalidCoprocessor(16)));
+    }
+
+    #[test]
+    fn test_unregistered_coprocessor_is_undefined() {
+        let mut cpu = init_gba_cpu(&GbaConfig::default()).unwrap();
+        let pc = 0x08000000;
+        cpu.write_register(PROGRAM_COUNTER, pc).unwrap();
+        let cpsr = cpu.cpsr().unwrap();
+
+        // MRC p15, 0, R0, c0, c0, 0
+        cpu.execute(0xEE100F10).unwrap();
+
+        assert_eq!(cpu.mode().unwrap(), Mode::UNDEFINED);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), Exception::Undefined.vector());
+        assert_eq!(cpu.read_register(LINK_REGISTER).unwrap(), pc + 4);
+        let spsr = cpu.register_set().unwrap().spsr.read().unwrap();
+        assert_eq!(spsr, cpsr.bits());
+    }
+
+    #[test]
+    fn test_unsupported_coprocessor_operation_is_undefined() {
+        let mut cpu = init_gba_cpu(&GbaConfig::default()).unwrap();
+        cpu.register_coprocessor(14, Box::new(DebugChannel::default())).unwrap();
+
+        // CDP p14, 3, c1, c2, c3, 4
+        cpu.execute(0b1110_1110_0011_0010_0001_1110_100_0_0011).unwrap();
+        assert_eq!(cpu.mode().unwrap(), Mode::UNDEFINED);
+    }
+
+    // Records every access, backed by flat RAM
+    #[derive(Debug, Default)]
+    struct RecordingBus {
+        ram: FlatBus,
+        accesses: RefCell<Vec<(&'static str, u32, AccessWidth)>>,
+    }
+
+    impl RecordingBus {
+        fn record(&self, kind: &'static str, address: u32, width: AccessWidth) {
+            self.accesses.borrow_mut().push((kind, address, width));
+        }
+    }
+
+    impl Bus for RecordingBus {
+        fn read_u8(&self, address: u32) -> Result<u8, MemoryError> {
+            self.record("read", address, AccessWidth::Byte);
+            self.ram.read_u8(address)
+        }
+
+        fn read_u16(&self, address: u32) -> Result<u16, MemoryError> {
+            self.record("read", address, AccessWidth::Halfword);
+            self.ram.read_u16(address)
+        }
+
+        fn read_u32(&self, address: u32) -> Result<u32, MemoryError> {
+            self.record("read", address, AccessWidth::Word);
+            self.ram.read_u32(address)
+        }
+
+        fn peek(&self, address: u32, width: AccessWidth) -> Result<u32, MemoryError> {
+            self.ram.peek(address, width)
+        }
+
+        fn write_u8(&self, address: u32, value: u8) -> Result<(), MemoryError> {
+            self.record("write", address, AccessWidth::Byte);
+            self.ram.write_u8(address, value)
+        }
+
+        fn write_u16(&self, address: u32, value: u16) -> Result<(), MemoryError> {
+            self.record("write", address, AccessWidth::Halfword);
+            self.ram.write_u16(address, value)
+        }
+
+        fn write_u32(&self, address: u32, value: u32) -> Result<(), MemoryError> {
+            self.record("write", address, AccessWidth::Word);
+            self.ram.write_u32(address, value)
+        }
+
+        fn fetch_u32(&self, address: u32) -> Result<u32, MemoryError> {
+            self.record("fetch", address, AccessWidth::Word);
+            self.ram.read_u32(address)
+        }
+    }
+
+    #[test]
+    fn test_step_on_flat_bus() {
+        let bus = FlatBus::new();
+        let pc = 0xC000_0000;
+        // MOV R1, #5
+        bus.write_u32(pc, 0xE3A01005).unwrap();
+        // ADD R2, R1, #5
+        bus.write_u32(pc + 4, 0xE2812005).unwrap();
+
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), bus);
+        cpu.write_register(PROGRAM_COUNTER, pc).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.read_register(1).unwrap(), 5);
+        assert_eq!(cpu.read_register(2).unwrap(), 10);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), pc + 8);
+    }
+
+    #[test]
+    fn test_step_cycles() {
+        let mut cpu = init_gba_cpu(&GbaConfig::default()).unwrap();
+        let pc = GAMEPAK_ROM_WS0_START;
+        // MOV R0, R0
+        write_memory(&cpu.memory_bus, pc, &0xE1A00000u32.to_le_bytes()).unwrap();
+        // LDR R0, [R1]
+        write_memory(&cpu.memory_bus, pc + 4, &0xE5910000u32.to_le_bytes()).unwrap();
+        cpu.write_register(1, WRAM_ONBOARD_START).unwrap();
+        cpu.write_register(PROGRAM_COUNTER, pc).unwrap();
+
+        // A non-sequential ROM fetch, then a sequential one and a load from EWRAM
+        let first = cpu.memory_bus.cycles(pc, AccessWidth::Word, false);
+        let second = cpu.memory_bus.cycles(pc + 4, AccessWidth::Word, true) + cpu.memory_bus.cycles(WRAM_ONBOARD_START, AccessWidth::Word, false);
+        assert_eq!((first, second), (8, 12));
+        assert_eq!(cpu.step().unwrap(), first);
+        assert_eq!(cpu.step().unwrap(), second);
+        assert_eq!(cpu.cycles(), (first + second) as u64);
+    }
+
+    #[test]
+    fn test_step_records_bus_accesses() {
+        let bus = RecordingBus::default();
+        // STC p14, c0, [R0]
+        bus.ram.write_u32(0, 0xED800E00).unwrap();
+
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), bus);
+        cpu.register_coprocessor(14, Box::new(DebugChannel { data: Rc::new(RefCell::new(vec![0x1234])) })).unwrap();
+        cpu.write_register(0, 0x100).unwrap();
+        cpu.write_register(PROGRAM_COUNTER, 0).unwrap();
+        cpu.step().unwrap();
+
+        assert_eq!(*cpu.memory_bus.accesses.borrow(), vec![("fetch", 0, AccessWidth::Word), ("write", 0x100, AccessWidth::Word)]);
+        assert_eq!(cpu.memory_bus.ram.read_u32(0x100).unwrap(), 0x1234);
+    }
+
+    fn interworking_cpu(architecture: Architecture) -> CPU<FlatBus> {
+        let bus = FlatBus::new();
+        // LDR PC, [R0]
+        bus.write_u32(0x100, 0xE590F000).unwrap();
+        bus.write_u32(0x200, 0x0000_0301).unwrap();
+
+        let cpu = CPU::with_architecture(init_gba_registers().unwrap(), bus, architecture);
+        cpu.write_register(0, 0x200).unwrap();
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_load_pc_interworking() {
+        let mut cpu = interworking_cpu(Architecture::ARMv5TE);
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x300);
+        assert_eq!(cpu.cpsr().unwrap().state(), CpuState::THUMB);
+
+        // ARMv4T ignores bit 0 and stays in ARM state
+        let mut cpu = interworking_cpu(Architecture::ARMv4T);
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x300);
+        assert_eq!(cpu.cpsr().unwrap().state(), CpuState::ARM);
+    }
+
+    #[test]
+    fn test_branch_link_exchange() {
+        let mut cpu = CPU::with_architecture(init_gba_registers().unwrap(), FlatBus::new(), Architecture::ARMv5TE);
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+        cpu.write_register(3, 0x401).unwrap();
+
+        // BLX R3
+        cpu.execute(0xE12FFF33).unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x400);
+        assert_eq!(cpu.read_register(LINK_REGISTER).unwrap(), 0x104);
+        assert_eq!(cpu.cpsr().unwrap().state(), CpuState::THUMB);
+    }
+
+    #[test]
+    fn test_v5te_instruction_is_undefined_on_v4t() {
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+
+        // CLZ R0, R1
+        cpu.execute(0xE16F0F11).unwrap();
+        assert_eq!(cpu.mode().unwrap(), Mode::UNDEFINED);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), Exception::Undefined.vector());
+        assert_eq!(cpu.read_register(LINK_REGISTER).unwrap(), 0x104);
+    }
+
+    #[test]
+    fn test_condition_not_satisfied() {
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+
+        // MOVEQ R1, #5 with Z clear
+        cpu.execute(0x03A01005).unwrap();
+        assert_eq!(cpu.read_register(1).unwrap(), 0);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x104);
+    }
+
+    #[test]
+    fn test_unimplemented_policy() {
+        // MRS R0, CPSR
+        let mrs = 0xE10F0000;
+
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+        cpu.execute(mrs).unwrap();
+        assert_eq!(cpu.mode().unwrap(), Mode::UNDEFINED);
+        assert_eq!(cpu.read_register(LINK_REGISTER).unwrap(), 0x104);
+
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.set_unimplemented_policy(UnimplementedPolicy::ReturnError);
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+        assert_eq!(cpu.execute(mrs), Err(CpuError::Unimplemented { pc: 0x100, opcode: mrs, mnemonic: "MRS" }));
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x100);
+
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.set_unimplemented_policy(UnimplementedPolicy::Skip);
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+        cpu.execute(mrs).unwrap();
+        cpu.execute(mrs).unwrap();
+        // MRSEQ R0, CPSR is not executed with Z clear
+        cpu.execute(0x010F0000).unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x10C);
+        assert_eq!(cpu.mode().unwrap(), Mode::SUPERVISOR);
+
+        let hits = cpu.unimplemented_report().hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].mnemonic, hits[0].first_pc, hits[0].count), ("MRS", 0x100, 2));
+    }
+
+    #[derive(Debug)]
+    struct DoubleR0;
+
+    impl<B: Bus> SwiHandler<B> for DoubleR0 {
+        fn software_interrupt(&mut self, number: u8, cpu: &CPU<B>) -> Result<SwiOutcome, CpuError> {
+            if number != 0x42 {
+                return Ok(SwiOutcome::Unhandled);
+            }
+            let register_set = cpu.register_set()?;
+            let value = swi_read_register(&register_set, 0)?;
+            swi_write_register(&register_set, 0, value * 2)?;
+            Ok(SwiOutcome::Returned)
+        }
+    }
+
+    #[test]
+    fn test_software_interrupt() {
+        // Without a handler SWI enters the BIOS
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+        cpu.execute(0xEF420000).unwrap();
+        assert_eq!(cpu.mode().unwrap(), Mode::SUPERVISOR);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), Exception::SoftwareInterrupt.vector());
+        assert_eq!(cpu.read_register(LINK_REGISTER).unwrap(), 0x104);
+        assert!(cpu.unimplemented_report().is_empty());
+
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.set_swi_handler(Box::new(DoubleR0));
+        cpu.write_register(PROGRAM_COUNTER, 0x100).unwrap();
+        cpu.write_register(0, 21).unwrap();
+        cpu.execute(0xEF420000).unwrap();
+        assert_eq!(cpu.read_register(0).unwrap(), 42);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x104);
+
+        // Calls the handler does not emulate still go to the BIOS
+        cpu.execute(0xEF010000).unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), Exception::SoftwareInterrupt.vector());
+    }
+}
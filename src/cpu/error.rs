@@ -1,4 +1,17 @@
+use crate::{instruction::InstructionError, memory::MemoryError};
+
+use super::CpuState;
+
 #[derive(Debug, PartialEq)]
 pub enum CpuError {
-    InitError(String)
+    InitError(String),
+    InvalidMode(u32),
+    UnsupportedState(CpuState),
+    RegisterError(String),
+    InstructionError(InstructionError),
+    MemoryError(MemoryError),
+    CoprocessorError(u8, String),
+    DuplicateCoprocessor(u8),
+    InvalidCoprocessor(u8),
+    Unimplemented { pc: u32, opcode: u32, mnemonic: &'static str },
 }
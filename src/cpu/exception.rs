@@ -0,0 +1,43 @@
+use crate::register::Mode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exception {
+    Reset,
+    Undefined,
+    SoftwareInterrupt,
+    PrefetchAbort,
+    DataAbort,
+    IRQ,
+    FIQ,
+}
+
+impl Exception {
+    // Address the CPU jumps to when the exception is raised
+    pub fn vector(&self) -> u32 {
+        match self {
+            Exception::Reset => 0x00,
+            Exception::Undefined => 0x04,
+            Exception::SoftwareInterrupt => 0x08,
+            Exception::PrefetchAbort => 0x0C,
+            Exception::DataAbort => 0x10,
+            Exception::IRQ => 0x18,
+            Exception::FIQ => 0x1C,
+        }
+    }
+
+    // Mode the CPU switches to when the exception is raised
+    pub fn mode(&self) -> Mode {
+        match self {
+            Exception::Reset | Exception::SoftwareInterrupt => Mode::SUPERVISOR,
+            Exception::Undefined => Mode::UNDEFINED,
+            Exception::PrefetchAbort | Exception::DataAbort => Mode::ABORT,
+            Exception::IRQ => Mode::IRQ,
+            Exception::FIQ => Mode::FIQ,
+        }
+    }
+
+    // FIQs are only disabled by Reset and FIQ, everything disables IRQs
+    pub fn disables_fiq(&self) -> bool {
+        matches!(self, Exception::Reset | Exception::FIQ)
+    }
+}
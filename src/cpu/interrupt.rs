@@ -0,0 +1,7 @@
+use core::fmt;
+
+// IRQ line of the system around the CPU
+pub trait InterruptController: fmt::Debug {
+    // An enabled interrupt is requested, the CPU takes it unless CPSR.I masks it
+    fn irq_pending(&self) -> bool;
+}
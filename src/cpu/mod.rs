@@ -1,6 +1,16 @@
 mod cpu;
 mod error;
+mod exception;
+mod coprocessor;
+mod unimplemented;
+mod swi;
+mod interrupt;
 
 
 pub use error::*;
-pub use cpu::*;
\ No newline at end of file
+pub use cpu::*;
+pub use exception::*;
+pub use coprocessor::*;
+pub use unimplemented::*;
+pub use swi::*;
+pub use interrupt::*;
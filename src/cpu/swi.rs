@@ -0,0 +1,45 @@
+use core::fmt;
+
+use crate::{memory::Bus, register::{read_register_set, write_register_set, RegisterSet}};
+
+use super::{CpuError, CPU};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwiOutcome {
+    // The call was emulated, execution continues after the SWI
+    Returned,
+    // The handler wrote the PC, execution continues there
+    Branched,
+    // The CPU halts after the SWI until `SwiHandler::resume` wakes it up
+    Halted,
+    // Not emulated, the CPU takes the SWI exception into the BIOS
+    Unhandled,
+}
+
+// High level emulation of the system calls a BIOS provides
+pub trait SwiHandler<B: Bus>: fmt::Debug {
+    // `number` is the BIOS function, bits 23-16 of an ARM SWI
+    fn software_interrupt(&mut self, number: u8, cpu: &CPU<B>) -> Result<SwiOutcome, CpuError>;
+
+    // Polled on every step while halted, returns whether the CPU continues
+    fn resume(&mut self, _cpu: &CPU<B>) -> Result<bool, CpuError> {
+        Ok(true)
+    }
+}
+
+// Register access for SWI handlers
+pub fn swi_read_register(register_set: &RegisterSet, register: u8) -> Result<u32, CpuError> {
+    read_register_set(register_set, register).map_err(|e| CpuError::RegisterError(e.to_string()))
+}
+
+pub fn swi_write_register(register_set: &RegisterSet, register: u8, value: u32) -> Result<(), CpuError> {
+    write_register_set(&mut register_set.clone(), register, value).map_err(|e| CpuError::RegisterError(e.to_string()))
+}
+
+// Comment field of an ARM SWI as the GBA BIOS reads it
+pub fn swi_number(value: u32) -> Option<u8> {
+    if (value >> 24) & 0xF != 0xF {
+        return None;
+    }
+    Some((value >> 16) as u8)
+}
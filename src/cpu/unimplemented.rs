@@ -0,0 +1,98 @@
+use core::fmt;
+use std::collections::HashMap;
+
+// What the CPU does when it reaches an instruction the emulator can not execute yet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnimplementedPolicy {
+    // Handle it like the hardware handles an undefined instruction
+    #[default]
+    RaiseUndefined,
+    // Stop and return CpuError::Unimplemented, the PC stays at the instruction
+    ReturnError,
+    // Continue with the next instruction, the first hit of each encoding is printed to stderr
+    Skip,
+}
+
+impl UnimplementedPolicy {
+    pub fn from_name(name: &str) -> Option<UnimplementedPolicy> {
+        match name {
+            "undefined" => Some(UnimplementedPolicy::RaiseUndefined),
+            "error" => Some(UnimplementedPolicy::ReturnError),
+            "skip" => Some(UnimplementedPolicy::Skip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnimplementedHit {
+    pub mnemonic: &'static str,
+    pub opcode: u32,
+    pub first_pc: u32,
+    pub count: usize,
+}
+
+// Unimplemented encodings hit while running, to see what is missing for a game
+#[derive(Debug, Default)]
+pub struct UnimplementedReport {
+    // Encoding -> hit
+    hits: HashMap<u32, UnimplementedHit>,
+}
+
+impl UnimplementedReport {
+    // Returns whether it is the first hit of the encoding
+    pub fn record(&mut self, pc: u32, opcode: u32, mnemonic: &'static str) -> bool {
+        let hit = self.hits.entry(opcode)
+            .or_insert(UnimplementedHit { mnemonic, opcode, first_pc: pc, count: 0 });
+        hit.count += 1;
+        hit.count == 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+
+    // Number of times any unimplemented instruction was hit
+    pub fn total(&self) -> usize {
+        self.hits.values().map(|hit| hit.count).sum()
+    }
+
+    // Most frequent first
+    pub fn hits(&self) -> Vec<&UnimplementedHit> {
+        let mut hits: Vec<&UnimplementedHit> = self.hits.values().collect();
+        hits.sort_by(|a, b| b.count.cmp(&a.count).then(a.opcode.cmp(&b.opcode)));
+        hits
+    }
+}
+
+impl fmt::Display for UnimplementedReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Unimplemented instructions: {} hits, {} encodings", self.total(), self.hits.len())?;
+        for hit in self.hits() {
+            writeln!(f, "{:>8}x {:#010X} {} (first at {:#010X})", hit.count, hit.opcode, hit.mnemonic, hit.first_pc)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_report_counts_encodings() {
+        let mut report = UnimplementedReport::default();
+        assert!(report.is_empty());
+
+        assert!(report.record(0x100, 0xEF000005, "SWI"));
+        assert!(report.record(0x200, 0xE10F0000, "MRS"));
+        assert!(!report.record(0x300, 0xEF000005, "SWI"));
+
+        assert_eq!(report.total(), 3);
+        let hits = report.hits();
+        assert_eq!(hits[0], &UnimplementedHit { mnemonic: "SWI", opcode: 0xEF000005, first_pc: 0x100, count: 2 });
+        assert_eq!(hits[1].mnemonic, "MRS");
+        assert_eq!(report.to_string().lines().next(), Some("Unimplemented instructions: 3 hits, 2 encodings"));
+    }
+}
@@ -0,0 +1,238 @@
+use core::fmt;
+use std::{cell::RefCell, fs, path::{Path, PathBuf}, rc::Rc, time::{Duration, Instant}};
+
+use super::{Eeprom, Flash, FlashSize, Sram};
+
+// Dirty save memory is written out at most this often while the emulator runs
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BackupError {
+    Io(String),
+    InvalidSize(usize),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupError::Io(error) => write!(f, "Failed to access the save file: {}", error),
+            BackupError::InvalidSize(size) => write!(f, "Save file is {} bytes, which does not fit the save memory", size),
+        }
+    }
+}
+
+// Battery-backed save memory on the Game Pak
+pub trait BackupMemory: fmt::Debug {
+    fn data(&self) -> &[u8];
+
+    // Replaces the contents with a save file, which must have the size of the memory
+    fn load(&mut self, data: &[u8]) -> Result<(), BackupError>;
+
+    // Written to since the last save
+    fn is_dirty(&self) -> bool;
+    fn clear_dirty(&mut self);
+}
+
+pub type SharedBackup = Rc<RefCell<dyn BackupMemory>>;
+
+// Save memory the cartridge uses, mapped at 0x0E000000
+#[derive(Debug, Clone)]
+pub enum Backup {
+    Sram(Rc<RefCell<Sram>>),
+    Flash(Rc<RefCell<Flash>>),
+    Eeprom(Rc<RefCell<Eeprom>>), // Mapped at 0x0D000000 instead
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Backup::Sram(Rc::new(RefCell::new(Sram::new())))
+    }
+}
+
+// Kinds of save memory, to pick the backup for a game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveType {
+    Sram,
+    Flash(FlashSize),
+    Eeprom,
+}
+
+impl SaveType {
+    pub fn from_name(name: &str) -> Option<SaveType> {
+        match name {
+            "sram" => Some(SaveType::Sram),
+            "flash64" => Some(SaveType::Flash(FlashSize::Flash64K)),
+            "flash128" => Some(SaveType::Flash(FlashSize::Flash128K)),
+            "eeprom" => Some(SaveType::Eeprom),
+            _ => None,
+        }
+    }
+
+    // Empty save memory of the type, flash chips report the usual ID for their size
+    pub fn backup(&self) -> Backup {
+        match self {
+            SaveType::Sram => Backup::Sram(Rc::new(RefCell::new(Sram::new()))),
+            SaveType::Flash(size) => Backup::Flash(Rc::new(RefCell::new(Flash::with_size(*size)))),
+            SaveType::Eeprom => Backup::Eeprom(Rc::new(RefCell::new(Eeprom::new()))),
+        }
+    }
+}
+
+impl Backup {
+    pub fn shared(&self) -> SharedBackup {
+        match self {
+            Backup::Sram(sram) => sram.clone(),
+            Backup::Flash(flash) => flash.clone(),
+            Backup::Eeprom(eeprom) => eeprom.clone(),
+        }
+    }
+}
+
+// Save file next to the ROM: game.gba keeps its progress in game.sav
+pub fn save_path<P: AsRef<Path>>(rom_path: P) -> PathBuf {
+    rom_path.as_ref().with_extension("sav")
+}
+
+// Keeps the save memory and its file in sync. `close` writes the last changes, dropping it
+// without closing saves as well but can only print errors.
+#[derive(Debug)]
+pub struct SaveFile {
+    path: PathBuf,
+    backup: SharedBackup,
+    last_save: Instant,
+    closed: bool,
+}
+
+impl SaveFile {
+    // Loads the file into the save memory when it exists, a new game starts without one
+    pub fn open<P: AsRef<Path>>(path: P, backup: SharedBackup) -> Result<SaveFile, BackupError> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            let data = fs::read(&path).map_err(|e| BackupError::Io(e.to_string()))?;
+            backup.borrow_mut().load(&data)?;
+        }
+        backup.borrow_mut().clear_dirty();
+        Ok(SaveFile { path, backup, last_save: Instant::now(), closed: false })
+    }
+
+    pub fn for_rom<P: AsRef<Path>>(rom_path: P, backup: SharedBackup) -> Result<SaveFile, BackupError> {
+        SaveFile::open(save_path(rom_path), backup)
+    }
+
+    // Writes the save memory when it changed. The data goes to a temporary file first,
+    // so a crash while saving leaves the old save intact.
+    pub fn save(&mut self) -> Result<(), BackupError> {
+        self.last_save = Instant::now();
+        if !self.backup.borrow().is_dirty() {
+            return Ok(());
+        }
+
+        let temporary = self.path.with_extension("sav.tmp");
+        fs::write(&temporary, self.backup.borrow().data()).map_err(|e| BackupError::Io(e.to_string()))?;
+        fs::rename(&temporary, &self.path).map_err(|e| BackupError::Io(e.to_string()))?;
+        self.backup.borrow_mut().clear_dirty();
+        Ok(())
+    }
+
+    // Called regularly while the emulator runs (once per frame), saves every SAVE_INTERVAL
+    pub fn update(&mut self) -> Result<(), BackupError> {
+        if self.last_save.elapsed() < SAVE_INTERVAL {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    pub fn close(mut self) -> Result<(), BackupError> {
+        self.closed = true;
+        self.save()
+    }
+}
+
+impl Drop for SaveFile {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(error) = self.save() {
+            eprintln!("Failed to write {}: {}", self.path.display(), error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+
+    use super::*;
+    use crate::memory::MemoryDevice;
+
+    fn temporary_rom(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("rusty_dolphine_{}_{}.gba", name, std::process::id()));
+        let _ = fs::remove_file(save_path(&path));
+        path
+    }
+
+    #[test]
+    fn test_save_path() {
+        assert_eq!(save_path("/games/pokemon.gba"), PathBuf::from("/games/pokemon.sav"));
+        assert_eq!(save_path("game"), PathBuf::from("game.sav"));
+    }
+
+    #[test]
+    fn test_save_file_round_trip() {
+        let rom = temporary_rom("round_trip");
+        let data: Vec<u8> = (0..Sram::new().size()).map(|i| (i * 7 + i / 256) as u8).collect();
+
+        let sram = Rc::new(RefCell::new(Sram::new()));
+        let path = save_path(&rom);
+        let mut save_file = SaveFile::for_rom(&rom, sram.clone()).unwrap();
+        assert!(!path.exists());
+        for (offset, byte) in data.iter().enumerate() {
+            sram.borrow_mut().write_u8(offset as u32, *byte).unwrap();
+        }
+        drop(save_file);
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // A new session picks up where the last one stopped
+        let sram = Rc::new(RefCell::new(Sram::new()));
+        save_file = SaveFile::for_rom(&rom, sram.clone()).unwrap();
+        assert_eq!(sram.borrow().data(), &data[..]);
+        assert!(!sram.borrow().is_dirty());
+
+        // Nothing changed, nothing is written
+        fs::remove_file(&path).unwrap();
+        save_file.save().unwrap();
+        assert!(!path.exists());
+
+        sram.borrow_mut().write_u8(0, 0xAB).unwrap();
+        save_file.update().unwrap();
+        assert!(!path.exists());
+        save_file.save().unwrap();
+        assert_eq!(fs::read(&path).unwrap()[0], 0xAB);
+        fs::remove_file(&path).unwrap();
+
+        sram.borrow_mut().write_u8(1, 0xCD).unwrap();
+        save_file.close().unwrap();
+        assert_eq!(fs::read(&path).unwrap()[..2], [0xAB, 0xCD]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_file_close_error() {
+        let rom = temporary_rom("close_error");
+        let sram = Rc::new(RefCell::new(Sram::new()));
+        let save_file = SaveFile::for_rom(rom.join("missing_directory"), sram.clone()).unwrap();
+        sram.borrow_mut().write_u8(0, 1).unwrap();
+        assert!(matches!(save_file.close(), Err(BackupError::Io(_))));
+    }
+
+    #[test]
+    fn test_save_file_invalid_size() {
+        let rom = temporary_rom("invalid_size");
+        fs::write(save_path(&rom), [0; 100]).unwrap();
+        let sram = Rc::new(RefCell::new(Sram::new()));
+        assert_eq!(SaveFile::for_rom(&rom, sram).err(), Some(BackupError::InvalidSize(100)));
+        fs::remove_file(save_path(&rom)).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use core::fmt;
+use std::{cell::Cell, fs, path::Path, rc::Rc};
+
+use super::BIOS_SIZE;
+use crate::memory::{MemoryDevice, MemoryError};
+
+// CRC32 of the BIOS dumps known to work
+pub const KNOWN_BIOS_CHECKSUMS: [(u32, &str); 1] = [
+    (0x81977335, "Game Boy Advance"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BiosError {
+    Io(String),
+    InvalidSize(usize),
+    UnknownChecksum(u32),
+}
+
+impl fmt::Display for BiosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BiosError::Io(error) => write!(f, "Failed to read the BIOS: {}", error),
+            BiosError::InvalidSize(size) => write!(f, "BIOS is {} bytes instead of {}", size, BIOS_SIZE),
+            BiosError::UnknownChecksum(checksum) => write!(f, "BIOS checksum 0x{:08X} does not match a known dump", checksum),
+        }
+    }
+}
+
+// System ROM image, all zeros until one is loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bios {
+    data: Vec<u8>,
+}
+
+impl Default for Bios {
+    fn default() -> Self {
+        Bios { data: vec![0; BIOS_SIZE] }
+    }
+}
+
+impl Bios {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Bios, BiosError> {
+        if data.len() != BIOS_SIZE {
+            return Err(BiosError::InvalidSize(data.len()));
+        }
+        Ok(Bios { data })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Bios, BiosError> {
+        let data = fs::read(path).map_err(|e| BiosError::Io(e.to_string()))?;
+        Bios::from_bytes(data)
+    }
+
+    pub fn checksum(&self) -> u32 {
+        crc32(&self.data)
+    }
+
+    // Name of the known dump, custom and patched BIOS images fail here
+    pub fn verify(&self) -> Result<&'static str, BiosError> {
+        let checksum = self.checksum();
+        KNOWN_BIOS_CHECKSUMS.iter()
+            .find(|(known, _)| *known == checksum)
+            .map(|(_, name)| *name)
+            .ok_or(BiosError::UnknownChecksum(checksum))
+    }
+}
+
+// CRC-32 (IEEE 802.3, reflected)
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| if (crc & 1) != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 })
+    })
+}
+
+// The BIOS can only be read while the CPU executes inside it. Other reads return the
+// last opcode fetched from the BIOS, which some games check.
+#[derive(Debug)]
+pub struct BiosDevice {
+    bios: Bios,
+    fetch_address: Rc<Cell<u32>>,
+    last_opcode: u32,
+}
+
+impl BiosDevice {
+    // `fetch_address` is the address of the last opcode fetch, see `MemoryBus::fetch_address`
+    pub fn new(bios: Bios, fetch_address: Rc<Cell<u32>>) -> BiosDevice {
+        BiosDevice { bios, fetch_address, last_opcode: 0 }
+    }
+
+    fn word(&self, offset: u32) -> u32 {
+        let offset = (offset & !3) as usize;
+        u32::from_le_bytes(self.bios.data[offset..offset + 4].try_into().expect("word is in bounds"))
+    }
+
+    fn readable(&self) -> bool {
+        (self.fetch_address.get() as usize) < BIOS_SIZE
+    }
+
+    fn protected_word(&self, offset: u32) -> Result<u32, MemoryError> {
+        if offset as usize >= BIOS_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        Ok(if self.readable() { self.word(offset) } else { self.last_opcode })
+    }
+}
+
+impl MemoryDevice for BiosDevice {
+    fn size(&self) -> usize {
+        BIOS_SIZE
+    }
+
+    fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError> {
+        Ok((self.protected_word(offset)? >> ((offset & 3) * 8)) as u8)
+    }
+
+    fn read_u16(&mut self, offset: u32) -> Result<u16, MemoryError> {
+        Ok((self.protected_word(offset)? >> ((offset & 2) * 8)) as u16)
+    }
+
+    fn read_u32(&mut self, offset: u32) -> Result<u32, MemoryError> {
+        self.protected_word(offset)
+    }
+
+    fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError> {
+        Ok((self.protected_word(offset)? >> ((offset & 3) * 8)) as u8)
+    }
+
+    fn peek_u16(&self, offset: u32) -> Result<u16, MemoryError> {
+        Ok((self.protected_word(offset)? >> ((offset & 2) * 8)) as u16)
+    }
+
+    fn peek_u32(&self, offset: u32) -> Result<u32, MemoryError> {
+        self.protected_word(offset)
+    }
+
+    // THUMB fetches latch the whole word on the 32-bit BIOS bus
+    fn fetch_u16(&mut self, offset: u32) -> Result<u16, MemoryError> {
+        self.last_opcode = self.word(offset);
+        Ok((self.last_opcode >> ((offset & 2) * 8)) as u16)
+    }
+
+    fn fetch_u32(&mut self, offset: u32) -> Result<u32, MemoryError> {
+        self.last_opcode = self.word(offset);
+        Ok(self.last_opcode)
+    }
+
+    // Writes to the system ROM are ignored
+    fn write_u8(&mut self, _offset: u32, _value: u8) -> Result<(), MemoryError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{gba::{init_gba_memory_bus, BiosSource, GbaConfig, BIOS_START, GAMEPAK_ROM_WS0_START}, memory::Bus};
+
+    fn test_bios() -> Bios {
+        let mut data = vec![0; BIOS_SIZE];
+        data[0..4].copy_from_slice(&0xEA000018u32.to_le_bytes());
+        data[0xDC..0xE0].copy_from_slice(&0xE129F000u32.to_le_bytes());
+        data[0x100..0x104].copy_from_slice(&0x12345678u32.to_le_bytes());
+        Bios::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    fn test_bios_validation() {
+        assert_eq!(Bios::from_bytes(vec![0; 100]).err(), Some(BiosError::InvalidSize(100)));
+        assert!(matches!(Bios::from_file("/nonexistent/gba_bios.bin"), Err(BiosError::Io(_))));
+
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        let bios = Bios::default();
+        assert_eq!(bios.verify(), Err(BiosError::UnknownChecksum(bios.checksum())));
+    }
+
+    #[test]
+    fn test_bios_read_protection() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig { bios: BiosSource::Image(test_bios()), ..GbaConfig::default() }).unwrap();
+
+        // Executing inside the BIOS, reads see the data
+        assert_eq!(memory_bus.fetch_u32(BIOS_START + 0xDC).unwrap(), 0xE129F000);
+        assert_eq!(memory_bus.read_u32(BIOS_START + 0x100).unwrap(), 0x12345678);
+        assert_eq!(memory_bus.read_u8(BIOS_START + 0x101).unwrap(), 0x56);
+
+        // Executing from the cartridge, reads return the last opcode fetched from the BIOS
+        memory_bus.fetch_u32(GAMEPAK_ROM_WS0_START).unwrap();
+        assert_eq!(memory_bus.read_u32(BIOS_START + 0x100).unwrap(), 0xE129F000);
+        assert_eq!(memory_bus.read_u16(BIOS_START + 0x2).unwrap(), 0xE129);
+        assert_eq!(memory_bus.read_u8(BIOS_START + 0x1).unwrap(), 0xF0);
+
+        // The BIOS can not be written
+        memory_bus.write_u32(BIOS_START, 0).unwrap();
+        memory_bus.fetch_u32(BIOS_START + 0x4).unwrap();
+        assert_eq!(memory_bus.read_u32(BIOS_START).unwrap(), 0xEA000018);
+    }
+}
@@ -0,0 +1,263 @@
+use core::fmt;
+use std::{fs, path::Path};
+
+use super::{FlashSize, SaveType, GAMEPAK_EEPROM, GAMEPAK_EEPROM_SIZE, GAMEPAK_EEPROM_START, GAMEPAK_ROM_SIZE, GAMEPAK_ROM_WS0_START};
+use crate::memory::{write_memory, MemoryBus, MemoryError};
+
+pub const CARTRIDGE_HEADER_SIZE: usize = 192;
+
+// Offsets into the cartridge header
+const LOGO_START: usize = 0x04;
+const LOGO_END: usize = 0xA0;
+const TITLE_START: usize = 0xA0;
+const GAME_CODE_START: usize = 0xAC;
+const MAKER_CODE_START: usize = 0xB0;
+const FIXED_VALUE: usize = 0xB2;
+const UNIT_CODE: usize = 0xB3;
+const SOFTWARE_VERSION: usize = 0xBC;
+const COMPLEMENT_CHECK: usize = 0xBD;
+
+// Value the BIOS expects at 0xB2
+const EXPECTED_FIXED_VALUE: u8 = 0x96;
+
+// ID strings the save libraries leave word aligned in the ROM, followed by their version
+const SAVE_LIBRARIES: [(&[u8], SaveType); 5] = [
+    (b"SRAM_V", SaveType::Sram),
+    (b"EEPROM_V", SaveType::Eeprom),
+    (b"FLASH_V", SaveType::Flash(FlashSize::Flash64K)),
+    (b"FLASH512_V", SaveType::Flash(FlashSize::Flash64K)),
+    (b"FLASH1M_V", SaveType::Flash(FlashSize::Flash128K)),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    Io(String),
+    TooSmall(usize),
+    TooLarge(usize),
+    TooLargeForEeprom(usize),
+    InvalidFixedValue(u8),
+    InvalidText(&'static str),
+    ChecksumMismatch { expected: u8, actual: u8 },
+    MemoryError(MemoryError),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(error) => write!(f, "Failed to read the ROM: {}", error),
+            CartridgeError::TooSmall(size) => write!(f, "ROM is {} bytes, smaller than the {} byte header", size, CARTRIDGE_HEADER_SIZE),
+            CartridgeError::TooLarge(size) => write!(f, "ROM is {} bytes, larger than the {} byte Game Pak ROM", size, GAMEPAK_ROM_SIZE),
+            CartridgeError::TooLargeForEeprom(size) => write!(f, "ROM is {} bytes, EEPROM carts hold at most {} bytes", size, GAMEPAK_ROM_SIZE - GAMEPAK_EEPROM_SIZE),
+            CartridgeError::InvalidFixedValue(value) => write!(f, "Header byte 0xB2 is 0x{:02X} instead of 0x{:02X}", value, EXPECTED_FIXED_VALUE),
+            CartridgeError::InvalidText(field) => write!(f, "Header {} is not ASCII", field),
+            CartridgeError::ChecksumMismatch { expected, actual } => write!(f, "Header complement check is 0x{:02X}, expected 0x{:02X}", actual, expected),
+            CartridgeError::MemoryError(error) => write!(f, "Failed to map the ROM: {:?}", error),
+        }
+    }
+}
+
+// First 192 bytes of every Game Pak ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub entry_point: u32, // 0x00 (ARM branch to the start of the game)
+    pub logo: Vec<u8>, // 0x04-0x9F (Compressed Nintendo logo, 156 bytes)
+    pub title: String, // 0xA0-0xAB (Uppercase ASCII, padded with zeros)
+    pub game_code: String, // 0xAC-0xAF
+    pub maker_code: String, // 0xB0-0xB1
+    pub unit_code: u8, // 0xB3 (00h for the GBA)
+    pub software_version: u8, // 0xBC
+    pub complement_check: u8, // 0xBD
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < CARTRIDGE_HEADER_SIZE {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        if rom[FIXED_VALUE] != EXPECTED_FIXED_VALUE {
+            return Err(CartridgeError::InvalidFixedValue(rom[FIXED_VALUE]));
+        }
+
+        let expected = complement_check(rom);
+        if rom[COMPLEMENT_CHECK] != expected {
+            return Err(CartridgeError::ChecksumMismatch { expected, actual: rom[COMPLEMENT_CHECK] });
+        }
+
+        Ok(CartridgeHeader {
+            entry_point: u32::from_le_bytes([rom[0], rom[1], rom[2], rom[3]]),
+            logo: rom[LOGO_START..LOGO_END].to_vec(),
+            title: header_text(&rom[TITLE_START..GAME_CODE_START], "title")?,
+            game_code: header_text(&rom[GAME_CODE_START..MAKER_CODE_START], "game code")?,
+            maker_code: header_text(&rom[MAKER_CODE_START..FIXED_VALUE], "maker code")?,
+            unit_code: rom[UNIT_CODE],
+            software_version: rom[SOFTWARE_VERSION],
+            complement_check: rom[COMPLEMENT_CHECK],
+        })
+    }
+
+    // Target of the entry branch, None when the first word is not a branch
+    pub fn entry_address(&self) -> Option<u32> {
+        if (self.entry_point & 0x0F00_0000) != 0x0A00_0000 {
+            return None;
+        }
+        let offset = ((self.entry_point << 8) as i32) >> 6;
+        Some(GAMEPAK_ROM_WS0_START.wrapping_add(8).wrapping_add(offset as u32))
+    }
+}
+
+// Checksum over 0xA0-0xBC as the BIOS computes it
+pub fn complement_check(rom: &[u8]) -> u8 {
+    let sum = rom[TITLE_START..COMPLEMENT_CHECK].iter().fold(0u8, |acc, byte| acc.wrapping_add(*byte));
+    0u8.wrapping_sub(sum).wrapping_sub(0x19)
+}
+
+fn header_text(bytes: &[u8], field: &'static str) -> Result<String, CartridgeError> {
+    let text = bytes.split(|byte| *byte == 0).next().unwrap_or_default();
+    if !text.iter().all(|byte| byte.is_ascii_graphic() || *byte == b' ') {
+        return Err(CartridgeError::InvalidText(field));
+    }
+    Ok(String::from_utf8_lossy(text).into_owned())
+}
+
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    pub rom: Vec<u8>,
+}
+
+impl Cartridge {
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        if rom.len() > GAMEPAK_ROM_SIZE {
+            return Err(CartridgeError::TooLarge(rom.len()));
+        }
+        let header = CartridgeHeader::parse(&rom)?;
+        Ok(Cartridge { header, rom })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartridgeError> {
+        let rom = fs::read(path).map_err(|e| CartridgeError::Io(e.to_string()))?;
+        Cartridge::from_bytes(rom)
+    }
+
+    // Save memory the game was built for, None when no save library is linked in
+    pub fn save_type(&self) -> Option<SaveType> {
+        (0..self.rom.len()).step_by(4).find_map(|offset| {
+            SAVE_LIBRARIES.iter()
+                .find(|(id, _)| self.rom[offset..].starts_with(id))
+                .map(|(_, save_type)| *save_type)
+        })
+    }
+
+    // Copies the ROM to 0x08000000, the wait state 1 and 2 regions share the data. The EEPROM
+    // takes the upper half of wait state 2, 32 MB carts only keep it at 0x0DFFFF00 which is not
+    // emulated, so larger ROMs are rejected.
+    pub fn load(&self, memory_bus: &MemoryBus) -> Result<(), CartridgeError> {
+        let eeprom = memory_bus.sector(GAMEPAK_EEPROM_START).is_some_and(|sector| sector.name == GAMEPAK_EEPROM);
+        if eeprom && self.rom.len() > GAMEPAK_ROM_SIZE - GAMEPAK_EEPROM_SIZE {
+            return Err(CartridgeError::TooLargeForEeprom(self.rom.len()));
+        }
+        write_memory(memory_bus, GAMEPAK_ROM_WS0_START, &self.rom).map_err(CartridgeError::MemoryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{gba::{init_gba_memory_bus, GbaConfig, GAMEPAK_ROM_WS2_START}, memory::Bus};
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x200];
+        rom[0..4].copy_from_slice(&0xEA00002Eu32.to_le_bytes()); // B 0x080000C0
+        rom[TITLE_START..TITLE_START + 9].copy_from_slice(b"TEST GAME");
+        rom[GAME_CODE_START..MAKER_CODE_START].copy_from_slice(b"ATST");
+        rom[MAKER_CODE_START..FIXED_VALUE].copy_from_slice(b"01");
+        rom[FIXED_VALUE] = EXPECTED_FIXED_VALUE;
+        rom[SOFTWARE_VERSION] = 2;
+        rom[COMPLEMENT_CHECK] = complement_check(&rom);
+        rom
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let header = CartridgeHeader::parse(&test_rom()).unwrap();
+        assert_eq!(header.title, "TEST GAME");
+        assert_eq!(header.game_code, "ATST");
+        assert_eq!(header.maker_code, "01");
+        assert_eq!(header.unit_code, 0);
+        assert_eq!(header.software_version, 2);
+        assert_eq!(header.logo.len(), 156);
+        assert_eq!(header.entry_address(), Some(0x080000C0));
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert_eq!(Cartridge::from_bytes(vec![0; 100]).err(), Some(CartridgeError::TooSmall(100)));
+
+        let mut rom = test_rom();
+        rom[FIXED_VALUE] = 0;
+        assert_eq!(CartridgeHeader::parse(&rom).err(), Some(CartridgeError::InvalidFixedValue(0)));
+
+        let mut rom = test_rom();
+        let expected = rom[COMPLEMENT_CHECK];
+        rom[SOFTWARE_VERSION] = 3;
+        assert_eq!(CartridgeHeader::parse(&rom).err(), Some(CartridgeError::ChecksumMismatch { expected: expected.wrapping_sub(1), actual: expected }));
+
+        let mut rom = test_rom();
+        rom[GAME_CODE_START] = 0xFF;
+        rom[COMPLEMENT_CHECK] = complement_check(&rom);
+        assert_eq!(CartridgeHeader::parse(&rom).err(), Some(CartridgeError::InvalidText("game code")));
+
+        assert!(matches!(Cartridge::from_file("/nonexistent/game.gba"), Err(CartridgeError::Io(_))));
+    }
+
+    #[test]
+    fn test_save_type() {
+        assert_eq!(Cartridge::from_bytes(test_rom()).unwrap().save_type(), None);
+
+        for (id, save_type) in [
+            (&b"SRAM_V113"[..], SaveType::Sram),
+            (b"EEPROM_V124", SaveType::Eeprom),
+            (b"FLASH_V126", SaveType::Flash(FlashSize::Flash64K)),
+            (b"FLASH512_V131", SaveType::Flash(FlashSize::Flash64K)),
+            (b"FLASH1M_V103", SaveType::Flash(FlashSize::Flash128K)),
+        ] {
+            let mut rom = test_rom();
+            rom[0x1C0..0x1C0 + id.len()].copy_from_slice(id);
+            assert_eq!(Cartridge::from_bytes(rom).unwrap().save_type(), Some(save_type));
+        }
+
+        // Only word aligned IDs count
+        let mut rom = test_rom();
+        rom[0x1C1..0x1C7].copy_from_slice(b"SRAM_V");
+        assert_eq!(Cartridge::from_bytes(rom).unwrap().save_type(), None);
+    }
+
+    #[test]
+    fn test_load_cartridge() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+        let cartridge = Cartridge::from_bytes(test_rom()).unwrap();
+        cartridge.load(&memory_bus).unwrap();
+
+        assert_eq!(memory_bus.read_u32(GAMEPAK_ROM_WS0_START).unwrap(), 0xEA00002E);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_ROM_WS2_START + TITLE_START as u32).unwrap(), b'T');
+    }
+
+    #[test]
+    fn test_load_cartridge_with_eeprom() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig { backup: SaveType::Eeprom.backup(), ..GbaConfig::default() }).unwrap();
+        let mut rom = test_rom();
+        rom.resize(GAMEPAK_ROM_SIZE - GAMEPAK_EEPROM_SIZE, 0);
+        let end = rom.len();
+        rom[end - 4..].copy_from_slice(&0x12345678u32.to_le_bytes());
+        Cartridge::from_bytes(rom.clone()).unwrap().load(&memory_bus).unwrap();
+        assert_eq!(memory_bus.read_u32(GAMEPAK_EEPROM_START - 4).unwrap(), 0x12345678);
+
+        // The upper half would hide behind the EEPROM in wait state 2
+        rom.push(0);
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert_eq!(cartridge.load(&memory_bus).err(), Some(CartridgeError::TooLargeForEeprom(GAMEPAK_ROM_SIZE - GAMEPAK_EEPROM_SIZE + 1)));
+        cartridge.load(&init_gba_memory_bus(&GbaConfig::default()).unwrap()).unwrap();
+    }
+}
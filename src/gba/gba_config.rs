@@ -0,0 +1,34 @@
+use super::{Backup, Bios, HleBios};
+
+// BIOS the GBA starts with
+#[derive(Debug, Clone)]
+pub enum BiosSource {
+    // A BIOS dump, all zeros by default
+    Image(Bios),
+    // The BIOS calls are emulated, for running games without a BIOS dump
+    Hle,
+}
+
+impl Default for BiosSource {
+    fn default() -> Self {
+        BiosSource::Image(Bios::default())
+    }
+}
+
+impl BiosSource {
+    // Image mapped at 0x00000000, the HLE BIOS only has its IRQ dispatcher there
+    pub fn image(&self) -> Bios {
+        match self {
+            BiosSource::Image(bios) => bios.clone(),
+            BiosSource::Hle => HleBios::bios(),
+        }
+    }
+}
+
+// Hardware the GBA is built with
+#[derive(Debug, Clone, Default)]
+pub struct GbaConfig {
+    pub bios: BiosSource,
+    // Shared with the bus, so its contents can be saved while the game runs
+    pub backup: Backup,
+}
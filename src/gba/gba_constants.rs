@@ -18,6 +18,7 @@ pub const REGISTER_15: u8 = 15;
 
 
 pub const KBYTES: usize = 1024;
+pub const MBYTES: usize = 1024 * KBYTES;
 
 // Sizes
 // General Internal Memory
@@ -30,11 +31,13 @@ pub const WRAM: &str = "WRAM - Work RAM";
 pub const WRAM_ONBOARD_SIZE: usize = 256 * KBYTES;
 pub const WRAM_ONBOARD_START: u32 = 0x02000000;
 pub const WRAM_ONBOARD_END: u32 = 0x0203FFFF;
+pub const WRAM_ONBOARD_MIRROR_END: u32 = 0x02FFFFFF; // Mirrored every 256K
 
 pub const WRAM_ONCHIP: &str = "WRAM - On-chip Work RAM";
 pub const WRAM_ONCHIP_SIZE: usize = 32 * KBYTES;
 pub const WRAM_ONCHIP_START: u32 = 0x03000000;
 pub const WRAM_ONCHIP_END: u32 = 0x03007FFF;
+pub const WRAM_ONCHIP_MIRROR_END: u32 = 0x03FFFFFF; // Mirrored every 32K
 
 pub const IO_REGISTERS: &str = "IO REGISTERS";
 
@@ -43,19 +46,81 @@ pub const IO_REGISTERS_SIZE: usize = (1 * KBYTES) - 1;
 pub const IO_REGISTERS_START: u32 = 0x04000000;
 pub const IO_REGISTERS_END: u32 = 0x040003FE;
 
+// Undocumented, sets the EWRAM wait states
+pub const INTERNAL_MEMORY_CONTROL: &str = "INTERNAL MEMORY CONTROL";
+pub const INTERNAL_MEMORY_CONTROL_SIZE: usize = 4;
+pub const INTERNAL_MEMORY_CONTROL_START: u32 = 0x04000800;
+pub const INTERNAL_MEMORY_CONTROL_END: u32 = 0x04000803;
+
 
 // Internal Display Memory
 pub const PALLETE_RAM: &str = "PALLETE RAM";
 pub const PALLETE_RAM_SIZE: usize = 1 * KBYTES;
+pub const PALLETE_RAM_START: u32 = 0x05000000;
+pub const PALLETE_RAM_END: u32 = 0x050003FF;
+pub const PALLETE_RAM_MIRROR_END: u32 = 0x05FFFFFF; // Mirrored every 1K
 
 pub const VRAM: &str = "VRAM - Video RAM";
 pub const VRAM_SIZE: usize = 96 * KBYTES;
+pub const VRAM_START: u32 = 0x06000000;
+pub const VRAM_END: u32 = 0x06017FFF;
+pub const VRAM_MIRROR_END: u32 = 0x06FFFFFF;
+pub const VRAM_MIRROR_PERIOD: usize = 128 * KBYTES; // 0x06018000-0x0601FFFF mirrors the upper 32K
+pub const VRAM_OBJ_START: u32 = 0x06010000; // Sprite tiles in the tile modes, the bitmap modes take BG data up to 0x06013FFF
 
 pub const OAM: &str = "OAM - Object Attributes";
 pub const OAM_SIZE: usize = 1 * KBYTES;
-
-
-
-
-
-
+pub const OAM_START: u32 = 0x07000000;
+pub const OAM_END: u32 = 0x070003FF;
+pub const OAM_MIRROR_END: u32 = 0x07FFFFFF; // Mirrored every 1K
+
+// External Memory (Game Pak)
+// The ROM is visible three times, each region with its own wait state settings
+pub const GAMEPAK_ROM_SIZE: usize = 32 * MBYTES;
+
+pub const GAMEPAK_ROM_WS0: &str = "GAME PAK ROM - Wait State 0";
+pub const GAMEPAK_ROM_WS0_START: u32 = 0x08000000;
+pub const GAMEPAK_ROM_WS0_END: u32 = 0x09FFFFFF;
+
+pub const GAMEPAK_ROM_WS1: &str = "GAME PAK ROM - Wait State 1";
+pub const GAMEPAK_ROM_WS1_START: u32 = 0x0A000000;
+pub const GAMEPAK_ROM_WS1_END: u32 = 0x0BFFFFFF;
+
+pub const GAMEPAK_ROM_WS2: &str = "GAME PAK ROM - Wait State 2";
+pub const GAMEPAK_ROM_WS2_START: u32 = 0x0C000000;
+pub const GAMEPAK_ROM_WS2_END: u32 = 0x0DFFFFFF;
+
+pub const GAMEPAK_SRAM: &str = "GAME PAK SRAM";
+pub const GAMEPAK_SRAM_SIZE: usize = 32 * KBYTES;
+pub const GAMEPAK_SRAM_START: u32 = 0x0E000000;
+pub const GAMEPAK_SRAM_END: u32 = 0x0E007FFF;
+pub const GAMEPAK_SRAM_MIRROR_END: u32 = 0x0FFFFFFF; // Mirrored every 32K
+
+// Flash save chips take the place of the SRAM
+pub const GAMEPAK_FLASH: &str = "GAME PAK FLASH";
+pub const GAMEPAK_FLASH_BANK_SIZE: usize = 64 * KBYTES; // 128 KB chips switch between two banks
+
+// EEPROM carts give up the upper half of wait state 2
+pub const GAMEPAK_EEPROM: &str = "GAME PAK EEPROM";
+pub const GAMEPAK_EEPROM_SIZE: usize = 16 * MBYTES;
+pub const GAMEPAK_EEPROM_START: u32 = 0x0D000000;
+pub const GAMEPAK_EEPROM_END: u32 = 0x0DFFFFFF;
+
+// IO register offsets from IO_REGISTERS_START
+pub const IO_DISPCNT: u32 = 0x000; // LCD Control
+pub const IO_IE: u32 = 0x200; // Interrupt Enable
+pub const IO_IF: u32 = 0x202; // Interrupt Request Flags / Acknowledge
+pub const IO_WAITCNT: u32 = 0x204; // Game Pak Waitstate Control
+pub const IO_IME: u32 = 0x208; // Interrupt Master Enable
+
+// Interrupt sources, bits of IE and IF
+pub const IRQ_VBLANK: u16 = 1 << 0;
+pub const IRQ_HBLANK: u16 = 1 << 1;
+pub const IRQ_SERIAL: u16 = 1 << 7;
+pub const IRQ_KEYPAD: u16 = 1 << 12;
+pub const IRQ_GAMEPAK: u16 = 1 << 13;
+
+// BIOS data at the top of on-chip WRAM
+pub const BIOS_INTERRUPT_FLAGS: u32 = 0x03007FF8; // Interrupt handlers set the flags IntrWait checks
+pub const BIOS_RESET_FLAG: u32 = 0x03007FFA; // SoftReset enters RAM (0x02000000) when nonzero
+pub const BIOS_RAM_START: u32 = 0x03007E00; // Cleared by SoftReset
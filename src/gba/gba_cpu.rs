@@ -1,17 +1,26 @@
-use super::{init_gba_memory_bus, init_gba_registers};
+use std::{cell::RefCell, rc::Rc};
+
+use super::{build_gba_memory_bus, init_gba_registers, BiosSource, GbaConfig, GbaInterrupts, HleBios, IoRegisters};
 
 use crate::cpu::{CpuError, CPU};
 
 
-pub fn init_gba_cpu() -> Result<CPU, CpuError> {
+pub fn init_gba_cpu(config: &GbaConfig) -> Result<CPU, CpuError> {
 
     let register_map = init_gba_registers().map_err(|e| {
         return CpuError::InitError(format!("Failed to init registers {:?}", e));
     })?;
 
-    let memory_bus = init_gba_memory_bus().map_err(|e| {
+    let io_registers = Rc::new(RefCell::new(IoRegisters::new()));
+    let memory_bus = build_gba_memory_bus(config, io_registers.clone()).map_err(|e| {
         return CpuError::InitError(format!("Failed to init memory bus {:?}", e));
     })?;
 
-    Ok(CPU::new(register_map, memory_bus))
-}
\ No newline at end of file
+    let mut cpu = CPU::new(register_map, memory_bus);
+    cpu.set_interrupt_controller(Box::new(GbaInterrupts::new(io_registers)));
+    // Without a BIOS dump, the BIOS calls are emulated
+    if let BiosSource::Hle = config.bios {
+        cpu.set_swi_handler(Box::new(HleBios::new()));
+    }
+    Ok(cpu)
+}
@@ -0,0 +1,386 @@
+use super::{BackupError, BackupMemory, GAMEPAK_EEPROM_SIZE};
+use crate::memory::{MemoryDevice, MemoryError};
+
+// EEPROMs are read and written in blocks of 64 bits
+const BLOCK_SIZE: usize = 8;
+const DATA_BITS: usize = BLOCK_SIZE * 8;
+
+// Requests start with 2 bits: 11 reads a block, 10 writes one. The address follows, then for
+// writes the data, and a final 0 bit.
+const READ_REQUEST: [u8; 2] = [1, 1];
+const WRITE_REQUEST: [u8; 2] = [1, 0];
+
+// A read answers with 4 bits to ignore before the data
+const READ_PADDING_BITS: usize = 4;
+
+// Reads that return busy after a write. Real chips take about 6.6 ms, games only poll until ready.
+pub const EEPROM_WRITE_BUSY_POLLS: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EepromSize {
+    Eeprom512, // 6 address bits
+    Eeprom8K, // 14 address bits, of which only the lower 10 are used
+}
+
+impl EepromSize {
+    pub fn bytes(&self) -> usize {
+        match self {
+            EepromSize::Eeprom512 => 512,
+            EepromSize::Eeprom8K => 8 * 1024,
+        }
+    }
+
+    pub fn address_bits(&self) -> usize {
+        match self {
+            EepromSize::Eeprom512 => 6,
+            EepromSize::Eeprom8K => 14,
+        }
+    }
+
+    // The DMA length of a request gives away the address width: reads send 2 + address + 1 bits,
+    // writes 64 more
+    fn from_request_bits(bits: usize) -> Option<EepromSize> {
+        [EepromSize::Eeprom512, EepromSize::Eeprom8K].into_iter().find(|size| {
+            let read_bits = READ_REQUEST.len() + size.address_bits() + 1;
+            bits == read_bits || bits == read_bits + DATA_BITS
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    Ready,
+    Sending { block: usize, bit: usize }, // Next bit of a read answer
+    Busy(u32), // Polls left until the write completes
+}
+
+// Serial EEPROM in the upper Game Pak ROM region. DMA3 moves one bit per halfword in bit 0:
+// the request is written bit by bit, the first read after it runs the request and reads
+// shift out the answer. Bit 0 reads 1 while the chip is ready and 0 while a write is busy.
+#[derive(Debug, Clone)]
+pub struct Eeprom {
+    data: Vec<u8>,
+    size: Option<EepromSize>, // None until the first request or save file shows it
+    request: Vec<u8>, // Bits written since the last read
+    state: EepromState,
+    dirty: bool,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Eeprom {
+            data: vec![0xFF; EepromSize::Eeprom8K.bytes()],
+            size: None,
+            request: Vec::new(),
+            state: EepromState::Ready,
+            dirty: false,
+        }
+    }
+}
+
+impl Eeprom {
+    // Size detected from the first request
+    pub fn new() -> Eeprom {
+        Eeprom::default()
+    }
+
+    pub fn with_size(size: EepromSize) -> Eeprom {
+        Eeprom { size: Some(size), ..Eeprom::default() }
+    }
+
+    pub fn eeprom_size(&self) -> Option<EepromSize> {
+        self.size
+    }
+
+    fn write_bit(&mut self, bit: u8) {
+        // A new request cancels an answer that was not read to the end
+        if let EepromState::Sending { .. } = self.state {
+            self.state = EepromState::Ready;
+        }
+        self.request.push(bit & 1);
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        if !self.request.is_empty() {
+            self.run_request();
+        }
+
+        let value = self.current_bit();
+        match self.state {
+            EepromState::Ready => {}
+            EepromState::Busy(polls) => {
+                self.state = if polls > 1 { EepromState::Busy(polls - 1) } else { EepromState::Ready };
+            }
+            EepromState::Sending { block, bit } => {
+                self.state = if bit + 1 < READ_PADDING_BITS + DATA_BITS {
+                    EepromState::Sending { block, bit: bit + 1 }
+                } else {
+                    EepromState::Ready
+                };
+            }
+        }
+        value
+    }
+
+    fn current_bit(&self) -> u8 {
+        match self.state {
+            EepromState::Ready => 1,
+            EepromState::Busy(_) => 0,
+            EepromState::Sending { bit, .. } if bit < READ_PADDING_BITS => 0,
+            EepromState::Sending { block, bit } => {
+                let bit = bit - READ_PADDING_BITS;
+                (self.data[block * BLOCK_SIZE + bit / 8] >> (7 - bit % 8)) & 1
+            }
+        }
+    }
+
+    // Requests of an unexpected length are dropped
+    fn run_request(&mut self) {
+        let request = std::mem::take(&mut self.request);
+        if self.size.is_none() {
+            self.size = EepromSize::from_request_bits(request.len());
+        }
+        let Some(size) = self.size else {
+            return;
+        };
+
+        let address_end = READ_REQUEST.len() + size.address_bits();
+        if request.len() < address_end + 1 {
+            return;
+        }
+        let address = request[READ_REQUEST.len()..address_end].iter().fold(0, |acc, bit| (acc << 1) | *bit as usize);
+        let block = address % (size.bytes() / BLOCK_SIZE);
+
+        if request[..2] == READ_REQUEST && request.len() == address_end + 1 {
+            self.state = EepromState::Sending { block, bit: 0 };
+        } else if request[..2] == WRITE_REQUEST && request.len() == address_end + DATA_BITS + 1 {
+            let data = &request[address_end..address_end + DATA_BITS];
+            for (index, bits) in data.chunks(8).enumerate() {
+                self.data[block * BLOCK_SIZE + index] = bits.iter().fold(0, |acc, bit| (acc << 1) | bit);
+            }
+            self.dirty = true;
+            self.state = EepromState::Busy(EEPROM_WRITE_BUSY_POLLS);
+        }
+    }
+}
+
+// Only halfword accesses carry a bit, the bus is 16 bits wide
+impl MemoryDevice for Eeprom {
+    fn size(&self) -> usize {
+        GAMEPAK_EEPROM_SIZE
+    }
+
+    fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError> {
+        self.peek_u8(offset)
+    }
+
+    fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError> {
+        if offset as usize >= GAMEPAK_EEPROM_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        Ok(0)
+    }
+
+    fn write_u8(&mut self, offset: u32, _value: u8) -> Result<(), MemoryError> {
+        if offset as usize >= GAMEPAK_EEPROM_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        Ok(())
+    }
+
+    fn read_u16(&mut self, offset: u32) -> Result<u16, MemoryError> {
+        if offset as usize >= GAMEPAK_EEPROM_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        Ok(self.read_bit() as u16)
+    }
+
+    // A pending request only runs on a read, until then the bit shows the state before it
+    fn peek_u16(&self, offset: u32) -> Result<u16, MemoryError> {
+        if offset as usize >= GAMEPAK_EEPROM_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        Ok(self.current_bit() as u16)
+    }
+
+    fn write_u16(&mut self, offset: u32, value: u16) -> Result<(), MemoryError> {
+        if offset as usize >= GAMEPAK_EEPROM_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        self.write_bit(value as u8);
+        Ok(())
+    }
+}
+
+impl BackupMemory for Eeprom {
+    fn data(&self) -> &[u8] {
+        let size = self.size.unwrap_or(EepromSize::Eeprom8K);
+        &self.data[..size.bytes()]
+    }
+
+    // The size of the save file also sets the address width
+    fn load(&mut self, data: &[u8]) -> Result<(), BackupError> {
+        let size = [EepromSize::Eeprom512, EepromSize::Eeprom8K].into_iter()
+            .find(|size| size.bytes() == data.len())
+            .ok_or(BackupError::InvalidSize(data.len()))?;
+        self.size = Some(size);
+        self.data[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, env, fs, rc::Rc};
+
+    use super::*;
+    use crate::{gba::{init_gba_memory_bus, Backup, GbaConfig, SaveFile, GAMEPAK_EEPROM, GAMEPAK_EEPROM_START, GAMEPAK_ROM_WS0_START, GAMEPAK_ROM_WS2, GAMEPAK_ROM_WS2_START}, memory::{write_memory, AccessWidth, Bus, MemoryBus}};
+
+    // Bits of a request as the DMA sends them
+    fn send(memory_bus: &MemoryBus, request: &[u8], address: usize, address_bits: usize, data: Option<[u8; 8]>) {
+        let mut bits = request.to_vec();
+        bits.extend((0..address_bits).rev().map(|bit| ((address >> bit) & 1) as u8));
+        if let Some(data) = data {
+            bits.extend(data.iter().flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1)));
+        }
+        bits.push(0);
+        for (i, bit) in bits.iter().enumerate() {
+            memory_bus.write_u16(GAMEPAK_EEPROM_START + i as u32 * 2, *bit as u16).unwrap();
+        }
+    }
+
+    fn receive(memory_bus: &MemoryBus) -> [u8; 8] {
+        let bits: Vec<u16> = (0..68).map(|_| memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap()).collect();
+        assert_eq!(&bits[..4], &[0, 0, 0, 0]);
+        let mut data = [0; 8];
+        for (i, bit) in bits[4..].iter().enumerate() {
+            data[i / 8] |= (*bit as u8) << (7 - i % 8);
+        }
+        data
+    }
+
+    fn wait_ready(memory_bus: &MemoryBus) -> u32 {
+        let mut polls = 0;
+        while memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap() & 1 == 0 {
+            polls += 1;
+        }
+        polls
+    }
+
+    fn eeprom_bus(eeprom: Eeprom) -> (MemoryBus, Rc<RefCell<Eeprom>>) {
+        let eeprom = Rc::new(RefCell::new(eeprom));
+        let memory_bus = init_gba_memory_bus(&GbaConfig { backup: Backup::Eeprom(eeprom.clone()), ..GbaConfig::default() }).unwrap();
+        (memory_bus, eeprom)
+    }
+
+    #[test]
+    fn test_eeprom_512() {
+        let (memory_bus, eeprom) = eeprom_bus(Eeprom::new());
+        let block = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF];
+
+        send(&memory_bus, &WRITE_REQUEST, 0x3F, 6, Some(block));
+        assert_eq!(wait_ready(&memory_bus), EEPROM_WRITE_BUSY_POLLS);
+        assert_eq!(eeprom.borrow().eeprom_size(), Some(EepromSize::Eeprom512));
+        assert_eq!(&eeprom.borrow().data()[0x1F8..], &block);
+
+        send(&memory_bus, &READ_REQUEST, 0x3F, 6, None);
+        assert_eq!(receive(&memory_bus), block);
+        send(&memory_bus, &READ_REQUEST, 0, 6, None);
+        assert_eq!(receive(&memory_bus), [0xFF; 8]);
+        assert_eq!(memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_eeprom_peek() {
+        let (memory_bus, eeprom) = eeprom_bus(Eeprom::with_size(EepromSize::Eeprom512));
+        let peek = || memory_bus.peek(GAMEPAK_EEPROM_START, AccessWidth::Halfword).unwrap();
+
+        // The request only runs on a read
+        send(&memory_bus, &WRITE_REQUEST, 0, 6, Some([0x80, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(peek(), 1);
+        assert_eq!(eeprom.borrow().data()[0], 0xFF);
+        assert_eq!(memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap(), 0);
+        assert_eq!(eeprom.borrow().data()[0], 0x80);
+
+        // Peeks don't count as polls
+        assert_eq!(peek(), 0);
+        assert_eq!(peek(), 0);
+        assert_eq!(wait_ready(&memory_bus), EEPROM_WRITE_BUSY_POLLS - 1);
+
+        // Nor do they shift out the answer
+        send(&memory_bus, &READ_REQUEST, 0, 6, None);
+        for _ in 0..READ_PADDING_BITS {
+            memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap();
+        }
+        assert_eq!(peek(), 1);
+        assert_eq!(peek(), 1);
+        assert_eq!(memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap(), 1);
+        assert_eq!(memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_eeprom_8k() {
+        let (memory_bus, eeprom) = eeprom_bus(Eeprom::new());
+
+        // The first request is a read, 17 bits
+        send(&memory_bus, &READ_REQUEST, 0x3FF, 14, None);
+        assert_eq!(receive(&memory_bus), [0xFF; 8]);
+        assert_eq!(eeprom.borrow().eeprom_size(), Some(EepromSize::Eeprom8K));
+
+        send(&memory_bus, &WRITE_REQUEST, 0x3FF, 14, Some([1, 2, 3, 4, 5, 6, 7, 8]));
+        wait_ready(&memory_bus);
+        // The upper 4 address bits are ignored
+        send(&memory_bus, &READ_REQUEST, 0x3FFF, 14, None);
+        assert_eq!(receive(&memory_bus), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(eeprom.borrow().data().len(), 8 * 1024);
+
+        // A request of the wrong length does nothing
+        send(&memory_bus, &WRITE_REQUEST, 0, 6, Some([0; 8]));
+        assert_eq!(memory_bus.read_u16(GAMEPAK_EEPROM_START).unwrap(), 1);
+        assert_eq!(eeprom.borrow().data()[0], 0xFF);
+    }
+
+    #[test]
+    fn test_eeprom_region() {
+        let (memory_bus, _) = eeprom_bus(Eeprom::new());
+        write_memory(&memory_bus, GAMEPAK_ROM_WS0_START, &[0x2E, 0x00, 0x00, 0xEA]).unwrap();
+
+        // The lower 16 MB of wait state 2 still show the ROM
+        assert_eq!(memory_bus.read_u32(GAMEPAK_ROM_WS2_START).unwrap(), 0xEA00002E);
+        assert_eq!(memory_bus.sector(GAMEPAK_EEPROM_START - 1).unwrap().name, GAMEPAK_ROM_WS2);
+        assert_eq!(memory_bus.sector(0x0DFFFF00).unwrap().name, GAMEPAK_EEPROM);
+        assert!(memory_bus.sector(0x0E000000).is_none());
+    }
+
+    #[test]
+    fn test_eeprom_save_file() {
+        let path = env::temp_dir().join(format!("rusty_dolphine_eeprom_{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (memory_bus, eeprom) = eeprom_bus(Eeprom::new());
+        let save_file = SaveFile::open(&path, eeprom.clone()).unwrap();
+        send(&memory_bus, &WRITE_REQUEST, 5, 6, Some([9; 8]));
+        wait_ready(&memory_bus);
+        drop(save_file);
+        let saved = fs::read(&path).unwrap();
+        assert_eq!(saved.len(), 512);
+
+        let (memory_bus, eeprom) = eeprom_bus(Eeprom::new());
+        let save_file = SaveFile::open(&path, eeprom.clone()).unwrap();
+        assert_eq!(eeprom.borrow().eeprom_size(), Some(EepromSize::Eeprom512));
+        assert_eq!(eeprom.borrow().data(), &saved[..]);
+        send(&memory_bus, &READ_REQUEST, 5, 6, None);
+        assert_eq!(receive(&memory_bus), [9; 8]);
+        drop(save_file);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+use super::{BackupError, BackupMemory, GAMEPAK_FLASH_BANK_SIZE};
+use crate::memory::{MemoryDevice, MemoryError};
+
+// Commands are unlocked by writing 0xAA to 0x5555 and 0x55 to 0x2AAA
+const COMMAND_ADDRESS: u32 = 0x5555;
+const UNLOCK_ADDRESS: u32 = 0x2AAA;
+const UNLOCK_1: u8 = 0xAA;
+const UNLOCK_2: u8 = 0x55;
+
+const COMMAND_ENTER_ID: u8 = 0x90;
+const COMMAND_EXIT_ID: u8 = 0xF0;
+const COMMAND_ERASE: u8 = 0x80; // Followed by a second unlock and the erase command
+const COMMAND_ERASE_CHIP: u8 = 0x10;
+const COMMAND_ERASE_SECTOR: u8 = 0x30; // Written to the sector
+const COMMAND_PROGRAM: u8 = 0xA0;
+const COMMAND_SWITCH_BANK: u8 = 0xB0; // 128 KB chips, the bank is written to 0x0000
+
+pub const FLASH_SECTOR_SIZE: usize = 4 * 1024;
+
+// Atmel chips have no erase commands, a program command rewrites a whole 128 byte page
+pub const FLASH_ATMEL_PAGE_SIZE: usize = 128;
+const ATMEL_MANUFACTURER: u8 = 0x1F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashSize {
+    Flash64K,
+    Flash128K,
+}
+
+impl FlashSize {
+    pub fn banks(&self) -> usize {
+        match self {
+            FlashSize::Flash64K => 1,
+            FlashSize::Flash128K => 2,
+        }
+    }
+
+    // IDs games expect when they check for a chip of the size
+    pub fn default_id(&self) -> FlashId {
+        match self {
+            FlashSize::Flash64K => FlashId::PANASONIC_64K,
+            FlashSize::Flash128K => FlashId::SANYO_128K,
+        }
+    }
+}
+
+// Bytes read at 0x0000 and 0x0001 in chip ID mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashId {
+    pub manufacturer: u8,
+    pub device: u8,
+}
+
+impl FlashId {
+    pub const PANASONIC_64K: FlashId = FlashId { manufacturer: 0x32, device: 0x1B };
+    pub const ATMEL_64K: FlashId = FlashId { manufacturer: 0x1F, device: 0x3D };
+    pub const MACRONIX_64K: FlashId = FlashId { manufacturer: 0xC2, device: 0x1C };
+    pub const SANYO_128K: FlashId = FlashId { manufacturer: 0x62, device: 0x13 };
+}
+
+// Where the chip is in a command sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Ready,
+    Unlocking, // 0xAA written
+    Unlocked, // 0x55 written, the next write is a command
+    Program, // The next write stores a byte
+    ProgramPage { page: usize, written: usize }, // Atmel, the first write selects the page
+    SwitchBank, // The next write to 0x0000 selects the bank
+}
+
+// Flash save chip on an 8-bit bus, 64 KB are visible at a time. Erases and programs finish
+// immediately, so games polling for completion read the final value right away.
+#[derive(Debug, Clone)]
+pub struct Flash {
+    data: Vec<u8>,
+    size: FlashSize,
+    id: FlashId,
+    bank: usize,
+    state: FlashState,
+    id_mode: bool,
+    erase_armed: bool, // Erase command received, waiting for the chip or sector erase
+    dirty: bool,
+}
+
+impl Flash {
+    pub fn new(size: FlashSize, id: FlashId) -> Flash {
+        Flash {
+            data: vec![0xFF; size.banks() * GAMEPAK_FLASH_BANK_SIZE],
+            size,
+            id,
+            bank: 0,
+            state: FlashState::Ready,
+            id_mode: false,
+            erase_armed: false,
+            dirty: false,
+        }
+    }
+
+    pub fn with_size(size: FlashSize) -> Flash {
+        Flash::new(size, size.default_id())
+    }
+
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
+    fn index(&self, offset: u32) -> usize {
+        self.bank * GAMEPAK_FLASH_BANK_SIZE + offset as usize
+    }
+
+    fn erase(&mut self, start: usize, size: usize) {
+        self.data[start..start + size].fill(0xFF);
+        self.dirty = true;
+    }
+
+    fn command(&mut self, offset: u32, value: u8) {
+        if self.erase_armed {
+            self.erase_armed = false;
+            match value {
+                COMMAND_ERASE_CHIP if offset == COMMAND_ADDRESS => self.erase(0, self.data.len()),
+                COMMAND_ERASE_SECTOR => {
+                    let start = self.index(offset) & !(FLASH_SECTOR_SIZE - 1);
+                    self.erase(start, FLASH_SECTOR_SIZE);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        if offset != COMMAND_ADDRESS {
+            return;
+        }
+        match value {
+            COMMAND_ENTER_ID => self.id_mode = true,
+            COMMAND_EXIT_ID => self.id_mode = false,
+            COMMAND_ERASE => self.erase_armed = true,
+            COMMAND_PROGRAM if self.id.manufacturer == ATMEL_MANUFACTURER => {
+                self.state = FlashState::ProgramPage { page: 0, written: 0 };
+            }
+            COMMAND_PROGRAM => self.state = FlashState::Program,
+            COMMAND_SWITCH_BANK if self.size == FlashSize::Flash128K => self.state = FlashState::SwitchBank,
+            _ => {}
+        }
+    }
+}
+
+impl MemoryDevice for Flash {
+    fn size(&self) -> usize {
+        GAMEPAK_FLASH_BANK_SIZE
+    }
+
+    fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError> {
+        if offset as usize >= GAMEPAK_FLASH_BANK_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        // A page is written once the game stops sending bytes, which it does to poll for completion
+        if let FlashState::ProgramPage { .. } = self.state {
+            self.state = FlashState::Ready;
+        }
+        self.peek_u8(offset)
+    }
+
+    fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError> {
+        if offset as usize >= GAMEPAK_FLASH_BANK_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        match offset {
+            0 if self.id_mode => Ok(self.id.manufacturer),
+            1 if self.id_mode => Ok(self.id.device),
+            _ => Ok(self.data[self.index(offset)]),
+        }
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) -> Result<(), MemoryError> {
+        if offset as usize >= GAMEPAK_FLASH_BANK_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+
+        self.state = match self.state {
+            FlashState::Ready if offset == COMMAND_ADDRESS && value == UNLOCK_1 => FlashState::Unlocking,
+            // Leaves ID mode without the unlock sequence
+            FlashState::Ready if value == COMMAND_EXIT_ID => {
+                self.id_mode = false;
+                FlashState::Ready
+            }
+            FlashState::Unlocking if offset == UNLOCK_ADDRESS && value == UNLOCK_2 => FlashState::Unlocked,
+            FlashState::Unlocked => {
+                self.state = FlashState::Ready;
+                self.command(offset, value);
+                self.state
+            }
+            FlashState::Program => {
+                let index = self.index(offset);
+                self.data[index] = value;
+                self.dirty = true;
+                FlashState::Ready
+            }
+            FlashState::ProgramPage { page, written } => {
+                // Bytes the game does not send are left erased
+                let page = if written == 0 { self.index(offset) & !(FLASH_ATMEL_PAGE_SIZE - 1) } else { page };
+                if written == 0 {
+                    self.data[page..page + FLASH_ATMEL_PAGE_SIZE].fill(0xFF);
+                }
+                self.data[page + (offset as usize % FLASH_ATMEL_PAGE_SIZE)] = value;
+                self.dirty = true;
+                if written + 1 < FLASH_ATMEL_PAGE_SIZE {
+                    FlashState::ProgramPage { page, written: written + 1 }
+                } else {
+                    FlashState::Ready
+                }
+            }
+            FlashState::SwitchBank if offset == 0 => {
+                self.bank = (value as usize) % self.size.banks();
+                FlashState::Ready
+            }
+            _ => FlashState::Ready,
+        };
+        Ok(())
+    }
+
+    // Wider reads repeat the byte, wider writes only store the low byte
+    fn read_u16(&mut self, offset: u32) -> Result<u16, MemoryError> {
+        Ok(self.read_u8(offset)? as u16 * 0x0101)
+    }
+
+    fn read_u32(&mut self, offset: u32) -> Result<u32, MemoryError> {
+        Ok(self.read_u8(offset)? as u32 * 0x01010101)
+    }
+
+    fn peek_u16(&self, offset: u32) -> Result<u16, MemoryError> {
+        Ok(self.peek_u8(offset)? as u16 * 0x0101)
+    }
+
+    fn peek_u32(&self, offset: u32) -> Result<u32, MemoryError> {
+        Ok(self.peek_u8(offset)? as u32 * 0x01010101)
+    }
+
+    fn write_u16(&mut self, offset: u32, value: u16) -> Result<(), MemoryError> {
+        self.write_u8(offset, value as u8)
+    }
+
+    fn write_u32(&mut self, offset: u32, value: u32) -> Result<(), MemoryError> {
+        self.write_u8(offset, value as u8)
+    }
+}
+
+impl BackupMemory for Flash {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), BackupError> {
+        if data.len() != self.data.len() {
+            return Err(BackupError::InvalidSize(data.len()));
+        }
+        self.data.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{gba::{init_gba_memory_bus, Backup, GbaConfig, GAMEPAK_SRAM_START}, memory::{AccessWidth, Bus, MemoryBus}};
+
+    fn command(memory_bus: &MemoryBus, value: u8) {
+        memory_bus.write_u8(GAMEPAK_SRAM_START + COMMAND_ADDRESS, UNLOCK_1).unwrap();
+        memory_bus.write_u8(GAMEPAK_SRAM_START + UNLOCK_ADDRESS, UNLOCK_2).unwrap();
+        memory_bus.write_u8(GAMEPAK_SRAM_START + COMMAND_ADDRESS, value).unwrap();
+    }
+
+    fn program(memory_bus: &MemoryBus, offset: u32, value: u8) {
+        command(memory_bus, COMMAND_PROGRAM);
+        memory_bus.write_u8(GAMEPAK_SRAM_START + offset, value).unwrap();
+    }
+
+    fn flash_bus(flash: Flash) -> (MemoryBus, Rc<RefCell<Flash>>) {
+        let flash = Rc::new(RefCell::new(flash));
+        let memory_bus = init_gba_memory_bus(&GbaConfig { backup: Backup::Flash(flash.clone()), ..GbaConfig::default() }).unwrap();
+        (memory_bus, flash)
+    }
+
+    #[test]
+    fn test_flash_chip_id() {
+        let (memory_bus, _) = flash_bus(Flash::new(FlashSize::Flash64K, FlashId::MACRONIX_64K));
+        command(&memory_bus, COMMAND_ENTER_ID);
+        assert_eq!(memory_bus.peek(GAMEPAK_SRAM_START, AccessWidth::Halfword).unwrap(), 0xC2C2);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START).unwrap(), 0xC2);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 1).unwrap(), 0x1C);
+        command(&memory_bus, COMMAND_EXIT_ID);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START).unwrap(), 0xFF);
+
+        let (memory_bus, _) = flash_bus(Flash::with_size(FlashSize::Flash128K));
+        command(&memory_bus, COMMAND_ENTER_ID);
+        assert_eq!(memory_bus.read_u16(GAMEPAK_SRAM_START).unwrap(), 0x6262);
+        memory_bus.write_u8(GAMEPAK_SRAM_START, COMMAND_EXIT_ID).unwrap();
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 1).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn test_flash_program_and_erase() {
+        let (memory_bus, flash) = flash_bus(Flash::with_size(FlashSize::Flash64K));
+
+        // Writes without the command do nothing
+        memory_bus.write_u8(GAMEPAK_SRAM_START + 0x10, 0x12).unwrap();
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x10).unwrap(), 0xFF);
+        assert!(!flash.borrow().is_dirty());
+
+        program(&memory_bus, 0x10, 0x12);
+        program(&memory_bus, 0x1010, 0x34);
+        program(&memory_bus, 0x2010, 0x56);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x10).unwrap(), 0x12);
+        assert!(flash.borrow().is_dirty());
+
+        // Sector erase clears the 4 KB the address lies in
+        command(&memory_bus, COMMAND_ERASE);
+        memory_bus.write_u8(GAMEPAK_SRAM_START + COMMAND_ADDRESS, UNLOCK_1).unwrap();
+        memory_bus.write_u8(GAMEPAK_SRAM_START + UNLOCK_ADDRESS, UNLOCK_2).unwrap();
+        memory_bus.write_u8(GAMEPAK_SRAM_START + 0x1FFF, COMMAND_ERASE_SECTOR).unwrap();
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x10).unwrap(), 0x12);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x1010).unwrap(), 0xFF);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x2010).unwrap(), 0x56);
+
+        command(&memory_bus, COMMAND_ERASE);
+        command(&memory_bus, COMMAND_ERASE_CHIP);
+        assert!(flash.borrow().data().iter().all(|byte| *byte == 0xFF));
+    }
+
+    #[test]
+    fn test_flash_bank_switch() {
+        let (memory_bus, flash) = flash_bus(Flash::with_size(FlashSize::Flash128K));
+        program(&memory_bus, 0x20, 0xAB);
+        command(&memory_bus, COMMAND_SWITCH_BANK);
+        memory_bus.write_u8(GAMEPAK_SRAM_START, 1).unwrap();
+        assert_eq!(flash.borrow().bank(), 1);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x20).unwrap(), 0xFF);
+        program(&memory_bus, 0x20, 0xCD);
+        assert_eq!(flash.borrow().data()[GAMEPAK_FLASH_BANK_SIZE + 0x20], 0xCD);
+
+        command(&memory_bus, COMMAND_SWITCH_BANK);
+        memory_bus.write_u8(GAMEPAK_SRAM_START, 0).unwrap();
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x20).unwrap(), 0xAB);
+
+        // 64 KB chips have a single bank
+        let (memory_bus, flash) = flash_bus(Flash::with_size(FlashSize::Flash64K));
+        command(&memory_bus, COMMAND_SWITCH_BANK);
+        memory_bus.write_u8(GAMEPAK_SRAM_START, 1).unwrap();
+        assert_eq!(flash.borrow().bank(), 0);
+        assert_eq!(flash.borrow().data().len(), GAMEPAK_FLASH_BANK_SIZE);
+    }
+
+    #[test]
+    fn test_flash_atmel_page_program() {
+        let (memory_bus, flash) = flash_bus(Flash::new(FlashSize::Flash64K, FlashId::ATMEL_64K));
+        program(&memory_bus, 0x180, 0x11);
+        // Unlike a read, a peek leaves the page loading
+        assert_eq!(memory_bus.peek(GAMEPAK_SRAM_START + 0x180, AccessWidth::Byte).unwrap(), 0x11);
+        for i in 1..FLASH_ATMEL_PAGE_SIZE as u32 {
+            memory_bus.write_u8(GAMEPAK_SRAM_START + 0x180 + i, i as u8).unwrap();
+        }
+        let page: Vec<u8> = (0..FLASH_ATMEL_PAGE_SIZE).map(|i| if i == 0 { 0x11 } else { i as u8 }).collect();
+        assert_eq!(&flash.borrow().data()[0x180..0x200], &page[..]);
+
+        // The page is done, further writes need a new command
+        memory_bus.write_u8(GAMEPAK_SRAM_START + 0x200, 0x22).unwrap();
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x200).unwrap(), 0xFF);
+
+        // Reprogramming replaces the page without an erase, unsent bytes read erased
+        program(&memory_bus, 0x1C0, 0x33);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x1C0).unwrap(), 0x33);
+        command(&memory_bus, COMMAND_ENTER_ID);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START).unwrap(), 0x1F);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 1).unwrap(), 0x3D);
+        command(&memory_bus, COMMAND_EXIT_ID);
+        assert_eq!(&flash.borrow().data()[0x180..0x183], &[0xFF, 0xFF, 0xFF]);
+        assert_eq!(flash.borrow().data()[0x1C0], 0x33);
+    }
+}
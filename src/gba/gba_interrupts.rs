@@ -0,0 +1,27 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::IoRegisters;
+use crate::cpu::InterruptController;
+
+// Interrupts of the GBA hardware, requested in IF and enabled by IE and IME
+#[derive(Debug, Clone)]
+pub struct GbaInterrupts {
+    io_registers: Rc<RefCell<IoRegisters>>,
+}
+
+impl GbaInterrupts {
+    pub fn new(io_registers: Rc<RefCell<IoRegisters>>) -> GbaInterrupts {
+        GbaInterrupts { io_registers }
+    }
+
+    // Sets the IF bits, as the hardware raising the interrupts does
+    pub fn request(&self, mask: u16) {
+        self.io_registers.borrow_mut().request_interrupt(mask);
+    }
+}
+
+impl InterruptController for GbaInterrupts {
+    fn irq_pending(&self) -> bool {
+        self.io_registers.borrow().irq_pending()
+    }
+}
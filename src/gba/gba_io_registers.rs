@@ -0,0 +1,115 @@
+use crate::memory::{MemoryDevice, MemoryError};
+
+use super::{WaitControl, IO_IE, IO_IF, IO_IME, IO_REGISTERS_SIZE, IO_WAITCNT};
+
+// IO register block at 0x04000000. Registers without a hardware model behave like RAM.
+#[derive(Debug, Clone)]
+pub struct IoRegisters {
+    data: Vec<u8>,
+    // Updated when WAITCNT is written
+    wait_control: WaitControl,
+}
+
+impl Default for IoRegisters {
+    fn default() -> Self {
+        IoRegisters { data: vec![0; IO_REGISTERS_SIZE], wait_control: WaitControl::default() }
+    }
+}
+
+impl IoRegisters {
+    pub fn new() -> IoRegisters {
+        IoRegisters::default()
+    }
+
+    pub fn wait_control(&self) -> &WaitControl {
+        &self.wait_control
+    }
+
+    // Raised by the hardware, the game acknowledges them through IF
+    pub fn request_interrupt(&mut self, mask: u16) {
+        let flags = self.interrupt_flags() | mask;
+        self.data[IO_IF as usize..IO_IF as usize + 2].copy_from_slice(&flags.to_le_bytes());
+    }
+
+    pub fn interrupt_flags(&self) -> u16 {
+        self.halfword(IO_IF)
+    }
+
+    // IME is set and an interrupt enabled in IE is requested
+    pub fn irq_pending(&self) -> bool {
+        (self.halfword(IO_IME) & 1) != 0 && (self.halfword(IO_IE) & self.interrupt_flags()) != 0
+    }
+
+    fn halfword(&self, offset: u32) -> u16 {
+        u16::from_le_bytes([self.data[offset as usize], self.data[offset as usize + 1]])
+    }
+}
+
+impl MemoryDevice for IoRegisters {
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError> {
+        self.peek_u8(offset)
+    }
+
+    fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError> {
+        self.data.get(offset as usize).copied().ok_or(MemoryError::OutOfBounds(offset))
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) -> Result<(), MemoryError> {
+        let byte = self.data.get_mut(offset as usize).ok_or(MemoryError::OutOfBounds(offset))?;
+        if offset & !1 == IO_IF {
+            // Writing 1 to a bit of IF acknowledges the interrupt
+            *byte &= !value;
+        } else {
+            *byte = value;
+        }
+
+        if offset & !1 == IO_WAITCNT {
+            self.wait_control.set_waitcnt(self.halfword(IO_WAITCNT));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+    use crate::{gba::{IO_REGISTERS, IO_REGISTERS_START}, memory::{Bus, MemoryBus}};
+
+    #[test]
+    fn test_interrupt_acknowledge() {
+        let io = Rc::new(RefCell::new(IoRegisters::new()));
+        let memory_bus = MemoryBus::builder()
+            .sector_with_device(IO_REGISTERS.to_string(), IO_REGISTERS_START, io.clone()).unwrap()
+            .build();
+
+        io.borrow_mut().request_interrupt(0b101);
+        assert_eq!(memory_bus.read_u16(IO_REGISTERS_START + IO_IF).unwrap(), 0b101);
+
+        memory_bus.write_u16(IO_REGISTERS_START + IO_IF, 0b001).unwrap();
+        assert_eq!(io.borrow().interrupt_flags(), 0b100);
+
+        // IE next to it is plain storage
+        memory_bus.write_u32(IO_REGISTERS_START + IO_IE, 0x0004_3FFF).unwrap();
+        assert_eq!(memory_bus.read_u16(IO_REGISTERS_START + IO_IE).unwrap(), 0x3FFF);
+        assert_eq!(io.borrow().interrupt_flags(), 0);
+    }
+
+    #[test]
+    fn test_irq_pending() {
+        let mut io = IoRegisters::new();
+        io.request_interrupt(0b10);
+        io.write_u16(IO_IE, 0b11).unwrap();
+        assert!(!io.irq_pending());
+
+        io.write_u16(IO_IME, 1).unwrap();
+        assert!(io.irq_pending());
+        io.write_u16(IO_IE, 0b01).unwrap();
+        assert!(!io.irq_pending());
+    }
+}
@@ -1,13 +1,71 @@
-use super::{BIOS, BIOS_END, BIOS_START, IO_REGISTERS, IO_REGISTERS_END, IO_REGISTERS_START, WRAM, WRAM_ONBOARD_END, WRAM_ONBOARD_START, WRAM_ONCHIP, WRAM_ONCHIP_END, WRAM_ONCHIP_START};
-use crate::memory::{MemoryBus, MemoryError};
+use std::{cell::RefCell, rc::Rc};
 
+use super::*;
+use crate::memory::{AccessFlags, AccessTiming, AccessWidth, MemoryBus, MemoryError, PrefetchBuffer};
 
-pub fn init_gba_memory_bus() -> Result<MemoryBus, MemoryError> {
-    let memory_bus = MemoryBus::builder()
-        .sector_with_addresses(BIOS.to_string(), BIOS_START, BIOS_END)?
+
+pub fn init_gba_memory_bus(config: &GbaConfig) -> Result<MemoryBus, MemoryError> {
+    build_gba_memory_bus(config, Rc::new(RefCell::new(IoRegisters::new())))
+}
+
+// The IO registers are shared with the hardware that requests interrupts
+pub(crate) fn build_gba_memory_bus(config: &GbaConfig, io_registers: Rc<RefCell<IoRegisters>>) -> Result<MemoryBus, MemoryError> {
+    let mut builder = MemoryBus::builder();
+    let bios = BiosDevice::new(config.bios.image(), builder.fetch_address());
+    let backup = &config.backup;
+    let wait_control = io_registers.borrow().wait_control().clone();
+    // Regions on a 16-bit bus without wait states
+    let halfword_bus = || AccessTiming::new(AccessWidth::Halfword, 0, 0).shared();
+    match backup {
+        Backup::Sram(sram) => builder
+            .sector_with_device(GAMEPAK_SRAM.to_string(), GAMEPAK_SRAM_START, sram.clone())?
+            .mirror(GAMEPAK_SRAM_START, GAMEPAK_SRAM_MIRROR_END, GAMEPAK_SRAM_SIZE as u32)?
+            .timing(GAMEPAK_SRAM_START, wait_control.sram.clone())?,
+        Backup::Flash(flash) => builder
+            .sector_with_device(GAMEPAK_FLASH.to_string(), GAMEPAK_SRAM_START, flash.clone())?
+            .mirror(GAMEPAK_SRAM_START, GAMEPAK_SRAM_MIRROR_END, GAMEPAK_FLASH_BANK_SIZE as u32)?
+            .timing(GAMEPAK_SRAM_START, wait_control.sram.clone())?,
+        Backup::Eeprom(eeprom) => builder
+            .sector_with_device(GAMEPAK_EEPROM.to_string(), GAMEPAK_EEPROM_START, eeprom.clone())?
+            .timing(GAMEPAK_EEPROM_START, wait_control.rom[2].clone())?,
+    };
+    let rom_ws2_end = match backup {
+        Backup::Eeprom(_) => GAMEPAK_EEPROM_START - 1,
+        _ => GAMEPAK_ROM_WS2_END,
+    };
+    let memory_bus = builder
+        .sector_with_device(BIOS.to_string(), BIOS_START, Rc::new(RefCell::new(bios)))?
         .sector_with_addresses(WRAM.to_string(), WRAM_ONBOARD_START, WRAM_ONBOARD_END)?
+        .mirror(WRAM_ONBOARD_START, WRAM_ONBOARD_MIRROR_END, WRAM_ONBOARD_SIZE as u32)?
         .sector_with_addresses(WRAM_ONCHIP.to_string(), WRAM_ONCHIP_START, WRAM_ONCHIP_END)?
-        .sector_with_addresses(IO_REGISTERS.to_string(), IO_REGISTERS_START, IO_REGISTERS_END)?
+        .mirror(WRAM_ONCHIP_START, WRAM_ONCHIP_MIRROR_END, WRAM_ONCHIP_SIZE as u32)?
+        .sector_with_device(IO_REGISTERS.to_string(), IO_REGISTERS_START, io_registers)?
+        .sector_with_device(INTERNAL_MEMORY_CONTROL.to_string(), INTERNAL_MEMORY_CONTROL_START, Rc::new(RefCell::new(MemoryControl::new(wait_control.clone()))))?
+        .sector_with_addresses(PALLETE_RAM.to_string(), PALLETE_RAM_START, PALLETE_RAM_END)?
+        .mirror(PALLETE_RAM_START, PALLETE_RAM_MIRROR_END, PALLETE_RAM_SIZE as u32)?
+        .sector_with_addresses(VRAM.to_string(), VRAM_START, VRAM_END)?
+        .mirror(VRAM_START, VRAM_MIRROR_END, VRAM_MIRROR_PERIOD as u32)?
+        .sector_with_addresses(OAM.to_string(), OAM_START, OAM_END)?
+        .mirror(OAM_START, OAM_MIRROR_END, OAM_SIZE as u32)?
+        .sector_with_addresses(GAMEPAK_ROM_WS0.to_string(), GAMEPAK_ROM_WS0_START, GAMEPAK_ROM_WS0_END)?
+        .alias(GAMEPAK_ROM_WS1.to_string(), GAMEPAK_ROM_WS1_START, GAMEPAK_ROM_WS0_START)?
+        .alias_until(GAMEPAK_ROM_WS2.to_string(), GAMEPAK_ROM_WS2_START, rom_ws2_end, GAMEPAK_ROM_WS0_START)?
+        .access(BIOS_START, BIOS_END, AccessFlags::IGNORE_WRITES)?
+        .access(GAMEPAK_ROM_WS0_START, GAMEPAK_ROM_WS0_END, AccessFlags::IGNORE_WRITES)?
+        .access(GAMEPAK_ROM_WS1_START, GAMEPAK_ROM_WS1_END, AccessFlags::IGNORE_WRITES)?
+        .access(GAMEPAK_ROM_WS2_START, rom_ws2_end, AccessFlags::IGNORE_WRITES)?
+        .access(PALLETE_RAM_START, PALLETE_RAM_END, AccessFlags::DUPLICATE_BYTE_WRITES)?
+        .access(VRAM_START, VRAM_OBJ_START - 1, AccessFlags::DUPLICATE_BYTE_WRITES)?
+        .access(VRAM_OBJ_START, VRAM_END, AccessFlags::IGNORE_BYTE_WRITES)?
+        .access(OAM_START, OAM_END, AccessFlags::IGNORE_BYTE_WRITES)?
+        .timing(WRAM_ONBOARD_START, wait_control.ewram.clone())?
+        .timing(PALLETE_RAM_START, halfword_bus())?
+        .timing(VRAM_START, halfword_bus())?
+        .timing(GAMEPAK_ROM_WS0_START, wait_control.rom[0].clone())?
+        .timing(GAMEPAK_ROM_WS1_START, wait_control.rom[1].clone())?
+        .timing(GAMEPAK_ROM_WS2_START, wait_control.rom[2].clone())?
+        .prefetch_buffer(PrefetchBuffer::new(GAMEPAK_ROM_WS0_START, GAMEPAK_ROM_WS2_END, wait_control.prefetch.clone()))
+        .open_bus()
         .build();
 
     Ok(memory_bus)
@@ -16,48 +74,45 @@ pub fn init_gba_memory_bus() -> Result<MemoryBus, MemoryError> {
 #[cfg(test)]
 mod tests {
 
-    use crate::gba::{BIOS_SIZE, IO_REGISTERS_SIZE, WRAM_ONBOARD_SIZE, WRAM_ONCHIP_SIZE};
+    use crate::memory::{write_memory, Bus};
 
     use super::*;
 
     #[test]
     fn test_gba_memory_bus() {
-        let expected_total_size = BIOS_SIZE + WRAM_ONBOARD_SIZE + WRAM_ONCHIP_SIZE + IO_REGISTERS_SIZE;
-        match init_gba_memory_bus() {
+        // The ROM is counted once for its three wait state regions
+        let expected_total_size = BIOS_SIZE + WRAM_ONBOARD_SIZE + WRAM_ONCHIP_SIZE + IO_REGISTERS_SIZE + INTERNAL_MEMORY_CONTROL_SIZE
+            + PALLETE_RAM_SIZE + VRAM_SIZE + OAM_SIZE + GAMEPAK_ROM_SIZE + GAMEPAK_SRAM_SIZE;
+        match init_gba_memory_bus(&GbaConfig::default()) {
             Ok(memory_bus) => {
-                match memory_bus.sector(BIOS_START) {
-                    Some(sector) => {
-                        assert_eq!(sector.size(), BIOS_SIZE);
-                    }
-                    None => {
-                        assert!(false);
-                    }
-                }
-
-                match memory_bus.sector(WRAM_ONBOARD_START) {
-                    Some(sector) => {
-                        assert_eq!(sector.size(), WRAM_ONBOARD_SIZE);
-                    }
-                    None => {
-                        assert!(false);
-                    }
-                }
-
-                match memory_bus.sector(WRAM_ONCHIP_START) {
-                    Some(sector) => {
-                        assert_eq!(sector.size(), WRAM_ONCHIP_SIZE);
-                    }
-                    None => {
-                        assert!(false);
-                    }
-                }
+                // Name, start, last address including mirrors, size
+                let expected_sectors = [
+                    (BIOS, BIOS_START, BIOS_END, BIOS_SIZE),
+                    (WRAM, WRAM_ONBOARD_START, WRAM_ONBOARD_MIRROR_END, WRAM_ONBOARD_SIZE),
+                    (WRAM_ONCHIP, WRAM_ONCHIP_START, WRAM_ONCHIP_MIRROR_END, WRAM_ONCHIP_SIZE),
+                    (IO_REGISTERS, IO_REGISTERS_START, IO_REGISTERS_END, IO_REGISTERS_SIZE),
+                    (INTERNAL_MEMORY_CONTROL, INTERNAL_MEMORY_CONTROL_START, INTERNAL_MEMORY_CONTROL_END, INTERNAL_MEMORY_CONTROL_SIZE),
+                    (PALLETE_RAM, PALLETE_RAM_START, PALLETE_RAM_MIRROR_END, PALLETE_RAM_SIZE),
+                    (VRAM, VRAM_START, VRAM_MIRROR_END, VRAM_SIZE),
+                    (OAM, OAM_START, OAM_MIRROR_END, OAM_SIZE),
+                    (GAMEPAK_ROM_WS0, GAMEPAK_ROM_WS0_START, GAMEPAK_ROM_WS0_END, GAMEPAK_ROM_SIZE),
+                    (GAMEPAK_ROM_WS1, GAMEPAK_ROM_WS1_START, GAMEPAK_ROM_WS1_END, GAMEPAK_ROM_SIZE),
+                    (GAMEPAK_ROM_WS2, GAMEPAK_ROM_WS2_START, GAMEPAK_ROM_WS2_END, GAMEPAK_ROM_SIZE),
+                    (GAMEPAK_SRAM, GAMEPAK_SRAM_START, GAMEPAK_SRAM_MIRROR_END, GAMEPAK_SRAM_SIZE),
+                ];
+                assert_eq!(memory_bus.sectors().len(), expected_sectors.len());
 
-                match memory_bus.sector(IO_REGISTERS_START) {
-                    Some(sector) => {
-                        assert_eq!(sector.size(), IO_REGISTERS_SIZE);
-                    }
-                    None => {
-                        assert!(false);
+                for (name, start_address, last_address, size) in expected_sectors {
+                    for address in [start_address, last_address] {
+                        match memory_bus.sector(address) {
+                            Some(sector) => {
+                                assert_eq!(sector.name, name);
+                                assert_eq!(sector.size(), size);
+                            }
+                            None => {
+                                assert!(false, "{} is not mapped at 0x{:08X}", name, address);
+                            }
+                        }
                     }
                 }
 
@@ -65,16 +120,9 @@ mod tests {
 
                 // check invalid addresses
                 // addresses that are not in use according to GBA spec
-                let invalid_addresses = [0x00004000, 0x01FFFFFF, 0x02040000, 0x02FFFFFF, 0x03008000, 0x03FFFFFF, 0x04000400, 0x04FFFFFF];
+                let invalid_addresses = [0x00004000, 0x01FFFFFF, 0x04000400, 0x04FFFFFF, 0x10000000, 0xFFFFFFFF];
                 for invalid_address in invalid_addresses.iter() {
-                    match memory_bus.sector(*invalid_address) {
-                        Some(_) => {
-                            assert!(false);
-                        }
-                        None => {
-                            assert!(true);
-                        }
-                    }
+                    assert!(memory_bus.sector(*invalid_address).is_none());
                 }
             }
             Err(e) => {
@@ -83,4 +131,92 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_gba_memory_bus_mirrors() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+
+        memory_bus.write_u32(WRAM_ONBOARD_START + 0x10, 0x11223344).unwrap();
+        assert_eq!(memory_bus.read_u32(0x02040010).unwrap(), 0x11223344);
+        assert_eq!(memory_bus.read_u32(0x02FC0010).unwrap(), 0x11223344);
+
+        // IWRAM end mirrors: the BIOS reads the IRQ handler from 0x03FFFFFC
+        memory_bus.write_u32(0x03FFFFFC, 0x03000100).unwrap();
+        assert_eq!(memory_bus.read_u32(0x03007FFC).unwrap(), 0x03000100);
+    }
+
+    #[test]
+    fn test_gba_memory_bus_display_mirrors() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+
+        // VRAM 0x06010000-0x06017FFF shows up again at 0x06018000
+        memory_bus.write_u16(VRAM_START + 0x10000, 0x7FFF).unwrap();
+        assert_eq!(memory_bus.read_u16(VRAM_START + 0x18000).unwrap(), 0x7FFF);
+        assert_eq!(memory_bus.read_u16(VRAM_START + 0x20000 + 0x10000).unwrap(), 0x7FFF);
+
+        memory_bus.write_u16(PALLETE_RAM_START + 2, 0x001F).unwrap();
+        assert_eq!(memory_bus.read_u16(PALLETE_RAM_START + 0x402).unwrap(), 0x001F);
+        memory_bus.write_u16(OAM_START + 6, 0x1234).unwrap();
+        assert_eq!(memory_bus.read_u16(OAM_MIRROR_END - 0x3F9).unwrap(), 0x1234);
+
+        // One ROM behind the three wait state regions, loaded like a cartridge
+        write_memory(&memory_bus, GAMEPAK_ROM_WS0_START + 0xC0, &0xEA00002Eu32.to_le_bytes()).unwrap();
+        assert_eq!(memory_bus.read_u32(GAMEPAK_ROM_WS1_START + 0xC0).unwrap(), 0xEA00002E);
+        assert_eq!(memory_bus.read_u32(GAMEPAK_ROM_WS2_START + 0xC0).unwrap(), 0xEA00002E);
+
+        memory_bus.write_u8(GAMEPAK_SRAM_START + 1, 0x5A).unwrap();
+        assert_eq!(memory_bus.read_u8(0x0FFF0001).unwrap(), 0x5A);
+    }
+
+    #[test]
+    fn test_gba_memory_bus_open_bus() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+
+        // ARM code: the opcode fetched last
+        memory_bus.write_u32(WRAM_ONCHIP_START, 0xE3A00001).unwrap();
+        memory_bus.fetch_u32(WRAM_ONCHIP_START).unwrap();
+        assert_eq!(memory_bus.read_u32(0x00004000).unwrap(), 0xE3A00001);
+        assert_eq!(memory_bus.read_u8(0x10000003).unwrap(), 0xE3);
+
+        // THUMB code in ROM: the opcode twice
+        write_memory(&memory_bus, GAMEPAK_ROM_WS0_START, &0x46C02001u32.to_le_bytes()).unwrap();
+        memory_bus.fetch_u16(GAMEPAK_ROM_WS0_START + 2).unwrap();
+        assert_eq!(memory_bus.read_u32(0x01FFFFFC).unwrap(), 0x46C046C0);
+
+        // THUMB code in on-chip WRAM: the last two opcodes
+        memory_bus.write_u32(WRAM_ONCHIP_START + 4, 0x46C02001).unwrap();
+        memory_bus.fetch_u16(WRAM_ONCHIP_START + 4).unwrap();
+        memory_bus.fetch_u16(WRAM_ONCHIP_START + 6).unwrap();
+        assert_eq!(memory_bus.read_u32(0x04000400).unwrap(), 0x46C02001);
+        memory_bus.write_u32(0x04000400, 0).unwrap();
+        assert_eq!(memory_bus.read_u16(0x04000402).unwrap(), 0x46C0);
+    }
+
+    #[test]
+    fn test_gba_memory_bus_access_rules() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+
+        // Stray writes leave the BIOS and ROM alone
+        let bios = memory_bus.read_u32(BIOS_START + 8).unwrap();
+        memory_bus.write_u32(BIOS_START + 8, 0).unwrap();
+        assert_eq!(memory_bus.read_u32(BIOS_START + 8).unwrap(), bios);
+        write_memory(&memory_bus, GAMEPAK_ROM_WS0_START, &[0x2E, 0x00, 0x00, 0xEA]).unwrap();
+        memory_bus.write_u8(GAMEPAK_ROM_WS2_START, 0).unwrap();
+        assert_eq!(memory_bus.read_u32(GAMEPAK_ROM_WS0_START).unwrap(), 0xEA00002E);
+
+        // Palette and BG VRAM take the byte in both halves
+        memory_bus.write_u8(PALLETE_RAM_START + 1, 0x1F).unwrap();
+        assert_eq!(memory_bus.read_u16(PALLETE_RAM_START).unwrap(), 0x1F1F);
+        memory_bus.write_u8(VRAM_START + 0x20000 + 4, 0x11).unwrap();
+        assert_eq!(memory_bus.read_u16(VRAM_START + 4).unwrap(), 0x1111);
+
+        // OBJ VRAM and OAM drop it, also through the VRAM mirror of the upper 32K
+        memory_bus.write_u8(VRAM_OBJ_START, 0x11).unwrap();
+        memory_bus.write_u8(VRAM_OBJ_START + 0x8000 + 2, 0x11).unwrap();
+        memory_bus.write_u8(OAM_START, 0x11).unwrap();
+        assert_eq!(memory_bus.read_u32(VRAM_OBJ_START).unwrap(), 0);
+        assert_eq!(memory_bus.read_u16(OAM_START).unwrap(), 0);
+        memory_bus.write_u16(OAM_START, 0x1234).unwrap();
+        assert_eq!(memory_bus.read_u16(OAM_START).unwrap(), 0x1234);
+    }
+}
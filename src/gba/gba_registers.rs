@@ -4,7 +4,10 @@ use super::{REGISTER_0, REGISTER_1, REGISTER_10, REGISTER_11, REGISTER_12, REGIS
 pub fn init_gba_registers() -> Result<RegisterMap, RegisterError> {
     let mut register_map_builder = RegisterMap::builder();
 
-    let cpsr = CPSRCell::new(CPSR::default());
+    // Reset state: Supervisor mode, ARM state, IRQs and FIQs disabled
+    let mut reset_cpsr = CPSR::I | CPSR::F;
+    reset_cpsr.set_mode(Mode::SUPERVISOR);
+    let cpsr = CPSRCell::new(reset_cpsr);
     let spsr = CPSRCell::new(CPSR::default());
 
     // General purpose registers: r0 - r14
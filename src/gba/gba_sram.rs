@@ -0,0 +1,128 @@
+use super::{BackupError, BackupMemory, GAMEPAK_SRAM_SIZE};
+use crate::memory::{MemoryDevice, MemoryError};
+
+// 32 KB battery-backed SRAM on an 8-bit bus. Wider reads repeat the byte in every lane and
+// wider writes only store the low byte.
+#[derive(Debug, Clone)]
+pub struct Sram {
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+impl Default for Sram {
+    fn default() -> Self {
+        // Erased cells read as 0xFF
+        Sram { data: vec![0xFF; GAMEPAK_SRAM_SIZE], dirty: false }
+    }
+}
+
+impl Sram {
+    pub fn new() -> Sram {
+        Sram::default()
+    }
+}
+
+impl MemoryDevice for Sram {
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError> {
+        self.peek_u8(offset)
+    }
+
+    fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError> {
+        self.data.get(offset as usize).copied().ok_or(MemoryError::OutOfBounds(offset))
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) -> Result<(), MemoryError> {
+        let byte = self.data.get_mut(offset as usize).ok_or(MemoryError::OutOfBounds(offset))?;
+        *byte = value;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn read_u16(&mut self, offset: u32) -> Result<u16, MemoryError> {
+        Ok(self.read_u8(offset)? as u16 * 0x0101)
+    }
+
+    fn read_u32(&mut self, offset: u32) -> Result<u32, MemoryError> {
+        Ok(self.read_u8(offset)? as u32 * 0x01010101)
+    }
+
+    fn peek_u16(&self, offset: u32) -> Result<u16, MemoryError> {
+        Ok(self.peek_u8(offset)? as u16 * 0x0101)
+    }
+
+    fn peek_u32(&self, offset: u32) -> Result<u32, MemoryError> {
+        Ok(self.peek_u8(offset)? as u32 * 0x01010101)
+    }
+
+    fn write_u16(&mut self, offset: u32, value: u16) -> Result<(), MemoryError> {
+        self.write_u8(offset, value as u8)
+    }
+
+    fn write_u32(&mut self, offset: u32, value: u32) -> Result<(), MemoryError> {
+        self.write_u8(offset, value as u8)
+    }
+}
+
+impl BackupMemory for Sram {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), BackupError> {
+        if data.len() != self.data.len() {
+            return Err(BackupError::InvalidSize(data.len()));
+        }
+        self.data.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{gba::{init_gba_memory_bus, GbaConfig, GAMEPAK_SRAM_START}, memory::Bus};
+
+    #[test]
+    fn test_sram_byte_bus() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START).unwrap(), 0xFF);
+
+        memory_bus.write_u8(GAMEPAK_SRAM_START + 3, 0x5A).unwrap();
+        memory_bus.write_u32(GAMEPAK_SRAM_START + 4, 0x12345678).unwrap();
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 4).unwrap(), 0x78);
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 5).unwrap(), 0xFF);
+        assert_eq!(memory_bus.read_u16(GAMEPAK_SRAM_START + 4).unwrap(), 0x7878);
+        assert_eq!(memory_bus.read_u32(GAMEPAK_SRAM_START + 4).unwrap(), 0x78787878);
+
+        // 32 KB repeated up to 0x0FFFFFFF
+        assert_eq!(memory_bus.read_u8(GAMEPAK_SRAM_START + 0x8003).unwrap(), 0x5A);
+        assert_eq!(memory_bus.read_u8(0x0FFF8003).unwrap(), 0x5A);
+    }
+
+    #[test]
+    fn test_sram_dirty() {
+        let mut sram = Sram::new();
+        assert!(!sram.is_dirty());
+        sram.write_u8(0x7FFF, 1).unwrap();
+        assert!(sram.is_dirty());
+        assert_eq!(sram.write_u8(0x8000, 1), Err(MemoryError::OutOfBounds(0x8000)));
+
+        sram.clear_dirty();
+        assert_eq!(sram.load(&[0; 16]), Err(BackupError::InvalidSize(16)));
+        sram.load(&vec![0x42; GAMEPAK_SRAM_SIZE]).unwrap();
+        assert_eq!(sram.read_u8(0x1234).unwrap(), 0x42);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{cell::Cell, rc::Rc};
+
+use super::INTERNAL_MEMORY_CONTROL_SIZE;
+use crate::memory::{AccessTiming, AccessWidth, MemoryDevice, MemoryError, SharedTiming};
+
+// Wait states selected by the WAITCNT fields
+const NON_SEQUENTIAL_WAIT_STATES: [u32; 4] = [4, 3, 2, 8];
+const SEQUENTIAL_WAIT_STATES: [[u32; 2]; 3] = [[2, 1], [4, 1], [8, 1]]; // Wait state 0, 1, 2
+
+// WAITCNT bit 14 enables the Game Pak prefetch buffer
+const PREFETCH_ENABLE: u16 = 1 << 14;
+
+// Internal memory control after reset, EWRAM at 2 wait states
+pub const INTERNAL_MEMORY_CONTROL_RESET: u32 = 0x0D000020;
+
+// Timings of the regions with configurable wait states, shared with the memory bus sectors
+#[derive(Debug, Clone)]
+pub struct WaitControl {
+    pub sram: SharedTiming,
+    pub rom: [SharedTiming; 3], // Wait state 0, 1, 2
+    pub ewram: SharedTiming,
+    pub prefetch: Rc<Cell<bool>>,
+}
+
+impl Default for WaitControl {
+    fn default() -> Self {
+        let wait_control = WaitControl {
+            sram: AccessTiming::new(AccessWidth::Byte, 0, 0).shared(),
+            rom: [0, 1, 2].map(|_| AccessTiming::new(AccessWidth::Halfword, 0, 0).shared()),
+            ewram: AccessTiming::new(AccessWidth::Halfword, 0, 0).shared(),
+            prefetch: Rc::new(Cell::new(false)),
+        };
+        wait_control.set_waitcnt(0);
+        wait_control.set_memory_control(INTERNAL_MEMORY_CONTROL_RESET);
+        wait_control
+    }
+}
+
+impl WaitControl {
+    pub fn new() -> WaitControl {
+        WaitControl::default()
+    }
+
+    // WAITCNT: bits 1-0 SRAM, then per ROM wait state 2 bits non-sequential and 1 bit sequential
+    pub fn set_waitcnt(&self, value: u16) {
+        self.prefetch.set((value & PREFETCH_ENABLE) != 0);
+
+        let sram = NON_SEQUENTIAL_WAIT_STATES[(value & 0b11) as usize];
+        self.sram.set(AccessTiming::new(AccessWidth::Byte, sram, sram));
+
+        for (wait_state, timing) in self.rom.iter().enumerate() {
+            let fields = value >> (2 + wait_state * 3);
+            let non_sequential = NON_SEQUENTIAL_WAIT_STATES[(fields & 0b11) as usize];
+            let sequential = SEQUENTIAL_WAIT_STATES[wait_state][((fields >> 2) & 1) as usize];
+            timing.set(AccessTiming::new(AccessWidth::Halfword, non_sequential, sequential));
+        }
+    }
+
+    // Bits 27-24 set the EWRAM wait states to 15 minus their value
+    pub fn set_memory_control(&self, value: u32) {
+        let wait_states = 15 - ((value >> 24) & 0xF);
+        self.ewram.set(AccessTiming::new(AccessWidth::Halfword, wait_states, wait_states));
+    }
+}
+
+// Internal memory control register at 0x04000800
+#[derive(Debug)]
+pub struct MemoryControl {
+    value: u32,
+    wait_control: WaitControl,
+}
+
+impl MemoryControl {
+    pub fn new(wait_control: WaitControl) -> MemoryControl {
+        wait_control.set_memory_control(INTERNAL_MEMORY_CONTROL_RESET);
+        MemoryControl { value: INTERNAL_MEMORY_CONTROL_RESET, wait_control }
+    }
+}
+
+impl MemoryDevice for MemoryControl {
+    fn size(&self) -> usize {
+        INTERNAL_MEMORY_CONTROL_SIZE
+    }
+
+    fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError> {
+        self.peek_u8(offset)
+    }
+
+    fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError> {
+        if offset as usize >= INTERNAL_MEMORY_CONTROL_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        Ok((self.value >> (offset * 8)) as u8)
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) -> Result<(), MemoryError> {
+        if offset as usize >= INTERNAL_MEMORY_CONTROL_SIZE {
+            return Err(MemoryError::OutOfBounds(offset));
+        }
+        let shift = offset * 8;
+        self.value = (self.value & !(0xFF << shift)) | ((value as u32) << shift);
+        self.wait_control.set_memory_control(self.value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{gba::{init_gba_memory_bus, GbaConfig, GAMEPAK_ROM_WS0_START, GAMEPAK_ROM_WS2_START, GAMEPAK_SRAM_START, INTERNAL_MEMORY_CONTROL_START, IO_REGISTERS_START, IO_WAITCNT, VRAM_START, WRAM_ONBOARD_START, WRAM_ONCHIP_START}, memory::Bus};
+
+    #[test]
+    fn test_wait_control_reset() {
+        let wait_control = WaitControl::new();
+        assert_eq!(wait_control.sram.get(), AccessTiming::new(AccessWidth::Byte, 4, 4));
+        assert_eq!(wait_control.rom[0].get(), AccessTiming::new(AccessWidth::Halfword, 4, 2));
+        assert_eq!(wait_control.rom[1].get(), AccessTiming::new(AccessWidth::Halfword, 4, 4));
+        assert_eq!(wait_control.rom[2].get(), AccessTiming::new(AccessWidth::Halfword, 4, 8));
+        assert_eq!(wait_control.ewram.get(), AccessTiming::new(AccessWidth::Halfword, 2, 2));
+
+        // The setting most games use: SRAM 8, WS0 3/1, WS1 4/4, WS2 8/8 (0x4317)
+        wait_control.set_waitcnt(0x4317);
+        assert_eq!(wait_control.sram.get().non_sequential, 8);
+        assert_eq!(wait_control.rom[0].get(), AccessTiming::new(AccessWidth::Halfword, 3, 1));
+        assert!(wait_control.prefetch.get());
+    }
+
+    // Cycles of 8 THUMB opcodes from ROM, each followed by a load from on-chip WRAM
+    fn thumb_loop_cycles(waitcnt: u16) -> u64 {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+        memory_bus.write_u16(IO_REGISTERS_START + IO_WAITCNT, waitcnt).unwrap();
+        let start = memory_bus.cycle_count();
+        for i in 0..8 {
+            memory_bus.fetch_u16(GAMEPAK_ROM_WS0_START + i * 2).unwrap();
+            memory_bus.read_u32(WRAM_ONCHIP_START).unwrap();
+            memory_bus.idle(1);
+        }
+        memory_bus.cycle_count() - start
+    }
+
+    #[test]
+    fn test_gba_prefetch_buffer() {
+        // Without prefetch the loads make every opcode fetch non-sequential, 8 * 4 ROM cycles
+        // and 16 for the loads and internal cycles
+        assert_eq!(thumb_loop_cycles(0x0014), 8 * 4 + 16);
+        // With it each opcode after the first is buffered during the 2 free cycles
+        assert_eq!(thumb_loop_cycles(0x4014), 4 + 7 + 16);
+
+        // Data reads from the ROM stop prefetching
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+        memory_bus.write_u16(IO_REGISTERS_START + IO_WAITCNT, 0x4014).unwrap();
+        memory_bus.fetch_u16(GAMEPAK_ROM_WS0_START).unwrap();
+        memory_bus.idle(10);
+        assert_eq!(memory_bus.prefetch_buffer().unwrap().buffered(), 5);
+        memory_bus.read_u16(GAMEPAK_ROM_WS0_START + 0x100).unwrap();
+        assert_eq!(memory_bus.prefetch_buffer().unwrap().buffered(), 0);
+
+        // WAITCNT rewritten while a halfword at 8 wait states is fetched, at 1 wait state it is
+        // ready already
+        memory_bus.write_u16(IO_REGISTERS_START + IO_WAITCNT, 0x4000).unwrap();
+        memory_bus.fetch_u16(GAMEPAK_ROM_WS2_START).unwrap();
+        memory_bus.idle(4);
+        memory_bus.write_u16(IO_REGISTERS_START + IO_WAITCNT, 0x4400).unwrap();
+        let cycles = memory_bus.cycle_count();
+        memory_bus.fetch_u16(GAMEPAK_ROM_WS2_START + 2).unwrap();
+        assert_eq!(memory_bus.cycle_count() - cycles, 1);
+    }
+
+    #[test]
+    fn test_gba_access_cycles() {
+        let memory_bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+
+        assert_eq!(memory_bus.cycles(WRAM_ONCHIP_START, AccessWidth::Word, false), 1);
+        assert_eq!(memory_bus.cycles(WRAM_ONBOARD_START, AccessWidth::Word, false), 6);
+        assert_eq!(memory_bus.cycles(VRAM_START, AccessWidth::Word, false), 2);
+        assert_eq!(memory_bus.cycles(GAMEPAK_ROM_WS0_START, AccessWidth::Word, false), 8);
+        assert_eq!(memory_bus.cycles(GAMEPAK_ROM_WS0_START, AccessWidth::Halfword, true), 3);
+        assert_eq!(memory_bus.cycles(GAMEPAK_SRAM_START, AccessWidth::Byte, false), 5);
+
+        // WAITCNT written through the bus
+        memory_bus.write_u16(IO_REGISTERS_START + IO_WAITCNT, 0x4317).unwrap();
+        assert_eq!(memory_bus.read_u16(IO_REGISTERS_START + IO_WAITCNT).unwrap(), 0x4317);
+        assert_eq!(memory_bus.cycles(GAMEPAK_ROM_WS0_START, AccessWidth::Word, false), 6);
+        assert_eq!(memory_bus.cycles(GAMEPAK_ROM_WS2_START, AccessWidth::Halfword, true), 9);
+        assert_eq!(memory_bus.cycles(GAMEPAK_SRAM_START, AccessWidth::Byte, false), 9);
+
+        // EWRAM at 1 wait state
+        assert_eq!(memory_bus.read_u32(INTERNAL_MEMORY_CONTROL_START).unwrap(), INTERNAL_MEMORY_CONTROL_RESET);
+        memory_bus.write_u32(INTERNAL_MEMORY_CONTROL_START, 0x0E000020).unwrap();
+        assert_eq!(memory_bus.cycles(WRAM_ONBOARD_START, AccessWidth::Halfword, false), 2);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{cpu::{swi_read_register, CpuError}, memory::Bus, register::RegisterSet};
+
+// BIOS sine table, sin(i * 2pi / 256) in 1.14 fixed point truncated towards zero
+const SIN_LUT: [i16; 256] = [
+     0x0000,  0x0192,  0x0323,  0x04B5,  0x0645,  0x07D5,  0x0964,  0x0AF1,
+     0x0C7C,  0x0E05,  0x0F8C,  0x1111,  0x1294,  0x1413,  0x158F,  0x1708,
+     0x187D,  0x19EF,  0x1B5D,  0x1CC6,  0x1E2B,  0x1F8B,  0x20E7,  0x223D,
+     0x238E,  0x24DA,  0x261F,  0x275F,  0x2899,  0x29CD,  0x2AFA,  0x2C21,
+     0x2D41,  0x2E5A,  0x2F6B,  0x3076,  0x3179,  0x3274,  0x3367,  0x3453,
+     0x3536,  0x3612,  0x36E5,  0x37AF,  0x3871,  0x392A,  0x39DA,  0x3A82,
+     0x3B20,  0x3BB6,  0x3C42,  0x3CC5,  0x3D3E,  0x3DAE,  0x3E14,  0x3E71,
+     0x3EC5,  0x3F0E,  0x3F4E,  0x3F84,  0x3FB1,  0x3FD3,  0x3FEC,  0x3FFB,
+     0x4000,  0x3FFB,  0x3FEC,  0x3FD3,  0x3FB1,  0x3F84,  0x3F4E,  0x3F0E,
+     0x3EC5,  0x3E71,  0x3E14,  0x3DAE,  0x3D3E,  0x3CC5,  0x3C42,  0x3BB6,
+     0x3B20,  0x3A82,  0x39DA,  0x392A,  0x3871,  0x37AF,  0x36E5,  0x3612,
+     0x3536,  0x3453,  0x3367,  0x3274,  0x3179,  0x3076,  0x2F6B,  0x2E5A,
+     0x2D41,  0x2C21,  0x2AFA,  0x29CD,  0x2899,  0x275F,  0x261F,  0x24DA,
+     0x238E,  0x223D,  0x20E7,  0x1F8B,  0x1E2B,  0x1CC6,  0x1B5D,  0x19EF,
+     0x187D,  0x1708,  0x158F,  0x1413,  0x1294,  0x1111,  0x0F8C,  0x0E05,
+     0x0C7C,  0x0AF1,  0x0964,  0x07D5,  0x0645,  0x04B5,  0x0323,  0x0192,
+     0x0000, -0x0192, -0x0323, -0x04B5, -0x0645, -0x07D5, -0x0964, -0x0AF1,
+    -0x0C7C, -0x0E05, -0x0F8C, -0x1111, -0x1294, -0x1413, -0x158F, -0x1708,
+    -0x187D, -0x19EF, -0x1B5D, -0x1CC6, -0x1E2B, -0x1F8B, -0x20E7, -0x223D,
+    -0x238E, -0x24DA, -0x261F, -0x275F, -0x2899, -0x29CD, -0x2AFA, -0x2C21,
+    -0x2D41, -0x2E5A, -0x2F6B, -0x3076, -0x3179, -0x3274, -0x3367, -0x3453,
+    -0x3536, -0x3612, -0x36E5, -0x37AF, -0x3871, -0x392A, -0x39DA, -0x3A82,
+    -0x3B20, -0x3BB6, -0x3C42, -0x3CC5, -0x3D3E, -0x3DAE, -0x3E14, -0x3E71,
+    -0x3EC5, -0x3F0E, -0x3F4E, -0x3F84, -0x3FB1, -0x3FD3, -0x3FEC, -0x3FFB,
+    -0x4000, -0x3FFB, -0x3FEC, -0x3FD3, -0x3FB1, -0x3F84, -0x3F4E, -0x3F0E,
+    -0x3EC5, -0x3E71, -0x3E14, -0x3DAE, -0x3D3E, -0x3CC5, -0x3C42, -0x3BB6,
+    -0x3B20, -0x3A82, -0x39DA, -0x392A, -0x3871, -0x37AF, -0x36E5, -0x3612,
+    -0x3536, -0x3453, -0x3367, -0x3274, -0x3179, -0x3076, -0x2F6B, -0x2E5A,
+    -0x2D41, -0x2C21, -0x2AFA, -0x29CD, -0x2899, -0x275F, -0x261F, -0x24DA,
+    -0x238E, -0x223D, -0x20E7, -0x1F8B, -0x1E2B, -0x1CC6, -0x1B5D, -0x19EF,
+    -0x187D, -0x1708, -0x158F, -0x1413, -0x1294, -0x1111, -0x0F8C, -0x0E05,
+    -0x0C7C, -0x0AF1, -0x0964, -0x07D5, -0x0645, -0x04B5, -0x0323, -0x0192,
+];
+
+fn sin_lut(index: u8) -> i32 {
+    SIN_LUT[index as usize] as i32
+}
+
+// Sine and cosine of a BIOS angle, 0x10000 is the full circle and only the high byte is used
+fn sin_cos(angle: u16) -> (i32, i32) {
+    let index = (angle >> 8) as u8;
+    (sin_lut(index), sin_lut(index.wrapping_add(0x40)))
+}
+
+// Scaled and rotated matrix, (pa, pb, pc, pd)
+fn affine_matrix(scale_x: i16, scale_y: i16, angle: u16) -> (i16, i16, i16, i16) {
+    let (sin, cos) = sin_cos(angle);
+    let (scale_x, scale_y) = (scale_x as i32, scale_y as i32);
+    (
+        ((scale_x * cos) >> 14) as i16,
+        (-((scale_x * sin) >> 14)) as i16,
+        ((scale_y * sin) >> 14) as i16,
+        ((scale_y * cos) >> 14) as i16,
+    )
+}
+
+fn read_i16<B: Bus>(bus: &B, address: u32) -> Result<i16, CpuError> {
+    bus.read_u16(address).map(|value| value as i16).map_err(CpuError::MemoryError)
+}
+
+fn write_i16<B: Bus>(bus: &B, address: u32, value: i16) -> Result<(), CpuError> {
+    bus.write_u16(address, value as u16).map_err(CpuError::MemoryError)
+}
+
+// Background rotation/scaling parameters. R0 source, R1 destination, R2 count.
+// Source (20 bytes): s32 center x, s32 center y (8.8 texture), s16 display x, s16 display y, s16 scale x, s16 scale y, u16 angle
+// Destination (16 bytes): s16 pa, pb, pc, pd, s32 start x, s32 start y
+pub fn bg_affine_set<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let mut source = swi_read_register(register_set, 0)?;
+    let mut destination = swi_read_register(register_set, 1)?;
+    let count = swi_read_register(register_set, 2)?;
+
+    for _ in 0..count {
+        let origin_x = bus.read_u32(source).map_err(CpuError::MemoryError)? as i32;
+        let origin_y = bus.read_u32(source.wrapping_add(4)).map_err(CpuError::MemoryError)? as i32;
+        let center_x = read_i16(bus, source.wrapping_add(8))? as i32;
+        let center_y = read_i16(bus, source.wrapping_add(10))? as i32;
+        let scale_x = read_i16(bus, source.wrapping_add(12))?;
+        let scale_y = read_i16(bus, source.wrapping_add(14))?;
+        let angle = bus.read_u16(source.wrapping_add(16)).map_err(CpuError::MemoryError)?;
+
+        let (pa, pb, pc, pd) = affine_matrix(scale_x, scale_y, angle);
+        let start_x = origin_x.wrapping_sub((pa as i32 * center_x).wrapping_add(pb as i32 * center_y));
+        let start_y = origin_y.wrapping_sub((pc as i32 * center_x).wrapping_add(pd as i32 * center_y));
+
+        write_i16(bus, destination, pa)?;
+        write_i16(bus, destination.wrapping_add(2), pb)?;
+        write_i16(bus, destination.wrapping_add(4), pc)?;
+        write_i16(bus, destination.wrapping_add(6), pd)?;
+        bus.write_u32(destination.wrapping_add(8), start_x as u32).map_err(CpuError::MemoryError)?;
+        bus.write_u32(destination.wrapping_add(12), start_y as u32).map_err(CpuError::MemoryError)?;
+
+        source = source.wrapping_add(20);
+        destination = destination.wrapping_add(16);
+    }
+    Ok(())
+}
+
+// Sprite rotation/scaling parameters. R0 source, R1 destination, R2 count, R3 destination stride
+// (2 for consecutive halfwords, 8 for the OAM). Source (8 bytes): s16 scale x, s16 scale y, u16 angle.
+pub fn obj_affine_set<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let mut source = swi_read_register(register_set, 0)?;
+    let mut destination = swi_read_register(register_set, 1)?;
+    let count = swi_read_register(register_set, 2)?;
+    let stride = swi_read_register(register_set, 3)?;
+
+    for _ in 0..count {
+        let scale_x = read_i16(bus, source)?;
+        let scale_y = read_i16(bus, source.wrapping_add(2))?;
+        let angle = bus.read_u16(source.wrapping_add(4)).map_err(CpuError::MemoryError)?;
+
+        let (pa, pb, pc, pd) = affine_matrix(scale_x, scale_y, angle);
+        for (k, value) in [pa, pb, pc, pd].into_iter().enumerate() {
+            write_i16(bus, destination.wrapping_add((k as u32).wrapping_mul(stride)), value)?;
+        }
+
+        source = source.wrapping_add(8);
+        destination = destination.wrapping_add(stride.wrapping_mul(4));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{memory::FlatBus, register::RegisterCell};
+
+    fn register_set(values: [u32; 4]) -> RegisterSet {
+        let mut builder = RegisterSet::builder();
+        for (register, value) in values.iter().enumerate() {
+            builder.with_register(register as u8, RegisterCell::new(*value)).unwrap();
+        }
+        builder.build()
+    }
+
+    fn read_matrix(bus: &FlatBus, address: u32, stride: u32) -> [i16; 4] {
+        [0, 1, 2, 3].map(|k| bus.read_u16(address + k * stride).unwrap() as i16)
+    }
+
+    #[test]
+    fn test_sin_cos() {
+        assert_eq!(sin_cos(0), (0, 0x4000));
+        assert_eq!(sin_cos(0x4000), (0x4000, 0));
+        assert_eq!(sin_cos(0x80FF), (0, -0x4000));
+        assert_eq!(sin_cos(0xC000), (-0x4000, 0));
+
+        // Between the axes the table is truncated, not rounded
+        assert_eq!(sin_cos(0x0200), (0x0323, 0x3FEC));
+        assert_eq!(sin_cos(0x0400), (0x0645, 0x3FB1));
+        assert_eq!(sin_cos(0x05FF), (0x07D5, 0x3F84));
+        assert_eq!(sin_cos(0x2000), (0x2D41, 0x2D41));
+        assert_eq!(sin_cos(0x8200), (-0x0323, -0x3FEC));
+        assert_eq!(sin_cos(0xE000), (-0x2D41, 0x2D41));
+    }
+
+    #[test]
+    fn test_obj_affine_set() {
+        let bus = FlatBus::new();
+        // Identity, then 90 degrees at double size
+        bus.load(0x02000000, &[0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0, 0]);
+        bus.load(0x02000008, &[0x80, 0x00, 0x80, 0x00, 0x00, 0x40, 0, 0]);
+
+        obj_affine_set(&register_set([0x02000000, 0x07000006, 2, 8]), &bus).unwrap();
+        assert_eq!(read_matrix(&bus, 0x07000006, 8), [0x100, 0, 0, 0x100]);
+        assert_eq!(read_matrix(&bus, 0x07000026, 8), [0, -0x80, 0x80, 0]);
+
+        // 2.8 degrees at scale 127.996, a rounded table would give 1607 for the sine terms
+        bus.load(0x02000010, &[0xFF, 0x7F, 0xFF, 0x7F, 0x00, 0x02, 0, 0]);
+        obj_affine_set(&register_set([0x02000010, 0x07000046, 1, 2]), &bus).unwrap();
+        assert_eq!(read_matrix(&bus, 0x07000046, 2), [32727, -1605, 1605, 32727]);
+    }
+
+    #[test]
+    fn test_bg_affine_set() {
+        let bus = FlatBus::new();
+        bus.write_u32(0x02000000, 0x1000).unwrap(); // Texture center x
+        bus.write_u32(0x02000004, 0x2000).unwrap(); // Texture center y
+        bus.write_u16(0x02000008, 8).unwrap(); // Display center x
+        bus.write_u16(0x0200000A, 4).unwrap(); // Display center y
+        bus.write_u16(0x0200000C, 0x100).unwrap();
+        bus.write_u16(0x0200000E, 0x100).unwrap();
+        bus.write_u16(0x02000010, 0x4000).unwrap();
+
+        bg_affine_set(&register_set([0x02000000, 0x04000020, 1, 0]), &bus).unwrap();
+        assert_eq!(read_matrix(&bus, 0x04000020, 2), [0, -0x100, 0x100, 0]);
+        assert_eq!(bus.read_u32(0x04000028).unwrap() as i32, 0x1000 + 0x100 * 4);
+        assert_eq!(bus.read_u32(0x0400002C).unwrap() as i32, 0x2000 - 0x100 * 8);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{cpu::{swi_read_register, swi_write_register, CpuError}, register::RegisterSet};
+
+// Value GetBiosChecksum returns on a GBA
+pub const GBA_BIOS_CHECKSUM: u32 = 0xBAAE187F;
+
+// Signed division, R0 / R1 -> R0 quotient, R1 remainder, R3 absolute quotient
+pub fn div(register_set: &RegisterSet) -> Result<(), CpuError> {
+    let numerator = swi_read_register(register_set, 0)? as i32;
+    let denominator = swi_read_register(register_set, 1)? as i32;
+    write_division(register_set, numerator, denominator)
+}
+
+// Div with the operands swapped: R1 / R0
+pub fn div_arm(register_set: &RegisterSet) -> Result<(), CpuError> {
+    let denominator = swi_read_register(register_set, 0)? as i32;
+    let numerator = swi_read_register(register_set, 1)? as i32;
+    write_division(register_set, numerator, denominator)
+}
+
+fn write_division(register_set: &RegisterSet, numerator: i32, denominator: i32) -> Result<(), CpuError> {
+    let (quotient, remainder, absolute) = match denominator {
+        // The BIOS never returns for |numerator| > 1, this is what it leaves for 0 and +-1
+        0 => (if numerator < 0 { -1 } else { 1 }, numerator, 1),
+        // 0x80000000 / -1 overflows, R3 is not made positive
+        -1 if numerator == i32::MIN => (i32::MIN, 0, i32::MIN),
+        _ => {
+            let quotient = numerator / denominator;
+            (quotient, numerator % denominator, quotient.wrapping_abs())
+        }
+    };
+
+    swi_write_register(register_set, 0, quotient as u32)?;
+    swi_write_register(register_set, 1, remainder as u32)?;
+    swi_write_register(register_set, 3, absolute as u32)
+}
+
+// Unsigned square root of R0, rounded down
+pub fn sqrt(register_set: &RegisterSet) -> Result<(), CpuError> {
+    let value = swi_read_register(register_set, 0)?;
+    swi_write_register(register_set, 0, value.isqrt())
+}
+
+// Polynomial the BIOS uses, `tan` is 1.14 fixed point. Also returns the intermediate values left in R1 and R3.
+fn arc_tan_polynomial(tan: i32) -> (i32, i32, i32) {
+    let a = -(tan.wrapping_mul(tan) >> 14);
+    let b = [0x390, 0x91C, 0xFB6, 0x16AA, 0x2081, 0x3651, 0xA2F9].iter()
+        .fold(0xA9, |b: i32, constant| (b.wrapping_mul(a) >> 14) + constant);
+    (tan.wrapping_mul(b) >> 16, a, b)
+}
+
+// R0 = arctan(R0), the angle in 0x4000 = 90 degree units
+pub fn arc_tan(register_set: &RegisterSet) -> Result<(), CpuError> {
+    let tan = swi_read_register(register_set, 0)? as i32;
+    let (angle, a, b) = arc_tan_polynomial(tan);
+    swi_write_register(register_set, 0, angle as u32)?;
+    swi_write_register(register_set, 1, a as u32)?;
+    swi_write_register(register_set, 3, b as u32)
+}
+
+// Angle of the point (R0, R1) as 0x0000-0xFFFF for the full circle
+pub fn arc_tan2(register_set: &RegisterSet) -> Result<(), CpuError> {
+    let x = swi_read_register(register_set, 0)? as i32;
+    let y = swi_read_register(register_set, 1)? as i32;
+    swi_write_register(register_set, 0, arc_tan2_angle(x, y) as u32)
+}
+
+pub fn arc_tan2_angle(x: i32, y: i32) -> u16 {
+    let arc_tan = |tan: i32| arc_tan_polynomial(tan).0;
+    let angle = match (x, y) {
+        (x, 0) => if x >= 0 { 0 } else { 0x8000 },
+        (0, y) => if y >= 0 { 0x4000 } else { 0xC000 },
+        (x, y) if y >= 0 && x >= 0 && x >= y => arc_tan((y << 14) / x),
+        (x, y) if y >= 0 && x < 0 && -x >= y => arc_tan((y << 14) / x) + 0x8000,
+        (x, y) if y >= 0 => 0x4000 - arc_tan((x << 14) / y),
+        (x, y) if x <= 0 && -x > -y => arc_tan((y << 14) / x) + 0x8000,
+        (x, y) if x > 0 && x >= -y => arc_tan((y << 14) / x) + 0x10000,
+        (x, y) => 0xC000 - arc_tan((x << 14) / y),
+    };
+    angle as u16
+}
+
+pub fn get_bios_checksum(register_set: &RegisterSet) -> Result<(), CpuError> {
+    swi_write_register(register_set, 0, GBA_BIOS_CHECKSUM)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::register::RegisterCell;
+
+    fn register_set(values: [u32; 4]) -> RegisterSet {
+        let mut builder = RegisterSet::builder();
+        for (register, value) in values.iter().enumerate() {
+            builder.with_register(register as u8, RegisterCell::new(*value)).unwrap();
+        }
+        builder.build()
+    }
+
+    fn registers(register_set: &RegisterSet) -> [u32; 4] {
+        [0, 1, 2, 3].map(|register| swi_read_register(register_set, register).unwrap())
+    }
+
+    #[test]
+    fn test_div() {
+        let values = register_set([(-7i32) as u32, 2, 0, 0]);
+        div(&values).unwrap();
+        assert_eq!(registers(&values), [(-3i32) as u32, (-1i32) as u32, 0, 3]);
+
+        let values = register_set([2, 7, 0, 0]);
+        div_arm(&values).unwrap();
+        assert_eq!(registers(&values), [3, 1, 0, 3]);
+
+        // Quirks: division by zero and the overflowing division
+        let values = register_set([(-5i32) as u32, 0, 0, 0]);
+        div(&values).unwrap();
+        assert_eq!(registers(&values), [0xFFFFFFFF, (-5i32) as u32, 0, 1]);
+
+        let values = register_set([0x80000000, 0xFFFFFFFF, 0, 0]);
+        div(&values).unwrap();
+        assert_eq!(registers(&values), [0x80000000, 0, 0, 0x80000000]);
+    }
+
+    #[test]
+    fn test_sqrt() {
+        let values = register_set([0xFFFFFFFF, 0, 0, 0]);
+        sqrt(&values).unwrap();
+        assert_eq!(registers(&values)[0], 0xFFFF);
+
+        let values = register_set([99, 0, 0, 0]);
+        sqrt(&values).unwrap();
+        assert_eq!(registers(&values)[0], 9);
+    }
+
+    #[test]
+    fn test_arc_tan() {
+        // tan = 1.0 is 45 degrees
+        let values = register_set([0x4000, 0, 0, 0]);
+        arc_tan(&values).unwrap();
+        let [angle, a, _, _] = registers(&values);
+        assert!((0x1FF0..=0x2010).contains(&angle), "{:#X}", angle);
+        assert_eq!(a, (-0x4000i32) as u32);
+
+        assert_eq!(arc_tan2_angle(0x100, 0), 0);
+        assert_eq!(arc_tan2_angle(-0x100, 0), 0x8000);
+        assert_eq!(arc_tan2_angle(0, 0x100), 0x4000);
+        assert_eq!(arc_tan2_angle(0, -0x100), 0xC000);
+        for (x, y, expected) in [(0x100, 0x100, 0x2000), (-0x100, 0x100, 0x6000), (-0x100, -0x100, 0xA000), (0x100, -0x100, 0xE000)] {
+            let angle = arc_tan2_angle(x, y) as i32;
+            assert!((angle - expected).abs() <= 0x10, "({}, {}) -> {:#X}", x, y, angle);
+        }
+    }
+}
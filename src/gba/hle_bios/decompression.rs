@@ -0,0 +1,369 @@
+use crate::{cpu::{swi_read_register, CpuError}, memory::Bus, register::RegisterSet};
+
+// Width of the writes to the destination. VRAM ignores 8-bit writes, so the Vram variants write halfwords.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteWidth {
+    Byte,
+    Halfword,
+    Word,
+}
+
+// Reads the compressed stream byte by byte
+struct Source<'a, B: Bus> {
+    bus: &'a B,
+    address: u32,
+}
+
+impl<'a, B: Bus> Source<'a, B> {
+    fn new(bus: &'a B, address: u32) -> Source<'a, B> {
+        Source { bus, address }
+    }
+
+    fn next_u8(&mut self) -> Result<u8, CpuError> {
+        let value = self.bus.read_u8(self.address).map_err(CpuError::MemoryError)?;
+        self.address = self.address.wrapping_add(1);
+        Ok(value)
+    }
+
+    fn next_u16(&mut self) -> Result<u16, CpuError> {
+        Ok(u16::from_le_bytes([self.next_u8()?, self.next_u8()?]))
+    }
+
+    fn next_u32(&mut self) -> Result<u32, CpuError> {
+        Ok(u32::from_le_bytes([self.next_u8()?, self.next_u8()?, self.next_u8()?, self.next_u8()?]))
+    }
+}
+
+// Data header: bits 31-8 decompressed size in bytes, bits 7-4 type, bits 3-0 type specific
+fn read_header<B: Bus>(source: &mut Source<B>) -> Result<(usize, u8), CpuError> {
+    let header = source.next_u32()?;
+    Ok(((header >> 8) as usize, (header & 0xF) as u8))
+}
+
+// Writes the decompressed data, a trailing partial unit is padded with zeros
+pub fn write_output<B: Bus>(bus: &B, destination: u32, data: &[u8], width: WriteWidth) -> Result<(), CpuError> {
+    let unit = match width {
+        WriteWidth::Byte => 1,
+        WriteWidth::Halfword => 2,
+        WriteWidth::Word => 4,
+    };
+    for (i, chunk) in data.chunks(unit).enumerate() {
+        let mut bytes = [0; 4];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let address = destination.wrapping_add((i * unit) as u32);
+        match width {
+            WriteWidth::Byte => bus.write_u8(address, bytes[0]),
+            WriteWidth::Halfword => bus.write_u16(address, u16::from_le_bytes([bytes[0], bytes[1]])),
+            WriteWidth::Word => bus.write_u32(address, u32::from_le_bytes(bytes)),
+        }.map_err(CpuError::MemoryError)?;
+    }
+    Ok(())
+}
+
+// LZ77: a flag byte precedes 8 blocks (MSB first). 0 is a literal byte, 1 copies (bits 15-12) + 3 bytes
+// from (bits 11-0) + 1 bytes back.
+pub fn lz77_decompress<B: Bus>(bus: &B, source: u32, destination: u32) -> Result<Vec<u8>, CpuError> {
+    let mut source = Source::new(bus, source);
+    let (size, _) = read_header(&mut source)?;
+    let mut output = Vec::with_capacity(size);
+
+    while output.len() < size {
+        let flags = source.next_u8()?;
+        for block in (0..8).rev() {
+            if output.len() >= size {
+                break;
+            }
+            if (flags >> block) & 1 == 0 {
+                output.push(source.next_u8()?);
+                continue;
+            }
+
+            let reference = source.next_u16()?.swap_bytes();
+            let length = (reference >> 12) as usize + 3;
+            let displacement = (reference & 0xFFF) as usize + 1;
+            for _ in 0..length.min(size - output.len()) {
+                let value = match output.len().checked_sub(displacement) {
+                    Some(index) => output[index],
+                    // Reaches before the output, the BIOS reads whatever is in memory there
+                    None => bus.read_u8(destination.wrapping_add(output.len() as u32).wrapping_sub(displacement as u32)).map_err(CpuError::MemoryError)?,
+                };
+                output.push(value);
+            }
+        }
+    }
+    Ok(output)
+}
+
+// Huffman: bits 3-0 of the header are the data size (4 or 8). The tree follows the header, then the
+// bitstream as 32-bit words read MSB first.
+pub fn huffman_decompress<B: Bus>(bus: &B, source: u32) -> Result<Vec<u8>, CpuError> {
+    let mut reader = Source::new(bus, source);
+    let (size, data_bits) = read_header(&mut reader)?;
+    let data_bits = if data_bits == 4 { 4 } else { 8 };
+
+    let tree_size = reader.next_u8()? as u32;
+    let root = source.wrapping_add(5);
+    let mut stream = Source::new(bus, source.wrapping_add(4).wrapping_add((tree_size + 1) * 2));
+
+    let mut output = Vec::with_capacity(size);
+    let mut pending_nibble: Option<u8> = None;
+    let mut node = root;
+    let mut node_value = bus.read_u8(node).map_err(CpuError::MemoryError)?;
+
+    while output.len() < size {
+        let bits = stream.next_u32()?;
+        for bit in (0..32).rev() {
+            if output.len() >= size {
+                break;
+            }
+            // Node: bits 5-0 offset to the children, bit 7 node 0 is data, bit 6 node 1 is data
+            let direction = (bits >> bit) & 1;
+            let children = (node & !1).wrapping_add((node_value & 0x3F) as u32 * 2 + 2);
+            let is_data = (node_value >> (7 - direction)) & 1 != 0;
+            node = children + direction;
+            node_value = bus.read_u8(node).map_err(CpuError::MemoryError)?;
+            if !is_data {
+                continue;
+            }
+
+            if data_bits == 8 {
+                output.push(node_value);
+            } else if let Some(low) = pending_nibble.take() {
+                output.push(low | (node_value << 4));
+            } else {
+                pending_nibble = Some(node_value & 0xF);
+            }
+            node = root;
+            node_value = bus.read_u8(node).map_err(CpuError::MemoryError)?;
+        }
+    }
+    Ok(output)
+}
+
+// Run length: bit 7 of the flag byte set repeats the next byte (bits 6-0) + 3 times,
+// clear copies the next (bits 6-0) + 1 bytes
+pub fn run_length_decompress<B: Bus>(bus: &B, source: u32) -> Result<Vec<u8>, CpuError> {
+    let mut source = Source::new(bus, source);
+    let (size, _) = read_header(&mut source)?;
+    let mut output = Vec::with_capacity(size);
+
+    while output.len() < size {
+        let flag = source.next_u8()?;
+        let remaining = size - output.len();
+        if (flag & 0x80) != 0 {
+            let value = source.next_u8()?;
+            let length = (flag & 0x7F) as usize + 3;
+            output.extend(std::iter::repeat_n(value, length.min(remaining)));
+        } else {
+            for _ in 0..((flag & 0x7F) as usize + 1).min(remaining) {
+                output.push(source.next_u8()?);
+            }
+        }
+    }
+    Ok(output)
+}
+
+// Differential filter: every unit after the first is stored as the difference to the previous one
+pub fn diff_unfilter<B: Bus>(bus: &B, source: u32, unit_size: usize) -> Result<Vec<u8>, CpuError> {
+    let mut source = Source::new(bus, source);
+    let (size, _) = read_header(&mut source)?;
+    let mut output = Vec::with_capacity(size);
+
+    let mut previous: u16 = 0;
+    while output.len() < size {
+        if unit_size == 1 {
+            previous = (previous as u8).wrapping_add(source.next_u8()?) as u16;
+            output.push(previous as u8);
+        } else {
+            previous = previous.wrapping_add(source.next_u16()?);
+            output.extend(previous.to_le_bytes());
+        }
+    }
+    output.truncate(size);
+    Ok(output)
+}
+
+// Expands units of 1, 2, 4 or 8 bits to 1, 2, 4, 8, 16 or 32 bits. The info structure at `info` is:
+// u16 source length in bytes, u8 source width, u8 destination width, u32 bits 30-0 offset added to
+// each unit, bit 31 also add the offset to zero units.
+pub fn bit_unpack<B: Bus>(bus: &B, source: u32, info: u32) -> Result<Vec<u8>, CpuError> {
+    let mut info = Source::new(bus, info);
+    let length = info.next_u16()? as usize;
+    let source_width = info.next_u8()? as u32;
+    let destination_width = info.next_u8()? as u32;
+    let offset = info.next_u32()?;
+    let (zero_data, offset) = ((offset >> 31) != 0, offset & 0x7FFF_FFFF);
+
+    if !matches!(source_width, 1 | 2 | 4 | 8) || !matches!(destination_width, 1 | 2 | 4 | 8 | 16 | 32) {
+        return Ok(Vec::new());
+    }
+
+    let mut source = Source::new(bus, source);
+    let mut output = Vec::new();
+    let (mut word, mut bits) = (0u32, 0);
+    for _ in 0..length {
+        let byte = source.next_u8()? as u32;
+        for shift in (0..8).step_by(source_width as usize) {
+            let mut unit = (byte >> shift) & ((1 << source_width) - 1);
+            if unit != 0 || zero_data {
+                unit = unit.wrapping_add(offset);
+            }
+            if destination_width < 32 {
+                unit &= (1 << destination_width) - 1;
+            }
+            word |= unit.checked_shl(bits).unwrap_or(0);
+            bits += destination_width;
+            if bits == 32 {
+                output.extend(word.to_le_bytes());
+                (word, bits) = (0, 0);
+            }
+        }
+    }
+    Ok(output)
+}
+
+// R0 source, R1 destination
+fn source_destination(register_set: &RegisterSet) -> Result<(u32, u32), CpuError> {
+    Ok((swi_read_register(register_set, 0)?, swi_read_register(register_set, 1)?))
+}
+
+pub fn lz77_uncomp<B: Bus>(register_set: &RegisterSet, bus: &B, width: WriteWidth) -> Result<(), CpuError> {
+    let (source, destination) = source_destination(register_set)?;
+    let output = lz77_decompress(bus, source, destination)?;
+    write_output(bus, destination, &output, width)
+}
+
+pub fn huff_uncomp<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let (source, destination) = source_destination(register_set)?;
+    let output = huffman_decompress(bus, source)?;
+    write_output(bus, destination, &output, WriteWidth::Word)
+}
+
+pub fn rl_uncomp<B: Bus>(register_set: &RegisterSet, bus: &B, width: WriteWidth) -> Result<(), CpuError> {
+    let (source, destination) = source_destination(register_set)?;
+    let output = run_length_decompress(bus, source)?;
+    write_output(bus, destination, &output, width)
+}
+
+pub fn diff_8bit_unfilter<B: Bus>(register_set: &RegisterSet, bus: &B, width: WriteWidth) -> Result<(), CpuError> {
+    let (source, destination) = source_destination(register_set)?;
+    let output = diff_unfilter(bus, source, 1)?;
+    write_output(bus, destination, &output, width)
+}
+
+pub fn diff_16bit_unfilter<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let (source, destination) = source_destination(register_set)?;
+    let output = diff_unfilter(bus, source, 2)?;
+    write_output(bus, destination, &output, WriteWidth::Halfword)
+}
+
+// R2 points to the unpack info
+pub fn bit_unpack_swi<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let (source, destination) = source_destination(register_set)?;
+    let output = bit_unpack(bus, source, swi_read_register(register_set, 2)?)?;
+    write_output(bus, destination, &output, WriteWidth::Word)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::memory::{AccessWidth, FlatBus, MemoryError};
+
+    const SOURCE: u32 = 0x08000000;
+    const DESTINATION: u32 = 0x06000000;
+
+    fn header(kind: u8, size: usize) -> Vec<u8> {
+        ((size as u32) << 8 | kind as u32).to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_lz77() {
+        let bus = FlatBus::new();
+        let mut data = header(0x10, 11);
+        // Literals "abc", then copy 8 bytes from 3 back
+        data.extend([0b0001_0000, b'a', b'b', b'c', 0x50, 0x02]);
+        bus.load(SOURCE, &data);
+
+        assert_eq!(lz77_decompress(&bus, SOURCE, DESTINATION).unwrap(), b"abcabcabcab");
+    }
+
+    #[test]
+    fn test_huffman() {
+        let bus = FlatBus::new();
+        let mut data = header(0x28, 3);
+        // Root with two data children: 0 -> 'x', 1 -> 'y'
+        data.extend([0x01, 0xC0, b'x', b'y']);
+        // Bits 0, 1, 1
+        data.extend(0x6000_0000u32.to_le_bytes());
+        bus.load(SOURCE, &data);
+
+        assert_eq!(huffman_decompress(&bus, SOURCE).unwrap(), b"xyy");
+    }
+
+    #[test]
+    fn test_run_length() {
+        let bus = FlatBus::new();
+        let mut data = header(0x30, 7);
+        data.extend([0x01, b'a', b'b', 0x82, b'z']);
+        bus.load(SOURCE, &data);
+
+        assert_eq!(run_length_decompress(&bus, SOURCE).unwrap(), b"abzzzzz");
+    }
+
+    #[test]
+    fn test_diff_unfilter() {
+        let bus = FlatBus::new();
+        let mut data = header(0x81, 4);
+        data.extend([10, 1, 0xFF, 2]);
+        bus.load(SOURCE, &data);
+        assert_eq!(diff_unfilter(&bus, SOURCE, 1).unwrap(), [10, 11, 10, 12]);
+
+        let mut data = header(0x82, 4);
+        data.extend([0x00, 0x10, 0x01, 0x00]);
+        bus.load(SOURCE, &data);
+        assert_eq!(diff_unfilter(&bus, SOURCE, 2).unwrap(), [0x00, 0x10, 0x01, 0x10]);
+    }
+
+    #[test]
+    fn test_bit_unpack() {
+        let bus = FlatBus::new();
+        bus.load(SOURCE, &[0b1110_0100]);
+        // 1 byte of 2-bit units to 8-bit units, offset 0x10 except for zeros
+        bus.load(0x02000000, &[1, 0, 2, 8, 0x10, 0, 0, 0]);
+        assert_eq!(bit_unpack(&bus, SOURCE, 0x02000000).unwrap(), [0x00, 0x11, 0x12, 0x13]);
+
+        // Zero data flag
+        bus.load(0x02000000, &[1, 0, 2, 8, 0x10, 0, 0, 0x80]);
+        assert_eq!(bit_unpack(&bus, SOURCE, 0x02000000).unwrap(), [0x10, 0x11, 0x12, 0x13]);
+    }
+
+    // Drops 8-bit writes like VRAM
+    #[derive(Debug, Default)]
+    struct VramBus(FlatBus);
+
+    impl Bus for VramBus {
+        fn read_u8(&self, address: u32) -> Result<u8, MemoryError> { self.0.read_u8(address) }
+        fn read_u16(&self, address: u32) -> Result<u16, MemoryError> { self.0.read_u16(address) }
+        fn read_u32(&self, address: u32) -> Result<u32, MemoryError> { self.0.read_u32(address) }
+        fn peek(&self, address: u32, width: AccessWidth) -> Result<u32, MemoryError> { self.0.peek(address, width) }
+        fn write_u8(&self, _address: u32, _value: u8) -> Result<(), MemoryError> { Ok(()) }
+        fn write_u16(&self, address: u32, value: u16) -> Result<(), MemoryError> { self.0.write_u16(address, value) }
+        fn write_u32(&self, address: u32, value: u32) -> Result<(), MemoryError> { self.0.write_u32(address, value) }
+    }
+
+    #[test]
+    fn test_vram_writes_halfwords() {
+        let bus = VramBus::default();
+        let mut data = header(0x30, 3);
+        data.extend([0x80, 0xAB]);
+        bus.0.load(SOURCE, &data);
+
+        write_output(&bus, DESTINATION, &run_length_decompress(&bus, SOURCE).unwrap(), WriteWidth::Halfword).unwrap();
+        // The odd trailing byte is padded to a halfword
+        assert_eq!(bus.read_u32(DESTINATION).unwrap(), 0x00ABABAB);
+
+        write_output(&bus, DESTINATION + 4, &[1, 2], WriteWidth::Byte).unwrap();
+        assert_eq!(bus.read_u16(DESTINATION + 4).unwrap(), 0);
+    }
+}
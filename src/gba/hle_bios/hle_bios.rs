@@ -0,0 +1,275 @@
+use crate::{cpu::{CpuError, SwiHandler, SwiOutcome, CPU}, gba::{Bios, BIOS_SIZE, IO_REGISTERS_START}, memory::Bus};
+
+use super::{arc_tan, arc_tan2, bg_affine_set, bit_unpack_swi, cpu_fast_set, cpu_set, diff_16bit_unfilter, diff_8bit_unfilter, div, div_arm, get_bios_checksum, huff_uncomp, intr_wait, is_awake, lz77_uncomp, obj_affine_set, register_ram_reset, rl_uncomp, soft_reset, sqrt, vblank_intr_wait, WakeCondition, WriteWidth};
+
+// BIOS function numbers
+pub const SWI_SOFT_RESET: u8 = 0x00;
+pub const SWI_REGISTER_RAM_RESET: u8 = 0x01;
+pub const SWI_HALT: u8 = 0x02;
+pub const SWI_STOP: u8 = 0x03;
+pub const SWI_INTR_WAIT: u8 = 0x04;
+pub const SWI_VBLANK_INTR_WAIT: u8 = 0x05;
+pub const SWI_DIV: u8 = 0x06;
+pub const SWI_DIV_ARM: u8 = 0x07;
+pub const SWI_SQRT: u8 = 0x08;
+pub const SWI_ARC_TAN: u8 = 0x09;
+pub const SWI_ARC_TAN2: u8 = 0x0A;
+pub const SWI_CPU_SET: u8 = 0x0B;
+pub const SWI_CPU_FAST_SET: u8 = 0x0C;
+pub const SWI_GET_BIOS_CHECKSUM: u8 = 0x0D;
+pub const SWI_BG_AFFINE_SET: u8 = 0x0E;
+pub const SWI_OBJ_AFFINE_SET: u8 = 0x0F;
+pub const SWI_BIT_UNPACK: u8 = 0x10;
+pub const SWI_LZ77_UNCOMP_WRAM: u8 = 0x11;
+pub const SWI_LZ77_UNCOMP_VRAM: u8 = 0x12;
+pub const SWI_HUFF_UNCOMP: u8 = 0x13;
+pub const SWI_RL_UNCOMP_WRAM: u8 = 0x14;
+pub const SWI_RL_UNCOMP_VRAM: u8 = 0x15;
+pub const SWI_DIFF_8BIT_UNFILTER_WRAM: u8 = 0x16;
+pub const SWI_DIFF_8BIT_UNFILTER_VRAM: u8 = 0x17;
+pub const SWI_DIFF_16BIT_UNFILTER: u8 = 0x18;
+
+// The IRQ vector branches to a dispatcher like the one of the real BIOS: it saves the scratch
+// registers, calls the handler the game stored at 0x03FFFFFC and returns from the exception
+const IRQ_DISPATCHER: [(usize, u32); 8] = [
+    (0x018, 0xEA000042), // B 0x128
+    (0x128, 0xE92D500F), // STMFD SP!, {R0-R3, R12, LR}
+    (0x12C, 0xE59F000C), // LDR R0, =0x04000000
+    (0x130, 0xE28FE000), // ADD LR, PC, #0
+    (0x134, 0xE510F004), // LDR PC, [R0, #-4]
+    (0x138, 0xE8BD500F), // LDMFD SP!, {R0-R3, R12, LR}
+    (0x13C, 0xE25EF004), // SUBS PC, LR, #4
+    (0x140, IO_REGISTERS_START),
+];
+
+// Emulates the GBA BIOS calls natively, for running games without a BIOS dump
+#[derive(Debug, Default)]
+pub struct HleBios {
+    // Set while the CPU waits in Halt, Stop or IntrWait
+    wake_condition: Option<WakeCondition>,
+}
+
+impl HleBios {
+    pub fn new() -> HleBios {
+        HleBios::default()
+    }
+
+    // BIOS image to run with, only the IRQ dispatcher is real code
+    pub fn bios() -> Bios {
+        let mut data = vec![0; BIOS_SIZE];
+        for (address, opcode) in IRQ_DISPATCHER {
+            data[address..address + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+        Bios::from_bytes(data).expect("the image has the BIOS size")
+    }
+
+    fn halt(&mut self, condition: WakeCondition) -> SwiOutcome {
+        self.wake_condition = Some(condition);
+        SwiOutcome::Halted
+    }
+}
+
+impl<B: Bus> SwiHandler<B> for HleBios {
+    fn software_interrupt(&mut self, number: u8, cpu: &CPU<B>) -> Result<SwiOutcome, CpuError> {
+        let register_set = &cpu.register_set()?;
+        let bus = &cpu.memory_bus;
+        match number {
+            SWI_SOFT_RESET => {
+                soft_reset(cpu)?;
+                return Ok(SwiOutcome::Branched);
+            }
+            SWI_REGISTER_RAM_RESET => register_ram_reset(register_set, bus)?,
+            SWI_HALT => return Ok(self.halt(WakeCondition::Interrupt)),
+            SWI_STOP => return Ok(self.halt(WakeCondition::StopInterrupt)),
+            SWI_INTR_WAIT => return Ok(self.halt(intr_wait(register_set, bus)?)),
+            SWI_VBLANK_INTR_WAIT => return Ok(self.halt(vblank_intr_wait(bus)?)),
+            SWI_DIV => div(register_set)?,
+            SWI_DIV_ARM => div_arm(register_set)?,
+            SWI_SQRT => sqrt(register_set)?,
+            SWI_ARC_TAN => arc_tan(register_set)?,
+            SWI_ARC_TAN2 => arc_tan2(register_set)?,
+            SWI_CPU_SET => cpu_set(register_set, bus)?,
+            SWI_CPU_FAST_SET => cpu_fast_set(register_set, bus)?,
+            SWI_GET_BIOS_CHECKSUM => get_bios_checksum(register_set)?,
+            SWI_BG_AFFINE_SET => bg_affine_set(register_set, bus)?,
+            SWI_OBJ_AFFINE_SET => obj_affine_set(register_set, bus)?,
+            SWI_BIT_UNPACK => bit_unpack_swi(register_set, bus)?,
+            SWI_LZ77_UNCOMP_WRAM => lz77_uncomp(register_set, bus, WriteWidth::Byte)?,
+            SWI_LZ77_UNCOMP_VRAM => lz77_uncomp(register_set, bus, WriteWidth::Halfword)?,
+            SWI_HUFF_UNCOMP => huff_uncomp(register_set, bus)?,
+            SWI_RL_UNCOMP_WRAM => rl_uncomp(register_set, bus, WriteWidth::Byte)?,
+            SWI_RL_UNCOMP_VRAM => rl_uncomp(register_set, bus, WriteWidth::Halfword)?,
+            SWI_DIFF_8BIT_UNFILTER_WRAM => diff_8bit_unfilter(register_set, bus, WriteWidth::Byte)?,
+            SWI_DIFF_8BIT_UNFILTER_VRAM => diff_8bit_unfilter(register_set, bus, WriteWidth::Halfword)?,
+            SWI_DIFF_16BIT_UNFILTER => diff_16bit_unfilter(register_set, bus)?,
+            _ => return Ok(SwiOutcome::Unhandled),
+        }
+        Ok(SwiOutcome::Returned)
+    }
+
+    fn resume(&mut self, cpu: &CPU<B>) -> Result<bool, CpuError> {
+        let awake = match &self.wake_condition {
+            Some(condition) => is_awake(condition, &cpu.memory_bus)?,
+            None => true,
+        };
+        if awake {
+            self.wake_condition = None;
+        }
+        Ok(awake)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{cpu::{swi_read_register, swi_write_register, CpuState}, gba::{build_gba_memory_bus, init_gba_cpu, init_gba_registers, BiosSource, GbaConfig, GbaInterrupts, IoRegisters, BIOS_INTERRUPT_FLAGS, IO_IE, IO_IF, IO_IME, IRQ_HBLANK, IRQ_VBLANK, WRAM_ONBOARD_START, WRAM_ONCHIP_START}, instruction::{LINK_REGISTER, PROGRAM_COUNTER}, memory::MemoryBus, register::{Mode, CPSR}};
+
+    const STACK_POINTER: u8 = 13;
+    const IRQ_STACK: u32 = 0x03007FA0;
+
+    // Acknowledges the requested interrupts in IF and reports them to IntrWait at 0x03007FF8
+    const IRQ_HANDLER: [u32; 10] = [
+        0xE59F1018, // LDR R1, =0x04000202
+        0xE1D120B0, // LDRH R2, [R1]
+        0xE1C120B0, // STRH R2, [R1]
+        0xE59F1010, // LDR R1, =0x03007FF8
+        0xE1D130B0, // LDRH R3, [R1]
+        0xE1833002, // ORR R3, R3, R2
+        0xE1C130B0, // STRH R3, [R1]
+        0xE12FFF1E, // BX LR
+        0x04000202,
+        0x03007FF8,
+    ];
+
+    fn irq_cpu() -> (CPU<MemoryBus>, GbaInterrupts) {
+        let io_registers = Rc::new(RefCell::new(IoRegisters::new()));
+        let memory_bus = build_gba_memory_bus(&GbaConfig { bios: BiosSource::Hle, ..GbaConfig::default() }, io_registers.clone()).unwrap();
+        let interrupts = GbaInterrupts::new(io_registers);
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), memory_bus);
+        cpu.set_swi_handler(Box::new(HleBios::new()));
+        cpu.set_interrupt_controller(Box::new(interrupts.clone()));
+
+        let mut cpsr = CPSR::from_bits_truncate(Mode::SYSTEM.bits());
+        cpsr.set_state(CpuState::ARM);
+        cpu.set_cpsr(cpsr).unwrap();
+        swi_write_register(&cpu.register_map.get(Mode::IRQ).unwrap(), STACK_POINTER, IRQ_STACK).unwrap();
+
+        for (i, opcode) in IRQ_HANDLER.iter().enumerate() {
+            cpu.memory_bus.write_u32(WRAM_ONCHIP_START + i as u32 * 4, *opcode).unwrap();
+        }
+        cpu.memory_bus.write_u32(0x03007FFC, WRAM_ONCHIP_START).unwrap();
+        (cpu, interrupts)
+    }
+
+    fn step_until(cpu: &mut CPU<MemoryBus>, done: impl Fn(&CPU<MemoryBus>) -> bool) {
+        for _ in 0..100 {
+            cpu.step().unwrap();
+            if done(cpu) {
+                return;
+            }
+        }
+        panic!("Still running at {:#010X}", cpu.read_register(PROGRAM_COUNTER).unwrap());
+    }
+
+    #[test]
+    fn test_irq_during_intr_wait() {
+        let (mut cpu, interrupts) = irq_cpu();
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START, 0xEF050000).unwrap(); // SWI 0x05 (VBlankIntrWait)
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START + 4, 0xE3A05001).unwrap(); // MOV R5, #1
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IE, IRQ_VBLANK | IRQ_HBLANK).unwrap();
+        cpu.write_register(PROGRAM_COUNTER, WRAM_ONBOARD_START).unwrap();
+        cpu.write_register(0, 0x1234).unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.is_halted());
+
+        // The handler runs from the vector, but HBlank is not what the wait is for
+        interrupts.request(IRQ_HBLANK);
+        cpu.step().unwrap();
+        assert_eq!(cpu.mode().unwrap(), Mode::IRQ);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x18);
+        assert_eq!(cpu.read_register(LINK_REGISTER).unwrap(), WRAM_ONBOARD_START + 8);
+        step_until(&mut cpu, |cpu| cpu.is_halted());
+        assert_eq!(cpu.mode().unwrap(), Mode::SYSTEM);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), WRAM_ONBOARD_START + 4);
+        assert_eq!(cpu.memory_bus.read_u16(IO_REGISTERS_START + IO_IF).unwrap(), 0);
+        assert_eq!(cpu.memory_bus.read_u16(BIOS_INTERRUPT_FLAGS).unwrap(), IRQ_HBLANK);
+
+        // VBlank ends the wait after the handler returns
+        interrupts.request(IRQ_VBLANK);
+        step_until(&mut cpu, |cpu| cpu.read_register(5).unwrap() == 1);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), WRAM_ONBOARD_START + 8);
+        assert_eq!(cpu.memory_bus.read_u16(BIOS_INTERRUPT_FLAGS).unwrap(), IRQ_HBLANK);
+        assert_eq!(cpu.read_register(0).unwrap(), 0x1234);
+        assert_eq!(swi_read_register(&cpu.register_map.get(Mode::IRQ).unwrap(), STACK_POINTER).unwrap(), IRQ_STACK);
+    }
+
+    #[test]
+    fn test_irq_handler_leaving_intr_wait() {
+        let (mut cpu, interrupts) = irq_cpu();
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START, 0xEF050000).unwrap(); // SWI 0x05 (VBlankIntrWait)
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START + 4, 0xE3A05001).unwrap(); // MOV R5, #1
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START + 0x10, 0xE3A06001).unwrap(); // MOV R6, #1
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START + 0x14, 0xEAFFFFFA).unwrap(); // B WRAM_ONBOARD_START + 4
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IE, IRQ_VBLANK | IRQ_HBLANK).unwrap();
+        cpu.write_register(PROGRAM_COUNTER, WRAM_ONBOARD_START).unwrap();
+        cpu.step().unwrap();
+
+        // The handler swaps the stacked return address, like a thread switch does
+        interrupts.request(IRQ_HBLANK);
+        step_until(&mut cpu, |cpu| cpu.read_register(PROGRAM_COUNTER).unwrap() == WRAM_ONCHIP_START);
+        cpu.memory_bus.write_u32(IRQ_STACK - 4, WRAM_ONBOARD_START + 0x14).unwrap();
+        step_until(&mut cpu, |cpu| cpu.read_register(6).unwrap() == 1);
+        assert_eq!(cpu.mode().unwrap(), Mode::SYSTEM);
+
+        // Passing the address of the wait later on does not wait again
+        step_until(&mut cpu, |cpu| cpu.read_register(5).unwrap() == 1);
+        assert!(!cpu.is_halted());
+    }
+
+    #[test]
+    fn test_irq_while_running() {
+        let (mut cpu, interrupts) = irq_cpu();
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START, 0xE3A05001).unwrap(); // MOV R5, #1
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IE, IRQ_VBLANK).unwrap();
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IME, 1).unwrap();
+        cpu.write_register(PROGRAM_COUNTER, WRAM_ONBOARD_START).unwrap();
+
+        // Masked by CPSR.I
+        let mut cpsr = cpu.cpsr().unwrap();
+        cpsr.seti(true);
+        cpu.set_cpsr(cpsr.clone()).unwrap();
+        interrupts.request(IRQ_VBLANK);
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_register(5).unwrap(), 1);
+
+        cpsr.seti(false);
+        cpu.set_cpsr(cpsr).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x18);
+        step_until(&mut cpu, |cpu| cpu.mode().unwrap() == Mode::SYSTEM);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), WRAM_ONBOARD_START + 4);
+        assert_eq!(cpu.memory_bus.read_u16(BIOS_INTERRUPT_FLAGS).unwrap(), IRQ_VBLANK);
+    }
+
+    #[test]
+    fn test_hle_bios_swi() {
+        let mut cpu = init_gba_cpu(&GbaConfig { bios: BiosSource::Hle, ..GbaConfig::default() }).unwrap();
+        cpu.write_register(PROGRAM_COUNTER, 0x08000000).unwrap();
+        cpu.write_register(0, 100).unwrap();
+        cpu.write_register(1, 7).unwrap();
+
+        // SWI 0x060000 (Div)
+        cpu.execute(0xEF060000).unwrap();
+        assert_eq!(cpu.read_register(0).unwrap(), 14);
+        assert_eq!(cpu.read_register(1).unwrap(), 2);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), 0x08000004);
+
+        // SWI 0x0D0000 (GetBiosChecksum)
+        cpu.execute(0xEF0D0000).unwrap();
+        assert_eq!(cpu.read_register(0).unwrap(), 0xBAAE187F);
+    }
+}
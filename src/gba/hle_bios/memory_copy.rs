@@ -0,0 +1,109 @@
+use crate::{cpu::{swi_read_register, CpuError}, memory::Bus, register::RegisterSet};
+
+// R2 control bits of CpuSet and CpuFastSet
+const COUNT_MASK: u32 = 0x1F_FFFF; // Bits 20-0 (Number of halfwords/words)
+const FILL: u32 = 1 << 24; // Bit 24 (0=copy, 1=fill with the value at the source)
+const WORDS: u32 = 1 << 26; // Bit 26 (CpuSet: 0=halfwords, 1=words)
+
+// The BIOS refuses to copy from itself
+fn source_in_bios(source: u32) -> bool {
+    (source & 0x0E00_0000) == 0
+}
+
+// Copies or fills R2 halfwords/words from R0 to R1
+pub fn cpu_set<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let source = swi_read_register(register_set, 0)?;
+    let destination = swi_read_register(register_set, 1)?;
+    let control = swi_read_register(register_set, 2)?;
+    if source_in_bios(source) {
+        return Ok(());
+    }
+
+    let count = control & COUNT_MASK;
+    let fill = (control & FILL) != 0;
+    if (control & WORDS) != 0 {
+        let (source, destination) = (source & !3, destination & !3);
+        for i in 0..count {
+            let value = bus.read_u32(if fill { source } else { source.wrapping_add(i * 4) }).map_err(CpuError::MemoryError)?;
+            bus.write_u32(destination.wrapping_add(i * 4), value).map_err(CpuError::MemoryError)?;
+        }
+    } else {
+        let (source, destination) = (source & !1, destination & !1);
+        for i in 0..count {
+            let value = bus.read_u16(if fill { source } else { source.wrapping_add(i * 2) }).map_err(CpuError::MemoryError)?;
+            bus.write_u16(destination.wrapping_add(i * 2), value).map_err(CpuError::MemoryError)?;
+        }
+    }
+    Ok(())
+}
+
+// Copies or fills words in blocks of 8, the count is rounded up
+pub fn cpu_fast_set<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let source = swi_read_register(register_set, 0)? & !3;
+    let destination = swi_read_register(register_set, 1)? & !3;
+    let control = swi_read_register(register_set, 2)?;
+    if source_in_bios(source) {
+        return Ok(());
+    }
+
+    let count = ((control & COUNT_MASK) + 7) & !7;
+    let fill = (control & FILL) != 0;
+    let fill_value = bus.read_u32(source).map_err(CpuError::MemoryError)?;
+    for i in 0..count {
+        let value = if fill { fill_value } else { bus.read_u32(source.wrapping_add(i * 4)).map_err(CpuError::MemoryError)? };
+        bus.write_u32(destination.wrapping_add(i * 4), value).map_err(CpuError::MemoryError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{memory::FlatBus, register::RegisterCell};
+
+    fn register_set(values: [u32; 3]) -> RegisterSet {
+        let mut builder = RegisterSet::builder();
+        for (register, value) in values.iter().enumerate() {
+            builder.with_register(register as u8, RegisterCell::new(*value)).unwrap();
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_cpu_set() {
+        let bus = FlatBus::new();
+        bus.load(0x02000000, &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // Three halfwords, misaligned addresses are forced to halfwords
+        cpu_set(&register_set([0x02000001, 0x03000000, 3]), &bus).unwrap();
+        assert_eq!(bus.read_u32(0x03000000).unwrap(), 0x04030201);
+        assert_eq!(bus.read_u32(0x03000004).unwrap(), 0x00000605);
+
+        // Fill two words
+        cpu_set(&register_set([0x02000004, 0x03000100, WORDS | FILL | 2]), &bus).unwrap();
+        assert_eq!(bus.read_u32(0x03000100).unwrap(), 0x08070605);
+        assert_eq!(bus.read_u32(0x03000104).unwrap(), 0x08070605);
+        assert_eq!(bus.read_u32(0x03000108).unwrap(), 0);
+
+        // Reading the BIOS is refused
+        bus.load(0x100, &[0xFF; 4]);
+        cpu_set(&register_set([0x100, 0x03000200, WORDS | 1]), &bus).unwrap();
+        assert_eq!(bus.read_u32(0x03000200).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cpu_fast_set() {
+        let bus = FlatBus::new();
+        bus.write_u32(0x02000000, 0xAABBCCDD).unwrap();
+
+        // One word is rounded up to a block of 8
+        cpu_fast_set(&register_set([0x02000000, 0x06000000, FILL | 1]), &bus).unwrap();
+        assert_eq!(bus.read_u32(0x0600001C).unwrap(), 0xAABBCCDD);
+        assert_eq!(bus.read_u32(0x06000020).unwrap(), 0);
+
+        cpu_fast_set(&register_set([0x06000000, 0x06000100, 9]), &bus).unwrap();
+        assert_eq!(bus.read_u32(0x0600013C).unwrap(), 0);
+        assert_eq!(bus.read_u32(0x0600011C).unwrap(), 0xAABBCCDD);
+    }
+}
@@ -0,0 +1,13 @@
+mod hle_bios;
+mod arithmetic;
+mod memory_copy;
+mod affine;
+mod decompression;
+mod system;
+
+pub use hle_bios::*;
+pub use arithmetic::*;
+pub use memory_copy::*;
+pub use affine::*;
+pub use decompression::*;
+pub use system::*;
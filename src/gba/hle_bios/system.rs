@@ -0,0 +1,283 @@
+use crate::{cpu::{swi_read_register, CpuError, CpuState, CPU}, instruction::{LINK_REGISTER, PROGRAM_COUNTER}, memory::Bus, register::{write_register_set, Mode, RegisterError, RegisterSet, WriteRegister, CPSR}};
+use crate::gba::{BIOS_INTERRUPT_FLAGS, BIOS_RAM_START, BIOS_RESET_FLAG, GAMEPAK_ROM_WS0_START, IO_DISPCNT, IO_IE, IO_IF, IO_IME, IO_REGISTERS_START, IRQ_GAMEPAK, IRQ_KEYPAD, IRQ_SERIAL, IRQ_VBLANK, OAM_SIZE, OAM_START, PALLETE_RAM_SIZE, PALLETE_RAM_START, VRAM_SIZE, VRAM_START, WRAM_ONBOARD_SIZE, WRAM_ONBOARD_START, WRAM_ONCHIP_SIZE, WRAM_ONCHIP_START};
+
+const STACK_POINTER: u8 = 13;
+
+// Stack pointers SoftReset sets up
+const SUPERVISOR_STACK: u32 = 0x03007FE0;
+const IRQ_STACK: u32 = 0x03007FA0;
+const SYSTEM_STACK: u32 = 0x03007F00;
+
+// What a halted CPU waits for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WakeCondition {
+    // Halt: any enabled interrupt is requested
+    Interrupt,
+    // Stop: only the keypad, Game Pak and serial interrupts end it
+    StopInterrupt,
+    // IntrWait: one of the flags is set at 0x03007FF8
+    BiosFlags(u16),
+}
+
+fn requested_interrupts<B: Bus>(bus: &B) -> Result<u16, CpuError> {
+    let enabled = bus.read_u16(IO_REGISTERS_START + IO_IE).map_err(CpuError::MemoryError)?;
+    let requested = bus.read_u16(IO_REGISTERS_START + IO_IF).map_err(CpuError::MemoryError)?;
+    Ok(enabled & requested)
+}
+
+// Checks the wait, IntrWait consumes the flags it was waiting for
+pub fn is_awake<B: Bus>(condition: &WakeCondition, bus: &B) -> Result<bool, CpuError> {
+    match condition {
+        WakeCondition::Interrupt => Ok(requested_interrupts(bus)? != 0),
+        WakeCondition::StopInterrupt => Ok(requested_interrupts(bus)? & (IRQ_KEYPAD | IRQ_GAMEPAK | IRQ_SERIAL) != 0),
+        WakeCondition::BiosFlags(mask) => {
+            let flags = bus.read_u16(BIOS_INTERRUPT_FLAGS).map_err(CpuError::MemoryError)?;
+            if flags & mask == 0 {
+                return Ok(false);
+            }
+            bus.write_u16(BIOS_INTERRUPT_FLAGS, flags & !mask).map_err(CpuError::MemoryError)?;
+            Ok(true)
+        }
+    }
+}
+
+// R0 nonzero discards flags set before the call, R1 the interrupts to wait for. Enables IME.
+pub fn intr_wait<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<WakeCondition, CpuError> {
+    let discard = swi_read_register(register_set, 0)? != 0;
+    let mask = swi_read_register(register_set, 1)? as u16;
+    wait_for_flags(bus, discard, mask)
+}
+
+// IntrWait(1, VBlank)
+pub fn vblank_intr_wait<B: Bus>(bus: &B) -> Result<WakeCondition, CpuError> {
+    wait_for_flags(bus, true, IRQ_VBLANK)
+}
+
+fn wait_for_flags<B: Bus>(bus: &B, discard: bool, mask: u16) -> Result<WakeCondition, CpuError> {
+    if discard {
+        let flags = bus.read_u16(BIOS_INTERRUPT_FLAGS).map_err(CpuError::MemoryError)?;
+        bus.write_u16(BIOS_INTERRUPT_FLAGS, flags & !mask).map_err(CpuError::MemoryError)?;
+    }
+    bus.write_u16(IO_REGISTERS_START + IO_IME, 1).map_err(CpuError::MemoryError)?;
+    Ok(WakeCondition::BiosFlags(mask))
+}
+
+// Clears the top 0x200 bytes of on-chip WRAM, resets the registers and enters the ROM, or
+// EWRAM when the flag at 0x03007FFA is set
+pub fn soft_reset<B: Bus>(cpu: &CPU<B>) -> Result<(), CpuError> {
+    let bus = &cpu.memory_bus;
+    let entry = if bus.read_u8(BIOS_RESET_FLAG).map_err(CpuError::MemoryError)? != 0 { WRAM_ONBOARD_START } else { GAMEPAK_ROM_WS0_START };
+    clear(bus, BIOS_RAM_START, WRAM_ONCHIP_START + WRAM_ONCHIP_SIZE as u32 - BIOS_RAM_START)?;
+
+    let register_error = |e: RegisterError| CpuError::RegisterError(e.to_string());
+    for (mode, stack) in [(Mode::SUPERVISOR, SUPERVISOR_STACK), (Mode::IRQ, IRQ_STACK)] {
+        let mut register_set = cpu.register_map.get(mode.clone()).ok_or(CpuError::InvalidMode(mode.bits()))?;
+        write_register_set(&mut register_set, STACK_POINTER, stack).map_err(register_error)?;
+        write_register_set(&mut register_set, LINK_REGISTER, 0).map_err(register_error)?;
+        register_set.spsr.write(0).map_err(register_error)?;
+    }
+
+    let mut cpsr = CPSR::from_bits_truncate(Mode::SYSTEM.bits());
+    cpsr.set_state(CpuState::ARM);
+    cpu.set_cpsr(cpsr)?;
+    for register in 0..=12 {
+        cpu.write_register(register, 0)?;
+    }
+    cpu.write_register(STACK_POINTER, SYSTEM_STACK)?;
+    cpu.write_register(LINK_REGISTER, 0)?;
+    cpu.write_register(PROGRAM_COUNTER, entry)
+}
+
+// Bits of the RegisterRamReset flags in R0
+const RESET_EWRAM: u32 = 1 << 0;
+const RESET_IWRAM: u32 = 1 << 1; // Except the top 0x200 bytes
+const RESET_PALETTE: u32 = 1 << 2;
+const RESET_VRAM: u32 = 1 << 3;
+const RESET_OAM: u32 = 1 << 4;
+const RESET_SIO: u32 = 1 << 5;
+const RESET_SOUND: u32 = 1 << 6;
+const RESET_OTHER_REGISTERS: u32 = 1 << 7;
+
+// IO register ranges, offsets from IO_REGISTERS_START
+const SIO_REGISTERS: [(u32, u32); 1] = [(0x120, 0x160)];
+const SOUND_REGISTERS: [(u32, u32); 1] = [(0x060, 0x0B0)];
+const OTHER_REGISTERS: [(u32, u32); 3] = [(0x000, 0x060), (0x0B0, 0x120), (0x200, 0x20C)];
+
+fn clear<B: Bus>(bus: &B, start: u32, size: u32) -> Result<(), CpuError> {
+    for address in (start..start + size).step_by(4) {
+        bus.write_u32(address, 0).map_err(CpuError::MemoryError)?;
+    }
+    Ok(())
+}
+
+fn clear_registers<B: Bus>(bus: &B, ranges: &[(u32, u32)]) -> Result<(), CpuError> {
+    for (start, end) in ranges {
+        clear(bus, IO_REGISTERS_START + start, end - start)?;
+    }
+    Ok(())
+}
+
+// Clears the memory and IO registers selected by R0
+pub fn register_ram_reset<B: Bus>(register_set: &RegisterSet, bus: &B) -> Result<(), CpuError> {
+    let flags = swi_read_register(register_set, 0)?;
+
+    let memory = [
+        (RESET_EWRAM, WRAM_ONBOARD_START, WRAM_ONBOARD_SIZE as u32),
+        (RESET_IWRAM, WRAM_ONCHIP_START, BIOS_RAM_START - WRAM_ONCHIP_START),
+        (RESET_PALETTE, PALLETE_RAM_START, PALLETE_RAM_SIZE as u32),
+        (RESET_VRAM, VRAM_START, VRAM_SIZE as u32),
+        (RESET_OAM, OAM_START, OAM_SIZE as u32),
+    ];
+    for (flag, start, size) in memory {
+        if (flags & flag) != 0 {
+            clear(bus, start, size)?;
+        }
+    }
+
+    if (flags & RESET_SIO) != 0 {
+        clear_registers(bus, &SIO_REGISTERS)?;
+    }
+    if (flags & RESET_SOUND) != 0 {
+        clear_registers(bus, &SOUND_REGISTERS)?;
+    }
+    if (flags & RESET_OTHER_REGISTERS) != 0 {
+        clear_registers(bus, &OTHER_REGISTERS)?;
+        // IF is cleared by acknowledging, the display is left in forced blank
+        bus.write_u16(IO_REGISTERS_START + IO_IF, 0xFFFF).map_err(CpuError::MemoryError)?;
+        bus.write_u16(IO_REGISTERS_START + IO_DISPCNT, 0x0080).map_err(CpuError::MemoryError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{cpu::swi_write_register, gba::{init_gba_memory_bus, GbaConfig, init_gba_registers, HleBios}, memory::FlatBus};
+
+    const NOP: u32 = 0xE1A00000; // MOV R0, R0
+
+    fn hle_cpu() -> CPU<FlatBus> {
+        let mut cpu = CPU::new(init_gba_registers().unwrap(), FlatBus::new());
+        cpu.set_swi_handler(Box::new(HleBios::new()));
+        cpu.write_register(PROGRAM_COUNTER, WRAM_ONBOARD_START).unwrap();
+        cpu.memory_bus.write_u32(WRAM_ONBOARD_START + 4, NOP).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_halt() {
+        let mut cpu = hle_cpu();
+        cpu.execute(0xEF020000).unwrap(); // SWI 0x02 (Halt)
+        assert!(cpu.is_halted());
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), WRAM_ONBOARD_START + 4);
+
+        // A requested but disabled interrupt does not wake the CPU
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IF, IRQ_VBLANK).unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.is_halted());
+
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IE, IRQ_VBLANK).unwrap();
+        cpu.step().unwrap();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), WRAM_ONBOARD_START + 8);
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut cpu = hle_cpu();
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IE, 0xFFFF).unwrap();
+        cpu.execute(0xEF030000).unwrap(); // SWI 0x03 (Stop)
+
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IF, IRQ_VBLANK).unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.is_halted());
+
+        cpu.memory_bus.write_u16(IO_REGISTERS_START + IO_IF, IRQ_KEYPAD).unwrap();
+        cpu.step().unwrap();
+        assert!(!cpu.is_halted());
+    }
+
+    #[test]
+    fn test_vblank_intr_wait() {
+        let mut cpu = hle_cpu();
+        // Set before the call, discarded
+        cpu.memory_bus.write_u16(BIOS_INTERRUPT_FLAGS, IRQ_VBLANK | IRQ_KEYPAD).unwrap();
+        cpu.execute(0xEF050000).unwrap(); // SWI 0x05 (VBlankIntrWait)
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.memory_bus.read_u16(BIOS_INTERRUPT_FLAGS).unwrap(), IRQ_KEYPAD);
+        assert_eq!(cpu.memory_bus.read_u16(IO_REGISTERS_START + IO_IME).unwrap(), 1);
+
+        cpu.step().unwrap();
+        assert!(cpu.is_halted());
+
+        // The interrupt handler sets the flag
+        cpu.memory_bus.write_u16(BIOS_INTERRUPT_FLAGS, IRQ_VBLANK | IRQ_KEYPAD).unwrap();
+        cpu.step().unwrap();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.memory_bus.read_u16(BIOS_INTERRUPT_FLAGS).unwrap(), IRQ_KEYPAD);
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), WRAM_ONBOARD_START + 8);
+    }
+
+    #[test]
+    fn test_intr_wait_keeps_old_flags() {
+        let mut cpu = hle_cpu();
+        cpu.memory_bus.write_u16(BIOS_INTERRUPT_FLAGS, IRQ_KEYPAD).unwrap();
+        cpu.write_register(0, 0).unwrap();
+        cpu.write_register(1, IRQ_KEYPAD as u32).unwrap();
+        cpu.execute(0xEF040000).unwrap(); // SWI 0x04 (IntrWait)
+
+        cpu.step().unwrap();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.memory_bus.read_u16(BIOS_INTERRUPT_FLAGS).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_soft_reset() {
+        let mut cpu = hle_cpu();
+        cpu.memory_bus.write_u8(BIOS_RESET_FLAG, 1).unwrap();
+        cpu.memory_bus.write_u32(BIOS_RAM_START, 0xDEADBEEF).unwrap();
+        cpu.write_register(5, 0x1234).unwrap();
+
+        cpu.execute(0xEF000000).unwrap(); // SWI 0x00 (SoftReset)
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), WRAM_ONBOARD_START);
+        assert_eq!(cpu.mode().unwrap(), Mode::SYSTEM);
+        assert_eq!(cpu.read_register(5).unwrap(), 0);
+        assert_eq!(cpu.read_register(STACK_POINTER).unwrap(), SYSTEM_STACK);
+        assert_eq!(cpu.memory_bus.read_u32(BIOS_RAM_START).unwrap(), 0);
+        assert_eq!(cpu.memory_bus.read_u8(BIOS_RESET_FLAG).unwrap(), 0);
+
+        let supervisor = cpu.register_map.get(Mode::SUPERVISOR).unwrap();
+        assert_eq!(swi_read_register(&supervisor, STACK_POINTER).unwrap(), SUPERVISOR_STACK);
+        let irq = cpu.register_map.get(Mode::IRQ).unwrap();
+        assert_eq!(swi_read_register(&irq, STACK_POINTER).unwrap(), IRQ_STACK);
+
+        // Without the flag the ROM is entered
+        cpu.execute(0xEF000000).unwrap();
+        assert_eq!(cpu.read_register(PROGRAM_COUNTER).unwrap(), GAMEPAK_ROM_WS0_START);
+    }
+
+    #[test]
+    fn test_register_ram_reset() {
+        let bus = init_gba_memory_bus(&GbaConfig::default()).unwrap();
+        let registers = init_gba_registers().unwrap().get(Mode::SYSTEM).unwrap();
+        bus.write_u32(WRAM_ONBOARD_START + 0x100, 1).unwrap();
+        bus.write_u32(WRAM_ONCHIP_START, 2).unwrap();
+        bus.write_u32(BIOS_INTERRUPT_FLAGS, 3).unwrap();
+        bus.write_u32(VRAM_START + 0x100, 4).unwrap();
+        bus.write_u16(IO_REGISTERS_START + IO_IE, 0x3FFF).unwrap();
+        bus.write_u16(IO_REGISTERS_START + 0x80, 5).unwrap();
+
+        swi_write_register(&registers, 0, RESET_EWRAM | RESET_IWRAM | RESET_OTHER_REGISTERS).unwrap();
+        register_ram_reset(&registers, &bus).unwrap();
+        assert_eq!(bus.read_u32(WRAM_ONBOARD_START + 0x100).unwrap(), 0);
+        assert_eq!(bus.read_u32(WRAM_ONCHIP_START).unwrap(), 0);
+        assert_eq!(bus.read_u32(BIOS_INTERRUPT_FLAGS).unwrap(), 3);
+        assert_eq!(bus.read_u32(VRAM_START + 0x100).unwrap(), 4);
+        assert_eq!(bus.read_u16(IO_REGISTERS_START + IO_IE).unwrap(), 0);
+        assert_eq!(bus.read_u16(IO_REGISTERS_START + IO_DISPCNT).unwrap(), 0x0080);
+        assert_eq!(bus.read_u16(IO_REGISTERS_START + 0x80).unwrap(), 5);
+    }
+}
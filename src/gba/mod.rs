@@ -1,9 +1,31 @@
 mod gba_constants;
+mod gba_config;
 mod gba_cpu;
 mod gba_memory_bus;
 mod gba_registers;
+mod gba_io_registers;
+mod gba_interrupts;
+mod gba_cartridge;
+mod gba_bios;
+mod gba_wait_states;
+mod gba_backup;
+mod gba_sram;
+mod gba_flash;
+mod gba_eeprom;
+mod hle_bios;
 
+pub use gba_config::*;
 pub use gba_cpu::*;
 pub use gba_memory_bus::*;
 pub use gba_registers::*;
-pub use gba_constants::*;
\ No newline at end of file
+pub use gba_constants::*;
+pub use gba_io_registers::*;
+pub use gba_interrupts::*;
+pub use gba_cartridge::*;
+pub use gba_bios::*;
+pub use gba_wait_states::*;
+pub use gba_backup::*;
+pub use gba_sram::*;
+pub use gba_flash::*;
+pub use gba_eeprom::*;
+pub use hle_bios::*;
\ No newline at end of file
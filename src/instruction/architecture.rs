@@ -0,0 +1,30 @@
+use strum_macros::Display;
+
+// Instruction set the decoder accepts
+#[derive(Debug, Clone, Copy, Default, Display, PartialEq, Eq, Hash)]
+pub enum Architecture {
+    // ARM7TDMI (GBA)
+    #[default]
+    ARMv4T,
+    // ARM9E (DS): adds CLZ, BLX, saturating arithmetic, DSP multiplies and LDRD/STRD
+    ARMv5TE,
+}
+
+impl Architecture {
+    pub fn from_name(name: &str) -> Option<Architecture> {
+        match name {
+            "v4t" => Some(Architecture::ARMv4T),
+            "v5te" => Some(Architecture::ARMv5TE),
+            _ => None,
+        }
+    }
+
+    pub fn has_v5te(&self) -> bool {
+        matches!(self, Architecture::ARMv5TE)
+    }
+
+    // ARMv5 switches to THUMB when LDR/LDM load the PC with bit 0 set
+    pub fn has_load_interworking(&self) -> bool {
+        self.has_v5te()
+    }
+}
@@ -0,0 +1,217 @@
+use core::fmt;
+
+use crate::{memory::{AccessWidth, Bus}, register::{ReadRegister, RegisterSet, CPSR}};
+
+use super::{encode_flag, read_register, write_cpsr, write_register, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, Instruction, InstructionMetadata, InstructionError, PROGRAM_COUNTER};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDataTransferInstruction {
+    pub condition_bits: u8, // Bits 31-28
+    // 27-25 must be 100b for this instruction
+    pub pre_index: bool, // Bit 24 (0=post: add offset after transfer, 1=pre: before transfer)
+    pub up: bool, // Bit 23 (0=down: subtract offset from base, 1=up: add to base)
+    pub psr: bool, // Bit 22 (PSR & force user bit) (LDM with R15: restore the CPSR from the SPSR)
+    pub write_back: bool, // Bit 21 (0=no write-back, 1=write address into base)
+    pub load: bool, // Bit 20 (0=STM, 1=LDM)
+    pub rn: u8, // Bits 19-16 (Base register: R0-R14)
+    pub register_list: u16, // Bits 15-0 (Bit n set transfers Rn)
+}
+
+impl BlockDataTransferInstruction {
+    pub fn condition(&self) -> Condition {
+        Condition::from_bits_truncate(self.condition_bits)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        if self.load { "LDM" } else { "STM" }
+    }
+
+    pub fn registers(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..16).filter(|register| (self.register_list & (1 << register)) != 0)
+    }
+}
+
+impl fmt::Display for BlockDataTransferInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addressing_mode = match (self.up, self.pre_index) {
+            (true, false) => "IA",
+            (true, true) => "IB",
+            (false, false) => "DA",
+            (false, true) => "DB",
+        };
+        let registers: Vec<String> = self.registers().map(|register| format!("R{}", register)).collect();
+        write!(f, "{}{}{{{}}} R{}{},{{{}}}{}", self.mnemonic(), addressing_mode, self.condition(), self.rn,
+            if self.write_back { "!" } else { "" }, registers.join(","), if self.psr { "^" } else { "" })
+    }
+}
+
+pub fn is_block_data_transfer_instruction(value: u32) -> bool {
+    let bits_27_25 = (value >> 25) & 0b111;
+    bits_27_25 == 0b100
+}
+
+impl DecodeInstruction for BlockDataTransferInstruction {
+    fn decode(value: u32) -> Result<Self, InstructionError>
+        where
+            Self: Sized {
+
+        let condition_bits = (value >> 28) as u8;
+        if !is_block_data_transfer_instruction(value) {
+            return Err(InstructionError::InvalidInstruction(value));
+        }
+
+        Ok(BlockDataTransferInstruction {
+            condition_bits,
+            pre_index: (value & (1 << 24)) != 0,
+            up: (value & (1 << 23)) != 0,
+            psr: (value & (1 << 22)) != 0,
+            write_back: (value & (1 << 21)) != 0,
+            load: (value & (1 << 20)) != 0,
+            rn: ((value >> 16) & 0xF) as u8,
+            register_list: (value & 0xFFFF) as u16,
+        })
+    }
+}
+
+impl DescribeInstruction for BlockDataTransferInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        let registers: Vec<u8> = self.registers().collect();
+        let mut metadata = InstructionMetadata::new(self.condition()).read(&[self.rn]);
+
+        metadata = if self.load { metadata.write(&registers) } else { metadata.read(&registers) };
+        if self.write_back {
+            metadata = metadata.write(&[self.rn]);
+        }
+        // LDM with R15 and the S bit restores the CPSR from the SPSR
+        if self.load && self.psr && registers.contains(&PROGRAM_COUNTER) {
+            metadata = metadata.write_flags(CPSR::all());
+        }
+        metadata.access(self.load, AccessWidth::Word, Some(registers.len())).with_pc_write()
+    }
+}
+
+impl EncodeInstruction for BlockDataTransferInstruction {
+    fn encode(&self) -> u32 {
+        ((self.condition_bits as u32) << 28)
+            | (0b100 << 25)
+            | encode_flag(self.pre_index, 24)
+            | encode_flag(self.up, 23)
+            | encode_flag(self.psr, 22)
+            | encode_flag(self.write_back, 21)
+            | encode_flag(self.load, 20)
+            | ((self.rn as u32) << 16)
+            | self.register_list as u32
+    }
+}
+
+impl Instruction for BlockDataTransferInstruction {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, bus: &B) -> Result<(), InstructionError> {
+        let base = read_register(register_set, self.rn)?;
+        let size = self.register_list.count_ones() * 4;
+
+        // Registers are always transferred lowest first, at the lowest address
+        let start_address = match (self.up, self.pre_index) {
+            (true, false) => base,
+            (true, true) => base.wrapping_add(4),
+            (false, false) => base.wrapping_sub(size).wrapping_add(4),
+            (false, true) => base.wrapping_sub(size),
+        };
+        let final_address = if self.up { base.wrapping_add(size) } else { base.wrapping_sub(size) };
+
+        if self.load {
+            if self.write_back {
+                write_register(register_set, self.rn, final_address)?;
+            }
+            for (i, register) in self.registers().enumerate() {
+                // Block transfers ignore the low bits of the base
+                let value = bus.read_u32(start_address.wrapping_add(i as u32 * 4) & !3).map_err(InstructionError::MemoryError)?;
+                write_register(register_set, register, value)?;
+            }
+
+            // Return from exception: restore the CPSR from the SPSR
+            // TODO: S without R15 transfers the User mode registers
+            if self.psr && (self.register_list & (1 << PROGRAM_COUNTER)) != 0 {
+                let spsr = register_set.spsr.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))?;
+                write_cpsr(register_set, CPSR::from_bits(spsr).ok_or(InstructionError::InvalidCPSR())?)?;
+            }
+            return Ok(());
+        }
+
+        for (i, register) in self.registers().enumerate() {
+            let mut value = read_register(register_set, register)?;
+            // Storing the PC stores the address of the instruction + 12
+            if register == PROGRAM_COUNTER {
+                value = value.wrapping_add(4);
+            }
+            bus.store_u32(start_address.wrapping_add(i as u32 * 4), value).map_err(InstructionError::MemoryError)?;
+        }
+        if self.write_back {
+            write_register(register_set, self.rn, final_address)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{instruction::assert_round_trip, memory::FlatBus, register::RegisterCell};
+
+    use super::*;
+
+    fn register_set() -> RegisterSet {
+        let mut builder = RegisterSet::builder();
+        for register in 0..16 {
+            builder.with_register(register, RegisterCell::new(register as u32)).unwrap();
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_stmdb_ldmia() {
+        // STMDB R13!, {R1, R2, R14}
+        let value: u32 = 0xE92D4006;
+        let mut stmdb = BlockDataTransferInstruction::decode(value).unwrap();
+        assert_eq!(stmdb.to_string(), "STMDB{AL} R13!,{R1,R2,R14}");
+
+        let bus = FlatBus::new();
+        let register_set = register_set();
+        write_register(&register_set, 13, 0x110).unwrap();
+        stmdb.execute(&register_set, &bus).unwrap();
+        assert_eq!(read_register(&register_set, 13).unwrap(), 0x104);
+        assert_eq!(bus.read_u32(0x104).unwrap(), 1);
+        assert_eq!(bus.read_u32(0x108).unwrap(), 2);
+        assert_eq!(bus.read_u32(0x10C).unwrap(), 14);
+
+        // LDMIA R13!, {R4, R5, R6}
+        let value: u32 = 0xE8BD0070;
+        let mut ldmia = BlockDataTransferInstruction::decode(value).unwrap();
+        ldmia.execute(&register_set, &bus).unwrap();
+        assert_eq!(read_register(&register_set, 13).unwrap(), 0x110);
+        assert_eq!(read_register(&register_set, 4).unwrap(), 1);
+        assert_eq!(read_register(&register_set, 5).unwrap(), 2);
+        assert_eq!(read_register(&register_set, 6).unwrap(), 14);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Every register list and flag combination
+        let values = (0..32u32).flat_map(|bits_24_20| (0..=0xFFFFu32).step_by(3).map(move |register_list| {
+            (0xE << 28) | (0b100 << 25) | (bits_24_20 << 20) | ((bits_24_20 & 0xF) << 16) | register_list
+        }));
+        assert_eq!(assert_round_trip::<BlockDataTransferInstruction>(values), 32 * 21846);
+
+        let values = (0..16u32).map(|condition| (condition << 28) | 0x08BD_8070);
+        assert_eq!(assert_round_trip::<BlockDataTransferInstruction>(values), 16);
+    }
+
+    #[test]
+    fn test_metadata() {
+        // LDMIA R13!, {R4, PC}
+        let metadata = BlockDataTransferInstruction::decode(0xE8BD8010).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![13]);
+        assert_eq!(metadata.registers_written, vec![4, 13, PROGRAM_COUNTER]);
+        assert_eq!(metadata.memory_access.unwrap().count, Some(2));
+        assert_eq!(metadata.control_flow, crate::instruction::ControlFlow::Indirect { link: false });
+    }
+}
@@ -0,0 +1,283 @@
+use core::fmt;
+
+use crate::{cpu::CpuState, memory::Bus, register::{RegisterSet, CPSR}};
+
+use super::{encode_flag, read_cpsr, read_register, write_cpsr, write_register, Condition, ControlFlow, DecodeInstruction, DescribeInstruction, EncodeInstruction, Instruction, InstructionMetadata, InstructionError, LINK_REGISTER, PROGRAM_COUNTER};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchInstruction {
+    pub condition_bits: u8, // Bits 31-28 (Must be 1111b for BLX with an immediate offset)
+    pub operation: BranchOperation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BranchOperation {
+    // B/BL: bits 27-25 must be 101b
+    Branch {
+        link: bool, // Bit 24 (0=B, 1=BL)
+        offset: i32, // Bits 23-0 (Signed offset in words, relative to PC+8)
+    },
+    // BX/BLX: bits 27-8 must be 0001_0010_1111_1111_1111b, bits 7-6 00b and bit 4 1
+    BranchExchange {
+        link: bool, // Bit 5 (0=BX, 1=BLX: ARMv5 and above)
+        rm: u8, // Bits 3-0 (Target address register, bit 0 selects THUMB state)
+    },
+    // BLX: bits 27-25 must be 101b (ARMv5 and above)
+    BranchLinkExchange {
+        halfword: bool, // Bit 24 (Added to the target as bit 1)
+        offset: i32, // Bits 23-0 (Signed offset in words, relative to PC+8)
+    },
+}
+
+impl BranchInstruction {
+    pub fn condition(&self) -> Condition {
+        match self.operation {
+            // The condition field is part of the BLX encoding, it always executes
+            BranchOperation::BranchLinkExchange { .. } => Condition::AL,
+            _ => Condition::from_bits_truncate(self.condition_bits),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self.operation {
+            BranchOperation::Branch { link: false, .. } => "B",
+            BranchOperation::Branch { link: true, .. } => "BL",
+            BranchOperation::BranchExchange { link: false, .. } => "BX",
+            BranchOperation::BranchExchange { link: true, .. } | BranchOperation::BranchLinkExchange { .. } => "BLX",
+        }
+    }
+
+    // Target relative to the address of the branch, for immediate branches
+    pub fn relative_target(&self) -> Option<i32> {
+        match self.operation {
+            BranchOperation::Branch { offset, .. } => Some(8 + offset * 4),
+            BranchOperation::BranchLinkExchange { halfword, offset } => Some(8 + offset * 4 + if halfword { 2 } else { 0 }),
+            BranchOperation::BranchExchange { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for BranchInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.operation {
+            BranchOperation::BranchExchange { rm, .. } => write!(f, "{}{{{}}} R{}", self.mnemonic(), self.condition(), rm),
+            BranchOperation::BranchLinkExchange { .. } => write!(f, "{} ${:+}", self.mnemonic(), self.relative_target().unwrap_or_default()),
+            BranchOperation::Branch { .. } => write!(f, "{}{{{}}} ${:+}", self.mnemonic(), self.condition(), self.relative_target().unwrap_or_default()),
+        }
+    }
+}
+
+pub fn is_branch_instruction(value: u32) -> bool {
+    (value >> 25) & 0b111 == 0b101
+}
+
+pub fn is_branch_exchange_instruction(value: u32) -> bool {
+    (value & 0x0FFF_FFD0) == 0x012F_FF10
+}
+
+// Sign extends the 24-bit word offset
+fn branch_offset(value: u32) -> i32 {
+    ((value << 8) as i32) >> 8
+}
+
+impl DecodeInstruction for BranchInstruction {
+    fn decode(value: u32) -> Result<Self, InstructionError>
+        where
+            Self: Sized {
+
+        let condition_bits = (value >> 28) as u8;
+
+        let operation = if is_branch_exchange_instruction(value) {
+            BranchOperation::BranchExchange {
+                link: (value & (1 << 5)) != 0,
+                rm: (value & 0xF) as u8,
+            }
+        } else if is_branch_instruction(value) && Condition::from_bits_truncate(condition_bits) == Condition::NV {
+            BranchOperation::BranchLinkExchange {
+                halfword: (value & (1 << 24)) != 0,
+                offset: branch_offset(value),
+            }
+        } else if is_branch_instruction(value) {
+            BranchOperation::Branch {
+                link: (value & (1 << 24)) != 0,
+                offset: branch_offset(value),
+            }
+        } else {
+            return Err(InstructionError::InvalidInstruction(value));
+        };
+
+        Ok(BranchInstruction {
+            condition_bits,
+            operation,
+        })
+    }
+}
+
+impl DescribeInstruction for BranchInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        let metadata = InstructionMetadata::new(self.condition()).write(&[PROGRAM_COUNTER]);
+        let (metadata, link) = match self.operation {
+            BranchOperation::Branch { link, .. } => {
+                let relative_target = self.relative_target().unwrap_or_default();
+                (metadata.read(&[PROGRAM_COUNTER]).flow(ControlFlow::Branch { relative_target, link, exchange: false }), link)
+            }
+            BranchOperation::BranchExchange { link, rm } => {
+                (metadata.read(&[rm]).write_flags(CPSR::T).flow(ControlFlow::Indirect { link }), link)
+            }
+            BranchOperation::BranchLinkExchange { .. } => {
+                let relative_target = self.relative_target().unwrap_or_default();
+                (metadata.read(&[PROGRAM_COUNTER]).write_flags(CPSR::T).flow(ControlFlow::Branch { relative_target, link: true, exchange: true }), true)
+            }
+        };
+
+        if link {
+            return metadata.write(&[LINK_REGISTER]);
+        }
+        metadata
+    }
+}
+
+impl EncodeInstruction for BranchInstruction {
+    fn encode(&self) -> u32 {
+        let operation = match self.operation {
+            BranchOperation::Branch { link, offset } => (0b101 << 25) | encode_flag(link, 24) | (offset as u32 & 0x00FF_FFFF),
+            BranchOperation::BranchExchange { link, rm } => 0x012F_FF10 | encode_flag(link, 5) | rm as u32,
+            BranchOperation::BranchLinkExchange { halfword, offset } => (0b101 << 25) | encode_flag(halfword, 24) | (offset as u32 & 0x00FF_FFFF),
+        };
+
+        ((self.condition_bits as u32) << 28) | operation
+    }
+}
+
+impl Instruction for BranchInstruction {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, _bus: &B) -> Result<(), InstructionError> {
+        // PC reads as the address of this instruction + 8
+        let pc = read_register(register_set, PROGRAM_COUNTER)?;
+        let return_address = pc.wrapping_sub(4);
+
+        match self.operation {
+            BranchOperation::Branch { link, offset } => {
+                if link {
+                    write_register(register_set, LINK_REGISTER, return_address)?;
+                }
+                write_register(register_set, PROGRAM_COUNTER, pc.wrapping_add((offset * 4) as u32))
+            }
+            BranchOperation::BranchExchange { link, rm } => {
+                let target = read_register(register_set, rm)?;
+                if link {
+                    write_register(register_set, LINK_REGISTER, return_address)?;
+                }
+                branch_exchange(register_set, target)
+            }
+            BranchOperation::BranchLinkExchange { halfword, offset } => {
+                write_register(register_set, LINK_REGISTER, return_address)?;
+                let target = pc.wrapping_add((offset * 4) as u32).wrapping_add(if halfword { 2 } else { 0 });
+                // Bit 0 set to always switch to THUMB
+                branch_exchange(register_set, target | 1)
+            }
+        }
+    }
+}
+
+// Jumps to the target, bit 0 selects the THUMB (1) or ARM (0) state
+pub fn branch_exchange(register_set: &RegisterSet, target: u32) -> Result<(), InstructionError> {
+    let mut cpsr = read_cpsr(register_set)?;
+    if target & 1 != 0 {
+        cpsr.set_state(CpuState::THUMB);
+        write_cpsr(register_set, cpsr)?;
+        write_register(register_set, PROGRAM_COUNTER, target & !1)
+    } else {
+        cpsr.set_state(CpuState::ARM);
+        write_cpsr(register_set, cpsr)?;
+        write_register(register_set, PROGRAM_COUNTER, target & !3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{instruction::assert_round_trip, memory::FlatBus, register::{RegisterCell, CPSRCell, CPSR}};
+
+    use super::*;
+
+    fn register_set(pc: u32) -> RegisterSet {
+        RegisterSet::builder()
+            .with_register(0, RegisterCell::new(0)).unwrap()
+            .with_register(LINK_REGISTER, RegisterCell::new(0)).unwrap()
+            .with_register(PROGRAM_COUNTER, RegisterCell::new(pc + 8)).unwrap()
+            .with_cpsr(CPSRCell::new(CPSR::default())).unwrap()
+            .build()
+    }
+
+    #[test]
+    fn test_branch_link_decode_and_execute() {
+        // BL $-8 (offset -4 words)
+        let value: u32 = 0b1110_101_1_111111111111111111111100;
+        let mut instruction = BranchInstruction::decode(value).unwrap();
+        assert_eq!(instruction.operation, BranchOperation::Branch { link: true, offset: -4 });
+        assert_eq!(instruction.to_string(), "BL{AL} $-8");
+
+        let register_set = register_set(0x100);
+        instruction.execute(&register_set, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&register_set, PROGRAM_COUNTER).unwrap(), 0xF8);
+        assert_eq!(read_register(&register_set, LINK_REGISTER).unwrap(), 0x104);
+    }
+
+    #[test]
+    fn test_branch_exchange_to_thumb() {
+        // BX R0
+        let value: u32 = 0xE12FFF10;
+        let mut instruction = BranchInstruction::decode(value).unwrap();
+        assert_eq!(instruction.operation, BranchOperation::BranchExchange { link: false, rm: 0 });
+
+        let register_set = register_set(0x100);
+        write_register(&register_set, 0, 0x201).unwrap();
+        instruction.execute(&register_set, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&register_set, PROGRAM_COUNTER).unwrap(), 0x200);
+        assert_eq!(read_cpsr(&register_set).unwrap().state(), CpuState::THUMB);
+    }
+
+    #[test]
+    fn test_branch_link_exchange_immediate() {
+        // BLX $+14 (offset 1 word, H=1)
+        let value: u32 = 0b1111_101_1_000000000000000000000001;
+        let mut instruction = BranchInstruction::decode(value).unwrap();
+        assert_eq!(instruction.operation, BranchOperation::BranchLinkExchange { halfword: true, offset: 1 });
+        assert_eq!(instruction.relative_target(), Some(14));
+
+        let register_set = register_set(0x100);
+        instruction.execute(&register_set, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&register_set, PROGRAM_COUNTER).unwrap(), 0x10E);
+        assert_eq!(read_register(&register_set, LINK_REGISTER).unwrap(), 0x104);
+        assert_eq!(read_cpsr(&register_set).unwrap().state(), CpuState::THUMB);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let offsets: [u32; 6] = [0, 1, 0x7F_FFFF, 0x80_0000, 0xFF_FFFF, 0x12_3456];
+        let branches = (0..16u32).flat_map(|condition| (0xAu32..=0xB).flat_map(move |bits_27_24| offsets.map(|offset| {
+            (condition << 28) | (bits_27_24 << 24) | offset
+        })));
+        assert_eq!(assert_round_trip::<BranchInstruction>(branches), 16 * 2 * 6);
+
+        let exchanges = (0..16u32).flat_map(|condition| (0..2u32).flat_map(move |link| (0..16u32).map(move |rm| {
+            (condition << 28) | 0x012F_FF10 | (link << 5) | rm
+        })));
+        assert_eq!(assert_round_trip::<BranchInstruction>(exchanges), 16 * 2 * 16);
+    }
+
+    #[test]
+    fn test_metadata() {
+        // BLNE $-8
+        let metadata = BranchInstruction::decode(0x1BFFFFFC).unwrap().metadata();
+        assert_eq!(metadata.registers_written, vec![LINK_REGISTER, PROGRAM_COUNTER]);
+        assert_eq!(metadata.flags_read, CPSR::Z);
+        assert_eq!(metadata.control_flow, ControlFlow::Branch { relative_target: -8, link: true, exchange: false });
+
+        // BX LR
+        let metadata = BranchInstruction::decode(0xE12FFF1E).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![LINK_REGISTER]);
+        assert_eq!(metadata.flags_written, CPSR::T);
+        assert_eq!(metadata.control_flow, ControlFlow::Indirect { link: false });
+    }
+}
@@ -0,0 +1,250 @@
+use core::fmt;
+
+use crate::{instruction::{encode_flag, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, InstructionError, InstructionMetadata, PROGRAM_COUNTER}, memory::AccessWidth, register::CPSR};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoprocessorInstruction {
+    pub condition_bits: u8, // Bits 31-28
+    pub cp_num: u8, // Bits 11-8 (Coprocessor number: P0-P15)
+    pub operation: CoprocessorOperation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoprocessorOperation {
+    // CDP: bits 27-24 must be 1110b and bit 4 must be 0
+    DataOperation {
+        cp_opc: u8, // Bits 23-20 (Coprocessor operation code)
+        crn: u8, // Bits 19-16 (Coprocessor operand register: C0-C15)
+        crd: u8, // Bits 15-12 (Coprocessor destination register: C0-C15)
+        cp: u8, // Bits 7-5 (Coprocessor information)
+        crm: u8, // Bits 3-0 (Coprocessor operand register: C0-C15)
+    },
+    // MCR/MRC: bits 27-24 must be 1110b and bit 4 must be 1
+    RegisterTransfer {
+        cp_opc: u8, // Bits 23-21 (Coprocessor operation code)
+        load: bool, // Bit 20 (0=MCR: ARM to coprocessor, 1=MRC: coprocessor to ARM)
+        crn: u8, // Bits 19-16 (Coprocessor source/destination register: C0-C15)
+        rd: u8, // Bits 15-12 (ARM source/destination register: R0-R15)
+        cp: u8, // Bits 7-5 (Coprocessor information)
+        crm: u8, // Bits 3-0 (Coprocessor operand register: C0-C15)
+    },
+    // LDC/STC: bits 27-25 must be 110b
+    DataTransfer {
+        pre_index: bool, // Bit 24 (0=post, 1=pre)
+        up: bool, // Bit 23 (0=down, 1=up)
+        long: bool, // Bit 22 (Transfer length, meaning depends on the coprocessor)
+        write_back: bool, // Bit 21 (0=no write-back, 1=write address into Rn)
+        load: bool, // Bit 20 (0=STC: store to memory, 1=LDC: load from memory)
+        rn: u8, // Bits 19-16 (ARM base register: R0-R15)
+        crd: u8, // Bits 15-12 (Coprocessor source/destination register: C0-C15)
+        offset: u8, // Bits 7-0 (Unsigned 8-bit immediate offset, in words)
+    },
+}
+
+impl CoprocessorInstruction {
+    pub fn condition(&self) -> Condition {
+        Condition::from_bits_truncate(self.condition_bits)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self.operation {
+            CoprocessorOperation::DataOperation { .. } => "CDP",
+            CoprocessorOperation::RegisterTransfer { load: false, .. } => "MCR",
+            CoprocessorOperation::RegisterTransfer { load: true, .. } => "MRC",
+            CoprocessorOperation::DataTransfer { load: false, .. } => "STC",
+            CoprocessorOperation::DataTransfer { load: true, .. } => "LDC",
+        }
+    }
+}
+
+impl fmt::Display for CoprocessorInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{{{}}} P{},", self.mnemonic(), self.condition(), self.cp_num)?;
+        match self.operation {
+            CoprocessorOperation::DataOperation { cp_opc, crn, crd, cp, crm } => {
+                write!(f, "{},C{},C{},C{},{}", cp_opc, crd, crn, crm, cp)
+            }
+            CoprocessorOperation::RegisterTransfer { cp_opc, crn, rd, cp, crm, .. } => {
+                write!(f, "{},R{},C{},C{},{}", cp_opc, rd, crn, crm, cp)
+            }
+            CoprocessorOperation::DataTransfer { crd, rn, offset, .. } => {
+                write!(f, "C{},[R{}],#{}", crd, rn, offset as u32 * 4)
+            }
+        }
+    }
+}
+
+pub fn is_coprocessor_instruction(value: u32) -> bool {
+    let bits_27_24 = (value >> 24) & 0b1111;
+    let bits_27_25 = (value >> 25) & 0b111;
+    bits_27_24 == 0b1110 || bits_27_25 == 0b110
+}
+
+impl DecodeInstruction for CoprocessorInstruction {
+    fn decode(value: u32) -> Result<Self, InstructionError>
+        where
+            Self: Sized {
+
+        let condition_bits = (value >> 28) as u8;
+        if !is_coprocessor_instruction(value) {
+            return Err(InstructionError::InvalidInstruction(value));
+        }
+
+        let cp_num = ((value >> 8) & 0xF) as u8;
+        let crn = ((value >> 16) & 0xF) as u8;
+        let crd = ((value >> 12) & 0xF) as u8;
+        let cp = ((value >> 5) & 0x7) as u8;
+        let crm = (value & 0xF) as u8;
+
+        let operation = if (value >> 25) & 0b111 == 0b110 {
+            CoprocessorOperation::DataTransfer {
+                pre_index: (value & (1 << 24)) != 0,
+                up: (value & (1 << 23)) != 0,
+                long: (value & (1 << 22)) != 0,
+                write_back: (value & (1 << 21)) != 0,
+                load: (value & (1 << 20)) != 0,
+                rn: crn,
+                crd,
+                offset: (value & 0xFF) as u8,
+            }
+        } else if (value & (1 << 4)) != 0 {
+            CoprocessorOperation::RegisterTransfer {
+                cp_opc: ((value >> 21) & 0x7) as u8,
+                load: (value & (1 << 20)) != 0,
+                crn,
+                rd: crd,
+                cp,
+                crm,
+            }
+        } else {
+            CoprocessorOperation::DataOperation {
+                cp_opc: ((value >> 20) & 0xF) as u8,
+                crn,
+                crd,
+                cp,
+                crm,
+            }
+        };
+
+        Ok(CoprocessorInstruction {
+            condition_bits,
+            cp_num,
+            operation,
+        })
+    }
+}
+
+impl DescribeInstruction for CoprocessorInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        let metadata = InstructionMetadata::new(self.condition());
+        let metadata = match self.operation {
+            CoprocessorOperation::DataOperation { .. } => metadata,
+            CoprocessorOperation::RegisterTransfer { load: false, rd, .. } => metadata.read(&[rd]),
+            // MRC to R15 sets the flags instead
+            CoprocessorOperation::RegisterTransfer { load: true, rd: PROGRAM_COUNTER, .. } => metadata.write_flags(CPSR::N | CPSR::Z | CPSR::C | CPSR::V),
+            CoprocessorOperation::RegisterTransfer { load: true, rd, .. } => metadata.write(&[rd]),
+            CoprocessorOperation::DataTransfer { write_back, load, rn, .. } => {
+                // The coprocessor decides how many words are transferred
+                let metadata = metadata.read(&[rn]).access(load, AccessWidth::Word, None);
+                if write_back { metadata.write(&[rn]) } else { metadata }
+            }
+        };
+        metadata.with_pc_write()
+    }
+}
+
+impl EncodeInstruction for CoprocessorInstruction {
+    fn encode(&self) -> u32 {
+        let operation = match self.operation {
+            CoprocessorOperation::DataOperation { cp_opc, crn, crd, cp, crm } => {
+                (0b1110 << 24) | ((cp_opc as u32) << 20) | ((crn as u32) << 16) | ((crd as u32) << 12) | ((cp as u32) << 5) | crm as u32
+            }
+            CoprocessorOperation::RegisterTransfer { cp_opc, load, crn, rd, cp, crm } => {
+                (0b1110 << 24) | ((cp_opc as u32) << 21) | encode_flag(load, 20) | ((crn as u32) << 16) | ((rd as u32) << 12) | ((cp as u32) << 5) | (1 << 4) | crm as u32
+            }
+            CoprocessorOperation::DataTransfer { pre_index, up, long, write_back, load, rn, crd, offset } => {
+                (0b110 << 25) | encode_flag(pre_index, 24) | encode_flag(up, 23) | encode_flag(long, 22) | encode_flag(write_back, 21) | encode_flag(load, 20)
+                    | ((rn as u32) << 16) | ((crd as u32) << 12) | offset as u32
+            }
+        };
+
+        ((self.condition_bits as u32) << 28) | ((self.cp_num as u32) << 8) | operation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::instruction::assert_round_trip;
+
+    use super::*;
+
+    #[test]
+    fn test_mrc_decode() {
+        // MRC p15, 0, R0, c0, c0, 0
+        let value: u32 = 0xEE100F10;
+        let instruction = CoprocessorInstruction::decode(value).unwrap();
+        assert_eq!(instruction.condition(), Condition::AL);
+        assert_eq!(instruction.cp_num, 15);
+        assert_eq!(instruction.mnemonic(), "MRC");
+        assert_eq!(instruction.operation, CoprocessorOperation::RegisterTransfer {
+            cp_opc: 0,
+            load: true,
+            crn: 0,
+            rd: 0,
+            cp: 0,
+            crm: 0,
+        });
+    }
+
+    #[test]
+    fn test_cdp_decode() {
+        // CDP p14, 3, c1, c2, c3, 4
+        let value: u32 = 0b1110_1110_0011_0010_0001_1110_100_0_0011;
+        let instruction = CoprocessorInstruction::decode(value).unwrap();
+        assert_eq!(instruction.cp_num, 14);
+        assert_eq!(instruction.operation, CoprocessorOperation::DataOperation {
+            cp_opc: 3,
+            crn: 2,
+            crd: 1,
+            cp: 4,
+            crm: 3,
+        });
+        assert_eq!(instruction.to_string(), "CDP{AL} P14,3,C1,C2,C3,4");
+    }
+
+    #[test]
+    fn test_ldc_decode() {
+        // LDC p6, c2, [R3, #+8]!
+        let value: u32 = 0b1110_110_1_1_0_1_1_0011_0010_0110_00000010;
+        let instruction = CoprocessorInstruction::decode(value).unwrap();
+        assert_eq!(instruction.cp_num, 6);
+        assert_eq!(instruction.mnemonic(), "LDC");
+        assert_eq!(instruction.operation, CoprocessorOperation::DataTransfer {
+            pre_index: true,
+            up: true,
+            long: false,
+            write_back: true,
+            load: true,
+            rn: 3,
+            crd: 2,
+            offset: 2,
+        });
+    }
+
+    #[test]
+    fn test_invalid_coprocessor_instruction() {
+        // SWI is not a coprocessor instruction
+        let value: u32 = 0xEF000000;
+        assert_eq!(CoprocessorInstruction::decode(value).err(), Some(InstructionError::InvalidInstruction(value)));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Every condition, bits 27-20, coprocessor number and bits 7-4
+        let values = (0..16u32).flat_map(|condition| (0xC0..0xF0u32).flat_map(move |bits_27_20| (0..256u32).map(move |bits_11_4| {
+            (condition << 28) | (bits_27_20 << 20) | (((bits_11_4 * 5) & 0xF) << 16) | ((condition ^ 0xA) << 12) | (bits_11_4 << 4) | (bits_11_4 & 0xF)
+        })));
+        assert_eq!(assert_round_trip::<CoprocessorInstruction>(values), 16 * 48 * 256);
+    }
+}
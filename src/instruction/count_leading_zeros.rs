@@ -0,0 +1,101 @@
+use core::fmt;
+
+use crate::{memory::Bus, register::RegisterSet};
+
+use super::{read_register, write_register, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, Instruction, InstructionError, InstructionMetadata};
+
+// CLZ: ARMv5 and above
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountLeadingZerosInstruction {
+    pub condition_bits: u8, // Bits 31-28
+    // 27-16 must be 0001_0110_1111b for this instruction
+    pub rd: u8, // Bits 15-12 (Destination register: R0-R14)
+    // 11-4 must be 1111_0001b for this instruction
+    pub rm: u8, // Bits 3-0 (Operand register: R0-R14)
+}
+
+impl CountLeadingZerosInstruction {
+    pub fn condition(&self) -> Condition {
+        Condition::from_bits_truncate(self.condition_bits)
+    }
+}
+
+impl fmt::Display for CountLeadingZerosInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CLZ{{{}}} R{},R{}", self.condition(), self.rd, self.rm)
+    }
+}
+
+pub fn is_count_leading_zeros_instruction(value: u32) -> bool {
+    (value & 0x0FFF_0FF0) == 0x016F_0F10
+}
+
+impl DecodeInstruction for CountLeadingZerosInstruction {
+    fn decode(value: u32) -> Result<Self, InstructionError>
+        where
+            Self: Sized {
+
+        if !is_count_leading_zeros_instruction(value) {
+            return Err(InstructionError::InvalidInstruction(value));
+        }
+
+        Ok(CountLeadingZerosInstruction {
+            condition_bits: (value >> 28) as u8,
+            rd: ((value >> 12) & 0xF) as u8,
+            rm: (value & 0xF) as u8,
+        })
+    }
+}
+
+impl DescribeInstruction for CountLeadingZerosInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        InstructionMetadata::new(self.condition()).read(&[self.rm]).write(&[self.rd]).with_pc_write()
+    }
+}
+
+impl EncodeInstruction for CountLeadingZerosInstruction {
+    fn encode(&self) -> u32 {
+        ((self.condition_bits as u32) << 28) | 0x016F_0F10 | ((self.rd as u32) << 12) | self.rm as u32
+    }
+}
+
+impl Instruction for CountLeadingZerosInstruction {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, _bus: &B) -> Result<(), InstructionError> {
+        let rm_value = read_register(register_set, self.rm)?;
+        write_register(register_set, self.rd, rm_value.leading_zeros())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{instruction::assert_round_trip, memory::FlatBus, register::RegisterCell};
+
+    use super::*;
+
+    #[test]
+    fn test_clz() {
+        // CLZ R0, R1
+        let mut instruction = CountLeadingZerosInstruction::decode(0xE16F0F11).unwrap();
+        assert_eq!(instruction.to_string(), "CLZ{AL} R0,R1");
+
+        let register_set = RegisterSet::builder()
+            .with_register(0, RegisterCell::new(0)).unwrap()
+            .with_register(1, RegisterCell::new(0x0000_8000)).unwrap()
+            .build();
+        instruction.execute(&register_set, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&register_set, 0).unwrap(), 16);
+
+        write_register(&register_set, 1, 0).unwrap();
+        instruction.execute(&register_set, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&register_set, 0).unwrap(), 32);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let values = (0..16u32).flat_map(|condition| (0..256u32).map(move |registers| {
+            (condition << 28) | 0x016F_0F10 | ((registers >> 4) << 12) | (registers & 0xF)
+        }));
+        assert_eq!(assert_round_trip::<CountLeadingZerosInstruction>(values), 16 * 256);
+    }
+}
@@ -4,11 +4,11 @@ use std::collections::HashSet;
 
 use strum_macros::Display;
 
-use crate::{instruction::{Condition, DecodeInstruction, Instruction, InstructionError}, memory::MemoryBus, register::{ReadRegister, RegisterCell, RegisterSet, WriteRegister, CPSR}};
+use crate::{instruction::{encode_flag, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, Instruction, InstructionError, InstructionMetadata, PROGRAM_COUNTER}, memory::Bus, register::{ReadRegister, RegisterCell, RegisterSet, WriteRegister, CPSR}};
 
-use super::{get_s_flag, is_data_processing_instruction, shift, ShiftBy, ShiftResult, ShiftType};
+use super::{get_s_flag, is_data_processing_instruction, shift, write_cpsr, ShiftBy, ShiftResult, ShiftType};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DataProccessingInstruction {
     pub condition_bits: u8, // Bits 31-28
     pub immediate: bool, // Bit 25 (Immediate 2nd Operand Flag) (0=Register, 1=Immediate)
@@ -85,6 +85,11 @@ impl DataProcessingOpcode {
         }
     }
 
+    // TST/TEQ/CMP/CMN only set the flags
+    pub fn is_test(&self) -> bool {
+        matches!(self, DataProcessingOpcode::TST | DataProcessingOpcode::TEQ | DataProcessingOpcode::CMP | DataProcessingOpcode::CMN)
+    }
+
     pub fn is_arithmetic(&self) -> bool {
         match self {
             DataProcessingOpcode::SUB | DataProcessingOpcode::RSB | DataProcessingOpcode::ADD | DataProcessingOpcode::ADC | DataProcessingOpcode::SBC | DataProcessingOpcode::RSC | DataProcessingOpcode::CMP | DataProcessingOpcode::CMN => true,
@@ -283,8 +288,68 @@ impl DecodeInstruction for DataProccessingInstruction {
     }
 }
 
+impl EncodeInstruction for DataProccessingInstruction {
+    fn encode(&self) -> u32 {
+        let operand = match &self.operand {
+            DataProccessingOperand::Immediate { shift_amount, nn } => (1 << 25) | ((*shift_amount as u32) << 8) | *nn as u32,
+            DataProccessingOperand::Register { shift_type, shift_by, rm } => {
+                let shift = match shift_by {
+                    ShiftBy::Immediate(shift_amount) => (*shift_amount as u32) << 7,
+                    ShiftBy::Register(rs) => ((*rs as u32) << 8) | (1 << 4),
+                };
+                shift | ((shift_type.bits() as u32) << 5) | *rm as u32
+            }
+        };
+
+        ((self.condition_bits as u32) << 28)
+            | ((self.opcode_bits as u32) << 21)
+            | encode_flag(self.s_flag, 20)
+            | ((self.rn as u32) << 16)
+            | ((self.rd as u32) << 12)
+            | operand
+    }
+}
+
+impl DescribeInstruction for DataProccessingInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        let opcode = self.opcode();
+        let mut metadata = InstructionMetadata::new(self.condition());
+
+        if !matches!(opcode, DataProcessingOpcode::MOV | DataProcessingOpcode::MVN) {
+            metadata = metadata.read(&[self.rn]);
+        }
+        if let DataProccessingOperand::Register { shift_type, shift_by, rm } = &self.operand {
+            metadata = metadata.read(&[*rm]);
+            match shift_by {
+                ShiftBy::Register(rs) => metadata = metadata.read(&[*rs]),
+                // ROR #0 is RRX, rotating the carry in
+                ShiftBy::Immediate(0) if *shift_type == ShiftType::ROR => metadata = metadata.read_flags(CPSR::C),
+                _ => {}
+            }
+        }
+        if matches!(opcode, DataProcessingOpcode::ADC | DataProcessingOpcode::SBC | DataProcessingOpcode::RSC) {
+            metadata = metadata.read_flags(CPSR::C);
+        }
+
+        if !opcode.is_test() {
+            metadata = metadata.write(&[self.rd]);
+        }
+        if self.s_flag {
+            metadata = if self.rd == PROGRAM_COUNTER && !opcode.is_test() {
+                // Restores the CPSR from the SPSR
+                metadata.write_flags(CPSR::all())
+            } else if opcode.is_logical() {
+                metadata.write_flags(CPSR::N | CPSR::Z | CPSR::C)
+            } else {
+                metadata.write_flags(CPSR::N | CPSR::Z | CPSR::C | CPSR::V)
+            };
+        }
+        metadata.with_pc_write()
+    }
+}
+
 impl Instruction for DataProccessingInstruction {
-    fn execute(&mut self, register_set: &RegisterSet, _memory_bus: &MemoryBus) -> Result<(), InstructionError> {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, _bus: &B) -> Result<(), InstructionError> {
 
         let mut write_result = true;
         let rn_value = self.rn_cell(register_set)
@@ -402,6 +467,14 @@ impl Instruction for DataProccessingInstruction {
             let result_sign = (result >> 31) & 1;
             cpsr.setv((rn_sign == op2_sign) && (rn_sign != result_sign));
         }
+
+        if self.s_flag && self.rd == 15 {
+            // Return from exception: restore the CPSR from the SPSR
+            let spsr = register_set.spsr.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))?;
+            write_cpsr(register_set, CPSR::from_bits(spsr).ok_or(InstructionError::InvalidCPSR())?)?;
+        } else if self.s_flag {
+            write_cpsr(register_set, cpsr)?;
+        }
         Ok(())
     }
 }
@@ -411,7 +484,7 @@ impl Instruction for DataProccessingInstruction {
 #[cfg(test)]
 mod tests {
 
-    use crate::register::RegisterCell;
+    use crate::{instruction::assert_round_trip, memory::MemoryBus, register::RegisterCell};
 
     use super::*;
 
@@ -513,4 +586,54 @@ mod tests {
         let value = instruction.rd_cell(&register_set).unwrap().read().unwrap();
         assert_eq!(value, expected_value);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Every condition, opcode, S flag and operand form
+        let values = (0..16u32).flat_map(|condition| (0..64u32).flat_map(move |bits_25_20| (0..256u32).map(move |bits_11_4| {
+            (condition << 28) | (bits_25_20 << 20) | (((bits_25_20 * 7) & 0xF) << 16) | ((bits_11_4 & 0xF) << 12) | (bits_11_4 << 4) | (condition ^ 0x5)
+        })));
+        // Bit 7 and 4 set with a register operand are multiplies and halfword transfers
+        let values = values.filter(|value| (value & (1 << 25)) != 0 || (value & 0x90) != 0x90);
+        assert!(assert_round_trip::<DataProccessingInstruction>(values) > 200_000);
+    }
+
+    #[test]
+    fn test_encode_structural() {
+        // MOVS R0, R1, LSL R2
+        let instruction = DataProccessingInstruction {
+            condition_bits: Condition::AL.bits(),
+            immediate: false,
+            opcode_bits: 0b1101,
+            s_flag: true,
+            rn: 0,
+            rd: 0,
+            operand: DataProccessingOperand::Register { shift_type: ShiftType::LSL, shift_by: ShiftBy::Register(2), rm: 1 },
+        };
+        assert_eq!(instruction.encode(), 0xE1B00211);
+        assert_eq!(DataProccessingInstruction::decode(0xE1B00211).unwrap(), instruction);
+    }
+
+    #[test]
+    fn test_metadata() {
+        // ADCS R0, R1, R2, RRX
+        let metadata = DataProccessingInstruction::decode(0xE0B10062).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![1, 2]);
+        assert_eq!(metadata.registers_written, vec![0]);
+        assert_eq!(metadata.flags_read, CPSR::C);
+        assert_eq!(metadata.flags_written, CPSR::N | CPSR::Z | CPSR::C | CPSR::V);
+        assert!(!metadata.control_flow.may_change());
+
+        // CMPNE R3, #1
+        let metadata = DataProccessingInstruction::decode(0x13530001).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![3]);
+        assert!(metadata.registers_written.is_empty());
+        assert_eq!(metadata.flags_read, CPSR::Z);
+
+        // MOV PC, LR
+        let metadata = DataProccessingInstruction::decode(0xE1A0F00E).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![14]);
+        assert_eq!(metadata.registers_written, vec![PROGRAM_COUNTER]);
+        assert_eq!(metadata.control_flow, crate::instruction::ControlFlow::Indirect { link: false });
+    }
+}
@@ -0,0 +1,316 @@
+use core::fmt;
+
+use strum_macros::Display;
+
+use crate::{memory::{AccessWidth, Bus}, register::RegisterSet};
+
+use super::{encode_flag, read_register, write_register, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, Instruction, InstructionMetadata, InstructionError, PROGRAM_COUNTER};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HalfwordDataTransferInstruction {
+    pub condition_bits: u8, // Bits 31-28
+    // 27-25 must be 000b for this instruction
+    pub pre_index: bool, // Bit 24 (0=post: add offset after transfer, 1=pre: before transfer)
+    pub up: bool, // Bit 23 (0=down: subtract offset from base, 1=up: add to base)
+    pub write_back: bool, // Bit 21 (0=no write-back, 1=write address into base) (post-indexed always writes back)
+    pub load: bool, // Bit 20 (0=store/doubleword, 1=load)
+    pub rn: u8, // Bits 19-16 (Base register: R0-R15) (Including PC=R15)
+    pub rd: u8, // Bits 15-12 (Source/Destination register: R0-R15) (Must be even for LDRD/STRD)
+    // bit 7 must be 1
+    pub sh_bits: u8, // Bits 6-5 (Opcode, together with the load bit)
+    // bit 4 must be 1
+    pub offset: HalfwordDataTransferOffset,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HalfwordDataTransferOffset {
+    // Bit 22 = 1
+    Immediate(u8), // Bits 11-8 (Upper 4 bits) and bits 3-0 (Lower 4 bits)
+    // Bit 22 = 0, bits 11-8 must be 0000b
+    Register(u8), // Bits 3-0 (Offset register: R0-R14)
+}
+
+#[derive(Debug, Clone, Display, PartialEq, Eq)]
+pub enum HalfwordDataTransferOpcode {
+    STRH,
+    // ARMv5TE and above
+    LDRD,
+    // ARMv5TE and above
+    STRD,
+    LDRH,
+    LDRSB,
+    LDRSH,
+}
+
+impl HalfwordDataTransferOpcode {
+    pub fn is_load(&self) -> bool {
+        matches!(self, HalfwordDataTransferOpcode::LDRD | HalfwordDataTransferOpcode::LDRH | HalfwordDataTransferOpcode::LDRSB | HalfwordDataTransferOpcode::LDRSH)
+    }
+}
+
+impl HalfwordDataTransferInstruction {
+    pub fn condition(&self) -> Condition {
+        Condition::from_bits_truncate(self.condition_bits)
+    }
+
+    pub fn opcode(&self) -> HalfwordDataTransferOpcode {
+        match (self.load, self.sh_bits) {
+            (false, 0b10) => HalfwordDataTransferOpcode::LDRD,
+            (false, 0b11) => HalfwordDataTransferOpcode::STRD,
+            (false, _) => HalfwordDataTransferOpcode::STRH,
+            (true, 0b10) => HalfwordDataTransferOpcode::LDRSB,
+            (true, 0b11) => HalfwordDataTransferOpcode::LDRSH,
+            (true, _) => HalfwordDataTransferOpcode::LDRH,
+        }
+    }
+}
+
+impl fmt::Display for HalfwordDataTransferInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.up { "+" } else { "-" };
+        write!(f, "{}{{{}}} R{},[R{}", self.opcode(), self.condition(), self.rd, self.rn)?;
+        if !self.pre_index {
+            write!(f, "]")?;
+        }
+        match self.offset {
+            HalfwordDataTransferOffset::Immediate(offset) => write!(f, ",#{}{}", sign, offset)?,
+            HalfwordDataTransferOffset::Register(rm) => write!(f, ",{}R{}", sign, rm)?,
+        }
+        if self.pre_index {
+            write!(f, "]{}", if self.write_back { "!" } else { "" })?;
+        }
+        Ok(())
+    }
+}
+
+pub fn is_halfword_data_transfer_instruction(value: u32) -> bool {
+    // bits 27-25 000b, bit 7 and bit 4 set, bits 6-5 not 00b (multiply and swap)
+    (value & 0x0E00_0090) == 0x0000_0090 && (value & 0x60) != 0
+}
+
+impl DecodeInstruction for HalfwordDataTransferInstruction {
+    fn decode(value: u32) -> Result<Self, InstructionError>
+        where
+            Self: Sized {
+
+        let condition_bits = (value >> 28) as u8;
+        if !is_halfword_data_transfer_instruction(value) {
+            return Err(InstructionError::InvalidInstruction(value));
+        }
+
+        let offset = if (value & (1 << 22)) != 0 {
+            HalfwordDataTransferOffset::Immediate((((value >> 4) & 0xF0) | (value & 0xF)) as u8)
+        } else {
+            HalfwordDataTransferOffset::Register((value & 0xF) as u8)
+        };
+
+        let instruction = HalfwordDataTransferInstruction {
+            condition_bits,
+            pre_index: (value & (1 << 24)) != 0,
+            up: (value & (1 << 23)) != 0,
+            write_back: (value & (1 << 21)) != 0,
+            load: (value & (1 << 20)) != 0,
+            rn: ((value >> 16) & 0xF) as u8,
+            rd: ((value >> 12) & 0xF) as u8,
+            sh_bits: ((value >> 5) & 0x3) as u8,
+            offset,
+        };
+
+        // LDRD/STRD transfer an even/odd register pair
+        if matches!(instruction.opcode(), HalfwordDataTransferOpcode::LDRD | HalfwordDataTransferOpcode::STRD) && !instruction.rd.is_multiple_of(2) {
+            return Err(InstructionError::UndefinedInstruction());
+        }
+
+        Ok(instruction)
+    }
+}
+
+impl DescribeInstruction for HalfwordDataTransferInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        let opcode = self.opcode();
+        let (registers, width, count) = match opcode {
+            HalfwordDataTransferOpcode::LDRD | HalfwordDataTransferOpcode::STRD => (vec![self.rd, self.rd + 1], AccessWidth::Word, 2),
+            HalfwordDataTransferOpcode::LDRSB => (vec![self.rd], AccessWidth::Byte, 1),
+            _ => (vec![self.rd], AccessWidth::Halfword, 1),
+        };
+
+        let mut metadata = InstructionMetadata::new(self.condition()).read(&[self.rn]);
+        if let HalfwordDataTransferOffset::Register(rm) = self.offset {
+            metadata = metadata.read(&[rm]);
+        }
+
+        metadata = if opcode.is_load() { metadata.write(&registers) } else { metadata.read(&registers) };
+        if self.write_back || !self.pre_index {
+            metadata = metadata.write(&[self.rn]);
+        }
+        metadata.access(opcode.is_load(), width, Some(count)).with_pc_write()
+    }
+}
+
+impl EncodeInstruction for HalfwordDataTransferInstruction {
+    fn encode(&self) -> u32 {
+        let offset = match self.offset {
+            HalfwordDataTransferOffset::Immediate(offset) => (1 << 22) | (((offset as u32) & 0xF0) << 4) | (offset as u32 & 0xF),
+            HalfwordDataTransferOffset::Register(rm) => rm as u32,
+        };
+
+        ((self.condition_bits as u32) << 28)
+            | encode_flag(self.pre_index, 24)
+            | encode_flag(self.up, 23)
+            | encode_flag(self.write_back, 21)
+            | encode_flag(self.load, 20)
+            | ((self.rn as u32) << 16)
+            | ((self.rd as u32) << 12)
+            | (1 << 7)
+            | ((self.sh_bits as u32) << 5)
+            | (1 << 4)
+            | offset
+    }
+}
+
+impl Instruction for HalfwordDataTransferInstruction {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, bus: &B) -> Result<(), InstructionError> {
+        let base = read_register(register_set, self.rn)?;
+        let offset = match self.offset {
+            HalfwordDataTransferOffset::Immediate(offset) => offset as u32,
+            HalfwordDataTransferOffset::Register(rm) => read_register(register_set, rm)?,
+        };
+        let offset_address = if self.up { base.wrapping_add(offset) } else { base.wrapping_sub(offset) };
+        let address = if self.pre_index { offset_address } else { base };
+        let write_back = self.write_back || !self.pre_index;
+
+        let opcode = self.opcode();
+        if !opcode.is_load() {
+            let mut value = read_register(register_set, self.rd)?;
+            // Storing the PC stores the address of the instruction + 12
+            if self.rd == PROGRAM_COUNTER {
+                value = value.wrapping_add(4);
+            }
+            if opcode == HalfwordDataTransferOpcode::STRD {
+                let high = read_register(register_set, self.rd + 1)?;
+                bus.write_u32(address, value).map_err(InstructionError::MemoryError)?;
+                bus.write_u32(address.wrapping_add(4), high).map_err(InstructionError::MemoryError)?;
+            } else {
+                bus.store_u16(address, value as u16).map_err(InstructionError::MemoryError)?;
+            }
+            if write_back {
+                write_register(register_set, self.rn, offset_address)?;
+            }
+            return Ok(());
+        }
+
+        // The loaded value wins when the base is also the destination
+        if write_back {
+            write_register(register_set, self.rn, offset_address)?;
+        }
+
+        match opcode {
+            HalfwordDataTransferOpcode::LDRD => {
+                let low = bus.read_u32(address).map_err(InstructionError::MemoryError)?;
+                let high = bus.read_u32(address.wrapping_add(4)).map_err(InstructionError::MemoryError)?;
+                write_register(register_set, self.rd, low)?;
+                write_register(register_set, self.rd + 1, high)
+            }
+            HalfwordDataTransferOpcode::LDRSB => {
+                let value = bus.read_u8(address).map_err(InstructionError::MemoryError)? as i8;
+                write_register(register_set, self.rd, value as i32 as u32)
+            }
+            HalfwordDataTransferOpcode::LDRSH => {
+                let value = bus.load_i16(address).map_err(InstructionError::MemoryError)?;
+                write_register(register_set, self.rd, value)
+            }
+            _ => {
+                let value = bus.load_u16(address).map_err(InstructionError::MemoryError)?;
+                write_register(register_set, self.rd, value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{instruction::assert_round_trip, memory::FlatBus, register::RegisterCell};
+
+    use super::*;
+
+    fn register_set() -> RegisterSet {
+        let mut builder = RegisterSet::builder();
+        for register in 0..4 {
+            builder.with_register(register, RegisterCell::new(0)).unwrap();
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_ldrsh_immediate_offset() {
+        // LDRSH R0, [R1, #-0x12]
+        let value: u32 = 0b1110_000_1_0_1_0_1_0001_0000_0001_1_11_1_0010;
+        let mut instruction = HalfwordDataTransferInstruction::decode(value).unwrap();
+        assert_eq!(instruction.opcode(), HalfwordDataTransferOpcode::LDRSH);
+        assert_eq!(instruction.to_string(), "LDRSH{AL} R0,[R1,#-18]");
+
+        let bus = FlatBus::new();
+        bus.write_u16(0x100, 0x8001).unwrap();
+        let register_set = register_set();
+        write_register(&register_set, 1, 0x112).unwrap();
+        instruction.execute(&register_set, &bus).unwrap();
+        assert_eq!(read_register(&register_set, 0).unwrap(), 0xFFFF_8001);
+        assert_eq!(read_register(&register_set, 1).unwrap(), 0x112);
+    }
+
+    #[test]
+    fn test_ldrd_strd() {
+        // STRD R2, [R0], #8
+        let value: u32 = 0b1110_000_0_1_1_0_0_0000_0010_0000_1_11_1_1000;
+        let mut strd = HalfwordDataTransferInstruction::decode(value).unwrap();
+        assert_eq!(strd.opcode(), HalfwordDataTransferOpcode::STRD);
+
+        let bus = FlatBus::new();
+        let register_set = register_set();
+        write_register(&register_set, 0, 0x100).unwrap();
+        write_register(&register_set, 2, 0x11111111).unwrap();
+        write_register(&register_set, 3, 0x22222222).unwrap();
+        strd.execute(&register_set, &bus).unwrap();
+        assert_eq!(bus.read_u32(0x104).unwrap(), 0x22222222);
+        assert_eq!(read_register(&register_set, 0).unwrap(), 0x108);
+
+        // LDRD R2, [R0, #-8]!
+        let value: u32 = 0b1110_000_1_0_1_1_0_0000_0010_0000_1_10_1_1000;
+        let mut ldrd = HalfwordDataTransferInstruction::decode(value).unwrap();
+        assert_eq!(ldrd.opcode(), HalfwordDataTransferOpcode::LDRD);
+        write_register(&register_set, 2, 0).unwrap();
+        write_register(&register_set, 3, 0).unwrap();
+        ldrd.execute(&register_set, &bus).unwrap();
+        assert_eq!(read_register(&register_set, 2).unwrap(), 0x11111111);
+        assert_eq!(read_register(&register_set, 3).unwrap(), 0x22222222);
+        assert_eq!(read_register(&register_set, 0).unwrap(), 0x100);
+    }
+
+    #[test]
+    fn test_ldrd_odd_register() {
+        // LDRD R1, [R0]
+        let value: u32 = 0xE1C010D0;
+        assert_eq!(HalfwordDataTransferInstruction::decode(value).err(), Some(InstructionError::UndefinedInstruction()));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Every condition, flag combination, opcode and offset, bits 11-8 must be 0 for register offsets
+        let values = (0..16u32).flat_map(|condition| (0..32u32).flat_map(move |bits_24_20| (1..4u32).flat_map(move |sh_bits| (0..256u32).map(move |offset| {
+            let bit_22 = (bits_24_20 >> 2) & 1;
+            let offset = if bit_22 == 1 { ((offset & 0xF0) << 4) | (offset & 0xF) } else { offset & 0xF };
+            (condition << 28) | (bits_24_20 << 20) | ((condition ^ 0x3) << 16) | ((sh_bits * 2) << 12) | 0x90 | (sh_bits << 5) | offset
+        }))));
+        assert_eq!(assert_round_trip::<HalfwordDataTransferInstruction>(values), 16 * 32 * 3 * 256);
+    }
+
+    #[test]
+    fn test_metadata() {
+        // STRD R2, [R0], #8
+        let metadata = HalfwordDataTransferInstruction::decode(0xE0C020F8).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![0, 2, 3]);
+        assert_eq!(metadata.registers_written, vec![0]);
+        assert_eq!(metadata.memory_access, Some(crate::instruction::MemoryAccess { load: false, width: AccessWidth::Word, count: Some(2) }));
+    }
+}
@@ -2,9 +2,12 @@ use core::fmt;
 
 use bitflags::bitflags;
 
-use crate::{memory::MemoryBus, register::RegisterSet};
+use crate::{memory::{Bus, MemoryError}, register::{ReadRegister, RegisterSet, WriteRegister, CPSR}};
 
-use super::{DataProccessingInstruction, MultiplyInstruction};
+use super::{is_branch_exchange_instruction, is_branch_instruction, is_coprocessor_instruction, is_count_leading_zeros_instruction, is_halfword_data_transfer_instruction, is_halfword_multiply_instruction, is_saturating_arithmetic_instruction, Architecture, BlockDataTransferInstruction, BranchInstruction, BranchOperation, CoprocessorInstruction, CountLeadingZerosInstruction, DataProccessingInstruction, DescribeInstruction, HalfwordDataTransferInstruction, HalfwordDataTransferOpcode, InstructionMetadata, MultiplyInstruction, SaturatingArithmeticInstruction, SingleDataTransferInstruction};
+
+pub const LINK_REGISTER: u8 = 14;
+pub const PROGRAM_COUNTER: u8 = 15;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum InstructionError {
@@ -16,10 +19,15 @@ pub enum InstructionError {
     RegisterWriteError(String),
     InvalidShiftType(u8),
     InvalidCPSR(),
+    MemoryError(MemoryError),
+    // The instruction has to be handled by raising the Undefined exception
+    UndefinedInstruction(),
+    // Valid encoding the emulator does not execute yet, with its mnemonic
+    Unimplemented(&'static str),
 }
 
 pub trait Instruction {
-    fn execute(&mut self, register_set: &RegisterSet, memory_bus: &MemoryBus) -> Result<(), InstructionError>;
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, bus: &B) -> Result<(), InstructionError>;
 }
 
 pub trait DecodeInstruction {
@@ -28,6 +36,40 @@ pub trait DecodeInstruction {
         Self: Sized;
 }
 
+// Inverse of DecodeInstruction, decoding the encoded value gives back the same instruction.
+// Only the round trip tests encode so far.
+#[cfg_attr(not(test), allow(dead_code))]
+pub trait EncodeInstruction {
+    fn encode(&self) -> u32;
+}
+
+// Encodes a boolean field as the bit at the given position
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn encode_flag(flag: bool, bit: u32) -> u32 {
+    (flag as u32) << bit
+}
+
+pub fn read_register(register_set: &RegisterSet, register: u8) -> Result<u32, InstructionError> {
+    register_set.get(register)
+        .ok_or(InstructionError::InvalidRegister(register as u32))?
+        .read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))
+}
+
+pub fn write_register(register_set: &RegisterSet, register: u8, value: u32) -> Result<(), InstructionError> {
+    register_set.get(register)
+        .ok_or(InstructionError::InvalidRegister(register as u32))?
+        .write(value).map_err(|e| InstructionError::RegisterWriteError(e.to_string()))
+}
+
+pub fn read_cpsr(register_set: &RegisterSet) -> Result<CPSR, InstructionError> {
+    let cpsr = register_set.cpsr.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))?;
+    CPSR::from_bits(cpsr).ok_or(InstructionError::InvalidCPSR())
+}
+
+pub fn write_cpsr(register_set: &RegisterSet, cpsr: CPSR) -> Result<(), InstructionError> {
+    register_set.cpsr.clone().write(cpsr.bits()).map_err(|e| InstructionError::RegisterWriteError(e.to_string()))
+}
+
 bitflags! {
     #[derive(Debug, Default, Clone, PartialEq, Eq)]
     pub struct Condition: u8 {
@@ -50,6 +92,31 @@ bitflags! {
     }
 }
 
+impl Condition {
+    // Whether the instruction executes with the given flags
+    pub fn is_satisfied(&self, cpsr: &CPSR) -> bool {
+        let (n, z, c, v) = (cpsr.is_negative(), cpsr.is_zero(), cpsr.carry() == 1, cpsr.is_overflow());
+        match self.bits() {
+            0b0000 => z,
+            0b0001 => !z,
+            0b0010 => c,
+            0b0011 => !c,
+            0b0100 => n,
+            0b0101 => !n,
+            0b0110 => v,
+            0b0111 => !v,
+            0b1000 => c && !z,
+            0b1001 => !c || z,
+            0b1010 => n == v,
+            0b1011 => n != v,
+            0b1100 => !z && n == v,
+            0b1101 => z || n != v,
+            0b1110 => true,
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for Condition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.bits() {
@@ -75,9 +142,48 @@ impl fmt::Display for Condition {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InstructionType {
     Multiply(MultiplyInstruction),
     DataProcessing(DataProccessingInstruction),
+    Coprocessor(CoprocessorInstruction),
+    Branch(BranchInstruction),
+    SingleDataTransfer(SingleDataTransferInstruction),
+    HalfwordDataTransfer(HalfwordDataTransferInstruction),
+    BlockDataTransfer(BlockDataTransferInstruction),
+    CountLeadingZeros(CountLeadingZerosInstruction),
+    SaturatingArithmetic(SaturatingArithmeticInstruction),
+}
+
+impl InstructionType {
+    pub fn condition(&self) -> Condition {
+        match self {
+            InstructionType::Multiply(instruction) => instruction.condition(),
+            InstructionType::DataProcessing(instruction) => instruction.condition(),
+            InstructionType::Coprocessor(instruction) => instruction.condition(),
+            InstructionType::Branch(instruction) => instruction.condition(),
+            InstructionType::SingleDataTransfer(instruction) => instruction.condition(),
+            InstructionType::HalfwordDataTransfer(instruction) => instruction.condition(),
+            InstructionType::BlockDataTransfer(instruction) => instruction.condition(),
+            InstructionType::CountLeadingZeros(instruction) => instruction.condition(),
+            InstructionType::SaturatingArithmetic(instruction) => instruction.condition(),
+        }
+    }
+
+    // Whether executing the instruction writes the PC, in which case it is not advanced afterwards
+    pub fn writes_pc(&self) -> bool {
+        self.metadata().registers_written.contains(&PROGRAM_COUNTER)
+    }
+
+    // Whether the PC is loaded from memory, which interworks on ARMv5
+    pub fn loads_pc(&self) -> bool {
+        match self {
+            InstructionType::SingleDataTransfer(_) => self.writes_pc(),
+            // LDM with the S bit restores the state from the SPSR instead
+            InstructionType::BlockDataTransfer(instruction) => self.writes_pc() && !instruction.psr,
+            _ => false,
+        }
+    }
 }
 
 pub fn get_s_flag(value: u32) -> bool {
@@ -95,32 +201,154 @@ pub fn is_multiply_instruction(value: u32) -> bool {
 }
 
 impl Instruction for InstructionType {
-    fn execute(&mut self, register_set: &RegisterSet, memory_bus: &MemoryBus) -> Result<(), InstructionError> {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, bus: &B) -> Result<(), InstructionError> {
         match self {
-            InstructionType::Multiply(multiply_instruction) => todo!(),
-            InstructionType::DataProcessing(data_proccessing_instruction) => data_proccessing_instruction.execute(register_set, memory_bus),
+            InstructionType::Multiply(multiply_instruction) => multiply_instruction.execute(register_set, bus),
+            InstructionType::DataProcessing(data_proccessing_instruction) => data_proccessing_instruction.execute(register_set, bus),
+            // Coprocessor instructions are dispatched by the CPU, without one attached they are undefined
+            InstructionType::Coprocessor(_) => Err(InstructionError::UndefinedInstruction()),
+            InstructionType::Branch(branch_instruction) => branch_instruction.execute(register_set, bus),
+            InstructionType::SingleDataTransfer(transfer_instruction) => transfer_instruction.execute(register_set, bus),
+            InstructionType::HalfwordDataTransfer(transfer_instruction) => transfer_instruction.execute(register_set, bus),
+            InstructionType::BlockDataTransfer(transfer_instruction) => transfer_instruction.execute(register_set, bus),
+            InstructionType::CountLeadingZeros(clz_instruction) => clz_instruction.execute(register_set, bus),
+            InstructionType::SaturatingArithmetic(saturating_instruction) => saturating_instruction.execute(register_set, bus),
         }
     }
 }
 
-pub fn get_instruction(value: u32) -> Result<InstructionType, InstructionError> {
-    // bits 27-25
-    let bits_27_25 = (value >> 25) & 0b111;
+// Encodings that are valid ARM instructions but are not executed yet
+pub fn unimplemented_mnemonic(value: u32) -> Option<&'static str> {
+    // SWP/SWPB: bits 27-23 00010b, bits 21-20 00b and bits 7-4 1001b
+    if (value & 0x0FB0_00F0) == 0x0100_0090 {
+        return Some(if (value & (1 << 22)) != 0 { "SWPB" } else { "SWP" });
+    }
 
-    // bits 24-21
-    let bits_24_21 = ((value >> 21) & 0b1111) as u8;
+    // MRS/MSR: TST/TEQ/CMP/CMN without the S flag
+    if (value & 0x0D90_0000) == 0x0100_0000 {
+        return Some(if (value & (1 << 21)) != 0 { "MSR" } else { "MRS" });
+    }
 
-    // check if it's a multiply instruction
-    if bits_27_25 == 0b000 {
-        // for multiply instructions, bits 24-21 must be in range 0x0 to 0xB
-        if bits_24_21 <= 0xB {
-            match MultiplyInstruction::decode(value) {
-                Ok(instruction) => return Ok(InstructionType::Multiply(instruction)),
-                Err(e) => return Err(e),
-            }
-        } else {
-            return Err(InstructionError::InvalidOpcode(bits_24_21));
+    // SWI: bits 27-24 1111b
+    if (value >> 24) & 0xF == 0xF {
+        return Some("SWI");
+    }
+
+    None
+}
+
+impl EncodeInstruction for InstructionType {
+    fn encode(&self) -> u32 {
+        match self {
+            InstructionType::Multiply(instruction) => instruction.encode(),
+            InstructionType::DataProcessing(instruction) => instruction.encode(),
+            InstructionType::Coprocessor(instruction) => instruction.encode(),
+            InstructionType::Branch(instruction) => instruction.encode(),
+            InstructionType::SingleDataTransfer(instruction) => instruction.encode(),
+            InstructionType::HalfwordDataTransfer(instruction) => instruction.encode(),
+            InstructionType::BlockDataTransfer(instruction) => instruction.encode(),
+            InstructionType::CountLeadingZeros(instruction) => instruction.encode(),
+            InstructionType::SaturatingArithmetic(instruction) => instruction.encode(),
+        }
+    }
+}
+
+impl DescribeInstruction for InstructionType {
+    fn metadata(&self) -> InstructionMetadata {
+        match self {
+            InstructionType::Multiply(instruction) => instruction.metadata(),
+            InstructionType::DataProcessing(instruction) => instruction.metadata(),
+            InstructionType::Coprocessor(instruction) => instruction.metadata(),
+            InstructionType::Branch(instruction) => instruction.metadata(),
+            InstructionType::SingleDataTransfer(instruction) => instruction.metadata(),
+            InstructionType::HalfwordDataTransfer(instruction) => instruction.metadata(),
+            InstructionType::BlockDataTransfer(instruction) => instruction.metadata(),
+            InstructionType::CountLeadingZeros(instruction) => instruction.metadata(),
+            InstructionType::SaturatingArithmetic(instruction) => instruction.metadata(),
+        }
+    }
+}
+
+// Asserts decode -> encode gives back every value that decodes, returns how many did
+#[cfg(test)]
+pub fn assert_round_trip<T>(values: impl IntoIterator<Item = u32>) -> usize
+where
+    T: DecodeInstruction + EncodeInstruction + fmt::Debug,
+{
+    let mut decoded = 0;
+    for value in values {
+        if let Ok(instruction) = T::decode(value) {
+            assert_eq!(instruction.encode(), value, "{:#010X} decoded as {:?}", value, instruction);
+            decoded += 1;
+        }
+    }
+    decoded
+}
+
+pub fn get_instruction(value: u32, architecture: Architecture) -> Result<InstructionType, InstructionError> {
+    let condition = Condition::from_bits_truncate((value >> 28) as u8);
+
+    // ARMv5 reuses the NV condition for unconditional instructions, only BLX is supported
+    if architecture.has_v5te() && condition == Condition::NV {
+        if is_branch_instruction(value) {
+            return Ok(InstructionType::Branch(BranchInstruction::decode(value)?));
+        }
+        return Err(InstructionError::UndefinedInstruction());
+    }
+
+    // ARMv4 never executes NV instructions, bit 24 of a branch is still the link bit
+    if !architecture.has_v5te() && condition == Condition::NV && is_branch_instruction(value) {
+        let mut instruction = BranchInstruction::decode(value)?;
+        if let BranchOperation::BranchLinkExchange { halfword, offset } = instruction.operation {
+            instruction.operation = BranchOperation::Branch { link: halfword, offset };
+        }
+        return Ok(InstructionType::Branch(instruction));
+    }
+
+    if is_branch_exchange_instruction(value) {
+        let instruction = BranchInstruction::decode(value)?;
+        // BLX Rm is ARMv5 and above
+        if !architecture.has_v5te() && matches!(instruction.operation, BranchOperation::BranchExchange { link: true, .. }) {
+            return Err(InstructionError::UndefinedInstruction());
+        }
+        return Ok(InstructionType::Branch(instruction));
+    }
+
+    // ARMv5TE extensions living in the data processing encoding space
+    if is_count_leading_zeros_instruction(value) || is_saturating_arithmetic_instruction(value) || is_halfword_multiply_instruction(value) {
+        if !architecture.has_v5te() {
+            return Err(InstructionError::UndefinedInstruction());
         }
+
+        if is_count_leading_zeros_instruction(value) {
+            return Ok(InstructionType::CountLeadingZeros(CountLeadingZerosInstruction::decode(value)?));
+        }
+
+        if is_saturating_arithmetic_instruction(value) {
+            return Ok(InstructionType::SaturatingArithmetic(SaturatingArithmeticInstruction::decode(value)?));
+        }
+
+        return Ok(InstructionType::Multiply(MultiplyInstruction::decode(value)?));
+    }
+
+    // bits 27-24 must be 0000b and bits 7-4 1001b for multiply instructions
+    if (value >> 24) & 0b1111 == 0b0000 && (value >> 4) & 0b1111 == 0b1001 {
+        match MultiplyInstruction::decode(value) {
+            Ok(instruction) => return Ok(InstructionType::Multiply(instruction)),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if is_halfword_data_transfer_instruction(value) {
+        let instruction = HalfwordDataTransferInstruction::decode(value)?;
+        if !architecture.has_v5te() && matches!(instruction.opcode(), HalfwordDataTransferOpcode::LDRD | HalfwordDataTransferOpcode::STRD) {
+            return Err(InstructionError::UndefinedInstruction());
+        }
+        return Ok(InstructionType::HalfwordDataTransfer(instruction));
+    }
+
+    if let Some(mnemonic) = unimplemented_mnemonic(value) {
+        return Err(InstructionError::Unimplemented(mnemonic));
     }
 
     let bits_27_26 = (value >> 26) & 0b11;
@@ -131,9 +359,208 @@ pub fn get_instruction(value: u32) -> Result<InstructionType, InstructionError>
         }
     }
 
+    if bits_27_26 == 0b01 {
+        return Ok(InstructionType::SingleDataTransfer(SingleDataTransferInstruction::decode(value)?));
+    }
+
+    // bits 27-25
+    let bits_27_25 = (value >> 25) & 0b111;
+    if bits_27_25 == 0b100 {
+        return Ok(InstructionType::BlockDataTransfer(BlockDataTransferInstruction::decode(value)?));
+    }
+
+    if is_branch_instruction(value) {
+        return Ok(InstructionType::Branch(BranchInstruction::decode(value)?));
+    }
+
+    if is_coprocessor_instruction(value) {
+        return Ok(InstructionType::Coprocessor(CoprocessorInstruction::decode(value)?));
+    }
+
     Err(InstructionError::InvalidInstruction(value))
 }
 
-pub fn execute(value: u32, register_set: &RegisterSet, memory_bus: &MemoryBus) -> Result<(), InstructionError> {
-    get_instruction(value)?.execute(register_set, memory_bus)
-}
\ No newline at end of file
+pub fn execute<B: Bus>(value: u32, architecture: Architecture, register_set: &RegisterSet, bus: &B) -> Result<(), InstructionError> {
+    get_instruction(value, architecture)?.execute(register_set, bus)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_condition_is_satisfied() {
+        let flags = CPSR::Z | CPSR::C;
+        assert!(Condition::EQ.is_satisfied(&flags));
+        assert!(!Condition::NE.is_satisfied(&flags));
+        assert!(!Condition::HI.is_satisfied(&flags));
+        assert!(Condition::LS.is_satisfied(&flags));
+        assert!(Condition::GE.is_satisfied(&flags));
+        assert!(Condition::AL.is_satisfied(&flags));
+        assert!(!Condition::NV.is_satisfied(&flags));
+    }
+
+    #[test]
+    fn test_get_instruction_dispatch() {
+        // ADD R2, R1, R3 (register operand, used to be mistaken for a multiply)
+        assert!(matches!(get_instruction(0xE0812003, Architecture::ARMv4T), Ok(InstructionType::DataProcessing(_))));
+        // MUL R2, R1, R3
+        assert!(matches!(get_instruction(0xE0020391, Architecture::ARMv4T), Ok(InstructionType::Multiply(_))));
+        // LDR R0, [R1]
+        assert!(matches!(get_instruction(0xE5910000, Architecture::ARMv4T), Ok(InstructionType::SingleDataTransfer(_))));
+        // LDRH R0, [R1]
+        assert!(matches!(get_instruction(0xE1D100B0, Architecture::ARMv4T), Ok(InstructionType::HalfwordDataTransfer(_))));
+        // LDMIA R0!, {R1, R2}
+        assert!(matches!(get_instruction(0xE8B00006, Architecture::ARMv4T), Ok(InstructionType::BlockDataTransfer(_))));
+        // BX LR
+        assert!(matches!(get_instruction(0xE12FFF1E, Architecture::ARMv4T), Ok(InstructionType::Branch(_))));
+    }
+
+    #[test]
+    fn test_metadata_matches_writes_pc() {
+        for class in ENCODING_CLASSES.iter() {
+            for value in class.encodings() {
+                if let Ok(instruction) = get_instruction(value, Architecture::ARMv5TE) {
+                    assert_eq!(instruction.metadata().control_flow.may_change(), instruction.writes_pc(), "{} {:#010X}", class.name, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_instruction_unimplemented() {
+        // MRS R0, CPSR / MSR CPSR_c, R0 / SWP R0, R1, [R2] / SWI 0
+        assert_eq!(get_instruction(0xE10F0000, Architecture::ARMv4T).err(), Some(InstructionError::Unimplemented("MRS")));
+        assert_eq!(get_instruction(0xE121F000, Architecture::ARMv4T).err(), Some(InstructionError::Unimplemented("MSR")));
+        assert_eq!(get_instruction(0xE1020091, Architecture::ARMv4T).err(), Some(InstructionError::Unimplemented("SWP")));
+        assert_eq!(get_instruction(0xEF000000, Architecture::ARMv4T).err(), Some(InstructionError::Unimplemented("SWI")));
+        // CMP R0, R1
+        assert!(matches!(get_instruction(0xE1500001, Architecture::ARMv4T), Ok(InstructionType::DataProcessing(_))));
+    }
+
+    #[test]
+    fn test_get_instruction_architecture() {
+        // CLZ R0, R1 / QADD R0, R1, R2 / SMULBB R0, R1, R2 / BLX R0 / LDRD R2, [R0]
+        let v5te_instructions: [u32; 5] = [0xE16F0F11, 0xE1020051, 0xE1600281, 0xE12FFF30, 0xE1C020D0];
+        for value in v5te_instructions {
+            assert_eq!(get_instruction(value, Architecture::ARMv4T).err(), Some(InstructionError::UndefinedInstruction()), "{:#010X}", value);
+            assert!(get_instruction(value, Architecture::ARMv5TE).is_ok(), "{:#010X}", value);
+        }
+
+        // BLX $+8 is a branch that never executes on ARMv4T
+        let instruction = get_instruction(0xFA000000, Architecture::ARMv4T).unwrap();
+        assert_eq!(instruction.condition(), Condition::NV);
+        let instruction = get_instruction(0xFA000000, Architecture::ARMv5TE).unwrap();
+        assert_eq!(instruction.condition(), Condition::AL);
+    }
+
+    // Encodings of an instruction class: every combination of the bits that select the instruction is
+    // walked, and each operand field steps through its range while the others keep a fixed value
+    struct EncodingClass {
+        name: &'static str,
+        base: u32,
+        walked: u32,
+        fields: &'static [(u32, u32)], // Shift and width
+    }
+
+    const COND: (u32, u32) = (28, 4);
+    const RN: (u32, u32) = (16, 4);
+    const RD: (u32, u32) = (12, 4);
+    const RS: (u32, u32) = (8, 4);
+    const RM: (u32, u32) = (0, 4);
+
+    const ENCODING_CLASSES: [EncodingClass; 16] = [
+        // Opcode and S
+        EncodingClass { name: "data processing immediate", base: 0x02000000, walked: 0x01F00000, fields: &[COND, RN, RD, (8, 4), (0, 8)] },
+        // Opcode, S and shift type
+        EncodingClass { name: "data processing immediate shift", base: 0x00000000, walked: 0x01F00060, fields: &[COND, RN, RD, (7, 5), RM] },
+        EncodingClass { name: "data processing register shift", base: 0x00000010, walked: 0x01F00060, fields: &[COND, RN, RD, RS, RM] },
+        // Long, signed, accumulate and S
+        EncodingClass { name: "multiply", base: 0x00000090, walked: 0x00F00000, fields: &[COND, RN, RD, RS, RM] },
+        // Operation, x and y
+        EncodingClass { name: "signed halfword multiply", base: 0x01000080, walked: 0x00600060, fields: &[COND, RN, RD, RS, RM] },
+        EncodingClass { name: "saturating arithmetic", base: 0x01000050, walked: 0x00600000, fields: &[COND, RN, RD, RM] },
+        EncodingClass { name: "count leading zeros", base: 0x016F0F10, walked: 0, fields: &[COND, RD, RM] },
+        // P, U, B, W and L
+        EncodingClass { name: "single data transfer immediate", base: 0x04000000, walked: 0x01F00000, fields: &[COND, RN, RD, (0, 12)] },
+        EncodingClass { name: "single data transfer register", base: 0x06000000, walked: 0x01F00060, fields: &[COND, RN, RD, (7, 5), RM] },
+        // P, U, I, W, L, S and H
+        EncodingClass { name: "halfword data transfer", base: 0x00000090, walked: 0x01F00060, fields: &[COND, RN, RD, RS, RM] },
+        // P, U, S, W and L
+        EncodingClass { name: "block data transfer", base: 0x08000000, walked: 0x01F00000, fields: &[COND, RN, (0, 16)] },
+        // Link
+        EncodingClass { name: "branch", base: 0x0A000000, walked: 0x01000000, fields: &[COND, (0, 24)] },
+        EncodingClass { name: "branch and exchange", base: 0x012FFF10, walked: 0x00000020, fields: &[COND, RM] },
+        // P, U, N, W and L
+        EncodingClass { name: "coprocessor data transfer", base: 0x0C000000, walked: 0x01F00000, fields: &[COND, RN, RD, RS, (0, 8)] },
+        EncodingClass { name: "coprocessor data operation", base: 0x0E000000, walked: 0, fields: &[COND, (20, 4), RN, RD, RS, (5, 3), RM] },
+        // Load
+        EncodingClass { name: "coprocessor register transfer", base: 0x0E000010, walked: 0x00100000, fields: &[COND, (21, 3), RN, RD, RS, (5, 3), RM] },
+    ];
+
+    fn field_mask(width: u32) -> u32 {
+        ((1u64 << width) - 1) as u32
+    }
+
+    // Every value of narrow fields, wide ones are strided and get each single bit set
+    fn field_values(width: u32) -> Vec<u32> {
+        let max = field_mask(width);
+        if width <= 5 {
+            return (0..=max).collect();
+        }
+        let mut values: Vec<u32> = (0..=max).step_by((max / 31) as usize).collect();
+        values.extend((0..width).map(|bit| 1 << bit));
+        values.push(max);
+        values
+    }
+
+    impl EncodingClass {
+        fn encodings(&self) -> Vec<u32> {
+            let fixed = self.fields.iter().fold(0, |acc, (shift, width)| acc | (0xE5A5A5A5 & (field_mask(*width) << shift)));
+            let mut encodings = Vec::new();
+            let mut walked: u32 = 0;
+            loop {
+                for (shift, width) in self.fields {
+                    let others = fixed & !(field_mask(*width) << shift);
+                    encodings.extend(field_values(*width).into_iter().map(|value| self.base | walked | others | (value << shift)));
+                }
+                // Next combination of the walked bits
+                walked = walked.wrapping_sub(self.walked) & self.walked;
+                if walked == 0 {
+                    return encodings;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoding_classes() {
+        for class in ENCODING_CLASSES.iter() {
+            let fields = class.fields.iter().fold(0, |acc, (shift, width)| {
+                assert_eq!(acc & (field_mask(*width) << shift), 0, "{}", class.name);
+                acc | (field_mask(*width) << shift)
+            });
+            assert_eq!(fields & (class.base | class.walked), 0, "{}", class.name);
+            assert_eq!(class.base & class.walked, 0, "{}", class.name);
+        }
+        assert_eq!(field_values(4).len(), 16);
+        assert!(field_values(24).contains(&0x800000));
+        assert!(field_values(24).contains(&0xFFFFFF));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Decoding the encoding gives the same instruction, for every encoding of a class that decodes
+        for class in ENCODING_CLASSES.iter() {
+            let mut decoded = 0;
+            for value in class.encodings() {
+                if let Ok(instruction) = get_instruction(value, Architecture::ARMv5TE) {
+                    assert_eq!(get_instruction(instruction.encode(), Architecture::ARMv5TE).as_ref(), Ok(&instruction), "{} {:#010X}", class.name, value);
+                    decoded += 1;
+                }
+            }
+            assert!(decoded > 0, "{}", class.name);
+        }
+    }
+}
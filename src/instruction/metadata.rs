@@ -0,0 +1,133 @@
+use crate::{memory::AccessWidth, register::CPSR};
+
+use super::{Condition, PROGRAM_COUNTER};
+
+// What a decoded instruction reads, writes and where execution may continue, for analysis tools
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstructionMetadata {
+    pub registers_read: Vec<u8>, // Sorted, without duplicates (R15 when the PC is an operand)
+    pub registers_written: Vec<u8>, // Sorted, without duplicates (R15 when the instruction may jump)
+    pub flags_read: CPSR, // Including the flags of the condition
+    pub flags_written: CPSR,
+    pub memory_access: Option<MemoryAccess>,
+    pub control_flow: ControlFlow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub load: bool, // 0=store, 1=load
+    pub width: AccessWidth,
+    pub count: Option<usize>, // Number of transfers (None when only known at run time: coprocessor transfers)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ControlFlow {
+    // Always continues with the next instruction
+    #[default]
+    Sequential,
+    // May jump to a target relative to the address of the instruction
+    Branch {
+        relative_target: i32,
+        link: bool, // LR is set to the return address
+        exchange: bool, // The state switches to THUMB
+    },
+    // May jump to an address only known at run time (BX, MOV PC, LDR PC, LDM with PC)
+    Indirect {
+        link: bool,
+    },
+}
+
+impl ControlFlow {
+    pub fn may_change(&self) -> bool {
+        *self != ControlFlow::Sequential
+    }
+}
+
+pub trait DescribeInstruction {
+    fn metadata(&self) -> InstructionMetadata;
+}
+
+impl InstructionMetadata {
+    pub fn new(condition: Condition) -> Self {
+        InstructionMetadata {
+            flags_read: condition.flags_read(),
+            ..Default::default()
+        }
+    }
+
+    pub fn read(mut self, registers: &[u8]) -> Self {
+        self.registers_read.extend_from_slice(registers);
+        self.registers_read.sort_unstable();
+        self.registers_read.dedup();
+        self
+    }
+
+    pub fn write(mut self, registers: &[u8]) -> Self {
+        self.registers_written.extend_from_slice(registers);
+        self.registers_written.sort_unstable();
+        self.registers_written.dedup();
+        self
+    }
+
+    pub fn read_flags(mut self, flags: CPSR) -> Self {
+        self.flags_read |= flags;
+        self
+    }
+
+    pub fn write_flags(mut self, flags: CPSR) -> Self {
+        self.flags_written |= flags;
+        self
+    }
+
+    pub fn access(mut self, load: bool, width: AccessWidth, count: Option<usize>) -> Self {
+        self.memory_access = Some(MemoryAccess { load, width, count });
+        self
+    }
+
+    pub fn flow(mut self, control_flow: ControlFlow) -> Self {
+        self.control_flow = control_flow;
+        self
+    }
+
+    // Writing R15 is an indirect jump
+    pub fn with_pc_write(self) -> Self {
+        if self.registers_written.contains(&PROGRAM_COUNTER) && self.control_flow == ControlFlow::Sequential {
+            return self.flow(ControlFlow::Indirect { link: false });
+        }
+        self
+    }
+}
+
+impl Condition {
+    // Flags checked before the instruction executes
+    pub fn flags_read(&self) -> CPSR {
+        match self.bits() {
+            0b0000 | 0b0001 => CPSR::Z,
+            0b0010 | 0b0011 => CPSR::C,
+            0b0100 | 0b0101 => CPSR::N,
+            0b0110 | 0b0111 => CPSR::V,
+            0b1000 | 0b1001 => CPSR::C | CPSR::Z,
+            0b1010 | 0b1011 => CPSR::N | CPSR::V,
+            0b1100 | 0b1101 => CPSR::Z | CPSR::N | CPSR::V,
+            _ => CPSR::empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_builder_sorts_registers() {
+        let metadata = InstructionMetadata::new(Condition::GT)
+            .read(&[3, 1, 3])
+            .write(&[PROGRAM_COUNTER])
+            .with_pc_write();
+        assert_eq!(metadata.registers_read, vec![1, 3]);
+        assert_eq!(metadata.flags_read, CPSR::Z | CPSR::N | CPSR::V);
+        assert_eq!(metadata.control_flow, ControlFlow::Indirect { link: false });
+        assert!(metadata.control_flow.may_change());
+    }
+}
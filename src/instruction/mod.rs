@@ -1,9 +1,27 @@
 mod instruction;
+mod architecture;
 mod shift;
 mod data_proccessing;
 mod multiply;
+mod coprocessor;
+mod branch;
+mod single_data_transfer;
+mod halfword_data_transfer;
+mod block_data_transfer;
+mod count_leading_zeros;
+mod saturating_arithmetic;
+mod metadata;
 
 pub use instruction::*;
+pub use architecture::*;
 pub use shift::*;
 pub use data_proccessing::*;
-pub use multiply::*;
\ No newline at end of file
+pub use multiply::*;
+pub use coprocessor::*;
+pub use branch::*;
+pub use single_data_transfer::*;
+pub use halfword_data_transfer::*;
+pub use block_data_transfer::*;
+pub use count_leading_zeros::*;
+pub use saturating_arithmetic::*;
+pub use metadata::*;
@@ -1,11 +1,11 @@
 use core::fmt;
 
-use crate::{instruction::{get_s_flag, is_multiply_instruction, Condition, DecodeInstruction}, memory::MemoryBus, register::{ReadRegister, RegisterSet, WriteRegister}};
+use crate::{instruction::{encode_flag, get_s_flag, is_multiply_instruction, read_cpsr, write_cpsr, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, InstructionMetadata}, memory::Bus, register::{ReadRegister, RegisterSet, WriteRegister, CPSR}};
 
 use super::{Instruction, InstructionError};
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MultiplyInstruction {
     pub condition_bits: u8,
     // 27-25 must be 000b for this instruction
@@ -23,7 +23,11 @@ impl MultiplyInstruction {
     }
 
     pub fn opcode(&self) -> MultiplyOpcode {
-        MultiplyOpcode::from(self.opcode_bits)
+        // SMLAWy and SMULWy share opcode 1001b, x is set for SMULWy
+        match (MultiplyOpcode::from(self.opcode_bits), &self.operand) {
+            (MultiplyOpcode::SMLAWY, MultiplyOperand::HalfwordMultiplies { x: true, .. }) => MultiplyOpcode::SMULWY,
+            (opcode, _) => opcode,
+        }
     }
 }
 
@@ -57,13 +61,13 @@ impl From<u8> for MultiplyOpcode {
         0 => MultiplyOpcode::MUL,
         1 => MultiplyOpcode::MLA,
         2 => MultiplyOpcode::UMAAL,
-        3 => MultiplyOpcode::UMULL,
-        4 => MultiplyOpcode::UMLAL,
-        5 => MultiplyOpcode::SMULL,
-        6 => MultiplyOpcode::SMLAL,
-        7 => MultiplyOpcode::SMLAXY,
-        8 => MultiplyOpcode::SMLAWY,
-        9 => MultiplyOpcode::SMULWY,
+        4 => MultiplyOpcode::UMULL,
+        5 => MultiplyOpcode::UMLAL,
+        6 => MultiplyOpcode::SMULL,
+        7 => MultiplyOpcode::SMLAL,
+        8 => MultiplyOpcode::SMLAXY,
+        // SMULWy when x is set
+        9 => MultiplyOpcode::SMLAWY,
         10 => MultiplyOpcode::SMLALXY,
         11 => MultiplyOpcode::SMULXY,
         _ => MultiplyOpcode::Invalid,
@@ -106,6 +110,11 @@ pub enum MultiplyOperand {
     }
 }
 
+// SMLAxy/SMLAWy/SMULWy/SMLALxy/SMULxy: bits 27-23 00010b, bit 20 0, bit 7 1 and bit 4 0
+pub fn is_halfword_multiply_instruction(value: u32) -> bool {
+    (value & 0x0F90_0090) == 0x0100_0080
+}
+
 impl DecodeInstruction for MultiplyInstruction {
     fn decode(value: u32) -> Result<Self, InstructionError>
         where
@@ -127,7 +136,7 @@ impl DecodeInstruction for MultiplyInstruction {
 
         let s_flag = get_s_flag(value);
         // s_flag must be 0 for Halfword & UMAAL
-        if s_flag && opcode == MultiplyOpcode::SMLAL {
+        if s_flag && opcode == MultiplyOpcode::UMAAL {
             return Err(InstructionError::InvalidInstruction(value));
         }
 
@@ -136,8 +145,8 @@ impl DecodeInstruction for MultiplyInstruction {
         let rs: u8 = ((value >> 8) & 0xF) as u8;
 
         let operand = if matches!(opcode, MultiplyOpcode::SMLAXY | MultiplyOpcode::SMLALXY | MultiplyOpcode::SMLAWY | MultiplyOpcode::SMULWY | MultiplyOpcode::SMULXY) {
-            // check bit 7 - must be 1 for these instructions   
-            if ((value >> 7) & 0x1) != 1 {
+            // check bit 7 - must be 1 and bit 4 - must be 0 for these instructions
+            if ((value >> 7) & 0x1) != 1 || ((value >> 4) & 0x1) != 0 {
                 return Err(InstructionError::InvalidInstruction(value));
             }
 
@@ -174,8 +183,49 @@ impl DecodeInstruction for MultiplyInstruction {
     }
 }
 
+impl EncodeInstruction for MultiplyInstruction {
+    fn encode(&self) -> u32 {
+        let operand = match self.operand {
+            MultiplyOperand::NonHalfwordMultiplies { rm } => 0b1001_0000 | rm as u32,
+            MultiplyOperand::HalfwordMultiplies { y, x, rm } => 0b1000_0000 | encode_flag(y, 6) | encode_flag(x, 5) | rm as u32,
+        };
+
+        ((self.condition_bits as u32) << 28)
+            | ((self.opcode_bits as u32) << 21)
+            | encode_flag(self.s_flag, 20)
+            | ((self.rd as u32) << 16)
+            | ((self.rn as u32) << 12)
+            | ((self.rs as u32) << 8)
+            | operand
+    }
+}
+
+impl DescribeInstruction for MultiplyInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        let rm = match self.operand {
+            MultiplyOperand::NonHalfwordMultiplies { rm } | MultiplyOperand::HalfwordMultiplies { rm, .. } => rm,
+        };
+        let metadata = InstructionMetadata::new(self.condition()).read(&[rm, self.rs]);
+
+        let metadata = match self.opcode() {
+            MultiplyOpcode::MUL | MultiplyOpcode::SMULXY | MultiplyOpcode::SMULWY => metadata.write(&[self.rd]),
+            MultiplyOpcode::MLA => metadata.read(&[self.rn]).write(&[self.rd]),
+            MultiplyOpcode::SMLAXY | MultiplyOpcode::SMLAWY => metadata.read(&[self.rn]).write(&[self.rd]).write_flags(CPSR::Q),
+            MultiplyOpcode::UMULL | MultiplyOpcode::SMULL => metadata.write(&[self.rd, self.rn]),
+            // RdHi:RdLo accumulate
+            MultiplyOpcode::UMLAL | MultiplyOpcode::SMLAL | MultiplyOpcode::SMLALXY | MultiplyOpcode::UMAAL => {
+                metadata.read(&[self.rd, self.rn]).write(&[self.rd, self.rn])
+            }
+            MultiplyOpcode::Invalid => metadata,
+        };
+
+        let metadata = if self.s_flag { metadata.write_flags(CPSR::N | CPSR::Z) } else { metadata };
+        metadata.with_pc_write()
+    }
+}
+
 impl Instruction for MultiplyInstruction {
-    fn execute(&mut self, register_set: &RegisterSet, _memory_bus: &MemoryBus) -> Result<(), InstructionError> {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, _bus: &B) -> Result<(), InstructionError> {
         let mut rd_cell = register_set.get(self.rd).ok_or(InstructionError::InvalidRegister(self.rd as u32))?;
         let rn_cell = register_set.get(self.rn).ok_or(InstructionError::InvalidRegister(self.rn as u32))?;
         let rs_cell = register_set.get(self.rs).ok_or(InstructionError::InvalidRegister(self.rs as u32))?;
@@ -188,35 +238,71 @@ impl Instruction for MultiplyInstruction {
                let rs_value = rs_cell.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))?; 
                let rn_value = rn_cell.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))?;
 
-                match self.opcode() {
-                    MultiplyOpcode::MUL => rm_value * rs_value,
-                    MultiplyOpcode::MLA => rm_value * rs_value + rn_value, // TODO: Check restrictions such as rd != rm and rd, rm, rs, rn != 15
-                    MultiplyOpcode::UMAAL => todo!(),
-                    MultiplyOpcode::UMULL => todo!(),
-                    MultiplyOpcode::UMLAL => todo!(),
-                    MultiplyOpcode::SMULL => todo!(),
-                    MultiplyOpcode::SMLAL => todo!(),
-                    MultiplyOpcode::SMLAXY => {
-                        return Err(InstructionError::InvalidOpcode(self.opcode_bits));
+                let opcode = self.opcode();
+                match opcode {
+                    MultiplyOpcode::MUL | MultiplyOpcode::MLA => {
+                        // TODO: Check restrictions such as rd != rm and rd, rm, rs, rn != 15
+                        let mut result = rm_value.wrapping_mul(rs_value);
+                        if opcode == MultiplyOpcode::MLA {
+                            result = result.wrapping_add(rn_value);
+                        }
+                        if self.s_flag {
+                            self.set_flags(register_set, (result >> 31) != 0, result == 0)?;
+                        }
+                        result
                     },
-                    MultiplyOpcode::SMLAWY => {
-                        return Err(InstructionError::InvalidOpcode(self.opcode_bits));
+                    MultiplyOpcode::UMULL | MultiplyOpcode::UMLAL | MultiplyOpcode::SMULL | MultiplyOpcode::SMLAL => {
+                        // RdHi:RdLo = Rm * Rs (+ RdHi:RdLo)
+                        let product = match opcode {
+                            MultiplyOpcode::UMULL | MultiplyOpcode::UMLAL => rm_value as u64 * rs_value as u64,
+                            _ => (rm_value as i32 as i64 * rs_value as i32 as i64) as u64,
+                        };
+                        let result = if matches!(opcode, MultiplyOpcode::UMLAL | MultiplyOpcode::SMLAL) {
+                            let rd_hi = rd_cell.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))? as u64;
+                            product.wrapping_add((rd_hi << 32) | rn_value as u64)
+                        } else {
+                            product
+                        };
+                        rn_cell.clone().write(result as u32).map_err(|e| InstructionError::RegisterWriteError(e.to_string()))?;
+                        if self.s_flag {
+                            self.set_flags(register_set, (result >> 63) != 0, result == 0)?;
+                        }
+                        (result >> 32) as u32
                     },
-                    MultiplyOpcode::SMULWY => {
+                    // UMAAL was added in ARMv6
+                    MultiplyOpcode::UMAAL => return Err(InstructionError::UndefinedInstruction()),
+                    _ => {
                         return Err(InstructionError::InvalidOpcode(self.opcode_bits));
                     },
+                }
+            },
+            MultiplyOperand::HalfwordMultiplies { y, x, rm } => {
+                let rm_cell = register_set.get(rm).ok_or(InstructionError::InvalidRegister(rm as u32))?;
+                let rm_value = rm_cell.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))? as i32;
+                let rs_value = rs_cell.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))? as i32;
+                let rn_value = rn_cell.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))? as i32;
+
+                // Signed 16-bit halves, T=upper, B=lower
+                let half = |value: i32, top: bool| if top { value >> 16 } else { (value as i16) as i32 };
+
+                match self.opcode() {
+                    MultiplyOpcode::SMULXY => (half(rm_value, x) * half(rs_value, y)) as u32,
+                    MultiplyOpcode::SMULWY => ((rm_value as i64 * half(rs_value, y) as i64) >> 16) as u32,
+                    MultiplyOpcode::SMLAXY => self.saturating_accumulate(register_set, half(rm_value, x) * half(rs_value, y), rn_value)?,
+                    MultiplyOpcode::SMLAWY => self.saturating_accumulate(register_set, ((rm_value as i64 * half(rs_value, y) as i64) >> 16) as i32, rn_value)?,
                     MultiplyOpcode::SMLALXY => {
-                        return Err(InstructionError::InvalidOpcode(self.opcode_bits));
+                        // RdHi:RdLo += Rm.x * Rs.y, no saturation
+                        let rd_hi = rd_cell.read().map_err(|e| InstructionError::RegisterReadError(e.to_string()))? as u64;
+                        let accumulator = ((rd_hi << 32) | rn_value as u32 as u64) as i64;
+                        let result = accumulator.wrapping_add((half(rm_value, x) * half(rs_value, y)) as i64) as u64;
+                        rn_cell.clone().write(result as u32).map_err(|e| InstructionError::RegisterWriteError(e.to_string()))?;
+                        (result >> 32) as u32
                     },
-                    MultiplyOpcode::SMULXY => {
+                    _ => {
                         return Err(InstructionError::InvalidOpcode(self.opcode_bits));
                     },
-                    MultiplyOpcode::Invalid => {
-                        return Err(InstructionError::InvalidOpcode(self.opcode_bits));
-                    }, // TODO: need to use correct opcode
                 }
             },
-            MultiplyOperand::HalfwordMultiplies { y, x, rm } => todo!(),
         };
 
         rd_cell.write(result).map_err(|e| InstructionError::RegisterWriteError(e.to_string()))?;
@@ -225,9 +311,32 @@ impl Instruction for MultiplyInstruction {
 
 }
 
+impl MultiplyInstruction {
+    // MUL/MLA and the long multiplies only update N and Z
+    fn set_flags(&self, register_set: &RegisterSet, negative: bool, zero: bool) -> Result<(), InstructionError> {
+        let mut cpsr = read_cpsr(register_set)?;
+        cpsr.setn(negative);
+        cpsr.setz(zero);
+        write_cpsr(register_set, cpsr)
+    }
+
+    // Adds the accumulate value, setting the sticky overflow flag (Q) if the addition overflows
+    fn saturating_accumulate(&self, register_set: &RegisterSet, product: i32, accumulate: i32) -> Result<u32, InstructionError> {
+        let (result, overflow) = product.overflowing_add(accumulate);
+        if overflow {
+            let mut cpsr = read_cpsr(register_set)?;
+            cpsr.setq(true);
+            write_cpsr(register_set, cpsr)?;
+        }
+        Ok(result as u32)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
+    use crate::instruction::assert_round_trip;
+
     use super::*;
 
     #[test]
@@ -254,4 +363,114 @@ mod tests {
         assert_eq!(instruction.rs, 3);
         assert_eq!(instruction.operand, MultiplyOperand::NonHalfwordMultiplies { rm: 1 });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_halfword_multiply_decode() {
+        // SMULWT R0, R1, R2
+        let value: u32 = 0b1110_0001_0010_0000_0000_0010_1110_0001;
+        let instruction = MultiplyInstruction::decode(value).unwrap();
+        assert_eq!(instruction.opcode(), MultiplyOpcode::SMULWY);
+        assert_eq!(instruction.operand, MultiplyOperand::HalfwordMultiplies { y: true, x: true, rm: 1 });
+
+        // SMLAWT R0, R1, R2, R3
+        let value: u32 = 0b1110_0001_0010_0000_0011_0010_1100_0001;
+        let instruction = MultiplyInstruction::decode(value).unwrap();
+        assert_eq!(instruction.opcode(), MultiplyOpcode::SMLAWY);
+    }
+
+    fn register_set(values: [u32; 4]) -> RegisterSet {
+        let mut builder = RegisterSet::builder();
+        for (register, value) in values.iter().enumerate() {
+            builder.with_register(register as u8, crate::register::RegisterCell::new(*value)).unwrap();
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_smulxy_execute() {
+        // SMULTB R0, R1, R2
+        let value: u32 = 0b1110_0001_0110_0000_0000_0010_1010_0001;
+        let mut instruction = MultiplyInstruction::decode(value).unwrap();
+        let register_set = register_set([0, 0xFFFE_0000, 0x0000_0003, 0]);
+        instruction.execute(&register_set, &crate::memory::FlatBus::new()).unwrap();
+        assert_eq!(register_set.get(0).unwrap().read().unwrap(), (-6i32) as u32);
+    }
+
+    #[test]
+    fn test_smlaxy_sets_q_flag() {
+        // SMLABB R0, R1, R2, R3
+        let value: u32 = 0b1110_0001_0000_0000_0011_0010_1000_0001;
+        let mut instruction = MultiplyInstruction::decode(value).unwrap();
+        let register_set = register_set([0, 2, 3, 0x7FFF_FFFF]);
+        instruction.execute(&register_set, &crate::memory::FlatBus::new()).unwrap();
+        // The result wraps, only Q records the overflow
+        assert_eq!(register_set.get(0).unwrap().read().unwrap(), 0x8000_0005);
+        assert!(read_cpsr(&register_set).unwrap().is_sticky_overflow());
+    }
+
+    #[test]
+    fn test_smlalxy_execute() {
+        // SMLALBB R2 (RdLo), R0 (RdHi), R1, R3
+        let value: u32 = 0b1110_0001_0100_0000_0010_0011_1000_0001;
+        let mut instruction = MultiplyInstruction::decode(value).unwrap();
+        let register_set = register_set([0, 2, 0xFFFF_FFFF, 4]);
+        instruction.execute(&register_set, &crate::memory::FlatBus::new()).unwrap();
+        assert_eq!(register_set.get(2).unwrap().read().unwrap(), 0x0000_0007);
+        assert_eq!(register_set.get(0).unwrap().read().unwrap(), 0x0000_0001);
+    }
+
+    #[test]
+    fn test_long_multiply_execute() {
+        // SMULLS R2 (RdLo), R0 (RdHi), R1, R3
+        let mut instruction = MultiplyInstruction::decode(0xE0D02391).unwrap();
+        assert_eq!(instruction.opcode(), MultiplyOpcode::SMULL);
+        let signed = register_set([0, (-2i32) as u32, 0, 4]);
+        instruction.execute(&signed, &crate::memory::FlatBus::new()).unwrap();
+        assert_eq!(signed.get(2).unwrap().read().unwrap(), (-8i32) as u32);
+        assert_eq!(signed.get(0).unwrap().read().unwrap(), 0xFFFF_FFFF);
+        assert!(read_cpsr(&signed).unwrap().is_negative());
+
+        // UMLAL R2 (RdLo), R0 (RdHi), R1, R3
+        let mut instruction = MultiplyInstruction::decode(0xE0A02391).unwrap();
+        assert_eq!(instruction.opcode(), MultiplyOpcode::UMLAL);
+        let register_set = register_set([1, 2, 0xFFFF_FFFF, 4]);
+        instruction.execute(&register_set, &crate::memory::FlatBus::new()).unwrap();
+        assert_eq!(register_set.get(2).unwrap().read().unwrap(), 7);
+        assert_eq!(register_set.get(0).unwrap().read().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Every condition, opcode, S flag, bits 7-4 and register
+        let values = (0..16u32).flat_map(|condition| (0..32u32).flat_map(move |bits_24_20| (0..16u32).flat_map(move |bits_7_4| (0..16u32).map(move |register| {
+            (condition << 28) | (bits_24_20 << 20) | (register << 16) | ((register ^ 0x3) << 12) | ((register ^ 0xC) << 8) | (bits_7_4 << 4) | (15 - register)
+        }))));
+        // 13 MUL/MLA/long forms with bits 7-4 1001b, 16 halfword forms
+        assert_eq!(assert_round_trip::<MultiplyInstruction>(values), 16 * 16 * (13 + 16));
+    }
+
+    #[test]
+    fn test_encode_structural() {
+        // Same instruction as test_mul_decode
+        let instruction = MultiplyInstruction {
+            condition_bits: Condition::NE.bits(),
+            opcode_bits: 0,
+            s_flag: true,
+            rd: 2,
+            rn: 4,
+            rs: 3,
+            operand: MultiplyOperand::NonHalfwordMultiplies { rm: 1 },
+        };
+        assert_eq!(instruction.encode(), 0b0001_000_0000_1_0010_0100_0011_1001_0001);
+    }
+
+    #[test]
+    fn test_metadata() {
+        // SMLALS R2 (RdLo), R0 (RdHi), R1, R3
+        let metadata = MultiplyInstruction::decode(0xE0F02391).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![0, 1, 2, 3]);
+        assert_eq!(metadata.registers_written, vec![0, 2]);
+        assert_eq!(metadata.flags_written, CPSR::N | CPSR::Z);
+        assert_eq!(metadata.memory_access, None);
+    }
+}
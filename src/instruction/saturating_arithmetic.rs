@@ -0,0 +1,180 @@
+use core::fmt;
+
+use strum_macros::Display;
+
+use crate::{memory::Bus, register::{RegisterSet, CPSR}};
+
+use super::{read_cpsr, read_register, write_cpsr, write_register, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, Instruction, InstructionError, InstructionMetadata};
+
+// QADD/QSUB/QDADD/QDSUB: ARMv5TE and above
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaturatingArithmeticInstruction {
+    pub condition_bits: u8, // Bits 31-28
+    // 27-23 must be 00010b and bit 20 must be 0 for this instruction
+    pub opcode_bits: u8, // Bits 22-21
+    pub rn: u8, // Bits 19-16 (Second operand register: R0-R14) (Doubled for QDADD/QDSUB)
+    pub rd: u8, // Bits 15-12 (Destination register: R0-R14)
+    // 11-4 must be 0000_0101b for this instruction
+    pub rm: u8, // Bits 3-0 (First operand register: R0-R14)
+}
+
+#[derive(Debug, Clone, Display, PartialEq, Eq)]
+pub enum SaturatingArithmeticOpcode {
+    QADD,
+    QSUB,
+    QDADD,
+    QDSUB,
+}
+
+impl From<u8> for SaturatingArithmeticOpcode {
+    fn from(value: u8) -> Self {
+        match value & 0b11 {
+            0 => SaturatingArithmeticOpcode::QADD,
+            1 => SaturatingArithmeticOpcode::QSUB,
+            2 => SaturatingArithmeticOpcode::QDADD,
+            _ => SaturatingArithmeticOpcode::QDSUB,
+        }
+    }
+}
+
+impl SaturatingArithmeticInstruction {
+    pub fn condition(&self) -> Condition {
+        Condition::from_bits_truncate(self.condition_bits)
+    }
+
+    pub fn opcode(&self) -> SaturatingArithmeticOpcode {
+        SaturatingArithmeticOpcode::from(self.opcode_bits)
+    }
+}
+
+impl fmt::Display for SaturatingArithmeticInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{{{}}} R{},R{},R{}", self.opcode(), self.condition(), self.rd, self.rm, self.rn)
+    }
+}
+
+pub fn is_saturating_arithmetic_instruction(value: u32) -> bool {
+    (value & 0x0F90_0FF0) == 0x0100_0050
+}
+
+impl DecodeInstruction for SaturatingArithmeticInstruction {
+    fn decode(value: u32) -> Result<Self, InstructionError>
+        where
+            Self: Sized {
+
+        if !is_saturating_arithmetic_instruction(value) {
+            return Err(InstructionError::InvalidInstruction(value));
+        }
+
+        Ok(SaturatingArithmeticInstruction {
+            condition_bits: (value >> 28) as u8,
+            opcode_bits: ((value >> 21) & 0b11) as u8,
+            rn: ((value >> 16) & 0xF) as u8,
+            rd: ((value >> 12) & 0xF) as u8,
+            rm: (value & 0xF) as u8,
+        })
+    }
+}
+
+impl DescribeInstruction for SaturatingArithmeticInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        InstructionMetadata::new(self.condition()).read(&[self.rm, self.rn]).write(&[self.rd]).write_flags(CPSR::Q).with_pc_write()
+    }
+}
+
+impl EncodeInstruction for SaturatingArithmeticInstruction {
+    fn encode(&self) -> u32 {
+        ((self.condition_bits as u32) << 28)
+            | 0x0100_0050
+            | ((self.opcode_bits as u32) << 21)
+            | ((self.rn as u32) << 16)
+            | ((self.rd as u32) << 12)
+            | self.rm as u32
+    }
+}
+
+impl Instruction for SaturatingArithmeticInstruction {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, _bus: &B) -> Result<(), InstructionError> {
+        let rm_value = read_register(register_set, self.rm)? as i32;
+        let rn_value = read_register(register_set, self.rn)? as i32;
+
+        // Saturation of the doubling also sets Q
+        let (rn_value, doubled_saturated) = match self.opcode() {
+            SaturatingArithmeticOpcode::QDADD | SaturatingArithmeticOpcode::QDSUB => {
+                (rn_value.saturating_mul(2), rn_value.checked_mul(2).is_none())
+            }
+            _ => (rn_value, false),
+        };
+
+        let (result, saturated) = match self.opcode() {
+            SaturatingArithmeticOpcode::QADD | SaturatingArithmeticOpcode::QDADD => {
+                (rm_value.saturating_add(rn_value), rm_value.checked_add(rn_value).is_none())
+            }
+            SaturatingArithmeticOpcode::QSUB | SaturatingArithmeticOpcode::QDSUB => {
+                (rm_value.saturating_sub(rn_value), rm_value.checked_sub(rn_value).is_none())
+            }
+        };
+
+        if saturated || doubled_saturated {
+            let mut cpsr = read_cpsr(register_set)?;
+            cpsr.setq(true);
+            write_cpsr(register_set, cpsr)?;
+        }
+
+        write_register(register_set, self.rd, result as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{instruction::assert_round_trip, memory::FlatBus, register::RegisterCell};
+
+    use super::*;
+
+    fn register_set(rm: u32, rn: u32) -> RegisterSet {
+        RegisterSet::builder()
+            .with_register(0, RegisterCell::new(0)).unwrap()
+            .with_register(1, RegisterCell::new(rm)).unwrap()
+            .with_register(2, RegisterCell::new(rn)).unwrap()
+            .build()
+    }
+
+    #[test]
+    fn test_qadd_saturates() {
+        // QADD R0, R1, R2
+        let mut instruction = SaturatingArithmeticInstruction::decode(0xE1020051).unwrap();
+        assert_eq!(instruction.to_string(), "QADD{AL} R0,R1,R2");
+
+        let register_set = register_set(0x7FFF_FFF0, 0x100);
+        instruction.execute(&register_set, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&register_set, 0).unwrap(), 0x7FFF_FFFF);
+        assert!(read_cpsr(&register_set).unwrap().is_sticky_overflow());
+    }
+
+    #[test]
+    fn test_qdsub() {
+        // QDSUB R0, R1, R2
+        let mut instruction = SaturatingArithmeticInstruction::decode(0xE1620051).unwrap();
+        assert_eq!(instruction.opcode(), SaturatingArithmeticOpcode::QDSUB);
+
+        let unsaturated = register_set(10, 3);
+        instruction.execute(&unsaturated, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&unsaturated, 0).unwrap(), 4);
+        assert!(!read_cpsr(&unsaturated).unwrap().is_sticky_overflow());
+
+        // Doubling 0xA0000000 saturates to 0x80000000, 0 - 0x80000000 saturates again
+        let saturated = register_set(0, 0xA000_0000);
+        instruction.execute(&saturated, &FlatBus::new()).unwrap();
+        assert_eq!(read_register(&saturated, 0).unwrap(), 0x7FFF_FFFF);
+        assert!(read_cpsr(&saturated).unwrap().is_sticky_overflow());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let values = (0..16u32).flat_map(|condition| (0..4u32).flat_map(move |opcode| (0..4096u32).map(move |registers| {
+            (condition << 28) | 0x0100_0050 | (opcode << 21) | ((registers >> 8) << 16) | (((registers >> 4) & 0xF) << 12) | (registers & 0xF)
+        })));
+        assert_eq!(assert_round_trip::<SaturatingArithmeticInstruction>(values), 16 * 4 * 4096);
+    }
+}
@@ -0,0 +1,281 @@
+use core::fmt;
+
+use crate::{memory::{AccessWidth, Bus}, register::{RegisterSet, CPSR}};
+
+use super::{encode_flag, read_cpsr, read_register, write_register, Condition, DecodeInstruction, DescribeInstruction, EncodeInstruction, Instruction, InstructionMetadata, InstructionError, ShiftType, PROGRAM_COUNTER};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SingleDataTransferInstruction {
+    pub condition_bits: u8, // Bits 31-28
+    // 27-26 must be 01b for this instruction
+    pub pre_index: bool, // Bit 24 (0=post: add offset after transfer, 1=pre: before transfer)
+    pub up: bool, // Bit 23 (0=down: subtract offset from base, 1=up: add to base)
+    pub byte: bool, // Bit 22 (0=word, 1=byte)
+    pub write_back: bool, // Bit 21 (0=no write-back, 1=write address into base) (post-indexed always writes back)
+    pub load: bool, // Bit 20 (0=STR, 1=LDR)
+    pub rn: u8, // Bits 19-16 (Base register: R0-R15) (Including PC=R15)
+    pub rd: u8, // Bits 15-12 (Source/Destination register: R0-R15) (Including PC=R15)
+    pub offset: SingleDataTransferOffset, // Bits 11-0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SingleDataTransferOffset {
+    // Bit 25 = 0
+    Immediate(u16), // Bits 11-0 (Unsigned 12-bit offset)
+    // Bit 25 = 1, bit 4 must be 0
+    Register {
+        shift_amount: u8, // Bits 11-7 (Shift amount: 1-31, 0 is a special case)
+        shift_type: ShiftType, // Bits 6-5
+        rm: u8, // Bits 3-0 (Offset register: R0-R14)
+    },
+}
+
+impl SingleDataTransferInstruction {
+    pub fn condition(&self) -> Condition {
+        Condition::from_bits_truncate(self.condition_bits)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match (self.load, self.byte) {
+            (false, false) => "STR",
+            (false, true) => "STRB",
+            (true, false) => "LDR",
+            (true, true) => "LDRB",
+        }
+    }
+
+    fn offset_value(&self, register_set: &RegisterSet) -> Result<u32, InstructionError> {
+        match &self.offset {
+            SingleDataTransferOffset::Immediate(offset) => Ok(*offset as u32),
+            SingleDataTransferOffset::Register { shift_amount, shift_type, rm } => {
+                let rm_value = read_register(register_set, *rm)?;
+                let carry_in = read_cpsr(register_set)?.carry();
+                Ok(shift_type.clone().shift(*shift_amount, rm_value, carry_in).value)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SingleDataTransferInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.up { "+" } else { "-" };
+        write!(f, "{}{{{}}} R{},[R{}", self.mnemonic(), self.condition(), self.rd, self.rn)?;
+        if !self.pre_index {
+            write!(f, "]")?;
+        }
+        match &self.offset {
+            SingleDataTransferOffset::Immediate(offset) => write!(f, ",#{}{}", sign, offset)?,
+            SingleDataTransferOffset::Register { shift_amount, shift_type, rm } => write!(f, ",{}R{},{}#{}", sign, rm, shift_type, shift_amount)?,
+        }
+        if self.pre_index {
+            write!(f, "]{}", if self.write_back { "!" } else { "" })?;
+        }
+        Ok(())
+    }
+}
+
+pub fn is_single_data_transfer_instruction(value: u32) -> bool {
+    let bits_27_26 = (value >> 26) & 0b11;
+    bits_27_26 == 0b01
+}
+
+impl DecodeInstruction for SingleDataTransferInstruction {
+    fn decode(value: u32) -> Result<Self, InstructionError>
+        where
+            Self: Sized {
+
+        let condition_bits = (value >> 28) as u8;
+        if !is_single_data_transfer_instruction(value) {
+            return Err(InstructionError::InvalidInstruction(value));
+        }
+
+        let offset = if (value & (1 << 25)) != 0 {
+            // Bit 4 set is the undefined instruction space
+            if (value & (1 << 4)) != 0 {
+                return Err(InstructionError::UndefinedInstruction());
+            }
+            SingleDataTransferOffset::Register {
+                shift_amount: ((value >> 7) & 0x1F) as u8,
+                shift_type: ShiftType::from_bits_retain(((value >> 5) & 0x3) as u8),
+                rm: (value & 0xF) as u8,
+            }
+        } else {
+            SingleDataTransferOffset::Immediate((value & 0xFFF) as u16)
+        };
+
+        Ok(SingleDataTransferInstruction {
+            condition_bits,
+            pre_index: (value & (1 << 24)) != 0,
+            up: (value & (1 << 23)) != 0,
+            byte: (value & (1 << 22)) != 0,
+            write_back: (value & (1 << 21)) != 0,
+            load: (value & (1 << 20)) != 0,
+            rn: ((value >> 16) & 0xF) as u8,
+            rd: ((value >> 12) & 0xF) as u8,
+            offset,
+        })
+    }
+}
+
+impl DescribeInstruction for SingleDataTransferInstruction {
+    fn metadata(&self) -> InstructionMetadata {
+        let mut metadata = InstructionMetadata::new(self.condition()).read(&[self.rn]);
+        if let SingleDataTransferOffset::Register { shift_amount, shift_type, rm } = &self.offset {
+            metadata = metadata.read(&[*rm]);
+            // ROR #0 is RRX, rotating the carry in
+            if *shift_amount == 0 && *shift_type == ShiftType::ROR {
+                metadata = metadata.read_flags(CPSR::C);
+            }
+        }
+
+        metadata = if self.load { metadata.write(&[self.rd]) } else { metadata.read(&[self.rd]) };
+        if self.write_back || !self.pre_index {
+            metadata = metadata.write(&[self.rn]);
+        }
+
+        let width = if self.byte { AccessWidth::Byte } else { AccessWidth::Word };
+        metadata.access(self.load, width, Some(1)).with_pc_write()
+    }
+}
+
+impl EncodeInstruction for SingleDataTransferInstruction {
+    fn encode(&self) -> u32 {
+        let offset = match &self.offset {
+            SingleDataTransferOffset::Immediate(offset) => (*offset as u32) & 0xFFF,
+            SingleDataTransferOffset::Register { shift_amount, shift_type, rm } => {
+                (1 << 25) | ((*shift_amount as u32) << 7) | ((shift_type.bits() as u32) << 5) | *rm as u32
+            }
+        };
+
+        ((self.condition_bits as u32) << 28)
+            | (0b01 << 26)
+            | encode_flag(self.pre_index, 24)
+            | encode_flag(self.up, 23)
+            | encode_flag(self.byte, 22)
+            | encode_flag(self.write_back, 21)
+            | encode_flag(self.load, 20)
+            | ((self.rn as u32) << 16)
+            | ((self.rd as u32) << 12)
+            | offset
+    }
+}
+
+impl Instruction for SingleDataTransferInstruction {
+    fn execute<B: Bus>(&mut self, register_set: &RegisterSet, bus: &B) -> Result<(), InstructionError> {
+        let base = read_register(register_set, self.rn)?;
+        let offset = self.offset_value(register_set)?;
+        let offset_address = if self.up { base.wrapping_add(offset) } else { base.wrapping_sub(offset) };
+        let address = if self.pre_index { offset_address } else { base };
+        let write_back = self.write_back || !self.pre_index;
+
+        if self.load {
+            let value = if self.byte {
+                bus.read_u8(address).map_err(InstructionError::MemoryError)? as u32
+            } else {
+                bus.load_u32(address).map_err(InstructionError::MemoryError)?
+            };
+            // The loaded value wins when the base is also the destination
+            if write_back {
+                write_register(register_set, self.rn, offset_address)?;
+            }
+            write_register(register_set, self.rd, value)
+        } else {
+            let mut value = read_register(register_set, self.rd)?;
+            // Storing the PC stores the address of the instruction + 12
+            if self.rd == PROGRAM_COUNTER {
+                value = value.wrapping_add(4);
+            }
+            if self.byte {
+                bus.write_u8(address, value as u8).map_err(InstructionError::MemoryError)?;
+            } else {
+                bus.store_u32(address, value).map_err(InstructionError::MemoryError)?;
+            }
+            if write_back {
+                write_register(register_set, self.rn, offset_address)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{instruction::assert_round_trip, memory::FlatBus, register::{CPSRCell, RegisterCell, CPSR}};
+
+    use super::*;
+
+    fn register_set(values: [u32; 3]) -> RegisterSet {
+        let mut builder = RegisterSet::builder();
+        for (register, value) in values.iter().enumerate() {
+            builder.with_register(register as u8, RegisterCell::new(*value)).unwrap();
+        }
+        builder.with_cpsr(CPSRCell::new(CPSR::default())).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_ldr_post_indexed() {
+        // LDR R0, [R1], #4
+        let value: u32 = 0b1110_01_0_0_1_0_0_1_0001_0000_000000000100;
+        let mut instruction = SingleDataTransferInstruction::decode(value).unwrap();
+        assert_eq!(instruction.to_string(), "LDR{AL} R0,[R1],#+4");
+
+        let bus = FlatBus::new();
+        bus.write_u32(0x100, 0xDEADBEEF).unwrap();
+        let register_set = register_set([0, 0x100, 0]);
+        instruction.execute(&register_set, &bus).unwrap();
+        assert_eq!(read_register(&register_set, 0).unwrap(), 0xDEADBEEF);
+        assert_eq!(read_register(&register_set, 1).unwrap(), 0x104);
+    }
+
+    #[test]
+    fn test_ldr_misaligned_rotates() {
+        // LDR R0, [R1, #1] / STR R0, [R1, #6]
+        let mut load = SingleDataTransferInstruction::decode(0xE5910001).unwrap();
+        let mut store = SingleDataTransferInstruction::decode(0xE5810006).unwrap();
+
+        let bus = FlatBus::new();
+        bus.write_u32(0x100, 0x12345678).unwrap();
+        let register_set = register_set([0, 0x100, 0]);
+        load.execute(&register_set, &bus).unwrap();
+        assert_eq!(read_register(&register_set, 0).unwrap(), 0x78123456);
+
+        store.execute(&register_set, &bus).unwrap();
+        assert_eq!(bus.read_u32(0x104).unwrap(), 0x78123456);
+    }
+
+    #[test]
+    fn test_strb_register_offset() {
+        // STRB R0, [R1, -R2, LSL#2]!
+        let value: u32 = 0b1110_01_1_1_0_1_1_0_0001_0000_00010_00_0_0010;
+        let mut instruction = SingleDataTransferInstruction::decode(value).unwrap();
+        assert_eq!(instruction.offset, SingleDataTransferOffset::Register { shift_amount: 2, shift_type: ShiftType::LSL, rm: 2 });
+
+        let bus = FlatBus::new();
+        let register_set = register_set([0x1234, 0x110, 4]);
+        instruction.execute(&register_set, &bus).unwrap();
+        assert_eq!(bus.read_u32(0x100).unwrap(), 0x34);
+        assert_eq!(read_register(&register_set, 1).unwrap(), 0x100);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        // Every condition, flag combination and offset form
+        let values = (0..16u32).flat_map(|condition| (0..64u32).flat_map(move |bits_25_20| (0..256u32).map(move |bits_11_4| {
+            (condition << 28) | (0b01 << 26) | (bits_25_20 << 20) | ((bits_11_4 & 0xF) << 16) | ((bits_25_20 & 0xF) << 12) | (bits_11_4 << 4) | (condition ^ 0x9)
+        })));
+        // Register offsets with bit 4 set are undefined
+        assert_eq!(assert_round_trip::<SingleDataTransferInstruction>(values), 16 * (32 * 256 + 32 * 128));
+    }
+
+    #[test]
+    fn test_metadata() {
+        // LDR PC, [R1], #4
+        let metadata = SingleDataTransferInstruction::decode(0xE491F004).unwrap().metadata();
+        assert_eq!(metadata.registers_read, vec![1]);
+        assert_eq!(metadata.registers_written, vec![1, PROGRAM_COUNTER]);
+        assert_eq!(metadata.memory_access, Some(crate::instruction::MemoryAccess { load: true, width: AccessWidth::Word, count: Some(1) }));
+        assert!(metadata.control_flow.may_change());
+    }
+}
@@ -1,13 +1,325 @@
-
 mod cpu;
 mod memory;
 mod register;
 mod gba;
 mod instruction;
+mod analysis;
+
+use std::{env, fs, process, slice};
+
+use analysis::{analyze_rom, ExportFormat};
+use cpu::{UnimplementedPolicy, CPU};
+use gba::{init_gba_cpu, soft_reset, Bios, BiosSource, Cartridge, GbaConfig, SaveFile, SaveType};
+use instruction::{Architecture, PROGRAM_COUNTER};
+
+const USAGE: &str = "Usage: rusty_dolphine [[--bios <file>] [--save-type <sram|flash64|flash128|eeprom>] [--unimplemented <undefined|error|skip>] <rom> | --analyze <dot|calls|json> [--arch <v4t|v5te>] [--entry <address>]... <rom>]";
+
+// 228 lines of 1232 cycles
+const CYCLES_PER_FRAME: u64 = 280896;
 
-use gba::init_gba_cpu;
+// Control flow graph of the ROM in the format, the options come before the ROM path
+fn analyze(format: &str, args: &[String]) -> Result<String, String> {
+    let format = ExportFormat::from_name(format).ok_or_else(|| format!("Unknown format {}\n{}", format, USAGE))?;
+    let mut architecture = Architecture::default();
+    // Addresses in hex, odd ones for THUMB code
+    let mut entry_points = Vec::new();
+    let mut args = args.iter();
+    let rom_path = loop {
+        match args.next().map(String::as_str) {
+            Some("--arch") => {
+                let name = option_value(&mut args, "--arch")?;
+                architecture = Architecture::from_name(name).ok_or_else(|| format!("Unknown architecture {}\n{}", name, USAGE))?;
+            }
+            Some("--entry") => {
+                let address = option_value(&mut args, "--entry")?;
+                entry_points.push(u32::from_str_radix(address.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("Invalid address {}\n{}", address, USAGE))?);
+            }
+            Some(rom_path) if !rom_path.starts_with("--") && args.len() == 0 => break rom_path,
+            _ => return Err(USAGE.to_string()),
+        }
+    };
+    let rom = fs::read(rom_path).map_err(|e| format!("Failed to read {}: {}", rom_path, e))?;
+    Ok(analyze_rom(&rom, architecture, &entry_points).export(format))
+}
 
+#[derive(Debug, Default)]
+struct RunOptions {
+    // BIOS dump to run with instead of the emulated BIOS
+    bios_path: Option<String>,
+    // Overrides the save type found in the ROM
+    save_type: Option<SaveType>,
+    unimplemented_policy: UnimplementedPolicy,
+    // Runs until the CPU stops without a limit
+    frames: Option<u64>,
+}
+
+impl RunOptions {
+    // Options followed by the ROM path
+    fn parse(args: &[String]) -> Result<(RunOptions, &str), String> {
+        let mut options = RunOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bios" => options.bios_path = Some(option_value(&mut args, arg)?.to_string()),
+                "--save-type" => {
+                    let name = option_value(&mut args, arg)?;
+                    options.save_type = Some(SaveType::from_name(name).ok_or_else(|| format!("Unknown save type {}\n{}", name, USAGE))?);
+                }
+                "--unimplemented" => {
+                    let name = option_value(&mut args, arg)?;
+                    options.unimplemented_policy = UnimplementedPolicy::from_name(name)
+                        .ok_or_else(|| format!("Unknown policy {}\n{}", name, USAGE))?;
+                }
+                rom_path if !rom_path.starts_with("--") && args.len() == 0 => return Ok((options, rom_path)),
+                _ => break,
+            }
+        }
+        Err(USAGE.to_string())
+    }
+}
+
+fn option_value<'a>(args: &mut slice::Iter<'a, String>, option: &str) -> Result<&'a str, String> {
+    args.next().map(String::as_str).ok_or_else(|| format!("Missing value for {}\n{}", option, USAGE))
+}
+
+// BIOS dump at the path, the BIOS calls are emulated without one
+fn bios_source(bios_path: Option<&str>) -> Result<BiosSource, String> {
+    let Some(bios_path) = bios_path else {
+        return Ok(BiosSource::Hle);
+    };
+    let bios = Bios::from_file(bios_path).map_err(|e| format!("Failed to load {}: {}", bios_path, e))?;
+    if let Err(error) = bios.verify() {
+        eprintln!("Warning: {}", error);
+    }
+    Ok(BiosSource::Image(bios))
+}
+
+// Runs the ROM until the CPU stops or the frames ran. The save file next to the ROM is
+// loaded first and written when the run ends. The boot skips the BIOS intro with either BIOS.
+fn run(rom_path: &str, options: &RunOptions) -> Result<(), String> {
+    let cartridge = Cartridge::from_file(rom_path).map_err(|e| format!("Failed to load {}: {}", rom_path, e))?;
+    // Games without a save library get SRAM
+    let save_type = options.save_type.or(cartridge.save_type()).unwrap_or(SaveType::Sram);
+    let config = GbaConfig { bios: bios_source(options.bios_path.as_deref())?, backup: save_type.backup() };
+    let mut cpu = init_gba_cpu(&config).map_err(|e| format!("Failed to initialize GBA CPU: {:?}", e))?;
+    cpu.set_unimplemented_policy(options.unimplemented_policy);
+    cartridge.load(&cpu.memory_bus).map_err(|e| e.to_string())?;
+    soft_reset(&cpu).map_err(|e| format!("Failed to reset: {:?}", e))?;
+
+    let mut save_file = SaveFile::for_rom(rom_path, config.backup.shared()).map_err(|e| e.to_string())?;
+    let result = run_frames(&mut cpu, &mut save_file, options.frames);
+    let report = cpu.unimplemented_report();
+    if !report.is_empty() {
+        eprint!("{}", report);
+    }
+    let closed = save_file.close().map_err(|e| e.to_string());
+    result.and(closed)
+}
+
+fn run_frames(cpu: &mut CPU, save_file: &mut SaveFile, frames: Option<u64>) -> Result<(), String> {
+    let mut frame = 0;
+    while frames.is_none_or(|frames| frame < frames) {
+        let end = cpu.cycles() + CYCLES_PER_FRAME;
+        while cpu.cycles() < end {
+            if let Err(error) = cpu.step() {
+                let pc = cpu.read_register(PROGRAM_COUNTER).unwrap_or_default();
+                return Err(format!("CPU stopped at {:#010X}: {:?}", pc, error));
+            }
+        }
+        save_file.update().map_err(|e| e.to_string())?;
+        frame += 1;
+    }
+    Ok(())
+}
 
 fn main() {
-    let gba_cpu = init_gba_cpu().expect("Failed to initialize GBA CPU");    
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => {
+            let gba_cpu = init_gba_cpu(&GbaConfig::default()).expect("Failed to initialize GBA CPU");
+        }
+        [flag, format, args @ ..] if flag == "--analyze" => match analyze(format, args) {
+            Ok(output) => print!("{}", output),
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        },
+        args => {
+            let (options, rom_path) = RunOptions::parse(args).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                process::exit(2);
+            });
+            if let Err(error) = run(rom_path, &options) {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::path::PathBuf;
+
+    use super::*;
+    use gba::{complement_check, save_path, FlashSize, BIOS_SIZE, GAMEPAK_FLASH_BANK_SIZE, GAMEPAK_SRAM_SIZE};
+
+    // Header the cartridge loader accepts, with the code at the entry point 0x080000C0
+    fn temporary_rom(name: &str, code: &[u32]) -> PathBuf {
+        let mut rom = vec![0; 0x200];
+        rom[0..4].copy_from_slice(&0xEA00002Eu32.to_le_bytes()); // B 0x080000C0
+        rom[0xB2] = 0x96;
+        rom[0xBD] = complement_check(&rom);
+        for (i, opcode) in code.iter().enumerate() {
+            rom[0xC0 + i * 4..0xC4 + i * 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+
+        let rom_path = env::temp_dir().join(format!("rusty_dolphine_{}_{}.gba", name, process::id()));
+        fs::write(&rom_path, rom).unwrap();
+        let _ = fs::remove_file(save_path(&rom_path));
+        rom_path
+    }
+
+    #[test]
+    fn test_run_saves() {
+        let rom_path = temporary_rom("run", &[
+            0xE59F0008, // LDR R0, =0x0E000000
+            0xE3A0105A, // MOV R1, #0x5A
+            0xE5C01000, // STRB R1, [R0]
+            0xEAFFFFFE, // B $
+            0x0E000000,
+        ]);
+        run(rom_path.to_str().unwrap(), &RunOptions { frames: Some(2), ..RunOptions::default() }).unwrap();
+        let save = fs::read(save_path(&rom_path)).unwrap();
+        assert_eq!(save.len(), GAMEPAK_SRAM_SIZE);
+        assert_eq!(save[0], 0x5A);
+
+        // The next run starts from the save
+        run(rom_path.to_str().unwrap(), &RunOptions { frames: Some(1), ..RunOptions::default() }).unwrap();
+        assert_eq!(fs::read(save_path(&rom_path)).unwrap(), save);
+        fs::remove_file(save_path(&rom_path)).unwrap();
+        fs::remove_file(rom_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_saves_when_the_cpu_stops() {
+        let rom_path = temporary_rom("run_stops", &[
+            0xE59F000C, // LDR R0, =0x0E000000
+            0xE3A0105A, // MOV R1, #0x5A
+            0xE5C01000, // STRB R1, [R0]
+            0xE28F2001, // ADD R2, PC, #1
+            0xE12FFF12, // BX R2, THUMB is not supported
+            0x0E000000,
+        ]);
+        let error = run(rom_path.to_str().unwrap(), &RunOptions::default()).unwrap_err();
+        assert!(error.starts_with("CPU stopped at 0x080000D4"), "{}", error);
+        assert_eq!(fs::read(save_path(&rom_path)).unwrap()[0], 0x5A);
+        fs::remove_file(save_path(&rom_path)).unwrap();
+        fs::remove_file(rom_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_skips_unimplemented() {
+        let rom_path = temporary_rom("run_skips", &[
+            0xE10F3000, // MRS R3, CPSR
+            0xE59F0008, // LDR R0, =0x0E000000
+            0xE3A0105A, // MOV R1, #0x5A
+            0xE5C01000, // STRB R1, [R0]
+            0xEAFFFFFE, // B $
+            0x0E000000,
+        ]);
+        let options = RunOptions { unimplemented_policy: UnimplementedPolicy::Skip, frames: Some(1), ..RunOptions::default() };
+        run(rom_path.to_str().unwrap(), &options).unwrap();
+        assert_eq!(fs::read(save_path(&rom_path)).unwrap()[0], 0x5A);
+        fs::remove_file(save_path(&rom_path)).unwrap();
+        fs::remove_file(rom_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_save_type() {
+        // B $, then the ID of the 128K flash library
+        let mut code = vec![0xEAFFFFFE];
+        code.extend(b"FLASH1M_V103".chunks(4).map(|id| u32::from_le_bytes(id.try_into().unwrap())));
+        let rom_path = temporary_rom("run_save_type", &code);
+        fs::write(save_path(&rom_path), vec![0xFF; 2 * GAMEPAK_FLASH_BANK_SIZE]).unwrap();
+        let rom_path = rom_path.to_str().unwrap();
+        run(rom_path, &RunOptions { frames: Some(1), ..RunOptions::default() }).unwrap();
+
+        let options = RunOptions { save_type: Some(SaveType::Sram), frames: Some(1), ..RunOptions::default() };
+        assert_eq!(run(rom_path, &options).unwrap_err(), "Save file is 131072 bytes, which does not fit the save memory");
+        fs::remove_file(save_path(rom_path)).unwrap();
+        fs::remove_file(rom_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_with_bios() {
+        // SWI 0x05 (VBlankIntrWait) enters the BIOS, which stores to SRAM instead of waiting
+        let rom_path = temporary_rom("run_bios", &[0xEF050000]);
+        let mut bios = vec![0; BIOS_SIZE];
+        for (i, opcode) in [
+            0xE59F0008, // LDR R0, =0x0E000000
+            0xE3A01042, // MOV R1, #0x42
+            0xE5C01000, // STRB R1, [R0]
+            0xEAFFFFFE, // B $
+            0x0E000000,
+        ].iter().enumerate() {
+            bios[0x08 + i * 4..0x0C + i * 4].copy_from_slice(&u32::to_le_bytes(*opcode));
+        }
+        let bios_path = rom_path.with_extension("bin");
+        fs::write(&bios_path, bios).unwrap();
+
+        let options = RunOptions { bios_path: Some(bios_path.to_str().unwrap().to_string()), frames: Some(1), ..RunOptions::default() };
+        run(rom_path.to_str().unwrap(), &options).unwrap();
+        assert_eq!(fs::read(save_path(&rom_path)).unwrap()[0], 0x42);
+
+        fs::write(&bios_path, [0; 16]).unwrap();
+        let error = run(rom_path.to_str().unwrap(), &options).unwrap_err();
+        assert!(error.ends_with("BIOS is 16 bytes instead of 16384"), "{}", error);
+        fs::remove_file(bios_path).unwrap();
+        fs::remove_file(save_path(&rom_path)).unwrap();
+        fs::remove_file(rom_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_options() {
+        let args: Vec<String> = ["--unimplemented", "skip", "--bios", "gba_bios.bin", "--save-type", "flash128", "game.gba"].iter().map(|arg| arg.to_string()).collect();
+        let (options, rom_path) = RunOptions::parse(&args).unwrap();
+        assert_eq!(options.unimplemented_policy, UnimplementedPolicy::Skip);
+        assert_eq!(options.bios_path.as_deref(), Some("gba_bios.bin"));
+        assert_eq!(options.save_type, Some(SaveType::Flash(FlashSize::Flash128K)));
+        assert_eq!(rom_path, "game.gba");
+
+        let (options, _) = RunOptions::parse(&args[6..]).unwrap();
+        assert_eq!((options.bios_path, options.save_type, options.unimplemented_policy), (None, None, UnimplementedPolicy::RaiseUndefined));
+        assert!(RunOptions::parse(&args[..2]).is_err());
+        assert!(RunOptions::parse(&args[..1]).unwrap_err().starts_with("Missing value for --unimplemented"));
+        assert!(RunOptions::parse(&["--unimplemented".to_string(), "log".to_string(), "game.gba".to_string()]).unwrap_err().starts_with("Unknown policy log"));
+        assert!(RunOptions::parse(&["--save-type".to_string(), "fram".to_string(), "game.gba".to_string()]).unwrap_err().starts_with("Unknown save type fram"));
+        assert!(RunOptions::parse(&["--frames".to_string(), "game.gba".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_analyze() {
+        let rom_path = env::temp_dir().join(format!("rusty_dolphine_analyze_{}.gba", process::id()));
+        // B $ in ARM, then twice in THUMB
+        fs::write(&rom_path, [0xEAFFFFFEu32, 0xE7FEE7FE].iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+        let rom_path = rom_path.to_str().unwrap().to_string();
+        let args = |options: &[&str]| -> Vec<String> {
+            options.iter().map(|option| option.to_string()).chain([rom_path.clone()]).collect()
+        };
+
+        assert!(analyze("json", &args(&[])).unwrap().starts_with("{\"blocks\":[{\"start\":134217728,"));
+        assert!(analyze("calls", &args(&[])).unwrap().contains("\"08000000\" [label=\"sub_08000000\"];"));
+        let dot = analyze("dot", &args(&["--arch", "v5te", "--entry", "0x08000005"])).unwrap();
+        assert!(dot.contains("label=\"sub_08000004 (THUMB)\";"));
+        assert!(analyze("svg", &args(&[])).unwrap_err().starts_with("Unknown format svg"));
+        assert!(analyze("dot", &args(&["--arch", "v6"])).unwrap_err().starts_with("Unknown architecture v6"));
+        assert!(analyze("dot", &args(&["--entry", "main"])).unwrap_err().starts_with("Invalid address main"));
+        assert!(analyze("dot", &args(&["--entry"])).is_err());
+        fs::remove_file(&rom_path).unwrap();
+        assert!(analyze("dot", &args(&[])).unwrap_err().starts_with("Failed to read"));
+    }
 }
@@ -0,0 +1,61 @@
+use std::{cell::Cell, rc::Rc};
+
+use super::AccessWidth;
+
+// Bus width and wait states of a memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessTiming {
+    pub bus_width: AccessWidth,
+    // Wait states added to the 1 cycle access, for non-sequential and sequential accesses
+    pub non_sequential: u32,
+    pub sequential: u32,
+}
+
+// Shared with the registers that configure the wait states
+pub type SharedTiming = Rc<Cell<AccessTiming>>;
+
+impl Default for AccessTiming {
+    fn default() -> Self {
+        AccessTiming::new(AccessWidth::Word, 0, 0)
+    }
+}
+
+impl AccessTiming {
+    pub fn new(bus_width: AccessWidth, non_sequential: u32, sequential: u32) -> AccessTiming {
+        AccessTiming { bus_width, non_sequential, sequential }
+    }
+
+    pub fn shared(self) -> SharedTiming {
+        Rc::new(Cell::new(self))
+    }
+
+    // An access wider than the bus is split into transfers, the ones after the first are sequential
+    pub fn cycles(&self, width: AccessWidth, sequential: bool) -> u32 {
+        let transfers = (width.size() / self.bus_width.size()).max(1) as u32;
+        let first = 1 + if sequential { self.sequential } else { self.non_sequential };
+        first + (transfers - 1) * (1 + self.sequential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_access_timing_cycles() {
+        let ram = AccessTiming::default();
+        assert_eq!(ram.cycles(AccessWidth::Word, false), 1);
+
+        // Game Pak ROM at 3/1 wait states on a 16-bit bus
+        let rom = AccessTiming::new(AccessWidth::Halfword, 3, 1);
+        assert_eq!(rom.cycles(AccessWidth::Byte, false), 4);
+        assert_eq!(rom.cycles(AccessWidth::Halfword, true), 2);
+        assert_eq!(rom.cycles(AccessWidth::Word, false), 6);
+        assert_eq!(rom.cycles(AccessWidth::Word, true), 4);
+
+        // 8-bit SRAM
+        let sram = AccessTiming::new(AccessWidth::Byte, 4, 4);
+        assert_eq!(sram.cycles(AccessWidth::Word, false), 20);
+    }
+}
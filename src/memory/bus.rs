@@ -0,0 +1,417 @@
+use core::fmt;
+
+use super::{sector_cycles, AccessFlags, HookKind, MemoryBus, MemoryError, Region};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessWidth {
+    Byte,
+    Halfword,
+    Word,
+}
+
+impl AccessWidth {
+    pub fn size(&self) -> usize {
+        match self {
+            AccessWidth::Byte => 1,
+            AccessWidth::Halfword => 2,
+            AccessWidth::Word => 4,
+        }
+    }
+}
+
+// Memory as seen by the CPU core. Accesses go through `&self` so implementations
+// with side effects keep their state behind a `RefCell`/`Cell`, like `MemoryBus` does.
+pub trait Bus: fmt::Debug {
+    fn read_u8(&self, address: u32) -> Result<u8, MemoryError>;
+    fn read_u16(&self, address: u32) -> Result<u16, MemoryError>;
+    fn read_u32(&self, address: u32) -> Result<u32, MemoryError>;
+
+    fn write_u8(&self, address: u32, value: u8) -> Result<(), MemoryError>;
+    fn write_u16(&self, address: u32, value: u16) -> Result<(), MemoryError>;
+    fn write_u32(&self, address: u32, value: u32) -> Result<(), MemoryError>;
+
+    // Opcode fetches, separate from data reads for buses that treat them differently
+    fn fetch_u16(&self, address: u32) -> Result<u16, MemoryError> {
+        self.read_u16(address)
+    }
+
+    fn fetch_u32(&self, address: u32) -> Result<u32, MemoryError> {
+        self.read_u32(address)
+    }
+
+    // Reads without side effects or timing (debuggers, disassemblers)
+    fn peek(&self, address: u32, width: AccessWidth) -> Result<u32, MemoryError>;
+
+    // Loads and stores as the ARM7TDMI performs them, for any alignment.
+    // A misaligned LDR reads the aligned word rotated so the addressed byte is in bits 7-0
+    fn load_u32(&self, address: u32) -> Result<u32, MemoryError> {
+        Ok(self.read_u32(address & !3)?.rotate_right((address & 3) * 8))
+    }
+
+    // A misaligned LDRH reads the aligned halfword rotated by 8 over the full 32 bits
+    fn load_u16(&self, address: u32) -> Result<u32, MemoryError> {
+        Ok((self.read_u16(address & !1)? as u32).rotate_right((address & 1) * 8))
+    }
+
+    // A misaligned LDRSH sign extends the addressed byte
+    fn load_i16(&self, address: u32) -> Result<u32, MemoryError> {
+        if (address & 1) != 0 {
+            return Ok(self.read_u8(address)? as i8 as i32 as u32);
+        }
+        Ok(self.read_u16(address)? as i16 as i32 as u32)
+    }
+
+    // Stores ignore the low address bits
+    fn store_u16(&self, address: u32, value: u16) -> Result<(), MemoryError> {
+        self.write_u16(address & !1, value)
+    }
+
+    fn store_u32(&self, address: u32, value: u32) -> Result<(), MemoryError> {
+        self.write_u32(address & !3, value)
+    }
+
+    // Number of cycles an access takes
+    fn cycles(&self, _address: u32, _width: AccessWidth, _sequential: bool) -> u32 {
+        1
+    }
+
+    // Internal cycles of the CPU, the bus is free meanwhile
+    fn idle(&self, _cycles: u32) {}
+
+    // Cycles spent on accesses so far, buses without timing count none
+    fn cycle_count(&self) -> u64 {
+        0
+    }
+}
+
+// The address lines below the access width are not connected, so halfword and word
+// accesses always hit the aligned location
+impl Bus for MemoryBus {
+    fn read_u8(&self, address: u32) -> Result<u8, MemoryError> {
+        Ok(self.read(address, AccessWidth::Byte, DeviceRead::Read)? as u8)
+    }
+
+    fn read_u16(&self, address: u32) -> Result<u16, MemoryError> {
+        Ok(self.read(address & !1, AccessWidth::Halfword, DeviceRead::Read)? as u16)
+    }
+
+    fn read_u32(&self, address: u32) -> Result<u32, MemoryError> {
+        self.read(address & !3, AccessWidth::Word, DeviceRead::Read)
+    }
+
+    fn fetch_u16(&self, address: u32) -> Result<u16, MemoryError> {
+        Ok(self.read(address & !1, AccessWidth::Halfword, DeviceRead::Fetch)? as u16)
+    }
+
+    fn fetch_u32(&self, address: u32) -> Result<u32, MemoryError> {
+        self.read(address & !3, AccessWidth::Word, DeviceRead::Fetch)
+    }
+
+    fn write_u8(&self, address: u32, value: u8) -> Result<(), MemoryError> {
+        self.write(address, AccessWidth::Byte, value as u32)
+    }
+
+    fn write_u16(&self, address: u32, value: u16) -> Result<(), MemoryError> {
+        self.write(address & !1, AccessWidth::Halfword, value as u32)
+    }
+
+    fn write_u32(&self, address: u32, value: u32) -> Result<(), MemoryError> {
+        self.write(address & !3, AccessWidth::Word, value)
+    }
+
+    // Peeking takes no time and runs no hooks
+    fn peek(&self, address: u32, width: AccessWidth) -> Result<u32, MemoryError> {
+        let address = address & !(width.size() as u32 - 1);
+        self.read_region(address, self.region(address), width, DeviceRead::Peek)
+    }
+
+    fn cycles(&self, address: u32, width: AccessWidth, sequential: bool) -> u32 {
+        sector_cycles(self.sector(address), width, sequential)
+    }
+
+    fn idle(&self, cycles: u32) {
+        self.record_idle(cycles);
+    }
+
+    fn cycle_count(&self) -> u64 {
+        MemoryBus::cycle_count(self)
+    }
+}
+
+// How a read reaches a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeviceRead {
+    Read,
+    Fetch, // Opcode fetches, only devices that latch fetched values tell them apart
+    Peek,
+}
+
+// Part of a bus word an aligned access reads
+fn lane(value: u32, address: u32, width: AccessWidth) -> u32 {
+    match width {
+        AccessWidth::Byte => (value >> ((address & 3) * 8)) & 0xFF,
+        AccessWidth::Halfword => (value >> ((address & 2) * 8)) & 0xFFFF,
+        AccessWidth::Word => value,
+    }
+}
+
+impl MemoryBus {
+    // Timed and hooked access for an aligned address, the region is resolved once for all of it
+    fn read(&self, address: u32, width: AccessWidth, kind: DeviceRead) -> Result<u32, MemoryError> {
+        let region = self.region(address);
+        let fetch = kind == DeviceRead::Fetch;
+        if fetch {
+            self.record_fetch(address);
+        }
+        self.record_access(address, region.map(|region| region.sector), width, fetch);
+        let value = self.read_region(address, region, width, kind)?;
+        if !fetch {
+            return Ok(self.run_hooks(HookKind::Read, address, width, value));
+        }
+        self.latch_opcode(address, width, value);
+        Ok(self.run_hooks(HookKind::Execute, address, width, value))
+    }
+
+    fn write(&self, address: u32, width: AccessWidth, value: u32) -> Result<(), MemoryError> {
+        let region = self.region(address);
+        self.record_access(address, region.map(|region| region.sector), width, false);
+        let value = self.run_hooks(HookKind::Write, address, width, value);
+
+        // Unmapped writes on an open bus and writes the sector's flags drop go nowhere
+        let Some(region) = region else {
+            return if self.has_open_bus() { Ok(()) } else { Err(MemoryError::InvalidAddress(address)) };
+        };
+        let flags = region.access_flags();
+        if flags.contains(AccessFlags::READ_ONLY) {
+            return Err(MemoryError::ReadOnly(address));
+        }
+        if flags.contains(AccessFlags::IGNORE_WRITES) || (width == AccessWidth::Byte && flags.contains(AccessFlags::IGNORE_BYTE_WRITES)) {
+            return Ok(());
+        }
+        if width == AccessWidth::Byte && flags.contains(AccessFlags::DUPLICATE_BYTE_WRITES) {
+            let region = Region { offset: region.offset & !1, ..region };
+            return self.write_region(address & !1, region, AccessWidth::Halfword, (value & 0xFF) * 0x0101);
+        }
+        self.write_region(address, region, width, value)
+    }
+
+    // Read without timing or hooks
+    fn read_region(&self, address: u32, region: Option<Region>, width: AccessWidth, kind: DeviceRead) -> Result<u32, MemoryError> {
+        let Some(region) = region else {
+            if !self.has_open_bus() {
+                return Err(MemoryError::InvalidAddress(address));
+            }
+            return Ok(lane(self.open_bus_value(), address, width));
+        };
+        let Some(device) = &region.sector.device else {
+            return region.read_ram(address, width);
+        };
+
+        // Device accesses must fit inside the sector, like RAM accesses
+        region.check_size(address, width.size())?;
+        let offset = region.offset;
+        if kind == DeviceRead::Peek {
+            let device = device.borrow();
+            return match width {
+                AccessWidth::Byte => device.peek_u8(offset).map(|value| value as u32),
+                AccessWidth::Halfword => device.peek_u16(offset).map(|value| value as u32),
+                AccessWidth::Word => device.peek_u32(offset),
+            };
+        }
+        let mut device = device.borrow_mut();
+        match (width, kind) {
+            (AccessWidth::Byte, _) => device.read_u8(offset).map(|value| value as u32),
+            (AccessWidth::Halfword, DeviceRead::Fetch) => device.fetch_u16(offset).map(|value| value as u32),
+            (AccessWidth::Halfword, _) => device.read_u16(offset).map(|value| value as u32),
+            (AccessWidth::Word, DeviceRead::Fetch) => device.fetch_u32(offset),
+            (AccessWidth::Word, _) => device.read_u32(offset),
+        }
+    }
+
+    // Write without timing, hooks or access checks
+    fn write_region(&self, address: u32, region: Region, width: AccessWidth, value: u32) -> Result<(), MemoryError> {
+        let Some(device) = &region.sector.device else {
+            return region.write_ram(address, width, value);
+        };
+
+        region.check_size(address, width.size())?;
+        let mut device = device.borrow_mut();
+        match width {
+            AccessWidth::Byte => device.write_u8(region.offset, value as u8),
+            AccessWidth::Halfword => device.write_u16(region.offset, value as u16),
+            AccessWidth::Word => device.write_u32(region.offset, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::memory::{AccessTiming, MemoryAccess};
+
+    #[test]
+    fn test_memory_bus_little_endian() {
+        let memory_bus = MemoryBus::builder().sector_with_size("Test".to_string(), 0, 16).unwrap().build();
+        memory_bus.write_u32(0, 0x12345678).unwrap();
+
+        assert_eq!(memory_bus.read_u8(0).unwrap(), 0x78);
+        assert_eq!(memory_bus.read_u16(2).unwrap(), 0x1234);
+        assert_eq!(memory_bus.fetch_u32(0).unwrap(), 0x12345678);
+        assert_eq!(memory_bus.peek(3, AccessWidth::Halfword).unwrap(), 0x1234);
+        assert_eq!(memory_bus.read_u32(16), Err(MemoryError::InvalidAddress(16)));
+    }
+
+    #[test]
+    fn test_memory_bus_misaligned() {
+        let memory_bus = MemoryBus::builder().sector_with_size("Test".to_string(), 0, 16).unwrap().build();
+        memory_bus.write_u32(0, 0x12345678).unwrap();
+        memory_bus.write_u16(4, 0x80FF).unwrap();
+
+        // The bus itself ignores the low bits
+        assert_eq!(memory_bus.read_u32(2).unwrap(), 0x12345678);
+        assert_eq!(memory_bus.read_u16(5).unwrap(), 0x80FF);
+
+        assert_eq!(memory_bus.load_u32(0).unwrap(), 0x12345678);
+        assert_eq!(memory_bus.load_u32(1).unwrap(), 0x78123456);
+        assert_eq!(memory_bus.load_u32(3).unwrap(), 0x34567812);
+        assert_eq!(memory_bus.load_u16(2).unwrap(), 0x1234);
+        assert_eq!(memory_bus.load_u16(3).unwrap(), 0x34000012);
+        assert_eq!(memory_bus.load_i16(4).unwrap(), 0xFFFF80FF);
+        assert_eq!(memory_bus.load_i16(5).unwrap(), 0xFFFFFF80);
+
+        memory_bus.store_u32(9, 0xAABBCCDD).unwrap();
+        memory_bus.store_u16(15, 0x1122).unwrap();
+        assert_eq!(memory_bus.read_u32(8).unwrap(), 0xAABBCCDD);
+        assert_eq!(memory_bus.read_u16(14).unwrap(), 0x1122);
+    }
+
+    #[test]
+    fn test_memory_bus_cycles() {
+        let rom_timing = AccessTiming::new(AccessWidth::Halfword, 3, 1).shared();
+        let memory_bus = MemoryBus::builder()
+            .sector_with_size("RAM".to_string(), 0, 16).unwrap()
+            .sector_with_size("ROM".to_string(), 0x100, 16).unwrap()
+            .timing(0x100, rom_timing.clone()).unwrap()
+            .build();
+
+        // Without a timing every access takes 1 cycle
+        memory_bus.read_u32(0).unwrap();
+        memory_bus.write_u8(1, 0).unwrap();
+        assert_eq!(memory_bus.cycle_count(), 2);
+
+        // Non-sequential word, then sequential ones
+        memory_bus.read_u32(0x100).unwrap();
+        assert_eq!(memory_bus.cycle_count(), 2 + 6);
+        memory_bus.fetch_u32(0x104).unwrap();
+        memory_bus.read_u16(0x108).unwrap();
+        assert_eq!(memory_bus.cycle_count(), 8 + 4 + 2);
+        assert_eq!(memory_bus.peek(0x100, AccessWidth::Word).unwrap(), 0);
+        assert_eq!(memory_bus.cycle_count(), 14);
+
+        // Changed wait states apply to the built bus
+        rom_timing.set(AccessTiming::new(AccessWidth::Halfword, 1, 0));
+        assert_eq!(memory_bus.cycles(0x100, AccessWidth::Word, false), 3);
+    }
+
+    #[test]
+    fn test_memory_bus_open_bus() {
+        let memory_bus = MemoryBus::builder()
+            .sector_with_size("RAM".to_string(), 0, 16).unwrap()
+            .sector_with_size("ROM".to_string(), 0x100, 16).unwrap()
+            .timing(0x100, AccessTiming::new(AccessWidth::Halfword, 0, 0).shared()).unwrap()
+            .open_bus()
+            .build();
+        memory_bus.write_u32(0, 0xE1A00000).unwrap();
+        memory_bus.write_u32(4, 0x46C04770).unwrap();
+        memory_bus.write_u32(0x100, 0x2001BD00).unwrap();
+
+        // ARM: the last opcode
+        memory_bus.fetch_u32(0).unwrap();
+        assert_eq!(memory_bus.read_u32(0x1000).unwrap(), 0xE1A00000);
+        assert_eq!(memory_bus.read_u16(0x1002).unwrap(), 0xE1A0);
+        assert_eq!(memory_bus.read_u8(0x1001).unwrap(), 0x00);
+
+        // THUMB on a 32-bit bus: the last two opcodes by address
+        memory_bus.fetch_u16(4).unwrap();
+        memory_bus.fetch_u16(6).unwrap();
+        assert_eq!(memory_bus.read_u32(0x1000).unwrap(), 0x46C04770);
+
+        // THUMB on a 16-bit bus: the opcode twice
+        memory_bus.fetch_u16(0x102).unwrap();
+        assert_eq!(memory_bus.read_u32(0x1000).unwrap(), 0x20012001);
+
+        // Writes are dropped
+        memory_bus.write_u32(0x1000, 0).unwrap();
+        assert_eq!(memory_bus.read_u32(0x1000).unwrap(), 0x20012001);
+    }
+
+    #[test]
+    fn test_memory_bus_access_flags() {
+        let memory_bus = MemoryBus::builder()
+            .sector_with_size("ROM".to_string(), 0, 16).unwrap()
+            .sector_with_size("Registers".to_string(), 0x100, 16).unwrap()
+            .sector_with_size("VRAM".to_string(), 0x200, 16).unwrap()
+            .mirror(0x200, 0x2FF, 16).unwrap()
+            .access(0, 15, AccessFlags::IGNORE_WRITES).unwrap()
+            .access(0x100, 0x103, AccessFlags::READ_ONLY).unwrap()
+            .access(0x200, 0x207, AccessFlags::DUPLICATE_BYTE_WRITES).unwrap()
+            .access(0x208, 0x20F, AccessFlags::IGNORE_BYTE_WRITES).unwrap()
+            .build();
+
+        memory_bus.write_u32(0, 0x12345678).unwrap();
+        memory_bus.write_u8(1, 0x12).unwrap();
+        assert_eq!(memory_bus.read_u32(0).unwrap(), 0);
+
+        assert_eq!(memory_bus.write_u16(0x102, 1), Err(MemoryError::ReadOnly(0x102)));
+        memory_bus.write_u16(0x104, 1).unwrap();
+
+        // Byte writes through a mirror
+        memory_bus.write_u8(0x213, 0x1F).unwrap();
+        assert_eq!(memory_bus.read_u16(0x202).unwrap(), 0x1F1F);
+        memory_bus.write_u8(0x209, 0x1F).unwrap();
+        memory_bus.write_u16(0x20A, 0x7FFF).unwrap();
+        assert_eq!(memory_bus.read_u32(0x208).unwrap(), 0x7FFF0000);
+
+        assert_eq!(MemoryBus::builder().sector_with_size("RAM".to_string(), 0, 16).unwrap()
+            .access(8, 16, AccessFlags::READ_ONLY).err(), Some(MemoryError::InvalidAddresses(8, 16)));
+    }
+
+    #[test]
+    fn test_memory_bus_hooks() {
+        let memory_bus = MemoryBus::builder()
+            .sector_with_size("RAM".to_string(), 0, 0x100).unwrap()
+            .build();
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+
+        let log = accesses.clone();
+        memory_bus.add_hook(HookKind::Write, 0x80, 0x8F, move |access: &MemoryAccess| {
+            log.borrow_mut().push(*access);
+            None
+        });
+        // Infinite lives: every write to the counter stores 9
+        memory_bus.add_hook(HookKind::Write, 0x90, 0x90, |_: &MemoryAccess| Some(9));
+        let patch = memory_bus.add_hook(HookKind::Execute, 0x10, 0x10, |_: &MemoryAccess| Some(0xE1A00000));
+        memory_bus.add_hook(HookKind::Read, 0xA0, 0xA3, |access: &MemoryAccess| Some(access.value | 0x80));
+
+        memory_bus.write_u32(0x10, 0xEAFFFFFE).unwrap();
+        assert_eq!(memory_bus.fetch_u32(0x10).unwrap(), 0xE1A00000);
+        memory_bus.write_u16(0x84, 0x1234).unwrap();
+        assert_eq!(*accesses.borrow(), vec![MemoryAccess {
+            kind: HookKind::Write, address: 0x84, width: AccessWidth::Halfword, value: 0x1234, pc: 0x10,
+        }]);
+
+        memory_bus.write_u8(0x90, 0).unwrap();
+        assert_eq!(memory_bus.read_u8(0x90).unwrap(), 9);
+        assert_eq!(memory_bus.read_u8(0xA1).unwrap(), 0x80);
+
+        // Peeks see memory as it is, without being hooked themselves
+        memory_bus.add_hook(HookKind::Read, 0x84, 0x85, |_: &MemoryAccess| panic!("peek ran a hook"));
+        assert_eq!(memory_bus.peek(0xA1, AccessWidth::Byte).unwrap(), 0);
+        assert_eq!(memory_bus.peek(0x85, AccessWidth::Halfword).unwrap(), 0x1234);
+        assert!(memory_bus.remove_hook(patch));
+        assert_eq!(memory_bus.fetch_u32(0x10).unwrap(), 0xEAFFFFFE);
+    }
+}
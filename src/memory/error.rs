@@ -1,9 +1,10 @@
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum MemoryError {
     InvalidAddress(u32),
     InvalidAddresses(u32, u32),
     InvalidSize(usize),
     OverlappingMemorySectors(u32),
     OutOfBounds(u32),
+    ReadOnly(u32),
 }
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use super::{AccessWidth, Bus, MemoryError};
+
+const PAGE_SIZE: usize = 4 * 1024;
+
+// Flat RAM covering the full 4 GiB address space.
+// Pages are only allocated once written, unwritten memory reads as zero.
+#[derive(Debug, Default, Clone)]
+pub struct FlatBus {
+    // page number -> page
+    pages: RefCell<HashMap<u32, Vec<u8>>>,
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus::default()
+    }
+
+    pub fn load(&self, address: u32, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), *byte);
+        }
+    }
+
+    fn read_byte(&self, address: u32) -> u8 {
+        let page = address / PAGE_SIZE as u32;
+        let offset = address as usize % PAGE_SIZE;
+        self.pages.borrow().get(&page).map_or(0, |data| data[offset])
+    }
+
+    fn write_byte(&self, address: u32, value: u8) {
+        let page = address / PAGE_SIZE as u32;
+        let offset = address as usize % PAGE_SIZE;
+        self.pages.borrow_mut().entry(page).or_insert_with(|| vec![0; PAGE_SIZE])[offset] = value;
+    }
+
+    fn read_bytes<const N: usize>(&self, address: u32) -> [u8; N] {
+        let mut buf = [0; N];
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.read_byte(address.wrapping_add(i as u32));
+        }
+        buf
+    }
+}
+
+impl Bus for FlatBus {
+    fn read_u8(&self, address: u32) -> Result<u8, MemoryError> {
+        Ok(self.read_byte(address))
+    }
+
+    fn read_u16(&self, address: u32) -> Result<u16, MemoryError> {
+        Ok(u16::from_le_bytes(self.read_bytes(address)))
+    }
+
+    fn read_u32(&self, address: u32) -> Result<u32, MemoryError> {
+        Ok(u32::from_le_bytes(self.read_bytes(address)))
+    }
+
+    // Reads have no side effects
+    fn peek(&self, address: u32, width: AccessWidth) -> Result<u32, MemoryError> {
+        Ok(match width {
+            AccessWidth::Byte => self.read_byte(address) as u32,
+            AccessWidth::Halfword => u16::from_le_bytes(self.read_bytes(address)) as u32,
+            AccessWidth::Word => u32::from_le_bytes(self.read_bytes(address)),
+        })
+    }
+
+    fn write_u8(&self, address: u32, value: u8) -> Result<(), MemoryError> {
+        self.write_byte(address, value);
+        Ok(())
+    }
+
+    fn write_u16(&self, address: u32, value: u16) -> Result<(), MemoryError> {
+        self.load(address, &value.to_le_bytes());
+        Ok(())
+    }
+
+    fn write_u32(&self, address: u32, value: u32) -> Result<(), MemoryError> {
+        self.load(address, &value.to_le_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_flat_bus_full_address_space() {
+        let bus = FlatBus::new();
+        assert_eq!(bus.read_u32(0xFFFF_FFF0).unwrap(), 0);
+
+        bus.write_u32(0xFFFF_FFFC, 0xDEADBEEF).unwrap();
+        bus.write_u16(0x0000_0000, 0x1234).unwrap();
+        assert_eq!(bus.read_u32(0xFFFF_FFFC).unwrap(), 0xDEADBEEF);
+        assert_eq!(bus.read_u16(0).unwrap(), 0x1234);
+        assert_eq!(bus.pages.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_flat_bus_across_pages() {
+        let bus = FlatBus::new();
+        let address = PAGE_SIZE as u32 - 2;
+        bus.write_u32(address, 0x11223344).unwrap();
+        assert_eq!(bus.read_u32(address).unwrap(), 0x11223344);
+        assert_eq!(bus.read_u8(PAGE_SIZE as u32).unwrap(), 0x22);
+    }
+}
@@ -1,37 +1,38 @@
 use super::{error::MemoryError, MemoryBus};
 
 
+// Copies memory to the buffer. Devices see byte accesses.
 pub fn read_memory(memory_bus: &MemoryBus, address: u32, buf: &mut [u8]) -> Result<(), MemoryError> {
-    match memory_bus.sector(address) {
-        Some(sector) => {
-            let data = sector.data.borrow();
-            let offset = address - sector.start_address;
-            let buf_len = buf.len();
-            if offset as usize + buf_len > data.len() {
-                return Err(MemoryError::OutOfBounds(address));
+    let region = memory_bus.region(address).ok_or(MemoryError::InvalidAddress(address))?;
+    region.check_size(address, buf.len())?;
+    match &region.sector.device {
+        Some(device) => {
+            let mut device = device.borrow_mut();
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = device.read_u8(region.offset + i as u32)?;
             }
-            buf.copy_from_slice(&data[offset as usize..offset as usize + buf_len]);
         }
         None => {
-            return Err(MemoryError::InvalidAddress(address));
+            let offset = region.offset as usize;
+            buf.copy_from_slice(&region.sector.data.borrow()[offset..offset + buf.len()]);
         }
     }
     Ok(())
 }
 
 pub fn write_memory(memory_bus: &MemoryBus, address: u32, buf: &[u8]) -> Result<(), MemoryError> {
-    match memory_bus.sector(address) {
-        Some(sector) => {
-            let mut data = sector.data.borrow_mut();
-            let offset = address - sector.start_address;
-            let buf_len = buf.len();
-            if offset as usize + buf_len > data.len() {
-                return Err(MemoryError::OutOfBounds(address));
+    let region = memory_bus.region(address).ok_or(MemoryError::InvalidAddress(address))?;
+    region.check_size(address, buf.len())?;
+    match &region.sector.device {
+        Some(device) => {
+            let mut device = device.borrow_mut();
+            for (i, byte) in buf.iter().enumerate() {
+                device.write_u8(region.offset + i as u32, *byte)?;
             }
-            data[offset as usize..offset as usize + buf_len].copy_from_slice(buf);
         }
         None => {
-            return Err(MemoryError::InvalidAddress(address));
+            let offset = region.offset as usize;
+            region.sector.data.borrow_mut()[offset..offset + buf.len()].copy_from_slice(buf);
         }
     }
     Ok(())
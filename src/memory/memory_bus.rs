@@ -1,36 +1,317 @@
 use core::fmt;
-use std::collections::HashMap;
+use std::{cell::{Cell, RefCell}, rc::Rc};
 
-use super::{MemoryError, MemorySector};
+use super::{AccessFlags, AccessRule, AccessWidth, Bus, HookId, HookKind, MemoryAccess, MemoryDevice, MemoryError, MemoryHooks, MemorySector, Mirror, PrefetchBuffer, SharedTiming};
 
+// Address bits 24-31 select a page, on the GBA every region starts on its own page
+pub const PAGE_SHIFT: u32 = 24;
+pub const PAGE_COUNT: usize = 1 << (32 - PAGE_SHIFT);
 
-
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct MemoryBus {
-    // Memory map: start_address -> MemorySector
-    memory_map: HashMap<u32, MemorySector>
+    // Sorted by start address
+    sectors: Vec<MemorySector>,
+    page_table: Vec<Page>,
+    // Address of the last opcode fetch, shared with devices that behave differently depending on the PC
+    fetch_address: Rc<Cell<u32>>,
+    // Cycles spent on accesses so far
+    cycle_count: Cell<u64>,
+    // Address that continues the last access, accesses to it are sequential
+    next_address: Cell<Option<u32>>,
+    prefetch_buffer: Option<RefCell<PrefetchBuffer>>,
+    // Reads of unmapped addresses return what is left on the bus instead of failing
+    open_bus: bool,
+    opcode_latch: Cell<OpcodeLatch>,
+    // Shared by clones of the bus
+    hooks: Rc<RefCell<MemoryHooks>>,
+}
+
+// Last opcode fetched, it stays on the data bus until the next access
+#[derive(Debug, Clone, Copy, Default)]
+struct OpcodeLatch {
+    address: u32,
+    thumb: bool,
+    value: u32,
+    // THUMB opcode fetched before it
+    previous: u16,
+}
+
+// What the addresses of a page resolve to
+#[derive(Debug, Clone, Default)]
+enum Page {
+    #[default]
+    Unmapped,
+    // The only sector in the page. Offsets into sectors without mirrors or repeating every
+    // power of two bytes are masked instead of going through the mirror.
+    Sector { index: usize, offset_mask: Option<u32> },
+    // Small sectors sharing the page
+    Shared(Vec<usize>),
+}
+
+// Sector an access lands in and the offset into its storage, resolved once per access
+#[derive(Debug, Clone, Copy)]
+pub struct Region<'a> {
+    pub sector: &'a MemorySector,
+    pub offset: u32,
+}
+
+impl Region<'_> {
+    // Fails for accesses running past the end of the sector
+    pub fn check_size(&self, address: u32, size: usize) -> Result<(), MemoryError> {
+        if self.offset as usize + size > self.sector.size() {
+            return Err(MemoryError::OutOfBounds(address));
+        }
+        Ok(())
+    }
+
+    pub fn access_flags(&self) -> AccessFlags {
+        self.sector.access_flags_at(self.offset)
+    }
+
+    // Plain RAM is read straight from the backing storage
+    pub fn read_ram(&self, address: u32, width: AccessWidth) -> Result<u32, MemoryError> {
+        let data = self.sector.data.borrow();
+        let offset = self.offset as usize;
+        let bytes = data.get(offset..offset + width.size()).ok_or(MemoryError::OutOfBounds(address))?;
+        let mut value = [0; 4];
+        value[..bytes.len()].copy_from_slice(bytes);
+        Ok(u32::from_le_bytes(value))
+    }
+
+    pub fn write_ram(&self, address: u32, width: AccessWidth, value: u32) -> Result<(), MemoryError> {
+        let mut data = self.sector.data.borrow_mut();
+        let offset = self.offset as usize;
+        let bytes = data.get_mut(offset..offset + width.size()).ok_or(MemoryError::OutOfBounds(address))?;
+        bytes.copy_from_slice(&value.to_le_bytes()[..width.size()]);
+        Ok(())
+    }
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        MemoryBus {
+            sectors: Vec::new(),
+            page_table: vec![Page::Unmapped; PAGE_COUNT],
+            fetch_address: Rc::new(Cell::new(0)),
+            cycle_count: Cell::new(0),
+            next_address: Cell::new(None),
+            prefetch_buffer: None,
+            open_bus: false,
+            opcode_latch: Cell::new(OpcodeLatch::default()),
+            hooks: Rc::new(RefCell::new(MemoryHooks::new())),
+        }
+    }
 }
 
 impl MemoryBus {
+    pub fn region(&self, address: u32) -> Option<Region<'_>> {
+        match &self.page_table[(address >> PAGE_SHIFT) as usize] {
+            Page::Unmapped => None,
+            Page::Sector { index, offset_mask } => {
+                let sector = &self.sectors[*index];
+                if !sector.contains(address) {
+                    return None;
+                }
+                let offset = match offset_mask {
+                    Some(mask) => (address - sector.start_address) & mask,
+                    None => sector.offset(address),
+                };
+                Some(Region { sector, offset })
+            }
+            Page::Shared(indices) => indices.iter()
+                .map(|index| &self.sectors[*index])
+                .find(|sector| sector.contains(address))
+                .map(|sector| Region { sector, offset: sector.offset(address) }),
+        }
+    }
+
     pub fn sector(&self, address: u32) -> Option<&MemorySector> {
-        self.memory_map.values().find(|sector| {
-            sector.start_address <= address && sector.end_address >= address
-        })
+        self.region(address).map(|region| region.sector)
+    }
+
+    // Access flags of the address, empty where nothing is mapped
+    pub fn access_flags(&self, address: u32) -> AccessFlags {
+        self.region(address).map_or(AccessFlags::empty(), |region| region.access_flags())
+    }
+
+    pub fn fetch_address(&self) -> Rc<Cell<u32>> {
+        self.fetch_address.clone()
+    }
+
+    pub fn record_fetch(&self, address: u32) {
+        self.fetch_address.set(address);
     }
 
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count.get()
+    }
+
+    // Adds the cost of an access to the cycle count and returns it
+    pub fn record_access(&self, address: u32, sector: Option<&MemorySector>, width: AccessWidth, fetch: bool) -> u32 {
+        let sequential = self.next_address.get() == Some(address);
+        let mut cycles = sector_cycles(sector, width, sequential);
+        self.next_address.set(Some(address.wrapping_add(width.size() as u32)));
+
+        if let Some(prefetch_buffer) = &self.prefetch_buffer {
+            let mut prefetch_buffer = prefetch_buffer.borrow_mut();
+            if !prefetch_buffer.contains(address) {
+                self.advance_prefetch(&mut prefetch_buffer, cycles);
+            } else if fetch {
+                cycles = prefetch_buffer.fetch(address, width, cycles, sector_cycles(sector, AccessWidth::Halfword, true));
+            } else {
+                prefetch_buffer.data_access();
+            }
+        }
+
+        self.cycle_count.set(self.cycle_count.get() + cycles as u64);
+        cycles
+    }
+
+    // Internal CPU cycles without a bus access
+    pub fn record_idle(&self, cycles: u32) {
+        if let Some(prefetch_buffer) = &self.prefetch_buffer {
+            self.advance_prefetch(&mut prefetch_buffer.borrow_mut(), cycles);
+        }
+        self.cycle_count.set(self.cycle_count.get() + cycles as u64);
+        self.next_address.set(None);
+    }
+
+    fn advance_prefetch(&self, prefetch_buffer: &mut PrefetchBuffer, cycles: u32) {
+        if let Some(address) = prefetch_buffer.fetch_address() {
+            prefetch_buffer.advance(cycles, self.cycles(address, AccessWidth::Halfword, true));
+        }
+    }
+
+    pub fn prefetch_buffer(&self) -> Option<PrefetchBuffer> {
+        self.prefetch_buffer.as_ref().map(|prefetch_buffer| prefetch_buffer.borrow().clone())
+    }
+
+    pub fn latch_opcode(&self, address: u32, width: AccessWidth, value: u32) {
+        let previous = self.opcode_latch.get().value as u16;
+        self.opcode_latch.set(OpcodeLatch { address, thumb: width != AccessWidth::Word, value, previous });
+    }
+
+    // Value a read of an unmapped address returns. After an ARM fetch that is the opcode, after a
+    // THUMB fetch it depends on the bus width of the region the code runs from:
+    // 16-bit regions repeat the opcode in both halves, 32-bit regions hold the last two opcodes
+    // in the halves their addresses select, like on-chip WRAM does (BIOS and OAM deviate for
+    // word aligned PCs, the BIOS protection covers the BIOS itself).
+    pub fn open_bus_value(&self) -> u32 {
+        let latch = self.opcode_latch.get();
+        if !latch.thumb {
+            return latch.value;
+        }
+
+        let opcode = latch.value & 0xFFFF;
+        let word_bus = self.sector(latch.address)
+            .and_then(|sector| sector.timing.as_ref())
+            .is_none_or(|timing| timing.get().bus_width == AccessWidth::Word);
+        if !word_bus {
+            return (opcode << 16) | opcode;
+        }
+
+        let previous = latch.previous as u32;
+        if (latch.address & 2) == 0 { (previous << 16) | opcode } else { (opcode << 16) | previous }
+    }
+
+    // Unmapped reads return the open bus value instead of failing
+    pub fn has_open_bus(&self) -> bool {
+        self.open_bus
+    }
+
+    // Calls the callback for accesses of the kind between the addresses, the PC it gets is the address
+    // of the last opcode fetched
+    pub fn add_hook<F: FnMut(&MemoryAccess) -> Option<u32> + 'static>(&self, kind: HookKind, start_address: u32, end_address: u32, callback: F) -> HookId {
+        self.hooks.borrow_mut().add(kind, start_address, end_address, Box::new(callback))
+    }
+
+    pub fn remove_hook(&self, id: HookId) -> bool {
+        self.hooks.borrow_mut().remove(id)
+    }
+
+    // Value the access goes on with after the hooks ran
+    pub(crate) fn run_hooks(&self, kind: HookKind, address: u32, width: AccessWidth, value: u32) -> u32 {
+        // Accesses a hook makes itself are not hooked
+        match self.hooks.try_borrow_mut() {
+            Ok(mut hooks) if !hooks.is_empty() => {
+                hooks.run(MemoryAccess { kind, address, width, value, pc: self.fetch_address.get() })
+            }
+            _ => value,
+        }
+    }
+
+    pub fn sectors(&self) -> &[MemorySector] {
+        &self.sectors
+    }
+
+    // Sectors sharing their storage through an alias are only counted once
     pub fn total_memory(&self) -> usize {
-        // adding + 1 since the addresses are inclusive
-        self.memory_map.values().fold(0, |acc, sector| acc + sector.size())
+        let mut storage: Vec<*const ()> = Vec::new();
+        self.sectors.iter().fold(0, |acc, sector| {
+            let pointer = match &sector.device {
+                Some(device) => Rc::as_ptr(device) as *const (),
+                None => Rc::as_ptr(&sector.data) as *const (),
+            };
+            if storage.contains(&pointer) {
+                return acc;
+            }
+            storage.push(pointer);
+            acc + sector.size()
+        })
     }
 
     pub fn builder() -> MemoryBusBuilder {
         MemoryBusBuilder::new()
     }
+
+    fn insert(&mut self, sector: MemorySector) {
+        let position = self.sectors.partition_point(|other| other.start_address < sector.start_address);
+        self.sectors.insert(position, sector);
+
+        // Indices after the new sector moved, so the table is rebuilt
+        self.rebuild_page_table();
+    }
+
+    fn rebuild_page_table(&mut self) {
+        self.page_table.fill(Page::Unmapped);
+        for (index, sector) in self.sectors.iter().enumerate() {
+            let offset_mask = match &sector.mirror {
+                None => Some(u32::MAX),
+                Some(mirror) if mirror.period.is_power_of_two() && mirror.period as usize == sector.size() => Some(mirror.period - 1),
+                Some(_) => None,
+            };
+            for page in (sector.start_address >> PAGE_SHIFT)..=(sector.last_address() >> PAGE_SHIFT) {
+                let page = &mut self.page_table[page as usize];
+                *page = match std::mem::take(page) {
+                    Page::Unmapped => Page::Sector { index, offset_mask },
+                    Page::Sector { index: other, .. } => Page::Shared(vec![other, index]),
+                    Page::Shared(mut indices) => {
+                        indices.push(index);
+                        Page::Shared(indices)
+                    }
+                };
+            }
+        }
+    }
+
+    // First sector lying inside the addresses, for sectors that cover a smaller one completely
+    fn sector_mut(&mut self, start_address: u32) -> Option<&mut MemorySector> {
+        self.sectors.iter_mut().find(|sector| sector.start_address == start_address)
+    }
+
+    fn sector_within(&self, start_address: u32, end_address: u32) -> Option<&MemorySector> {
+        self.sectors.iter().find(|sector| start_address <= sector.start_address && sector.start_address <= end_address)
+    }
+}
+
+// Cycles an access to the sector takes, 1 where nothing is mapped
+pub(crate) fn sector_cycles(sector: Option<&MemorySector>, width: AccessWidth, sequential: bool) -> u32 {
+    sector.and_then(|sector| sector.timing.as_ref()).map_or(1, |timing| timing.get().cycles(width, sequential))
 }
 
 impl fmt::Display for MemoryBus {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (_, sector) in self.memory_map.iter() {
+        for sector in self.sectors.iter() {
             write!(f, "{}\n", sector)?;
         }
 
@@ -51,33 +332,123 @@ impl MemoryBusBuilder {
     }
 
     pub fn sector_with_size(&mut self, name: String, start_address: u32, size: usize) -> Result<&mut Self, MemoryError>{
-        if let Some(_) = self.memory_bus.sector(start_address) {
-            return Err(MemoryError::OverlappingMemorySectors(start_address));
+        let sector = MemorySector::with_size(name, start_address, size)?;
+        self.add_sector(sector)
+    }
+
+    pub fn sector_with_addresses(&mut self, name: String, start_address: u32, end_address: u32) -> Result<&mut Self, MemoryError> {
+        let sector = MemorySector::with_addresses(name, start_address, end_address)?;
+        self.add_sector(sector)
+    }
+
+    // Sector whose accesses are handled by the device instead of plain RAM
+    pub fn sector_with_device<D: MemoryDevice + 'static>(&mut self, name: String, start_address: u32, device: Rc<RefCell<D>>) -> Result<&mut Self, MemoryError> {
+        let sector = MemorySector::with_device(name, start_address, device)?;
+        self.add_sector(sector)
+    }
+
+    fn add_sector(&mut self, sector: MemorySector) -> Result<&mut Self, MemoryError> {
+        if self.memory_bus.sector(sector.start_address).is_some() {
+            return Err(MemoryError::OverlappingMemorySectors(sector.start_address));
         }
 
-        if let Some(_) = self.memory_bus.sector(start_address + size as u32) {
-            return Err(MemoryError::OverlappingMemorySectors(start_address + size as u32));
+        if self.memory_bus.sector(sector.end_address).is_some() {
+            return Err(MemoryError::OverlappingMemorySectors(sector.end_address));
         }
 
-        let sector = MemorySector::with_size(name, start_address, size as usize).map_err(|e| e)?;
-        self.memory_bus.memory_map.insert(start_address, sector);
+        if let Some(inner) = self.memory_bus.sector_within(sector.start_address, sector.end_address) {
+            return Err(MemoryError::OverlappingMemorySectors(inner.start_address));
+        }
+
+        self.memory_bus.insert(sector);
         Ok(self)
     }
 
-    pub fn sector_with_addresses(&mut self, name: String, start_address: u32, end_address: u32) -> Result<&mut Self, MemoryError> {
-        if let Some(_) = self.memory_bus.sector(start_address) {
-            return Err(MemoryError::OverlappingMemorySectors(start_address));
+    // Shared with the bus that gets built, for devices created before it
+    pub fn fetch_address(&self) -> Rc<Cell<u32>> {
+        self.memory_bus.fetch_address()
+    }
+
+    // Second view of the sector starting at `source_address` that shares its storage
+    pub fn alias(&mut self, name: String, start_address: u32, source_address: u32) -> Result<&mut Self, MemoryError> {
+        let source = self.memory_bus.sectors.iter()
+            .find(|sector| sector.start_address == source_address)
+            .ok_or(MemoryError::InvalidAddress(source_address))?;
+        let end_address = start_address.checked_add(source.end_address - source.start_address)
+            .ok_or(MemoryError::InvalidAddresses(start_address, source.end_address))?;
+        self.alias_until(name, start_address, end_address, source_address)
+    }
+
+    // Alias showing only the first part of the source, up to `end_address`
+    pub fn alias_until(&mut self, name: String, start_address: u32, end_address: u32, source_address: u32) -> Result<&mut Self, MemoryError> {
+        let source = self.memory_bus.sectors.iter()
+            .find(|sector| sector.start_address == source_address)
+            .ok_or(MemoryError::InvalidAddress(source_address))?;
+        if end_address < start_address || end_address - start_address > source.end_address - source.start_address {
+            return Err(MemoryError::InvalidAddresses(start_address, end_address));
         }
 
-        if let Some(_) = self.memory_bus.sector(end_address) {
-            return Err(MemoryError::OverlappingMemorySectors(end_address));
+        let sector = MemorySector { name, start_address, end_address, mirror: None, timing: None, ..source.clone() };
+        self.add_sector(sector)
+    }
+
+    // Repeats the sector starting at `start_address` every `period` bytes up to `end_address`
+    pub fn mirror(&mut self, start_address: u32, end_address: u32, period: u32) -> Result<&mut Self, MemoryError> {
+        let sector = self.memory_bus.sector_mut(start_address).ok_or(MemoryError::InvalidAddress(start_address))?;
+        if (period as usize) < sector.size() {
+            return Err(MemoryError::InvalidSize(period as usize));
+        }
+        if end_address < sector.end_address {
+            return Err(MemoryError::InvalidAddresses(start_address, end_address));
+        }
+
+        // The mirrors must not run into the next sector
+        let sector_end = sector.end_address;
+        if let Some(next) = self.memory_bus.sectors.iter().find(|other| sector_end < other.start_address && other.start_address <= end_address) {
+            return Err(MemoryError::OverlappingMemorySectors(next.start_address));
         }
 
-        let sector = MemorySector::with_addresses(name, start_address, end_address).map_err(|e| e)?;
-        self.memory_bus.memory_map.insert(start_address, sector);
+        if let Some(sector) = self.memory_bus.sector_mut(start_address) {
+            sector.mirror = Some(Mirror { end_address, period });
+        }
+        self.memory_bus.rebuild_page_table();
         Ok(self)
     }
 
+    // Wait states of the sector starting at `start_address`, the timing can change after the bus is built
+    pub fn timing(&mut self, start_address: u32, timing: SharedTiming) -> Result<&mut Self, MemoryError> {
+        let sector = self.memory_bus.sector_mut(start_address).ok_or(MemoryError::InvalidAddress(start_address))?;
+        sector.timing = Some(timing);
+        Ok(self)
+    }
+
+    // Access flags for the addresses, which must lie in one sector (mirrors follow the sector)
+    pub fn access(&mut self, start_address: u32, end_address: u32, flags: AccessFlags) -> Result<&mut Self, MemoryError> {
+        let sector = self.memory_bus.sectors.iter_mut()
+            .find(|sector| sector.start_address <= start_address && start_address <= sector.end_address)
+            .ok_or(MemoryError::InvalidAddress(start_address))?;
+        if end_address < start_address || end_address > sector.end_address {
+            return Err(MemoryError::InvalidAddresses(start_address, end_address));
+        }
+        sector.access.push(AccessRule {
+            start_offset: start_address - sector.start_address,
+            end_offset: end_address - sector.start_address,
+            flags,
+        });
+        Ok(self)
+    }
+
+    pub fn prefetch_buffer(&mut self, prefetch_buffer: PrefetchBuffer) -> &mut Self {
+        self.memory_bus.prefetch_buffer = Some(RefCell::new(prefetch_buffer));
+        self
+    }
+
+    // Unmapped reads return the open bus value and unmapped writes are ignored
+    pub fn open_bus(&mut self) -> &mut Self {
+        self.memory_bus.open_bus = true;
+        self
+    }
+
     pub fn build(&self) -> MemoryBus {
         self.memory_bus.clone()
     }
@@ -88,6 +459,7 @@ mod tests {
 
 
     use super::*;
+    use crate::memory::Bus;
     use crate::gba::{BIOS_START, BIOS_END, BIOS_SIZE, WRAM_ONBOARD_SIZE, WRAM_ONBOARD_START, WRAM_ONBOARD_END};
 
     #[test]
@@ -96,7 +468,7 @@ mod tests {
         builder.sector_with_size("BIOS".to_string(), BIOS_START, BIOS_SIZE).unwrap();
 
         let memory_bus = builder.build();
-        assert_eq!(memory_bus.memory_map.len(), 1);
+        assert_eq!(memory_bus.sectors().len(), 1);
         assert_eq!(memory_bus.total_memory(), BIOS_SIZE);
 
         // check valid sector was added
@@ -114,7 +486,7 @@ mod tests {
             .sector_with_size("WRAM_ONBOARD_SIZE".to_string(), WRAM_ONBOARD_START, WRAM_ONBOARD_SIZE).unwrap();
 
         let memory_bus = builder.build();
-        assert_eq!(memory_bus.memory_map.len(), 2);
+        assert_eq!(memory_bus.sectors().len(), 2);
         assert_eq!(memory_bus.total_memory(), BIOS_SIZE + WRAM_ONBOARD_SIZE);
 
         let middle_of_bios = BIOS_START + 100;
@@ -136,7 +508,7 @@ mod tests {
         builder.sector_with_addresses("BIOS".to_string(), BIOS_START, BIOS_END).unwrap();
 
         let memory_bus = builder.build();
-        assert_eq!(memory_bus.memory_map.len(), 1);
+        assert_eq!(memory_bus.sectors().len(), 1);
         assert_eq!(memory_bus.total_memory(), BIOS_SIZE);
 
         // check valid sector was added
@@ -162,4 +534,102 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_memory_bus_page_table() {
+        let memory_bus = MemoryBus::builder()
+            .sector_with_addresses("ROM".to_string(), 0x08000000, 0x09FFFFFF).unwrap()
+            .sector_with_size("High".to_string(), 0x00001000, 0x100).unwrap()
+            .sector_with_size("Low".to_string(), 0x00000000, 0x100).unwrap()
+            .build();
+
+        // Two sectors sharing page 0, one sector spanning pages 8 and 9
+        assert_eq!(memory_bus.sector(0x000000FF).unwrap().name, "Low");
+        assert_eq!(memory_bus.sector(0x00001080).unwrap().name, "High");
+        assert!(memory_bus.sector(0x00000100).is_none());
+        assert_eq!(memory_bus.sector(0x09000000).unwrap().name, "ROM");
+        assert!(memory_bus.sector(0x0A000000).is_none());
+        assert!(matches!(memory_bus.page_table[0], Page::Shared(_)));
+        assert!(matches!(memory_bus.page_table[9], Page::Sector { index: 2, offset_mask: Some(u32::MAX) }));
+        assert_eq!(memory_bus.region(0x00001080).unwrap().offset, 0x80);
+        assert_eq!(memory_bus.region(0x09000004).unwrap().offset, 0x01000004);
+
+        // Sorted by address
+        let names: Vec<&str> = memory_bus.sectors().iter().map(|sector| sector.name.as_str()).collect();
+        assert_eq!(names, vec!["Low", "High", "ROM"]);
+        assert!(memory_bus.to_string().starts_with("MemorySector: { name: Low"));
+    }
+
+    #[test]
+    fn test_memory_bus_builder_covering_sector() {
+        let mut builder = MemoryBus::builder();
+        builder.sector_with_size("Inner".to_string(), 0x100, 0x10).unwrap();
+
+        match builder.sector_with_addresses("Outer".to_string(), 0x0, 0x1FF) {
+            Ok(_) => assert!(false),
+            Err(e) => assert_eq!(e, MemoryError::OverlappingMemorySectors(0x100))
+        }
+    }
+
+    #[test]
+    fn test_memory_bus_mirror() {
+        let memory_bus = MemoryBus::builder()
+            .sector_with_size("RAM".to_string(), 0x02000000, 0x100).unwrap()
+            .mirror(0x02000000, 0x02FFFFFF, 0x100).unwrap()
+            .sector_with_size("VRAM".to_string(), 0x06000000, 0x180).unwrap()
+            .mirror(0x06000000, 0x06FFFFFF, 0x200).unwrap()
+            .build();
+
+        memory_bus.write_u32(0x02000010, 0x12345678).unwrap();
+        assert_eq!(memory_bus.read_u32(0x02000110).unwrap(), 0x12345678);
+        assert_eq!(memory_bus.read_u32(0x02FFFF10).unwrap(), 0x12345678);
+
+        // The period is longer than the sector: the upper part repeats in the gap
+        memory_bus.write_u16(0x06000104, 0xBEEF).unwrap();
+        assert_eq!(memory_bus.read_u16(0x06000184).unwrap(), 0xBEEF);
+        assert_eq!(memory_bus.read_u16(0x06000304).unwrap(), 0xBEEF);
+        assert_eq!(memory_bus.read_u16(0x06000384).unwrap(), 0xBEEF);
+        assert_eq!(memory_bus.sector(0x06FFFFFF).unwrap().name, "VRAM");
+        assert_eq!(memory_bus.total_memory(), 0x280);
+
+        // Mirrors of the size of the sector are masked in the page table
+        assert!(matches!(memory_bus.page_table[2], Page::Sector { offset_mask: Some(0xFF), .. }));
+        assert_eq!(memory_bus.region(0x02FFFF13).unwrap().offset, 0x13);
+        assert!(matches!(memory_bus.page_table[6], Page::Sector { offset_mask: None, .. }));
+        assert_eq!(memory_bus.region(0x06000384).unwrap().offset, 0x104);
+    }
+
+    #[test]
+    fn test_memory_bus_invalid_mirror() {
+        let mut builder = MemoryBus::builder();
+        builder
+            .sector_with_size("Low".to_string(), 0x0, 0x100).unwrap()
+            .sector_with_size("High".to_string(), 0x1000, 0x100).unwrap();
+
+        assert_eq!(builder.mirror(0x10, 0xFFF, 0x100).err(), Some(MemoryError::InvalidAddress(0x10)));
+        assert_eq!(builder.mirror(0x0, 0xFFF, 0x80).err(), Some(MemoryError::InvalidSize(0x80)));
+        assert_eq!(builder.mirror(0x0, 0x1FFF, 0x100).err(), Some(MemoryError::OverlappingMemorySectors(0x1000)));
+        // A sector added later must not land in the mirrors either
+        builder.mirror(0x0, 0xFFF, 0x100).unwrap();
+        assert_eq!(builder.sector_with_size("Inside".to_string(), 0x800, 0x10).err(), Some(MemoryError::OverlappingMemorySectors(0x800)));
+    }
+
+    #[test]
+    fn test_memory_bus_alias() {
+        let mut builder = MemoryBus::builder();
+        builder
+            .sector_with_size("ROM".to_string(), 0x08000000, 0x100).unwrap()
+            .alias("ROM mirror".to_string(), 0x0A000000, 0x08000000).unwrap();
+        assert_eq!(builder.alias("Missing".to_string(), 0x0C000000, 0x0C000000).err(), Some(MemoryError::InvalidAddress(0x0C000000)));
+        assert_eq!(builder.alias("Overlap".to_string(), 0x0A000080, 0x08000000).err(), Some(MemoryError::OverlappingMemorySectors(0x0A000080)));
+        builder.alias_until("ROM first half".to_string(), 0x0C000000, 0x0C00007F, 0x08000000).unwrap();
+        assert_eq!(builder.alias_until("Too long".to_string(), 0x0D000000, 0x0D000100, 0x08000000).err(), Some(MemoryError::InvalidAddresses(0x0D000000, 0x0D000100)));
+
+        let memory_bus = builder.build();
+        memory_bus.write_u32(0x08000020, 0xCAFEBABE).unwrap();
+        assert_eq!(memory_bus.read_u32(0x0A000020).unwrap(), 0xCAFEBABE);
+        assert_eq!(memory_bus.sector(0x0A0000FF).unwrap().name, "ROM mirror");
+        assert_eq!(memory_bus.read_u32(0x0C000020).unwrap(), 0xCAFEBABE);
+        assert!(memory_bus.sector(0x0C000080).is_none());
+        assert_eq!(memory_bus.total_memory(), 0x100);
+    }
 }
@@ -0,0 +1,100 @@
+use core::fmt;
+use std::{cell::RefCell, rc::Rc};
+
+use super::MemoryError;
+
+// Hardware behind a memory sector whose accesses have side effects (IO registers, save chips).
+// Offsets are relative to the start of the sector and already aligned to the access width.
+pub trait MemoryDevice: fmt::Debug {
+    // Number of bytes the device occupies on the bus
+    fn size(&self) -> usize;
+
+    fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError>;
+    fn write_u8(&mut self, offset: u32, value: u8) -> Result<(), MemoryError>;
+
+    // What a read would return, without changing the device's state (debuggers, disassemblers)
+    fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError>;
+
+    fn peek_u16(&self, offset: u32) -> Result<u16, MemoryError> {
+        Ok(u16::from_le_bytes([self.peek_u8(offset)?, self.peek_u8(offset + 1)?]))
+    }
+
+    fn peek_u32(&self, offset: u32) -> Result<u32, MemoryError> {
+        Ok(self.peek_u16(offset)? as u32 | ((self.peek_u16(offset + 2)? as u32) << 16))
+    }
+
+    // Wider accesses are split into little-endian byte accesses unless the device handles them itself
+    fn read_u16(&mut self, offset: u32) -> Result<u16, MemoryError> {
+        Ok(u16::from_le_bytes([self.read_u8(offset)?, self.read_u8(offset + 1)?]))
+    }
+
+    fn read_u32(&mut self, offset: u32) -> Result<u32, MemoryError> {
+        Ok(self.read_u16(offset)? as u32 | ((self.read_u16(offset + 2)? as u32) << 16))
+    }
+
+    // Opcode fetches, only devices that latch fetched values need to handle them
+    fn fetch_u16(&mut self, offset: u32) -> Result<u16, MemoryError> {
+        self.read_u16(offset)
+    }
+
+    fn fetch_u32(&mut self, offset: u32) -> Result<u32, MemoryError> {
+        self.read_u32(offset)
+    }
+
+    fn write_u16(&mut self, offset: u32, value: u16) -> Result<(), MemoryError> {
+        let [low, high] = value.to_le_bytes();
+        self.write_u8(offset, low)?;
+        self.write_u8(offset + 1, high)
+    }
+
+    fn write_u32(&mut self, offset: u32, value: u32) -> Result<(), MemoryError> {
+        self.write_u16(offset, value as u16)?;
+        self.write_u16(offset + 2, (value >> 16) as u16)
+    }
+}
+
+// Devices are shared between the bus and the hardware models driving them
+pub type SharedDevice = Rc<RefCell<dyn MemoryDevice>>;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Latch {
+        value: [u8; 4],
+        writes: usize,
+    }
+
+    impl MemoryDevice for Latch {
+        fn size(&self) -> usize {
+            4
+        }
+
+        fn read_u8(&mut self, offset: u32) -> Result<u8, MemoryError> {
+            self.peek_u8(offset)
+        }
+
+        fn peek_u8(&self, offset: u32) -> Result<u8, MemoryError> {
+            Ok(self.value[offset as usize])
+        }
+
+        fn write_u8(&mut self, offset: u32, value: u8) -> Result<(), MemoryError> {
+            self.writes += 1;
+            self.value[offset as usize] = value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_wide_accesses_split_into_bytes() {
+        let mut latch = Latch::default();
+        latch.write_u32(0, 0x12345678).unwrap();
+        assert_eq!(latch.writes, 4);
+        assert_eq!(latch.value, [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(latch.read_u16(2).unwrap(), 0x1234);
+        assert_eq!(latch.read_u32(0).unwrap(), 0x12345678);
+        assert_eq!(latch.peek_u32(0).unwrap(), 0x12345678);
+    }
+}
@@ -0,0 +1,124 @@
+use core::fmt;
+
+use super::AccessWidth;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HookKind {
+    Read,
+    Write,
+    Execute, // Opcode fetches
+}
+
+// What a hook gets to see of an access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: HookKind,
+    pub address: u32,
+    pub width: AccessWidth,
+    // Value read, about to be written or fetched
+    pub value: u32,
+    // Address of the instruction making the access
+    pub pc: u32,
+}
+
+// Returning Some replaces the value the access reads, writes or fetches
+pub type HookCallback = Box<dyn FnMut(&MemoryAccess) -> Option<u32>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(u32);
+
+struct Hook {
+    id: HookId,
+    kind: HookKind,
+    start_address: u32,
+    end_address: u32,
+    callback: HookCallback,
+}
+
+#[derive(Default)]
+pub struct MemoryHooks {
+    hooks: Vec<Hook>,
+    next_id: u32,
+}
+
+impl fmt::Debug for MemoryHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryHooks").field("hooks", &self.hooks.len()).finish()
+    }
+}
+
+impl MemoryHooks {
+    pub fn new() -> MemoryHooks {
+        MemoryHooks::default()
+    }
+
+    // Calls the callback for accesses of the kind starting between the addresses
+    pub fn add(&mut self, kind: HookKind, start_address: u32, end_address: u32, callback: HookCallback) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook { id, kind, start_address, end_address, callback });
+        id
+    }
+
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let count = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.hooks.len() != count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    // Runs the matching hooks in the order they were added, each sees the value the previous one left
+    pub fn run(&mut self, access: MemoryAccess) -> u32 {
+        let mut access = access;
+        for hook in self.hooks.iter_mut() {
+            if hook.kind == access.kind && hook.start_address <= access.address && access.address <= hook.end_address {
+                if let Some(value) = (hook.callback)(&access) {
+                    access.value = value;
+                }
+            }
+        }
+        access.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    fn access(kind: HookKind, address: u32, value: u32) -> MemoryAccess {
+        MemoryAccess { kind, address, width: AccessWidth::Word, value, pc: 0x08000000 }
+    }
+
+    #[test]
+    fn test_memory_hooks() {
+        let mut hooks = MemoryHooks::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+
+        let log = seen.clone();
+        let watch = hooks.add(HookKind::Write, 0x100, 0x1FF, Box::new(move |access| {
+            log.borrow_mut().push((access.address, access.value, access.pc));
+            None
+        }));
+        hooks.add(HookKind::Read, 0x100, 0x103, Box::new(|access| Some(access.value + 1)));
+        hooks.add(HookKind::Read, 0x100, 0x103, Box::new(|access| Some(access.value * 2)));
+
+        assert_eq!(hooks.run(access(HookKind::Write, 0x180, 7)), 7);
+        assert_eq!(hooks.run(access(HookKind::Write, 0x200, 7)), 7);
+        assert_eq!(*seen.borrow(), vec![(0x180, 7, 0x08000000)]);
+
+        // Overrides chain, other kinds are left alone
+        assert_eq!(hooks.run(access(HookKind::Read, 0x100, 1)), 4);
+        assert_eq!(hooks.run(access(HookKind::Execute, 0x100, 1)), 1);
+
+        assert!(hooks.remove(watch));
+        assert!(!hooks.remove(watch));
+        hooks.run(access(HookKind::Write, 0x180, 7));
+        assert_eq!(seen.borrow().len(), 1);
+    }
+}
@@ -1,13 +1,50 @@
 use core::fmt;
 use std::{cell::RefCell, rc::Rc};
-use super::MemoryError;
+use bitflags::bitflags;
+use super::{MemoryError, SharedDevice, SharedTiming};
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct AccessFlags: u8 {
+        // Writes fail with MemoryError::ReadOnly
+        const READ_ONLY = 1 << 0;
+
+        // Writes are dropped without an error (ROM, BIOS)
+        const IGNORE_WRITES = 1 << 1;
+
+        // 8-bit writes are dropped (OAM, OBJ VRAM)
+        const IGNORE_BYTE_WRITES = 1 << 2;
+
+        // 8-bit writes store the byte in both halves of the halfword (palette RAM, BG VRAM)
+        const DUPLICATE_BYTE_WRITES = 1 << 3;
+    }
+}
+
+// Flags for the part of a sector between two offsets, mirrors included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessRule {
+    pub start_offset: u32,
+    pub end_offset: u32,
+    pub flags: AccessFlags,
+}
 
 #[derive(Debug, Clone)]
 pub struct MemorySector {
     pub name: String,
     pub start_address: u32,
     pub end_address: u32,
-    pub data: Rc<RefCell<Vec<u8>>>,
+    pub data: Rc<RefCell<Vec<u8>>>, // Empty when a device backs the sector
+    pub device: Option<SharedDevice>,
+    pub mirror: Option<Mirror>,
+    pub timing: Option<SharedTiming>, // None takes 1 cycle per access
+    pub access: Vec<AccessRule>, // Empty allows every access
+}
+
+// The sector repeats every `period` bytes from its start up to `end_address`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    pub end_address: u32,
+    pub period: u32,
 }
 
 impl fmt::Display for MemorySector {
@@ -25,7 +62,11 @@ impl MemorySector {
                         name: name,
                         start_address: start_address,
                         end_address: end_address - 1,
-                        data: Rc::new(RefCell::new(vec![0; size]))
+                        data: Rc::new(RefCell::new(vec![0; size])),
+                        device: None,
+                        mirror: None,
+                        timing: None,
+                        access: Vec::new(),
                     });
         }
         Err(MemoryError::InvalidSize(size))
@@ -41,15 +82,73 @@ impl MemorySector {
                     name: name,
                     start_address: start_address,
                     end_address: end_address,
-                    data: Rc::new(RefCell::new(vec![0; size as usize]))
+                    data: Rc::new(RefCell::new(vec![0; size as usize])),
+                    device: None,
+                    mirror: None,
+                    timing: None,
+                    access: Vec::new(),
                 }
             );
         }
         Err(MemoryError::InvalidAddresses(start_address, end_address))
     }
 
+    pub fn with_device(name: String, start_address: u32, device: SharedDevice) -> Result<MemorySector, MemoryError> {
+        let size = device.borrow().size();
+        match start_address.checked_add(size as u32) {
+            Some(end_address) if size > 0 => Ok(MemorySector {
+                name,
+                start_address,
+                end_address: end_address - 1,
+                data: Rc::new(RefCell::new(Vec::new())),
+                device: Some(device),
+                mirror: None,
+                timing: None,
+                access: Vec::new(),
+            }),
+            _ => Err(MemoryError::InvalidSize(size)),
+        }
+    }
+
+    // Last address the sector answers to, including its mirrors
+    pub fn last_address(&self) -> u32 {
+        self.mirror.as_ref().map_or(self.end_address, |mirror| mirror.end_address)
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        self.start_address <= address && address <= self.last_address()
+    }
+
+    // Offset into the data for an address inside the sector or one of its mirrors
+    pub fn offset(&self, address: u32) -> u32 {
+        let offset = address - self.start_address;
+        match &self.mirror {
+            Some(mirror) => {
+                let offset = offset % mirror.period;
+                let size = self.size() as u32;
+                // A period longer than the sector repeats its upper part to fill the gap (VRAM)
+                if offset >= size { offset - (mirror.period - size) } else { offset }
+            }
+            None => offset,
+        }
+    }
+
+    // Flags of the rules covering the address
+    pub fn access_flags(&self, address: u32) -> AccessFlags {
+        self.access_flags_at(self.offset(address))
+    }
+
+    pub fn access_flags_at(&self, offset: u32) -> AccessFlags {
+        self.access.iter()
+            .filter(|rule| rule.start_offset <= offset && offset <= rule.end_offset)
+            .fold(AccessFlags::empty(), |flags, rule| flags | rule.flags)
+    }
+
     pub fn size(&self) -> usize {
-        self.data.borrow().len()
+        match &self.device {
+            Some(device) => device.borrow().size(),
+            None => self.data.borrow().len(),
+        }
     }
 }
 
@@ -103,4 +202,15 @@ mod tests {
             Err(e) => assert_eq!(e, MemoryError::InvalidAddresses(start_address, end_address))
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_memory_sector_access_flags() {
+        let mut sector = MemorySector::with_size("Test".to_string(), 0x1000, 0x100).unwrap();
+        sector.mirror = Some(Mirror { end_address: 0x1FFF, period: 0x100 });
+        sector.access.push(AccessRule { start_offset: 0, end_offset: 0xFF, flags: AccessFlags::DUPLICATE_BYTE_WRITES });
+        sector.access.push(AccessRule { start_offset: 0x80, end_offset: 0xFF, flags: AccessFlags::IGNORE_BYTE_WRITES });
+
+        assert_eq!(sector.access_flags(0x1010), AccessFlags::DUPLICATE_BYTE_WRITES);
+        assert_eq!(sector.access_flags(0x1F90), AccessFlags::DUPLICATE_BYTE_WRITES | AccessFlags::IGNORE_BYTE_WRITES);
+    }
+}
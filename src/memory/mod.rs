@@ -2,8 +2,20 @@ mod memory;
 mod error;
 mod memory_bus;
 mod memory_sector;
+mod bus;
+mod flat_bus;
+mod memory_device;
+mod access_timing;
+mod prefetch_buffer;
+mod memory_hooks;
 
 pub use memory_bus::*;
 pub use memory::*;
 pub use memory_sector::*;
-pub use error::*;
\ No newline at end of file
+pub use error::*;
+pub use bus::*;
+pub use flat_bus::*;
+pub use memory_device::*;
+pub use access_timing::*;
+pub use prefetch_buffer::*;
+pub use memory_hooks::*;
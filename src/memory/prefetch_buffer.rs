@@ -0,0 +1,150 @@
+use std::{cell::Cell, rc::Rc};
+
+use super::AccessWidth;
+
+// Halfwords the buffer holds
+pub const PREFETCH_CAPACITY: u32 = 8;
+
+// Reads the halfwords following the last opcode fetch from a slow region while the bus is free,
+// so sequential opcode fetches hitting the buffer take a single cycle
+#[derive(Debug, Clone)]
+pub struct PrefetchBuffer {
+    start_address: u32,
+    end_address: u32,
+    // Shared with the register that switches it on
+    enabled: Rc<Cell<bool>>,
+    // Address of the first buffered halfword, None while stopped
+    head: Option<u32>,
+    // Halfwords buffered
+    count: u32,
+    // Cycles spent on the halfword being fetched
+    progress: u32,
+}
+
+impl PrefetchBuffer {
+    pub fn new(start_address: u32, end_address: u32, enabled: Rc<Cell<bool>>) -> PrefetchBuffer {
+        PrefetchBuffer { start_address, end_address, enabled, head: None, count: 0, progress: 0 }
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        self.start_address <= address && address <= self.end_address
+    }
+
+    // Address the next halfword is fetched from
+    pub fn fetch_address(&self) -> Option<u32> {
+        self.head.map(|head| head.wrapping_add(self.count * 2))
+    }
+
+    pub fn buffered(&self) -> u32 {
+        self.count
+    }
+
+    fn stop(&mut self) {
+        self.head = None;
+        self.count = 0;
+        self.progress = 0;
+    }
+
+    // Opcode fetch in the region, returns its cycles. `access_cycles` is the cost without the buffer,
+    // `halfword_cycles` the cost of a sequential halfword.
+    pub fn fetch(&mut self, address: u32, width: AccessWidth, access_cycles: u32, halfword_cycles: u32) -> u32 {
+        if !self.enabled.get() {
+            self.stop();
+            return access_cycles;
+        }
+
+        let next = address.wrapping_add(width.size() as u32);
+        if self.head != Some(address) {
+            // Miss, prefetching restarts after this opcode
+            self.stop();
+            self.head = Some(next);
+            return access_cycles;
+        }
+
+        let halfwords = (width.size() / 2).max(1);
+        let mut cycles = 0;
+        for _ in 0..halfwords {
+            if self.count > 0 {
+                self.count -= 1;
+                cycles += 1;
+            } else {
+                // Waits for the halfword in flight, which may be done already when the wait
+                // states got shorter during the fetch
+                cycles += halfword_cycles.saturating_sub(self.progress).max(1);
+                self.progress = 0;
+            }
+        }
+        self.head = Some(next);
+        cycles
+    }
+
+    // Data accesses to the region take the bus from the buffer
+    pub fn data_access(&mut self) {
+        self.stop();
+    }
+
+    // Cycles the region's bus was free for
+    pub fn advance(&mut self, cycles: u32, halfword_cycles: u32) {
+        if !self.enabled.get() || self.head.is_none() {
+            return;
+        }
+        self.progress += cycles;
+        while self.count < PREFETCH_CAPACITY && self.progress >= halfword_cycles {
+            self.count += 1;
+            self.progress -= halfword_cycles;
+        }
+        if self.count == PREFETCH_CAPACITY {
+            self.progress = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const HALFWORD: u32 = 2; // Sequential halfword at 1 wait state
+
+    #[test]
+    fn test_prefetch_buffer() {
+        let enabled = Rc::new(Cell::new(true));
+        let mut buffer = PrefetchBuffer::new(0x08000000, 0x0DFFFFFF, enabled.clone());
+
+        // First fetch misses and starts prefetching after it
+        assert_eq!(buffer.fetch(0x08000000, AccessWidth::Halfword, 5, HALFWORD), 5);
+        assert_eq!(buffer.fetch_address(), Some(0x08000002));
+
+        // 5 free cycles fill 2 halfwords
+        buffer.advance(5, HALFWORD);
+        assert_eq!(buffer.buffered(), 2);
+        assert_eq!(buffer.fetch(0x08000002, AccessWidth::Halfword, 2, HALFWORD), 1);
+        assert_eq!(buffer.fetch(0x08000004, AccessWidth::Halfword, 2, HALFWORD), 1);
+
+        // The third halfword is half way, only the rest is waited for
+        assert_eq!(buffer.fetch(0x08000006, AccessWidth::Halfword, 2, HALFWORD), 1);
+        assert_eq!(buffer.fetch(0x08000008, AccessWidth::Halfword, 2, HALFWORD), 2);
+
+        // Never more than 8 halfwords
+        buffer.advance(100, HALFWORD);
+        assert_eq!(buffer.buffered(), PREFETCH_CAPACITY);
+        assert_eq!(buffer.fetch(0x0800000A, AccessWidth::Word, 4, HALFWORD), 2);
+
+        // A branch misses
+        assert_eq!(buffer.fetch(0x08000100, AccessWidth::Halfword, 5, HALFWORD), 5);
+        assert_eq!(buffer.buffered(), 0);
+
+        buffer.data_access();
+        assert_eq!(buffer.fetch_address(), None);
+
+        // Wait states shortened while a halfword is fetched
+        buffer.fetch(0x08000000, AccessWidth::Halfword, 5, 9);
+        buffer.advance(5, 9);
+        assert_eq!(buffer.fetch(0x08000002, AccessWidth::Halfword, 2, HALFWORD), 1);
+
+        enabled.set(false);
+        buffer.fetch(0x08000000, AccessWidth::Halfword, 5, HALFWORD);
+        buffer.advance(100, HALFWORD);
+        assert_eq!(buffer.fetch(0x08000002, AccessWidth::Halfword, 2, HALFWORD), 2);
+    }
+}
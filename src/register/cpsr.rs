@@ -4,9 +4,11 @@ use bitflags::bitflags;
 
 use crate::cpu::CpuState;
 
+use super::Mode;
+
 
 bitflags! {
-    #[derive(Debug, Clone, Default)]
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
     pub struct CPSR: u32 {
 
         // Sign flag (0 = positive, 1 = negative)
@@ -168,6 +170,14 @@ impl CPSR {
             _ => {}
         }
     }
+
+    pub fn mode(&self) -> Option<Mode> {
+        Mode::from_bits(self.bits())
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        *self = CPSR::from_bits_retain((self.bits() & !CPSR::M.bits()) | mode.bits());
+    }
 }
 
 impl fmt::Display for CPSR {
@@ -309,4 +319,15 @@ mod tests {
         cpsr.set_state(CpuState::THUMB);
         assert!(cpsr.state() == CpuState::THUMB);
     }
+
+    #[test]
+    fn test_set_mode() {
+        let mut cpsr = CPSR::N | CPSR::I;
+        assert_eq!(cpsr.mode(), None);
+        cpsr.set_mode(Mode::UNDEFINED);
+        assert_eq!(cpsr.mode(), Some(Mode::UNDEFINED));
+        assert_eq!(cpsr.bits() & CPSR::M.bits(), 0b11011);
+        assert!(cpsr.is_negative());
+        assert!(cpsr.is_irq_disable());
+    }
 }
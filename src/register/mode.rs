@@ -8,3 +8,31 @@ pub enum Mode {
     IRQ,
     UNDEFINED
 }
+
+impl Mode {
+    // Value of the CPSR mode bits (4-0) for this mode
+    pub fn bits(&self) -> u32 {
+        match self {
+            Mode::USER => 0b10000,
+            Mode::FIQ => 0b10001,
+            Mode::IRQ => 0b10010,
+            Mode::SUPERVISOR => 0b10011,
+            Mode::ABORT => 0b10111,
+            Mode::UNDEFINED => 0b11011,
+            Mode::SYSTEM => 0b11111,
+        }
+    }
+
+    pub fn from_bits(bits: u32) -> Option<Mode> {
+        match bits & 0b11111 {
+            0b10000 => Some(Mode::USER),
+            0b10001 => Some(Mode::FIQ),
+            0b10010 => Some(Mode::IRQ),
+            0b10011 => Some(Mode::SUPERVISOR),
+            0b10111 => Some(Mode::ABORT),
+            0b11011 => Some(Mode::UNDEFINED),
+            0b11111 => Some(Mode::SYSTEM),
+            _ => None,
+        }
+    }
+}